@@ -0,0 +1,6 @@
+#version 450
+
+// the shadow pass only writes depth
+
+void main() {
+}
@@ -0,0 +1,19 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+
+layout(set = 0, binding = 0) uniform Model {
+  mat4 model;
+} model;
+
+layout(set = 1, binding = 0) uniform Light {
+  vec3 dir;
+  vec3 ambient;
+  vec3 diffuse;
+  vec3 specular;
+  mat4 view_proj;
+} light;
+
+void main() {
+    gl_Position = light.view_proj * model.model * vec4(position, 1.0);
+}
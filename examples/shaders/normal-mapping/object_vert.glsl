@@ -3,7 +3,7 @@
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec2 tex_coord;
 layout(location = 2) in vec3 normal;
-layout(location = 3) in vec3 tangent;
+layout(location = 3) in vec4 tangent;
 
 layout(location = 0) out vec3 v_pos;
 layout(location = 1) out vec2 v_tex_coord;
@@ -34,8 +34,8 @@ void main() {
      gl_Position = camera.proj * camera.view * vec4(v_pos, 1.0);
 
      v_normal = normalize(normal);
-     vec3 tan = normalize(tangent);
-     vec3 bitangent = cross(tan, v_normal);
+     vec3 tan = normalize(tangent.xyz);
+     vec3 bitangent = cross(v_normal, tan) * tangent.w;
      mat3 TBN = transpose(mat3(tan, bitangent, v_normal));
      tan_light_pos = TBN * light.pos;
      tan_cam_pos = TBN * camera.pos;
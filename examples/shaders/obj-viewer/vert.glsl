@@ -3,7 +3,7 @@
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec2 tex_coord;
 layout(location = 2) in vec3 normal;
-layout(location = 3) in vec3 tangent;
+layout(location = 3) in vec4 tangent;
 
 layout(location = 0) out vec2 v_tex_coord;
 layout(location = 1) out vec3 tan_light_dir;
@@ -42,8 +42,8 @@ void main() {
   vec3 pos = vec3(model.model * vec4(position, 1.0));
   gl_Position = camera.proj * camera.view * vec4(pos, 1.0);
 
-  vec3 bitangent = cross(tangent, normal);
-  mat3 TBN = transpose(mat3(tangent, bitangent, normal));
+  vec3 bitangent = cross(normal, tangent.xyz) * tangent.w;
+  mat3 TBN = transpose(mat3(tangent.xyz, bitangent, normal));
   tan_light_dir = normalize(TBN * light.direction);
   tan_cam_pos = TBN * camera.pos;
   tan_frag_pos = TBN * pos;
@@ -1,5 +1,7 @@
 #version 450
 
+#include "linear_distance.glsl"
+
 layout(location = 0) in vec3 v_pos;
 layout(location = 1) in vec3 v_normal;
 
@@ -37,18 +39,17 @@ vec2 l_to_shadow_map_uv(vec3 v) {
   return uv;
 }
 
-bool is_in_shadow() {
+// 0 if fully lit, 1 if fully in shadow
+float shadow_amount() {
   // light is in center
   vec3 light_dir = normalize(v_pos);
   vec2 coords = l_to_shadow_map_uv(light_dir);
-  float sample_dist = texture(shadow_map, coords).r * 250.0;
 
   // because light is in center, this works
-  float frag_dist = length(v_pos);
   float bias = 0.005;
+  float frag_dist = linear_distance(v_pos, vec3(0.0), 250.0) - bias / 250.0;
 
-  // idk why i have to invert it
-  return !(sample_dist + bias > frag_dist);
+  return 1.0 - linear_distance_shadow(shadow_map, coords, frag_dist);
 }
 
 void main() {
@@ -56,7 +57,7 @@ void main() {
 
   vec3 ambient = norm * 0.3;
 
-  float shadow = is_in_shadow() ? 1.0 : 0.0;
+  float shadow = shadow_amount();
 
   vec3 diffuse = norm * (1.0 - shadow);
 
@@ -1,13 +1,11 @@
 #version 450
 
+#include "linear_distance.glsl"
+
 layout(location = 0) in vec3 v_pos;
+layout(location = 0) out float distance;
 
 void main() {
-  // light pos is fixed at center
-  float light_dist = length(v_pos);
-
-  // map to 0, 1 by dividing by far plane
-  light_dist /= 250.0;
-
-  gl_FragDepth = light_dist;
+  // light pos is fixed at center, 250 is the far plane
+  distance = linear_distance(v_pos, vec3(0.0), 250.0);
 }
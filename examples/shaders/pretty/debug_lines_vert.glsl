@@ -0,0 +1,21 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+layout(location = 0) out vec3 v_color;
+
+layout(set = 0, binding = 0) uniform sampler2D shadow_map;
+
+layout(set = 1, binding = 0) uniform Model {
+    mat4 model;
+} model;
+
+layout(set = 2, binding = 0) uniform Camera {
+    mat4 view;
+    mat4 proj;
+} camera;
+
+void main() {
+     v_color = color;
+     gl_Position = camera.proj * camera.view * model.model * vec4(position, 1.0);
+}
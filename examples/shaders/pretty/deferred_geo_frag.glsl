@@ -0,0 +1,45 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+layout(location = 1) in mat3 v_tbn;
+
+// G-buffer, see render_passes::deferred_read_depth
+layout(location = 0) out vec4 f_albedo;
+layout(location = 1) out vec4 f_normal;
+
+layout(set = 0, binding = 0) uniform sampler2D shadow_map;
+
+layout(set = 1, binding = 0) uniform Material {
+  vec3 ambient;
+  vec3 diffuse;
+  vec3 specular;
+  vec3 shininess;
+  vec3 use_texture;
+} material;
+
+layout(set = 1, binding = 1) uniform Model {
+  mat4 model;
+} model;
+
+layout(set = 2, binding = 0) uniform sampler2D diffuse_map;
+layout(set = 2, binding = 1) uniform sampler2D specular_map;
+layout(set = 2, binding = 2) uniform sampler2D normal_map;
+
+layout(set = 3, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+  vec3 pos;
+} camera;
+
+layout(set = 3, binding = 1) uniform Light {
+  vec3 position;
+  vec3 strength;
+} light;
+
+void main() {
+  vec4 tex_diffuse = material.use_texture.r > 0.5 ? texture(diffuse_map, v_tex_coord) : vec4(material.diffuse, 1.0);
+  f_albedo = vec4(tex_diffuse.rgb, 1.0);
+
+  vec3 normal = texture(normal_map, v_tex_coord).rgb * 2.0 - 1.0;
+  f_normal = vec4(normalize(v_tbn * normal), 0.0);
+}
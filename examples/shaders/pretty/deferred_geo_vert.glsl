@@ -0,0 +1,58 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 tex_coord;
+layout(location = 2) in vec3 normal;
+layout(location = 3) in vec4 tangent;
+
+layout(location = 0) out vec2 v_tex_coord;
+layout(location = 1) out mat3 v_tbn;
+
+// same sets as vert.glsl, so the objects' collections still fit
+layout(set = 0, binding = 0) uniform sampler2D shadow_map;
+
+layout(set = 1, binding = 0) uniform Material {
+  vec3 ambient;
+  vec3 diffuse;
+  vec3 specular;
+  vec3 shininess;
+  vec3 use_texture;
+} material;
+
+layout(set = 1, binding = 1) uniform Model {
+  mat4 model;
+} model;
+
+layout(set = 2, binding = 0) uniform sampler2D diffuse_map;
+layout(set = 2, binding = 1) uniform sampler2D specular_map;
+layout(set = 2, binding = 2) uniform sampler2D normal_map;
+
+layout(set = 3, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+  vec3 pos;
+} camera;
+
+layout(set = 3, binding = 1) uniform Light {
+  vec3 position;
+  vec3 strength;
+} light;
+
+// has to match depth_prepass_vert.glsl exactly, see there
+invariant gl_Position;
+
+void main() {
+  v_tex_coord = tex_coord;
+  vec3 world_pos = vec3(model.model * vec4(position, 1.0));
+  gl_Position = camera.proj * camera.view * vec4(world_pos, 1.0);
+
+  // tangent space to world space this time, the lighting subpass works in
+  // world space
+  mat3 model3 = mat3(model.model);
+  vec3 bitangent = cross(normal, tangent.xyz) * tangent.w;
+  v_tbn = mat3(
+    normalize(model3 * tangent.xyz),
+    normalize(model3 * bitangent),
+    normalize(model3 * normal)
+  );
+}
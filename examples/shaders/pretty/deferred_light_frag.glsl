@@ -0,0 +1,88 @@
+#version 450
+
+#include "point_shadow.glsl"
+
+layout(location = 0) in vec2 v_ndc;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D shadow_map;
+
+// written by deferred_geo_frag.glsl in the first subpass
+layout(set = 1, binding = 0, input_attachment_index = 0) uniform subpassInput albedo;
+layout(set = 1, binding = 1, input_attachment_index = 1) uniform subpassInput normal;
+layout(set = 1, binding = 2, input_attachment_index = 2) uniform subpassInput depth;
+
+layout(set = 2, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+  vec3 pos;
+} camera;
+
+layout(set = 2, binding = 1) uniform Light {
+  vec3 position;
+  vec3 strength; // vec3 really means float, idk why it doesn't work
+} light;
+
+float A = 0.15;
+float B = 0.50;
+float C = 0.10;
+float D = 0.20;
+float E = 0.02;
+float F = 0.30;
+float W = 11.2;
+
+// taken from: http://filmicworlds.com/blog/filmic-tonemapping-operators/
+vec3 Uncharted2Tonemap(vec3 x)
+{
+  return ((x*(A*x+C*B)+D*E)/(x*(A*x+B)+D*F))-E/F;
+}
+
+float shadowedness(vec3 pos, vec3 norm) {
+  return point_shadowedness(shadow_map, pos, norm, light.position);
+}
+
+// the same lighting as all_frag.glsl, just in world space
+void main() {
+  float frag_depth = subpassLoad(depth).r;
+  // nothing was drawn here
+  if (frag_depth >= 1.0) {
+    f_color = vec4(0.0, 0.0, 0.0, 1.0);
+    return;
+  }
+
+  vec4 world = inverse(camera.proj * camera.view) * vec4(v_ndc, frag_depth, 1.0);
+  vec3 pos = world.xyz / world.w;
+
+  vec3 tex_diffuse = subpassLoad(albedo).rgb;
+  vec3 norm = normalize(subpassLoad(normal).xyz);
+
+  // ambient
+  vec3 ambient = tex_diffuse * 0.02;
+
+  // diffuse
+  vec3 light_dir = normalize(light.position - pos);
+  float diff = max(dot(norm, light_dir), 0.0);
+  vec3 diffuse = diff * tex_diffuse;
+
+  // specular
+  vec3 view_dir = normalize(camera.pos - pos);
+  vec3 halfway_dir = normalize(light_dir + view_dir);
+  float spec = pow(max(dot(norm, halfway_dir), 0.0), 32.0);
+  vec3 specular = vec3(clamp(0.2 * spec, 0.0, 0.5));
+
+  // result
+  float dist = length(light.position - pos);
+  float shadow = shadowedness(pos, norm);
+
+  vec3 result = ambient + (1.0 - shadow) * (diffuse + specular) * light.strength.r / (dist * dist / 2000.0);
+
+  // uncharted 2 tone mapping
+  result *= 16;
+  float exposure_bias = 2.0;
+  vec3 curr = Uncharted2Tonemap(exposure_bias * result);
+
+  vec3 corrected = pow(curr, vec3(1/2.2));
+
+  f_color = vec4(corrected, 1.0);
+}
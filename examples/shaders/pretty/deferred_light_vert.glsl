@@ -0,0 +1,11 @@
+#version 450
+
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 v_ndc;
+
+// fullscreen_vert.glsl without the depth map, which would clash with set 0 of
+// deferred_light_frag.glsl
+void main() {
+  v_ndc = position;
+  gl_Position = vec4(position, 0.0, 1.0);
+}
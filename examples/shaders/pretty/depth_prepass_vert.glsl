@@ -11,7 +11,12 @@ layout(set = 1, binding = 0) uniform Camera {
     mat4 proj;
 } camera;
 
+// the geometry pass tests against this depth with Equal, so the position has
+// to come out bit for bit the same as in vert.glsl and deferred_geo_vert.glsl.
+// same expression, same inputs and invariant in all of them.
+invariant gl_Position;
+
 void main() {
-     gl_Position = camera.proj * camera.view * model.model * vec4(position, 1.0);
-     gl_Position.z += 0.0001;
+     vec3 world_pos = vec3(model.model * vec4(position, 1.0));
+     gl_Position = camera.proj * camera.view * vec4(world_pos, 1.0);
 }
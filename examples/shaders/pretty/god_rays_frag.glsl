@@ -0,0 +1,70 @@
+#version 450
+
+#include "point_shadow.glsl"
+
+// volumetric light shafts: marches along the view ray from the camera to the
+// surface, adding some scattered light for every sample the light can see
+
+layout(location = 0) in vec2 v_pos;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth_map;
+layout(set = 0, binding = 2) uniform sampler2D shadow_map;
+
+layout(set = 1, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+  vec3 pos;
+} camera;
+
+layout(set = 1, binding = 1) uniform Light {
+  vec3 position;
+  vec3 strength;
+} light;
+
+layout(set = 2, binding = 0) uniform GodRays {
+  // x: sample count, y: density, z: decay per sample
+  vec4 params;
+} god_rays;
+
+float visibility(vec3 world_pos) {
+  vec3 to_pos = world_pos - light.position;
+  // the shadow map stores distance to the light divided by the far plane
+  float occluder_dist = texture(shadow_map, l_to_shadow_map_uv(normalize(to_pos))).r * SHADOW_FAR;
+
+  return length(to_pos) < occluder_dist + 0.5 ? 1.0 : 0.0;
+}
+
+void main() {
+  vec3 base = texture(color, v_pos).rgb;
+  float depth = texture(depth_map, v_pos).r;
+
+  // reconstruct the world position of the surface from depth
+  vec4 ndc = vec4(v_pos * 2.0 - 1.0, depth, 1.0);
+  vec4 world = inverse(camera.proj * camera.view) * ndc;
+  vec3 frag_pos = world.xyz / world.w;
+
+  int samples = int(god_rays.params.x);
+  float density = god_rays.params.y;
+  float decay = god_rays.params.z;
+
+  vec3 ray = frag_pos - camera.pos;
+  vec3 step_vec = ray / float(samples);
+
+  float scattering = 0.0;
+  float weight = 1.0;
+  vec3 sample_pos = camera.pos;
+  for (int i = 0; i < samples; i++) {
+    sample_pos += step_vec;
+
+    // air close to the light is lit more strongly
+    float light_dist = length(sample_pos - light.position);
+    scattering += visibility(sample_pos) * weight / (1.0 + light_dist * light_dist * 0.001);
+
+    weight *= decay;
+  }
+  scattering *= density * length(ray) / float(samples);
+
+  f_color = vec4(base + vec3(scattering) * light.strength.r, 1.0);
+}
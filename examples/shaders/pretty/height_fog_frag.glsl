@@ -0,0 +1,51 @@
+#version 450
+
+// exponential height fog: density falls off exponentially above base_height,
+// and the amount of fog between the camera and the surface is integrated
+// analytically along the view ray, so low areas pool with fog
+
+layout(location = 0) in vec2 v_pos;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth_map;
+
+layout(set = 1, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+  vec3 pos;
+} camera;
+
+layout(set = 2, binding = 0) uniform Fog {
+  vec4 color;
+  // x: base height, y: falloff, z: density
+  vec4 params;
+} fog;
+
+void main() {
+  vec3 base = texture(color, v_pos).rgb;
+  float depth = texture(depth_map, v_pos).r;
+
+  // reconstruct the world position of the surface from depth
+  vec4 ndc = vec4(v_pos * 2.0 - 1.0, depth, 1.0);
+  vec4 world = inverse(camera.proj * camera.view) * ndc;
+  vec3 frag_pos = world.xyz / world.w;
+
+  float base_height = fog.params.x;
+  float falloff = fog.params.y;
+  float density = fog.params.z;
+
+  vec3 ray = frag_pos - camera.pos;
+  float ray_len = length(ray);
+
+  // integral of density * exp(-falloff * (h - base_height)) along the ray
+  float camera_density = density * exp(-falloff * (camera.pos.y - base_height));
+  float dy = falloff * ray.y;
+  // the ray is close to horizontal, density is constant along it
+  float height_term = abs(dy) > 0.0001 ? (1.0 - exp(-dy)) / dy : 1.0;
+  float fog_amount = camera_density * ray_len * height_term;
+
+  float fog_factor = clamp(1.0 - exp(-fog_amount), 0.0, 1.0);
+
+  f_color = vec4(mix(base, fog.color.rgb, fog_factor), 1.0);
+}
@@ -0,0 +1,78 @@
+// the point light shadow lookup every pretty shader shares. the shadow map is
+// the 6 faces of a cube around the light in a row, holding linear distance
+// (see shadow_cast_frag.glsl).
+
+#include "linear_distance.glsl"
+
+// the distance shadow_cast_frag.glsl divides by, so the map is 0-1
+#define SHADOW_FAR 250.0
+
+// the bias, in shadow map texels like render-engine's shadow.glsl: always
+// this much towards the light...
+#define POINT_SHADOW_CONSTANT_BIAS 1.0
+// ...plus this much per unit of tan(angle to the light)...
+#define POINT_SHADOW_SLOPE_BIAS 1.0
+// ...which is capped, since tan blows up at grazing angles
+#define POINT_SHADOW_MAX_SLOPE 10.0
+
+// cube faces +x, -x, +y, -y, +z, -z in a row
+// taken from: http://blue2rgb.sydneyzh.com/rendering-dynamic-cube-maps-for-omni-light-shadows-with-vulkan-api.html
+vec2 l_to_shadow_map_uv(vec3 v) {
+  float face_index;
+  vec3 v_abs = abs(v);
+  float ma;
+  vec2 uv;
+  if(v_abs.z >= v_abs.x && v_abs.z >= v_abs.y)
+    {
+      face_index = v.z < 0.0 ? 5.0 : 4.0;
+      ma = 0.5 / v_abs.z;
+      uv = vec2(v.z < 0.0 ? -v.x : v.x, -v.y);
+    }
+  else if(v_abs.y >= v_abs.x)
+    {
+      face_index = v.y < 0.0 ? 3.0 : 2.0;
+      ma = 0.5 / v_abs.y;
+      uv = vec2(v.x, v.y < 0.0 ? -v.z : v.z);
+    }
+  else
+    {
+      face_index = v.x < 0.0 ? 1.0 : 0.0;
+      ma = 0.5 / v_abs.x;
+      uv = vec2(v.x < 0.0 ? v.z : -v.z, -v.y);
+    }
+  uv = uv * ma + 0.5;
+  uv = uv * 0.9921875 + 0.00390625;
+  uv.x = (uv.x + face_index) / 6.f;
+  return uv;
+}
+
+// how shadowed pos is, 0 to 1. normal only sets the bias, so which way it
+// points doesn't matter, and the surface's own (not the normal mapped) one
+// works best.
+float point_shadowedness(sampler2D shadow_map, vec3 pos, vec3 normal, vec3 light_pos) {
+  vec3 light_dir = normalize(pos - light_pos);
+  vec2 coords = l_to_shadow_map_uv(light_dir);
+
+  float frag_dist = length(pos - light_pos);
+
+  // world units one texel covers at pos. every face is a 90 degree frustum,
+  // so 2 units wide at distance 1 along its axis, and the map is a row of
+  // them, so its height is a face's size
+  vec3 to_pos = abs(pos - light_pos);
+  float axis_dist = max(to_pos.x, max(to_pos.y, to_pos.z));
+  float texel = 2.0 * axis_dist / float(textureSize(shadow_map, 0).y);
+
+  // the more the surface slopes away from the light, the more of its depth
+  // one texel covers
+  float cos_angle = clamp(abs(dot(normalize(normal), light_dir)), 0.0, 1.0);
+  float sin_angle = sqrt(1.0 - cos_angle * cos_angle);
+  float tan_angle = min(sin_angle / max(cos_angle, 1e-4), POINT_SHADOW_MAX_SLOPE);
+
+  float bias = texel * (POINT_SHADOW_CONSTANT_BIAS + POINT_SHADOW_SLOPE_BIAS * tan_angle);
+
+  // shadow_map has distance to the light, this does the comparison (with
+  // 2x2 PCF) and gives how lit the fragment is
+  float lit = linear_distance_shadow(shadow_map, coords, (frag_dist - bias) / SHADOW_FAR);
+
+  return 1.0 - lit;
+}
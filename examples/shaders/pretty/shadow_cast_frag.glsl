@@ -1,6 +1,9 @@
 #version 450
 
+#include "point_shadow.glsl"
+
 layout(location = 0) in vec3 v_pos;
+layout(location = 0) out float distance;
 
 layout(set = 3, binding = 0) uniform Light {
   vec3 position;
@@ -8,10 +11,5 @@ layout(set = 3, binding = 0) uniform Light {
 } light;
 
 void main() {
-  float light_dist = length(v_pos - light.position);
-
-  // map to 0, 1 by dividing by far plane
-  light_dist /= 250.0;
-
-  gl_FragDepth = light_dist;
+  distance = linear_distance(v_pos, light.position, SHADOW_FAR);
 }
@@ -1,5 +1,7 @@
 #version 450
 
+#include "point_shadow.glsl"
+
 layout(location = 0) in vec2 v_tex_coord;
 layout(location = 1) in vec3 tan_light_pos;
 layout(location = 2) in vec3 tan_cam_pos;
@@ -36,49 +38,10 @@ layout(set = 3, binding = 1) uniform Light {
   vec3 strength; // vec3 really means float, idk why it doesn't work
 } light;
 
-// cube faces +x, -x, +y, -y, +z, -z in a row
-// taken from: http://blue2rgb.sydneyzh.com/rendering-dynamic-cube-maps-for-omni-light-shadows-with-vulkan-api.html
-vec2 l_to_shadow_map_uv(vec3 v) {
-  float face_index;
-  vec3 v_abs = abs(v);
-  float ma;
-  vec2 uv;
-  if(v_abs.z >= v_abs.x && v_abs.z >= v_abs.y)
-    {
-      face_index = v.z < 0.0 ? 5.0 : 4.0;
-      ma = 0.5 / v_abs.z;
-      uv = vec2(v.z < 0.0 ? -v.x : v.x, -v.y);
-    }
-  else if(v_abs.y >= v_abs.x)
-    {
-      face_index = v.y < 0.0 ? 3.0 : 2.0;
-      ma = 0.5 / v_abs.y;
-      uv = vec2(v.x, v.y < 0.0 ? -v.z : v.z);
-    }
-  else
-    {
-      face_index = v.x < 0.0 ? 1.0 : 0.0;
-      ma = 0.5 / v_abs.x;
-      uv = vec2(v.x < 0.0 ? v.z : -v.z, -v.y);
-    }
-  uv = uv * ma + 0.5;
-  uv = uv * 0.9921875 + 0.00390625;
-  uv.x = (uv.x + face_index) / 6.f;
-  return uv;
-}
-
 float shadowedness() {
-  vec3 light_dir = normalize(v_pos - light.position);
-  vec2 coords = l_to_shadow_map_uv(light_dir);
-  float sample_dist = texture(shadow_map, coords).r * 250.0;
-
-  float frag_dist = length(v_pos - light.position);
-  float bias = 0.05;
-
-  // idk why i have to invert it
-  float difference = abs(sample_dist - frag_dist);
-
-  return clamp(difference, 0.0, 1.0);
+  // the normal map doesn't matter for the bias, the face's normal is better
+  vec3 face_normal = cross(dFdx(v_pos), dFdy(v_pos));
+  return point_shadowedness(shadow_map, v_pos, face_normal, light.position);
 }
 
 void main() {
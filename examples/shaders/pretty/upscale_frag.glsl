@@ -0,0 +1,69 @@
+#version 450
+
+// spatial upscaler for rendering below window resolution: a lanczos-2 filter
+// (sharper than bilinear, a 4x4 texel footprint) plus an unsharp mask, with
+// the result clamped to the nearest texels so the filter's negative lobes
+// don't ring around edges. draws at full resolution while color is at the
+// render scale, see System::set_full_res_output.
+
+layout(location = 0) in vec2 v_pos;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+
+layout(set = 1, binding = 0) uniform Upscale {
+  // x: sharpness, 0 is plain lanczos, around 1 is very sharp
+  vec4 params;
+} upscale;
+
+const float PI = 3.14159265;
+
+float lanczos2(float x) {
+  if (abs(x) < 0.0001) {
+    return 1.0;
+  }
+  if (abs(x) >= 2.0) {
+    return 0.0;
+  }
+  float px = PI * x;
+  return 2.0 * sin(px) * sin(px / 2.0) / (px * px);
+}
+
+vec3 fetch(ivec2 texel, ivec2 size) {
+  return texelFetch(color, clamp(texel, ivec2(0), size - 1), 0).rgb;
+}
+
+void main() {
+  ivec2 size = textureSize(color, 0);
+
+  // position in source texels, relative to the texel centers
+  vec2 src_pos = v_pos * vec2(size) - 0.5;
+  ivec2 base = ivec2(floor(src_pos));
+  vec2 f = src_pos - vec2(base);
+
+  vec3 sum = vec3(0.0);
+  float weight_sum = 0.0;
+  vec3 near_min = vec3(1e10);
+  vec3 near_max = vec3(-1e10);
+
+  for (int y = -1; y <= 2; y++) {
+    for (int x = -1; x <= 2; x++) {
+      vec3 texel = fetch(base + ivec2(x, y), size);
+      float weight = lanczos2(float(x) - f.x) * lanczos2(float(y) - f.y);
+      sum += texel * weight;
+      weight_sum += weight;
+
+      // the 2x2 texels around the sample bound what the result can be
+      if (x >= 0 && x <= 1 && y >= 0 && y <= 1) {
+        near_min = min(near_min, texel);
+        near_max = max(near_max, texel);
+      }
+    }
+  }
+
+  vec3 sharp = sum / weight_sum;
+  vec3 smooth_color = texture(color, v_pos).rgb;
+  vec3 result = sharp + upscale.params.x * (sharp - smooth_color);
+
+  f_color = vec4(clamp(result, near_min, near_max), 1.0);
+}
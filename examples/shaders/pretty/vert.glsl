@@ -3,7 +3,7 @@
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec2 tex_coord;
 layout(location = 2) in vec3 normal;
-layout(location = 3) in vec3 tangent;
+layout(location = 3) in vec4 tangent;
 
 layout(location = 0) out vec2 v_tex_coord;
 layout(location = 1) out vec3 tan_light_pos;
@@ -40,13 +40,17 @@ layout(set = 3, binding = 1) uniform Light {
   vec3 strength; // vec3 really means float, idk why it doesn't work
 } light;
 
+// has to match depth_prepass_vert.glsl exactly, see there
+invariant gl_Position;
+
 void main() {
   v_tex_coord = tex_coord;
   v_pos = vec3(model.model * vec4(position, 1.0));
   gl_Position = camera.proj * camera.view * vec4(v_pos, 1.0);
 
-  vec3 bitangent = cross(tangent, normal);
-  mat3 TBN = transpose(mat3(normalize(tangent), normalize(bitangent), normalize(normal)));
+  // tangent.w flips the bitangent where the UVs are mirrored
+  vec3 bitangent = cross(normal, tangent.xyz) * tangent.w;
+  mat3 TBN = transpose(mat3(normalize(tangent.xyz), normalize(bitangent), normalize(normal)));
   tan_light_pos = TBN * light.position;
   tan_cam_pos = TBN * camera.pos;
   tan_frag_pos = TBN * v_pos;
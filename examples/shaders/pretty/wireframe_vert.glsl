@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+
+layout(set = 0, binding = 0) uniform sampler2D shadow_map;
+
+layout(set = 1, binding = 0) uniform Model {
+    mat4 model;
+} model;
+
+layout(set = 2, binding = 0) uniform Camera {
+    mat4 view;
+    mat4 proj;
+} camera;
+
+// same as light_vert.glsl minus the z offset, the overlay gets a depth bias
+// through its pipeline instead
+void main() {
+     gl_Position = camera.proj * camera.view * model.model * vec4(position, 1.0);
+}
@@ -1,8 +1,9 @@
+use render_engine::input::{ActionMap, VirtualKeyCode};
 use render_engine::mesh::PrimitiveTopology;
 use render_engine::render_passes;
 use render_engine::system::{Pass, System};
 use render_engine::window::Window;
-use render_engine::object::ObjectPrototype;
+use render_engine::object::{ObjectPrototype, PipelineOptions};
 use render_engine::utils::Timer;
 
 use nalgebra_glm::*;
@@ -17,6 +18,10 @@ fn main() {
     let (mut window, queue) = Window::new();
     let device = queue.device().clone();
 
+    let mut actions = ActionMap::new();
+    actions.bind("toggle_frame_dump", VirtualKeyCode::P);
+    window.set_action_map(actions);
+
     // create system
     let render_pass = render_passes::multisampled_with_depth(device.clone(), 4);
     let mut system = System::new(
@@ -63,12 +68,30 @@ fn main() {
             (camera_data,),
         ),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build(queue.clone(), render_pass.clone());
 
     let mut camera_timer = Timer::new("Camera uniform buffer");
 
     while !window.update() {
+        // P toggles dumping frames for a turntable video
+        if window.get_frame_info().action_active("toggle_frame_dump") {
+            if system.frame_dump_timestep().is_some() {
+                system.stop_frame_dump();
+            } else {
+                system.start_frame_dump(&relative_path("frames/"), 30.0);
+            }
+
+            // while dumping, every frame advances time by exactly one video
+            // frame so the result is smooth no matter how fast we render
+            window.set_fixed_timestep(system.frame_dump_timestep());
+        }
+
+        if system.frame_dump_timestep().is_some() {
+            camera.yaw += window.get_frame_info().delta * 0.5;
+        }
+
         // update camera and camera buffer
         camera_timer.start();
         camera.update(window.get_frame_info());
@@ -81,11 +104,19 @@ fn main() {
         camera_timer.stop();
 
         // draw
-        system.start_window(&mut window);
+        if !system.start_window(&mut window) {
+            // minimized, idle until the window is back
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
         system.add_object(&object);
         system.finish_to_window(&mut window);
     }
 
+    // the last few frames of a frame dump are still on the GPU, let them
+    // finish so they get written too
+    system.flush_captures(&mut window);
+
     println!("FPS: {}", window.get_fps());
     println!("Avg. delta: {}", window.get_avg_delta() * 1_000.0);
     window.print_avg_update();
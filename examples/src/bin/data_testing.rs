@@ -1,5 +1,5 @@
 use render_engine::mesh::{Mesh, PrimitiveTopology};
-use render_engine::object::ObjectPrototype;
+use render_engine::object::{ObjectPrototype, PipelineOptions};
 use render_engine::render_passes;
 use render_engine::system::{Pass, System};
 use render_engine::utils::load_texture;
@@ -64,6 +64,7 @@ fn main() {
         },
         collection: ((model_data,),),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build(queue.clone(), render_pass.clone());
 
@@ -95,13 +96,18 @@ fn main() {
         },
         collection: ((texture,),),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build(queue.clone(), render_pass.clone());
 
     // used in main loop
     while !window.update() {
         // draw
-        system.start_window(&mut window);
+        if !system.start_window(&mut window) {
+            // minimized, idle until the window is back
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
         system.add_object(&object2);
         system.add_object(&object1);
         system.finish_to_window(&mut window);
@@ -1,17 +1,20 @@
 use render_engine::input::get_elapsed;
-use render_engine::mesh::PrimitiveTopology;
-use render_engine::object::ObjectPrototype;
+use render_engine::mesh::{Aabb, PrimitiveTopology};
+use render_engine::object::{ObjectPrototype, PipelineOptions};
+use render_engine::pipeline_cache::PipelineCache;
 use render_engine::render_passes;
-use render_engine::system::{Pass, System};
-use render_engine::utils::load_texture;
+use render_engine::system::{CustomImages, Pass, System};
+use render_engine::utils::{load_texture, SamplerSpec};
 use render_engine::window::Window;
 use render_engine::collection::Data;
+use render_engine::Image;
 
 // TODO: reeeeee i shouldn't have to do this
 use nalgebra_glm::*;
+use vulkano::command_buffer::DynamicState;
 use vulkano::format::Format;
-
-use std::collections::HashMap;
+use vulkano::image::AttachmentImage;
+use vulkano::pipeline::viewport::Viewport;
 
 use tests_render_engine::mesh::{add_tangents, convert_meshes, load_obj};
 use tests_render_engine::{relative_path, OrbitCamera, Matrix4};
@@ -22,34 +25,51 @@ fn main() {
     let device = queue.device().clone();
 
     // create system
+    // the sun's shadow map, a plain depth image it gets sampled with a
+    // comparison sampler (see shaders/include/shadow.glsl in render-engine)
+    let shadow_map: Image =
+        AttachmentImage::sampled(device.clone(), SHADOW_MAP_DIMS, Format::D32Sfloat).unwrap();
+    let rpass_shadow = render_passes::only_depth(device.clone());
     let render_pass = render_passes::multisampled_with_depth(device.clone(), 4);
     let mut system = System::new(
         queue.clone(),
-        vec![Pass {
-            name: "geometry",
-            images_created_tags: vec![
-                "resolve_color",
-                "multisampeld_color",
-                "multisampled_depth",
-            ],
-            images_needed_tags: vec![],
-            render_pass: render_pass.clone(),
-        }],
-        // custom images, we use none
-        HashMap::new(),
+        vec![
+            Pass {
+                name: "shadow",
+                images_created_tags: vec!["shadow_map"],
+                images_needed_tags: vec![],
+                render_pass: rpass_shadow.clone(),
+            },
+            Pass {
+                name: "geometry",
+                images_created_tags: vec![
+                    "resolve_color",
+                    "multisampeld_color",
+                    "multisampled_depth",
+                ],
+                images_needed_tags: vec!["shadow_map"],
+                render_pass: render_pass.clone(),
+            },
+        ],
+        CustomImages::new().insert("shadow_map", shadow_map),
         "resolve_color",
     );
+    system.set_sampler("shadow_map", SamplerSpec::shadow());
 
     window.set_render_pass(render_pass.clone());
 
+    let mut pipeline_cache = PipelineCache::new(device.clone(), render_pass.clone());
+    let mut pipeline_cache_shadow = PipelineCache::new(device.clone(), rpass_shadow.clone());
+
     // create buffers for model matrix, light and materials
     let model_data: Matrix4 = translate(&Mat4::identity(), &vec3(0.0, -6.0, 0.0)).into();
 
     let mut light = Light {
-        position: [10.0, 0.0, 0.0, 0.0],
+        direction: [1.0, 0.0, 0.0, 0.0],
         ambient: [0.3, 0.3, 0.3, 0.0],
         diffuse: [1.3, 1.3, 1.3, 0.0],
         specular: [1.5, 1.5, 1.5, 0.0],
+        view_proj: Mat4::identity().into(),
     };
 
     // TODO: implement Copy for queue?
@@ -84,24 +104,48 @@ fn main() {
     let basic_mesh = convert_meshes(&[models.remove(0)]).remove(0);
     let mesh = add_tangents(&basic_mesh);
 
+    // the shadow map only has to cover the raptor
+    let bounds = mesh
+        .bounds()
+        .transformed(&translate(&Mat4::identity(), &vec3(0.0, -6.0, 0.0)));
+
+    // 0 is the shadow map
     let mut object = ObjectPrototype {
         vs_path: relative_path("shaders/lighting/object_vert.glsl"),
         fs_path: relative_path("shaders/lighting/object_frag.glsl"),
         fill_type: PrimitiveTopology::TriangleList,
         read_depth: true,
         write_depth: true,
-        mesh,
+        mesh: mesh.clone(),
 
-        // 00 model 01 material 10 camera 20 light 30 diff 31 spec 32 norm
+        // 10 model 11 material 20 camera 30 light 40 diff 41 spec 42 norm
         collection: (
-            (model_data, material_data),
+            (model_data.clone(), material_data),
             (camera_data,),
             (light.clone(),),
             (diffuse_texture, specular_texture, normal_texture),
         ),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
+    }
+    .build(queue.clone(), &mut pipeline_cache, 1);
+
+    let mut shadow_caster = ObjectPrototype {
+        vs_path: relative_path("shaders/lighting/shadow_vert.glsl"),
+        fs_path: relative_path("shaders/lighting/shadow_frag.glsl"),
+        fill_type: PrimitiveTopology::TriangleList,
+        read_depth: true,
+        write_depth: true,
+        mesh,
+        collection: ((model_data,), (light.clone(),)),
+        // otherwise the viewport would be the window's size
+        custom_dynamic_state: Some(dynamic_state_for_bounds(
+            [0.0, 0.0],
+            [SHADOW_MAP_DIMS[0] as f32, SHADOW_MAP_DIMS[1] as f32],
+        )),
+        pipeline_options: PipelineOptions::default(),
     }
-    .build(queue.clone(), render_pass.clone());
+    .build(queue.clone(), &mut pipeline_cache_shadow, 0);
 
     // used in main loop
     let start_time = std::time::Instant::now();
@@ -111,20 +155,28 @@ fn main() {
         camera.update(window.get_frame_info());
         let camera_data = camera.get_data();
 
-        // update light
+        // update light, it circles above the raptor like a very fast sun
         let time = get_elapsed(start_time);
-        let light_x = (time / 4.0).sin() * 20.0;
-        let light_z = (time / 4.0).cos() * 20.0;
-        light.position = [light_x, 0.0, light_z, 0.0];
+        let light_dir = normalize(&vec3((time / 4.0).sin() * 2.0, 1.0, (time / 4.0).cos() * 2.0));
+        light.direction = [light_dir.x, light_dir.y, light_dir.z, 0.0];
+        light.view_proj = sun_view_proj(&light_dir, &bounds);
 
         object.collection.1.data.0 = camera_data;
         object.collection.2.data.0 = light.clone();
+        shadow_caster.collection.1.data.0 = light.clone();
 
         object.collection.1.upload(device.clone());
         object.collection.2.upload(device.clone());
+        shadow_caster.collection.1.upload(device.clone());
 
         // draw
-        system.start_window(&mut window);
+        if !system.start_window(&mut window) {
+            // minimized, idle until the window is back
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
+        system.add_object(&shadow_caster);
+        system.next_pass();
         system.add_object(&object);
         system.finish_to_window(&mut window);
     }
@@ -132,13 +184,43 @@ fn main() {
     println!("FPS: {}", window.get_fps());
 }
 
+const SHADOW_MAP_DIMS: [u32; 2] = [2048, 2048];
+
+// an orthographic projection looking along -direction that just fits bounds,
+// for the sun's shadow map. depth goes from 0 to 1 like vulkan wants, which
+// shadow.glsl assumes.
+fn sun_view_proj(direction: &Vec3, bounds: &Aabb) -> [[f32; 4]; 4] {
+    let center = make_vec3(&bounds.center());
+    let radius = distance(&make_vec3(&bounds.min), &make_vec3(&bounds.max)) / 2.0;
+
+    let eye = center + direction * radius * 2.0;
+    let view = look_at(&eye, &center, &vec3(0.0, 1.0, 0.0));
+    let proj = ortho_rh_zo(-radius, radius, -radius, radius, radius, radius * 3.0);
+
+    (proj * view).into()
+}
+
+fn dynamic_state_for_bounds(origin: [f32; 2], dimensions: [f32; 2]) -> DynamicState {
+    DynamicState {
+        line_width: None,
+        viewports: Some(vec![Viewport {
+            origin,
+            dimensions,
+            depth_range: 0.0..1.0,
+        }]),
+        scissors: None,
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Clone)]
 struct Light {
-    position: [f32; 4],
+    // towards the light
+    direction: [f32; 4],
     ambient: [f32; 4],
     diffuse: [f32; 4],
     specular: [f32; 4],
+    view_proj: [[f32; 4]; 4],
 }
 
 #[allow(dead_code)]
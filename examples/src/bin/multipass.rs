@@ -1,6 +1,6 @@
 use render_engine as re;
 
-use re::object::ObjectPrototype;
+use re::object::{ObjectPrototype, PipelineOptions};
 use re::render_passes;
 use re::system::{Pass, System};
 use re::window::Window;
@@ -68,6 +68,7 @@ fn main() {
         },
         collection: (),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build(queue.clone(), render_pass.clone(), 1);
 
@@ -80,7 +81,11 @@ fn main() {
 
     while !window.update() {
         // draw
-        system.start_window(&mut window);
+        if !system.start_window(&mut window) {
+            // minimized, idle until the window is back
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
         system.add_object(&triangle);
         system.next_pass();
         system.add_object(&quad);
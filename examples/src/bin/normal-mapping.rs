@@ -7,7 +7,7 @@ Why do I have to manage queue and device? :(
 
 use re::input::get_elapsed;
 use re::mesh::{Mesh, PrimitiveTopology};
-use re::object::ObjectPrototype;
+use re::object::{ObjectPrototype, PipelineOptions};
 use re::render_passes;
 use re::system::{Pass, System};
 use re::utils::load_texture;
@@ -95,6 +95,7 @@ fn main() {
             (normal_texture.clone(),),
         ),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build(queue.clone(), render_pass.clone());
 
@@ -110,6 +111,7 @@ fn main() {
             (camera_data,),
         ),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build(queue.clone(), render_pass.clone());
 
@@ -148,7 +150,11 @@ fn main() {
         }
 
         // draw
-        system.start_window(&mut window);
+        if !system.start_window(&mut window) {
+            // minimized, idle until the window is back
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
         system.add_object(&raptor);
 
         // if C is pressed, draw lines showing normals
@@ -184,8 +190,8 @@ fn normals_vis(mesh: &Mesh<VPosTexNormTan>) -> Mesh<VPosColor> {
         .iter()
         .flat_map(|v| {
             let normal = make_vec3(&v.normal);
-            let tangent = make_vec3(&v.tangent);
-            let bitangent = tangent.cross(&normal);
+            let tangent = vec3(v.tangent[0], v.tangent[1], v.tangent[2]);
+            let bitangent = normal.cross(&tangent) * v.tangent[3];
             let position = make_vec3(&v.position);
 
             vec![
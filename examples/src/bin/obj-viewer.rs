@@ -1,9 +1,9 @@
 use render_engine::collection::{Data, Set};
 use render_engine::input::get_elapsed;
-use render_engine::mesh::PrimitiveTopology;
-use render_engine::object::{Object, ObjectPrototype};
+use render_engine::mesh::{Aabb, PrimitiveTopology};
+use render_engine::object::{Drawcall, Object, ObjectPrototype, PipelineOptions};
 use render_engine::render_passes;
-use render_engine::system::{Pass, System};
+use render_engine::system::{Pass, RenderError, System};
 use render_engine::window::Window;
 use render_engine::Image;
 
@@ -25,6 +25,9 @@ fn main() {
     } else {
         Path::new(&args[1])
     };
+    // for models that were exported inside out, see the warning convert_meshes
+    // prints
+    let flip = args.iter().skip(2).any(|arg| arg == "--flip");
 
     // initialize window
     let (mut window, queue) = Window::new();
@@ -60,10 +63,23 @@ fn main() {
 
     // load meshes and materials
     let (models, materials) = load_obj(&path).expect("Couldn't open OBJ file");
-    let meshes = add_tangents_multi(&convert_meshes(&models));
+    let mut meshes = add_tangents_multi(&convert_meshes(&models));
+    if flip {
+        for mesh in meshes.iter_mut() {
+            mesh.flip_normals();
+            mesh.flip_winding();
+        }
+    }
+
+    // fit the camera's clip planes to whatever we loaded
+    let mesh_bounds: Vec<Aabb> = meshes.iter().map(|mesh| mesh.bounds()).collect();
+    camera.set_scene_bounds(Aabb::union_all(&mesh_bounds));
     let textures_path = path.parent().expect("Given path has no parent!");
     println!("Searching for textures in {:?}", textures_path);
-    let texture_sets = load_textures(queue.clone(), textures_path, &materials);
+    let (texture_sets, texture_warnings) = load_textures(queue.clone(), textures_path, &materials);
+    for warning in texture_warnings.iter() {
+        println!("Warning: {}", warning);
+    }
 
     let default_material = Material {
         ambient: [1.0, 1.0, 1.0, 0.0],
@@ -122,6 +138,7 @@ fn main() {
                     (camera.get_data(), light_data.clone()),
                 ),
                 custom_dynamic_state: None,
+                pipeline_options: PipelineOptions::default(),
             }
             .build(queue.clone(), render_pass.clone());
 
@@ -133,6 +150,12 @@ fn main() {
 
     // used in main loop
     while !window.update() {
+        // nothing to draw to while minimized, idle until the window is back
+        if window.is_minimized() {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
+
         // get updated info on camera and light
         camera.update(window.get_frame_info());
         let camera_data = camera.get_data();
@@ -145,19 +168,36 @@ fn main() {
             obj.collection.2.upload(device.clone());
         });
 
-        // draw
-        system.start_window(&mut window);
-
-        for object in objects.iter() {
-            system.add_object(object);
+        // draw. resizing or minimizing the window can make the swapchain out
+        // of date at any point, so rebuild it and try again next frame
+        // instead of crashing.
+        match draw_frame(&mut system, &mut window, &objects) {
+            Ok(()) => {}
+            Err(RenderError::SwapchainOutOfDate) => window.rebuild_swapchain(),
+            Err(e) => println!("Skipped a frame: {}", e),
         }
-
-        system.finish_to_window(&mut window);
     }
 
     println!("FPS: {}", window.get_fps());
 }
 
+fn draw_frame<O: Drawcall>(
+    system: &mut System,
+    window: &mut Window,
+    objects: &[O],
+) -> Result<(), RenderError> {
+    system.try_start_window(window)?;
+
+    for object in objects.iter() {
+        if let Err(e) = system.try_add_object(object) {
+            system.abandon_frame(window);
+            return Err(e);
+        }
+    }
+
+    system.try_finish_to_window(window)
+}
+
 #[derive(Clone)]
 struct Light {
     direction: [f32; 4],
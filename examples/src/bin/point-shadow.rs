@@ -3,7 +3,7 @@ use render_engine as re;
 use re::collection::{Set, Data, CollectionData};
 use re::collection_cache::pds_for_buffers;
 use re::mesh::{PrimitiveTopology, Vertex};
-use re::object::{ObjectPrototype, Object, Drawcall};
+use re::object::{ObjectPrototype, Object, Drawcall, PipelineOptions};
 use re::pipeline_cache::PipelineSpec;
 use re::system::{Pass, System};
 use re::window::Window;
@@ -30,7 +30,15 @@ fn main() {
     let device = queue.device().clone();
 
     // create system
+    // the shadow map holds distance to the light instead of depth, the depth
+    // image is only for depth testing while drawing the casters
     let patched_shadow_image: Image = vulkano::image::AttachmentImage::sampled(
+        device.clone(),
+        SHADOW_MAP_DIMS,
+        render_passes::DISTANCE_FORMAT,
+    )
+    .unwrap();
+    let shadow_depth_image: Image = vulkano::image::AttachmentImage::sampled(
         device.clone(),
         SHADOW_MAP_DIMS,
         Format::D32Sfloat,
@@ -38,8 +46,9 @@ fn main() {
     .unwrap();
     let mut custom_images = HashMap::new();
     custom_images.insert("shadow_map", patched_shadow_image);
+    custom_images.insert("shadow_depth", shadow_depth_image);
 
-    let rpass1 = render_passes::only_depth(device.clone());
+    let rpass1 = render_passes::linear_distance(device.clone());
     let rpass2 = render_passes::basic(device.clone());
     let rpass3 = render_passes::with_depth(device.clone());
 
@@ -49,7 +58,7 @@ fn main() {
             // renders to shadow cubemap
             Pass {
                 name: "shadow",
-                images_created_tags: vec!["shadow_map"],
+                images_created_tags: vec!["shadow_map", "shadow_depth"],
                 images_needed_tags: vec![],
                 render_pass: rpass1.clone(),
             },
@@ -71,6 +80,8 @@ fn main() {
         custom_images,
         "final_color",
     );
+    // the shadow map holds distance to the light, so empty means far away
+    system.set_clear_color("shadow_map", [1.0, 0.0, 0.0, 0.0]);
     window.set_render_pass(rpass1.clone());
 
     // create buffer and set for model matrix
@@ -94,6 +105,7 @@ fn main() {
         collection: (
         ),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build(queue.clone(), rpass3.clone());
 
@@ -119,6 +131,7 @@ fn main() {
         mesh,
         collection: (),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     };
 
     // create 6 different dragon objects, each with a different view matrix and
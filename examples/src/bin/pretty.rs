@@ -1,38 +1,84 @@
 use render_engine::collection::{CollectionData, Data, Set};
-use render_engine::input::{get_elapsed, VirtualKeyCode};
-use render_engine::mesh::{PrimitiveTopology, Vertex};
-use render_engine::object::{Drawcall, Object, ObjectPrototype};
+use render_engine::debug::{self, TextureViewer};
+use render_engine::decal::Decal;
+use render_engine::input::{ActionMap, Event, VirtualKeyCode, WindowEvent};
+use render_engine::mesh::{Aabb, Mesh, PrimitiveTopology, Vertex};
+use render_engine::object::{DeferredObject, Drawcall, Object, ObjectPrototype, PipelineOptions};
+use render_engine::particles::{Emitter, ParticleSystem};
 use render_engine::pipeline_cache::PipelineCache;
-use render_engine::render_passes;
-use render_engine::system::{Pass, System};
-use render_engine::utils::Timer;
-use render_engine::window::Window;
+use render_engine::quality::QualityPreset;
+use render_engine::ribbon::Ribbon;
+use render_engine::render_passes::{self, ReadDepthConfig};
+use render_engine::streams::StreamPrototype;
+use render_engine::system::{CustomImages, Pass, RenderPath, System};
+use render_engine::utils::{SamplerSpec, TimerRegistry};
+use render_engine::vertex_cache::DEFAULT_ACMR_CACHE_SIZE;
+use render_engine::window::{PresentMode, Window};
 use render_engine::{Format, Image, Queue, RenderPass};
 
 use vulkano::command_buffer::DynamicState;
+use vulkano::image::{Dimensions, ImmutableImage};
 use vulkano::pipeline::viewport::Viewport;
+use vulkano::sampler::{BorderColor, MipmapMode, SamplerAddressMode};
+use vulkano::sync::GpuFuture;
+
+use winit::{ElementState, MouseButton};
 
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use nalgebra_glm::*;
 
 use tests_render_engine::mesh::{
-    add_tangents, add_tangents_multi, convert_meshes, fullscreen_quad, load_obj, load_textures,
-    merge, only_pos, only_pos_from_ptnt, wireframe,
+    add_tangents, add_tangents_multi, convert_meshes, fullscreen_quad, fullscreen_quad_mesh,
+    load_obj, load_textures, merge, merged_positions, only_pos, split_positions, SceneStats,
 };
 use tests_render_engine::{relative_path, FlyCamera, Matrix4};
 
 const SHADOW_MAP_DIMS: [u32; 2] = [6_144, 1024];
-const PATCH_DIMS: [f32; 2] = [1024.0, 1024.0];
 
 fn main() {
     // initialize window
-    let (mut window, queue) = Window::new();
+    let (mut window, queue, async_queue) = Window::new_with_async_queue();
     let device = queue.device().clone();
 
+    // controls, see the checks in the main loop for what they do
+    let mut actions = ActionMap::new();
+    actions.bind("toggle_cursor", VirtualKeyCode::Escape);
+    actions.bind("next_view", VirtualKeyCode::C);
+    actions.bind("next_view", VirtualKeyCode::Right);
+    actions.bind("prev_view", VirtualKeyCode::V);
+    actions.bind("prev_view", VirtualKeyCode::Left);
+    actions.bind("toggle_wireframe", VirtualKeyCode::R);
+    actions.bind("toggle_normals", VirtualKeyCode::N);
+    actions.bind("toggle_god_rays", VirtualKeyCode::G);
+    actions.bind("toggle_fog", VirtualKeyCode::F);
+    actions.bind("toggle_trilinear", VirtualKeyCode::L);
+    actions.bind("next_quality", VirtualKeyCode::Q);
+    actions.bind("toggle_render_scale", VirtualKeyCode::M);
+    actions.bind("toggle_upscaler", VirtualKeyCode::U);
+    actions.bind("toggle_frames_in_flight", VirtualKeyCode::K);
+    actions.bind("toggle_engine_prepass", VirtualKeyCode::P);
+    actions.bind("toggle_sparks", VirtualKeyCode::H);
+    actions.bind("toggle_render_path", VirtualKeyCode::B);
+    actions.bind("freeze_frustum", VirtualKeyCode::J);
+    actions.bind("next_texture_view", VirtualKeyCode::T);
+    actions.bind("next_texture_channel", VirtualKeyCode::Y);
+    actions.bind("toggle_input_recording", VirtualKeyCode::F5);
+    actions.bind("replay_input", VirtualKeyCode::F6);
+    actions.bind("screenshot", VirtualKeyCode::F12);
+    actions.bind("toggle_vsync", VirtualKeyCode::F7);
+    window.set_action_map(actions);
+
     // create system
+    // the shadow maps hold distance to the light instead of depth, the depth
+    // image is only for depth testing while drawing the casters
     let patched_shadow: Image = vulkano::image::AttachmentImage::sampled(
+        device.clone(),
+        SHADOW_MAP_DIMS,
+        render_passes::DISTANCE_FORMAT,
+    )
+    .unwrap();
+    let shadow_depth: Image = vulkano::image::AttachmentImage::sampled(
         device.clone(),
         SHADOW_MAP_DIMS,
         Format::D32Sfloat,
@@ -41,69 +87,163 @@ fn main() {
     let shadow_blur: Image = vulkano::image::AttachmentImage::sampled(
         device.clone(),
         SHADOW_MAP_DIMS,
-        Format::D32Sfloat,
+        render_passes::DISTANCE_FORMAT,
     )
     .unwrap();
-    let mut custom_images = HashMap::new();
-    custom_images.insert("shadow_map", patched_shadow);
-    custom_images.insert("shadow_map_blur", shadow_blur);
-
-    let render_pass = render_passes::read_depth(device.clone());
-    let rpass_shadow = render_passes::only_depth(device.clone());
-    let rpass_shadow_blur = render_passes::only_depth(device.clone());
+    let custom_images = CustomImages::new()
+        .insert("shadow_map", patched_shadow)
+        .insert("shadow_depth", shadow_depth)
+        .insert("shadow_map_blur", shadow_blur);
+
+    // geometry keeps the depth the prepass drew and only shades what's exactly
+    // at it, so every pixel is shaded once
+    let geo_depth = ReadDepthConfig::after_prepass();
+    let render_pass = render_passes::read_depth_with(device.clone(), geo_depth);
+    let rpass_shadow = render_passes::linear_distance(device.clone());
+    let rpass_shadow_blur =
+        render_passes::basic_with_format(device.clone(), render_passes::DISTANCE_FORMAT);
     let rpass_cubeview = render_passes::basic(device.clone());
     let rpass_prepass = render_passes::only_depth(device.clone());
     let rpass_test = render_passes::basic(device.clone());
+    let rpass_decals = render_passes::overlay(device.clone());
+    let rpass_particles = render_passes::overlay(device.clone());
+    let rpass_god_rays = render_passes::basic(device.clone());
+    let rpass_fog = render_passes::basic(device.clone());
+    let rpass_upscale = render_passes::basic(device.clone());
+    let rpass_viewer = render_passes::basic(device.clone());
+    // geometry pass of the deferred render path, see the B key
+    let rpass_deferred = render_passes::deferred_read_depth(device.clone());
     
     // Create pipeline caches
     let mut pipeline_cache_main = PipelineCache::new(device.clone(), render_pass.clone());
     let mut pipeline_cache_shadow = PipelineCache::new(device.clone(), rpass_shadow.clone());
+    let mut pipeline_cache_decals = PipelineCache::new(device.clone(), rpass_decals.clone());
+    let mut pipeline_cache_particles =
+        PipelineCache::new(device.clone(), rpass_particles.clone());
+    let mut pipeline_cache_viewer = PipelineCache::new(device.clone(), rpass_viewer.clone());
+    let mut pipeline_cache_deferred = PipelineCache::new(device.clone(), rpass_deferred.clone());
+
+    let forward_passes = vec![
+        // renders to shadow cubemap
+        Pass {
+            name: "shadow",
+            images_created_tags: vec!["shadow_map", "shadow_depth"],
+            images_needed_tags: vec![],
+            render_pass: rpass_shadow.clone(),
+        },
+        // blurs shadow cubemap
+        Pass {
+            name: "shadow_blur",
+            images_created_tags: vec!["shadow_map_blur"],
+            images_needed_tags: vec!["shadow_map"],
+            render_pass: rpass_shadow_blur.clone(),
+        },
+        // depth prepass
+        Pass {
+            name: "depth_prepass",
+            images_created_tags: vec!["depth_prepass"],
+            images_needed_tags: vec![],
+            render_pass: rpass_prepass.clone(),
+        },
+        // displays any depth buffer for debugging
+        Pass {
+            name: "depth_viewer",
+            images_created_tags: vec!["depth_view"],
+            images_needed_tags: vec!["depth_prepass", "shadow_map_blur"],
+            render_pass: rpass_cubeview.clone(),
+        },
+        Pass {
+            name: "geometry",
+            images_created_tags: vec!["color", "depth_prepass"],
+            images_needed_tags: vec!["shadow_map_blur"],
+            render_pass: render_pass.clone(),
+        },
+        Pass {
+            name: "decals",
+            images_created_tags: vec!["color"],
+            images_needed_tags: vec!["depth_prepass"],
+            render_pass: rpass_decals.clone(),
+        },
+        // sparks coming off the light, and the trail behind it
+        Pass {
+            name: "particles",
+            images_created_tags: vec!["color"],
+            images_needed_tags: vec!["depth_prepass"],
+            render_pass: rpass_particles.clone(),
+        },
+        // adds light shafts on top of color
+        Pass {
+            name: "god_rays",
+            images_created_tags: vec!["god_rays"],
+            images_needed_tags: vec!["color", "depth_prepass", "shadow_map_blur"],
+            render_pass: rpass_god_rays.clone(),
+        },
+        // adds height fog
+        Pass {
+            name: "fog",
+            images_created_tags: vec!["fog"],
+            images_needed_tags: vec!["god_rays", "depth_prepass"],
+            render_pass: rpass_fog.clone(),
+        },
+        // final pass, upscales to the window when the render scale isn't 1
+        Pass {
+            name: "upscale",
+            images_created_tags: vec!["upscale"],
+            images_needed_tags: vec!["fog"],
+            render_pass: rpass_upscale.clone(),
+        },
+        // the texture viewer's pass gets added at the end while it's on
+    ];
 
-    let mut system = System::new(
-        queue.clone(),
-        vec![
-            // renders to shadow cubemap
-            Pass {
-                name: "shadow",
-                images_created_tags: vec!["shadow_map"],
-                images_needed_tags: vec![],
-                render_pass: rpass_shadow.clone(),
-            },
-            // blurs shadow cubemap
-            Pass {
-                name: "shadow_blur",
-                images_created_tags: vec!["shadow_map_blur"],
-                images_needed_tags: vec!["shadow_map"],
-                render_pass: rpass_shadow_blur.clone(),
-            },
-            // depth prepass
-            Pass {
-                name: "depth_prepass",
-                images_created_tags: vec!["depth_prepass"],
-                images_needed_tags: vec![],
-                render_pass: rpass_prepass.clone(),
-            },
-            // displays any depth buffer for debugging
-            Pass {
-                name: "depth_viewer",
-                images_created_tags: vec!["depth_view"],
-                images_needed_tags: vec!["depth_prepass", "shadow_map_blur"],
-                render_pass: rpass_cubeview.clone(),
-            },
-            // final pass
-            Pass {
-                name: "geometry",
-                images_created_tags: vec!["color", "depth_prepass"],
-                images_needed_tags: vec!["shadow_map_blur"],
-                render_pass: render_pass.clone(),
-            },
-        ],
-        custom_images,
-        "color",
+    // the same, but the geometry pass fills a G-buffer in its first subpass and
+    // lights it in the second
+    let deferred_passes: Vec<Pass> = forward_passes
+        .iter()
+        .cloned()
+        .map(|pass| {
+            if pass.name == "geometry" {
+                Pass {
+                    images_created_tags: vec!["color", "albedo", "normal", "depth_prepass"],
+                    render_pass: rpass_deferred.clone(),
+                    ..pass
+                }
+            } else {
+                pass
+            }
+        })
+        .collect();
+
+    let mut system = System::new(queue.clone(), forward_passes.clone(), custom_images, "upscale");
+    system.add_render_path(RenderPath::Deferred, deferred_passes.clone());
+    system.set_full_res_output(true);
+    system.set_debug_lines_pass("geometry");
+    // the shadow map holds distance to the light, so empty means far away
+    system.set_clear_color("shadow_map", [1.0, 0.0, 0.0, 0.0]);
+
+    // shadows don't depend on anything else, so they can start while the last
+    // frame is still being post-processed
+    if let Some(async_queue) = async_queue {
+        system.run_on_async_queue(&["shadow", "shadow_blur"], async_queue);
+    }
+
+    // anything sampled outside the shadow map is lit, not in shadow
+    system.set_sampler(
+        "shadow_map_blur",
+        SamplerSpec {
+            address_mode: SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueWhite),
+            border_color: BorderColor::FloatOpaqueWhite,
+            ..SamplerSpec::default()
+        },
     );
 
     window.set_render_pass(render_pass.clone());
 
+    // shows any image fullscreen, see the T and Y keys
+    let viewer_tags = TextureViewer::all_tags(&system, &["texture_viewer"]);
+    let mut texture_viewer =
+        TextureViewer::new(queue.clone(), &mut pipeline_cache_viewer, viewer_tags);
+    let mut texture_viewer_on = false;
+
     // initialize camera
     let mut camera = FlyCamera::default();
     camera.yaw = 0.0;
@@ -112,7 +252,7 @@ fn main() {
 
     // light
     let light = MovingLight::new();
-    let light_data = light.get_data();
+    let light_data = light.get_data(0.0);
 
     // a model buffer with .1 scale, used for a couple different objects
     let model_data: Matrix4 = scale(&Mat4::identity(), &vec3(0.1, 0.1, 0.1)).into();
@@ -132,17 +272,43 @@ fn main() {
         load_obj(&relative_path("meshes/sponza/sponza.obj")).expect("Couldn't load OBJ file");
 
     // convert to meshes and load textures
-    let meshes = add_tangents_multi(&convert_meshes(&models));
-    let textures = load_textures(queue.clone(), &relative_path("meshes/sponza/"), &materials);
+    let mut meshes = add_tangents_multi(&convert_meshes(&models));
+
+    // reorder for the vertex cache, see render_engine::vertex_cache
+    let acmr_of = |meshes: &[Mesh<_>]| {
+        let misses: f32 = meshes
+            .iter()
+            .map(|mesh| mesh.acmr(DEFAULT_ACMR_CACHE_SIZE) * (mesh.indices.len() / 3) as f32)
+            .sum();
+        let tris: usize = meshes.iter().map(|mesh| mesh.indices.len() / 3).sum();
+        misses / tris as f32
+    };
+    let acmr_before = acmr_of(&meshes);
+    meshes.iter_mut().for_each(|mesh| {
+        mesh.optimize_vertex_cache();
+        mesh.optimize_vertex_fetch();
+    });
+    println!("ACMR: {:.3} before optimizing, {:.3} after", acmr_before, acmr_of(&meshes));
+    let (textures, texture_warnings) =
+        load_textures(queue.clone(), &relative_path("meshes/sponza/"), &materials);
+    for warning in texture_warnings.iter() {
+        println!("Warning: {}", warning);
+    }
+
+    SceneStats::new(&models, &meshes, &materials, &textures).print();
 
-    println!("Total meshes: {}", meshes.len());
+    // for drawing with debug lines, see the J key
+    let mesh_bounds: Vec<Aabb> = meshes.iter().map(|mesh| mesh.bounds()).collect();
 
-    // merge meshes for use in depth prepass and shadow casting
+    // merge meshes for use in depth prepass, shadow casting and the
+    // wireframe, which only need positions
+    let merged_mesh_pos_only = merged_positions(&models);
+    // the normals view is the only thing that needs everything merged
     let merged_mesh = merge(&meshes);
-    let merged_mesh_pos_only = only_pos_from_ptnt(&merged_mesh);
 
-    // create objects for the geometry pass
-    let mut geo_objects: Vec<Object<_>> = meshes
+    // create objects for the geometry pass. the meshes get uploaded first and
+    // the pipelines made all at once afterwards
+    let deferred_geo_objects: Vec<DeferredObject<_>> = meshes
         .into_iter()
         .enumerate()
         .map(|(idx, mesh)| {
@@ -156,7 +322,7 @@ fn main() {
             };
             let textures = textures[mat_idx].clone();
 
-            let object = ObjectPrototype {
+            ObjectPrototype {
                 vs_path: relative_path("shaders/pretty/vert.glsl"),
                 fs_path: relative_path("shaders/pretty/all_frag.glsl"),
                 fill_type: PrimitiveTopology::TriangleList,
@@ -169,14 +335,19 @@ fn main() {
                     (camera_data.clone(), light_data.clone()),
                 ),
                 custom_dynamic_state: None,
+                pipeline_options: PipelineOptions {
+                    depth_compare: geo_depth.compare,
+                    ..PipelineOptions::default()
+                },
             }
-            .build(queue.clone(), &mut pipeline_cache_main, 1);
-
-            object
+            .build_deferred(queue.clone(), 1)
         })
         .collect();
-
-    println!("Objects Loaded: {}", geo_objects.len());
+    let mut geo_objects = DeferredObject::finalize_all(
+        deferred_geo_objects,
+        queue.clone(),
+        &mut pipeline_cache_main,
+    );
 
     // shadow stuff
     // create fullscreen quad to debug cubemap
@@ -200,7 +371,6 @@ fn main() {
         relative_path("shaders/pretty/blur_frag.glsl"),
     );
     quad_blur.custom_dynamic_state = Some(dynamic_state_blur);
-    quad_blur.pipeline_spec.write_depth = true;
 
     let shadow_cast_base = ObjectPrototype {
         vs_path: relative_path("shaders/pretty/shadow_cast_vert.glsl"),
@@ -212,6 +382,7 @@ fn main() {
         // convert_to_shadow_casters adds proper collections
         collection: (),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     };
 
     let mut depth_prepass_object = ObjectPrototype {
@@ -220,40 +391,46 @@ fn main() {
         fill_type: PrimitiveTopology::TriangleList,
         read_depth: true,
         write_depth: true,
-        mesh: merged_mesh_pos_only,
+        mesh: merged_mesh_pos_only.clone(),
         collection: ((model_data,), (camera_data.clone(),)),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build_direct(queue.clone(), rpass_prepass.clone(), 0);
 
     // create mesh for light (just a sphere)
-    // we need 2 objects: one for the depth prepass and one for the geometry stage
-    let light_mesh = {
+    // we need 2 objects: one for the depth prepass and one for the geometry
+    // stage. it's uploaded as separate streams so the prepass can bind just
+    // the positions the geometry object uses too.
+    let light_streams = {
         let (models, _materials) =
             load_obj(&relative_path("meshes/sphere.obj")).expect("Couldn't load OBJ file");
         let mesh = convert_meshes(&[models[0].clone()]).remove(0);
-        add_tangents(&mesh)
+        split_positions(&add_tangents(&mesh)).upload(queue.clone())
     };
 
-    let mut light_object_prepass = ObjectPrototype {
+    let mut light_object_prepass = StreamPrototype {
         vs_path: relative_path("shaders/pretty/depth_prepass_vert.glsl"),
         fs_path: relative_path("shaders/pretty/depth_prepass_frag.glsl"),
         fill_type: PrimitiveTopology::TriangleList,
         read_depth: true,
         write_depth: true,
-        mesh: light_mesh.clone(),
+        streams: light_streams.clone(),
+        positions_only: true,
         collection: ((model_data,), (camera_data.clone(),)),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build_direct(queue.clone(), rpass_prepass.clone(), 0);
 
-    let mut light_object_geo = ObjectPrototype {
+    let mut light_object_geo = StreamPrototype {
         vs_path: relative_path("shaders/pretty/vert.glsl"),
         fs_path: relative_path("shaders/pretty/light_frag.glsl"),
         fill_type: PrimitiveTopology::TriangleList,
         read_depth: true,
         write_depth: true,
-        mesh: light_mesh,
+        streams: light_streams,
+        positions_only: false,
         collection: (
             (material_data.clone(), model_data),
             // take the textures of the first object just to fill the space
@@ -262,46 +439,209 @@ fn main() {
             (camera_data.clone(), light_data.clone()),
         ),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions {
+            depth_compare: geo_depth.compare,
+            ..PipelineOptions::default()
+        },
     }
     .build(queue.clone(), &mut pipeline_cache_main, 1);
 
-    // create wireframe mesh
-    let wireframe_mesh = wireframe(&only_pos_from_ptnt(&merged_mesh));
-    let mut wireframe_object = ObjectPrototype {
-        // the light vertex shader does exactly the same we need to do, just
-        // converts the position to screen space and nothing else, so we re-use
-        // it
-        vs_path: relative_path("shaders/pretty/light_vert.glsl"),
-        fs_path: relative_path("shaders/pretty/wireframe_frag.glsl"),
-        fill_type: PrimitiveTopology::LineList,
-        read_depth: true,
-        write_depth: true,
-        mesh: wireframe_mesh,
-        collection: ((model_data,), (camera_data,)),
+    // lights the G-buffer in the deferred geometry pass's second subpass. set
+    // 0 is the shadow map and set 1 the G-buffer, System fills in both.
+    let mut deferred_light_object = ObjectPrototype {
+        vs_path: relative_path("shaders/pretty/deferred_light_vert.glsl"),
+        fs_path: relative_path("shaders/pretty/deferred_light_frag.glsl"),
+        fill_type: PrimitiveTopology::TriangleStrip,
+        read_depth: false,
+        write_depth: false,
+        mesh: fullscreen_quad_mesh(),
+        collection: ((camera_data.clone(), light_data.clone()),),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
-    .build(queue.clone(), &mut pipeline_cache_main, 1);
+    .build_subpass(queue.clone(), &mut pipeline_cache_deferred, 2, 1);
+
+    // volumetric light shafts, ray-marched through the shadow map
+    let god_ray_params = GodRayParams {
+        samples: 64.0,
+        density: 0.02,
+        decay: 0.99,
+    };
+    // a logo on the floor in the middle of the atrium, 10 units across
+    let decal_projector: Mat4 = rotate(
+        &Mat4::identity(),
+        std::f32::consts::PI / 2.0,
+        &vec3(1.0, 0.0, 0.0),
+    ) * scale(&Mat4::identity(), &vec3(5.0, 5.0, 1.0));
+    let decal = Decal {
+        projector: decal_projector.into(),
+        texture: logo_texture(queue.clone()),
+    };
+    let mut decal_object = decal.build(
+        queue.clone(),
+        &mut pipeline_cache_decals,
+        camera_data.clone(),
+    );
+
+    // sparks that shoot out of the light and fall to the floor
+    let spark_emitter = Emitter {
+        rate: 40.0,
+        direction: [0.0, 1.0, 0.0],
+        spread: std::f32::consts::PI,
+        speed: [1.0, 4.0],
+        lifetime: [0.5, 1.5],
+        gravity: [0.0, -9.8, 0.0],
+        drag: 0.5,
+        size: [0.08, 0.02],
+        start_color: [1.0, 0.8, 0.3, 1.0],
+        end_color: [1.0, 0.2, 0.0, 0.0],
+        ..Emitter::default()
+    };
+    let mut sparks = ParticleSystem::new(
+        queue.clone(),
+        &mut pipeline_cache_particles,
+        spark_emitter,
+        camera_data.clone(),
+    );
+    let spark_emitter_rate = sparks.emitter.rate;
+
+    // a glowing trail showing where the light has been
+    let mut light_trail = Ribbon::new(
+        queue.clone(),
+        &mut pipeline_cache_particles,
+        camera_data.clone(),
+    );
+    light_trail.width = 0.3;
+    light_trail.length = 20.0;
+    light_trail.spacing = 0.25;
+    light_trail.color = [1.0, 0.9, 0.6, 1.0];
+    light_trail.fade = [0.8, 0.0];
+    let mut draw_sparks = true;
+
+    let mut god_rays_object = ObjectPrototype {
+        vs_path: relative_path("shaders/pretty/fullscreen_vert.glsl"),
+        fs_path: relative_path("shaders/pretty/god_rays_frag.glsl"),
+        fill_type: PrimitiveTopology::TriangleStrip,
+        read_depth: false,
+        write_depth: false,
+        mesh: fullscreen_quad_mesh(),
+        collection: (
+            (camera_data.clone(), light_data.clone()),
+            (god_ray_params.get_data(),),
+        ),
+        custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
+    }
+    .build_direct(queue.clone(), rpass_god_rays.clone(), 1);
+
+    // ground fog, pools on the floor and thins out with altitude
+    let fog_params = FogParams {
+        base_height: 0.0,
+        falloff: 0.15,
+        density: 0.02,
+        color: [0.5, 0.55, 0.6],
+    };
+    let mut fog_object = ObjectPrototype {
+        vs_path: relative_path("shaders/pretty/fullscreen_vert.glsl"),
+        fs_path: relative_path("shaders/pretty/height_fog_frag.glsl"),
+        fill_type: PrimitiveTopology::TriangleStrip,
+        read_depth: false,
+        write_depth: false,
+        mesh: fullscreen_quad_mesh(),
+        collection: ((camera_data.clone(),), (fog_params.get_data(),)),
+        custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
+    }
+    .build_direct(queue.clone(), rpass_fog.clone(), 1);
+
+    // spatial upscaler, see upscale_frag.glsl
+    let upscale_params = UpscaleParams { sharpness: 0.3 };
+    let upscale_shader = relative_path("shaders/pretty/upscale_frag.glsl");
+
+    // create wireframe overlay
+    let mut wireframe_object = debug::wireframe_overlay(
+        queue.clone(),
+        &mut pipeline_cache_main,
+        &merged_mesh_pos_only,
+        relative_path("shaders/pretty/wireframe_vert.glsl"),
+        relative_path("shaders/pretty/wireframe_frag.glsl"),
+        ((model_data,), (camera_data.clone(),)),
+        1,
+    );
+
+    // normals, tangents and bitangents of every vertex, for checking normal
+    // maps (view modes 6 to 8)
+    let mut normals_object = debug::draw_normals(
+        queue.clone(),
+        &mut pipeline_cache_main,
+        &merged_mesh,
+        0.05,
+        true,
+        relative_path("shaders/pretty/debug_lines_vert.glsl"),
+        relative_path("shaders/pretty/debug_lines_frag.glsl"),
+        ((model_data,), (camera_data,)),
+        1,
+    );
 
     // used in main loop
-    let mut timer_setup = Timer::new("Setup time");
-    let mut timer_draw = Timer::new("Overall draw time");
+    let mut timers = TimerRegistry::new();
+    let timer_setup = timers.register("Setup time", None);
+    let timer_shadow_casters = timers.register("Shadow casters", Some(timer_setup));
+    let timer_draw = timers.register("Overall draw time", None);
 
     let mut view_mode: i32 = 0;
     let mut update_view = false;
     let mut draw_wireframe = false;
+    let mut draw_normals = false;
+    let mut draw_god_rays = true;
+    let mut draw_fog = true;
     let mut cursor_grabbed = true;
+    let mut frame_count = 0;
+    // matches SHADOW_MAP_DIMS
+    let mut quality = QualityPreset::High;
+    let mut patch_size = SHADOW_MAP_DIMS[1] as f32;
+    let mut use_upscaler = true;
+    // whether the geometry pass uses System's depth prepass instead of the
+    // depth_prepass pass
+    let mut engine_prepass = false;
+    let mut trilinear = false;
+    // the camera's view and projection matrices when J was pressed. its
+    // frustum is drawn along with every mesh's bounds, and objects are culled
+    // against it instead of the current camera.
+    let mut frozen_frustum: Option<([[f32; 4]; 4], [[f32; 4]; 4])> = None;
+    // world space bounds of geo_objects, the meshes are drawn at a tenth of
+    // their size (see model_data)
+    let geo_bounds: Vec<Aabb> = mesh_bounds.iter().map(|b| scaled(b, 0.1)).collect();
+    let mut last_object_stats = system.object_stats();
+    // F5 starts and stops recording input to this file, F6 replays it
+    let input_recording_path = relative_path("input_recording.txt");
+    let mut recording_input = false;
+    // F12 saves the screen to numbered pngs in the examples directory.
+    // keeping frames costs a copy of the window every frame, so it's only on
+    // from the press until the frame after it has been saved.
+    let mut screenshot_num = 0;
+    let mut screenshot_pending = false;
 
     while !window.update() {
-        timer_setup.start();
+        // nothing to draw to while minimized, idle until the window is back
+        if window.is_minimized() {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
+
+        timers.get(timer_setup).start();
 
         // convert merged mesh into 6 casters, one for each cubemap face
         // Have to redo every frame because the light moves
+        timers.get(timer_shadow_casters).start();
         let shadow_casters = convert_to_shadow_casters(
             queue.clone(),
             shadow_cast_base.clone(),
-            light.get_data(),
-            &mut pipeline_cache_shadow
+            light.get_data(window.get_frame_info().elapsed),
+            &mut pipeline_cache_shadow,
+            patch_size,
         );
+        timers.get(timer_shadow_casters).stop();
         // update camera, but only if we're grabbing the cursor
         if cursor_grabbed {
             camera.update(window.get_frame_info());
@@ -309,7 +649,7 @@ fn main() {
         let camera_data = camera.get_data();
 
         // update light
-        let light_data = light.get_data();
+        let light_data = light.get_data(window.get_frame_info().elapsed);
 
         // update depth prepass objects' collections
         depth_prepass_object.collection.1.data.0 = camera_data.clone();
@@ -342,14 +682,38 @@ fn main() {
                 obj.collection.2.upload(device.clone());
             });
 
+        deferred_light_object.collection.0.data = (camera_data.clone(), light_data.clone());
+        deferred_light_object.collection.0.upload(device.clone());
+
         wireframe_object.collection.1.data.0 = camera_data.clone();
         wireframe_object.collection.1.upload(device.clone());
 
-        if window
-            .get_frame_info()
-            .keydowns
-            .contains(&VirtualKeyCode::Escape)
-        {
+        normals_object.collection.1.data.0 = camera_data.clone();
+        normals_object.collection.1.upload(device.clone());
+
+        decal_object.collection.2.data.0 = camera_data.clone();
+        decal_object.collection.2.upload(device.clone());
+
+        sparks.object.collection.1.data.0 = camera_data.clone();
+        sparks.object.collection.1.upload(device.clone());
+        sparks.emitter.position = [
+            light_data.position[0],
+            light_data.position[1],
+            light_data.position[2],
+        ];
+        sparks.update(window.get_frame_info().delta);
+
+        light_trail.object.collection.1.data.0 = camera_data.clone();
+        light_trail.object.collection.1.upload(device.clone());
+        light_trail.update(sparks.emitter.position, camera.position.into());
+
+        god_rays_object.collection.0.data = (camera_data.clone(), light_data.clone());
+        god_rays_object.collection.0.upload(device.clone());
+
+        fog_object.collection.0.data.0 = camera_data.clone();
+        fog_object.collection.0.upload(device.clone());
+
+        if window.get_frame_info().action_active("toggle_cursor") {
             cursor_grabbed = !cursor_grabbed;
             if cursor_grabbed {
                 window.get_surface().window().hide_cursor(true);
@@ -361,12 +725,12 @@ fn main() {
         }
 
         // Switch view mode, maybe
-        if window.get_frame_info().keydowns.contains(&VirtualKeyCode::C) {
+        if window.get_frame_info().action_active("next_view") {
             view_mode = (view_mode + 1) % 12;
             update_view = true;
         }
 
-        if window.get_frame_info().keydowns.contains(&VirtualKeyCode::V) {
+        if window.get_frame_info().action_active("prev_view") {
             view_mode = view_mode - 1;
             if view_mode < 0 {
                 view_mode = 12;
@@ -374,10 +738,230 @@ fn main() {
             update_view = true;
         }
 
-        if window.get_frame_info().keydowns.contains(&VirtualKeyCode::R) {
+        if window.get_frame_info().action_active("toggle_wireframe") {
             draw_wireframe = !draw_wireframe;
         }
 
+        if window.get_frame_info().action_active("toggle_normals") {
+            draw_normals = !draw_normals;
+        }
+
+        // the god ray and fog passes always run, disabling them just sets
+        // their density to 0 so they pass color through
+        if window.get_frame_info().action_active("toggle_god_rays") {
+            draw_god_rays = !draw_god_rays;
+            let density = if draw_god_rays { god_ray_params.density } else { 0.0 };
+            god_rays_object.collection.1.data.0 = GodRayParams {
+                density,
+                ..god_ray_params
+            }
+            .get_data();
+            god_rays_object.collection.1.upload(device.clone());
+        }
+
+        if window.get_frame_info().action_active("toggle_fog") {
+            draw_fog = !draw_fog;
+            let density = if draw_fog { fog_params.density } else { 0.0 };
+            fog_object.collection.1.data.0 = FogParams {
+                density,
+                ..fog_params
+            }
+            .get_data();
+            fog_object.collection.1.upload(device.clone());
+        }
+
+        // toggle trilinear filtering, which blends between mip levels
+        if window.get_frame_info().action_active("toggle_trilinear") {
+            trilinear = !trilinear;
+            println!("Trilinear filtering: {}", trilinear);
+            let mode = if trilinear { MipmapMode::Linear } else { MipmapMode::Nearest };
+            geo_objects.iter_mut().for_each(|obj| {
+                let sampler = SamplerSpec {
+                    mipmap_mode: mode,
+                    ..obj.collection.1.sampler
+                };
+                obj.collection.1.set_sampler(device.clone(), sampler);
+            });
+        }
+
+        // cycle quality presets
+        if window.get_frame_info().action_active("next_quality") {
+            quality = quality.next();
+            let settings = quality.settings();
+            println!("Quality: {:?}", quality);
+
+            system.apply_quality(&settings, &["shadow_map", "shadow_depth", "shadow_map_blur"]);
+            patch_size = settings.shadow_map_size as f32;
+            quad_blur.custom_dynamic_state =
+                Some(dynamic_state_for_bounds([0.0, 0.0], [patch_size * 6.0, patch_size]));
+
+            geo_objects.iter_mut().for_each(|obj| {
+                // keep whatever the trilinear toggle set
+                let sampler = SamplerSpec {
+                    mipmap_mode: obj.collection.1.sampler.mipmap_mode,
+                    ..settings.texture_sampler()
+                };
+                obj.collection.1.set_sampler(device.clone(), sampler);
+            });
+
+            draw_god_rays = settings.post_processing;
+            draw_fog = settings.post_processing;
+            let god_ray_density = if draw_god_rays { god_ray_params.density } else { 0.0 };
+            god_rays_object.collection.1.data.0 = GodRayParams {
+                density: god_ray_density,
+                ..god_ray_params
+            }
+            .get_data();
+            god_rays_object.collection.1.upload(device.clone());
+            let fog_density = if draw_fog { fog_params.density } else { 0.0 };
+            fog_object.collection.1.data.0 = FogParams {
+                density: fog_density,
+                ..fog_params
+            }
+            .get_data();
+            fog_object.collection.1.upload(device.clone());
+        }
+
+        // toggle rendering at a lower resolution
+        if window.get_frame_info().action_active("toggle_render_scale") {
+            let scale = if system.get_render_scale() == 1.0 { 0.75 } else { 1.0 };
+            println!("Render scale: {}", scale);
+            system.set_render_scale(scale);
+        }
+
+        // switch between the upscaling shader and a bilinear blit
+        if window.get_frame_info().action_active("toggle_upscaler") {
+            use_upscaler = !use_upscaler;
+            println!("Upscaler: {}", if use_upscaler { "lanczos" } else { "blit" });
+            system.set_full_res_output(use_upscaler);
+        }
+
+        // switch between the lowest latency and letting the CPU get ahead
+        if window.get_frame_info().action_active("toggle_frames_in_flight") {
+            let frames = if system.frames_in_flight() == 1 { 3 } else { 1 };
+            println!("Frames in flight: {}", frames);
+            system.set_max_frames_in_flight(frames);
+        }
+
+        // switch between our own depth prepass and System's, which shades
+        // with an equal depth test so it never overdraws
+        if window.get_frame_info().action_active("toggle_engine_prepass") {
+            engine_prepass = !engine_prepass;
+            let which = if engine_prepass { "System" } else { "depth_prepass pass" };
+            println!("Depth prepass: {}", which);
+            system.set_depth_prepass("geometry", engine_prepass);
+        }
+
+        // sparks stop spawning, the ones already out still fall down
+        if window.get_frame_info().action_active("toggle_sparks") {
+            draw_sparks = !draw_sparks;
+            sparks.emitter.rate = if draw_sparks { spark_emitter_rate } else { 0.0 };
+        }
+
+        // switch between forward and deferred rendering. the objects stay the
+        // same, only their shaders change (see update_view)
+        if window.get_frame_info().action_active("toggle_render_path") {
+            let path = match system.render_path() {
+                RenderPath::Forward => RenderPath::Deferred,
+                RenderPath::Deferred => RenderPath::Forward,
+            };
+            println!("Render path: {:?}", path);
+            system.set_render_path(path);
+            update_view = true;
+        }
+
+        // freeze the camera's frustum and show it and every mesh's bounds, so
+        // you can fly around and see what would be culled
+        if window.get_frame_info().action_active("freeze_frustum") {
+            frozen_frustum = match frozen_frustum {
+                Some(_) => None,
+                None => Some((camera_data.view, camera_data.proj)),
+            };
+        }
+
+        // cycle through every image with the texture viewer, then turn it off
+        // again
+        if window.get_frame_info().action_active("next_texture_view") {
+            if !texture_viewer_on {
+                texture_viewer_on = true;
+                texture_viewer.tag_idx = 0;
+                system.output_tag = "texture_viewer";
+            } else if texture_viewer.tag_idx + 1 == texture_viewer.tags.len() {
+                texture_viewer_on = false;
+                // the view mode picks the real one again below
+                system.output_tag = "upscale";
+                set_viewer_pass(&mut system, &forward_passes, &deferred_passes, None);
+                update_view = true;
+            } else {
+                texture_viewer.next_tag();
+            }
+
+            // the pass needs the tag being viewed
+            if texture_viewer_on {
+                let viewer_pass = texture_viewer.pass(rpass_viewer.clone());
+                set_viewer_pass(&mut system, &forward_passes, &deferred_passes, Some(&viewer_pass));
+                println!("Viewing {}", texture_viewer.current_tag());
+            }
+        }
+
+        if window.get_frame_info().action_active("next_texture_channel") {
+            texture_viewer.channel = texture_viewer.channel.next();
+            println!("Viewing channel {:?}", texture_viewer.channel);
+        }
+
+        // the F5 that stopped the recording is in it too, so it's ignored
+        // while replaying
+        if window.get_frame_info().action_active("toggle_input_recording")
+            && !window.is_replaying_input()
+        {
+            if recording_input {
+                window.stop_input_recording();
+                println!("Stopped recording input");
+            } else {
+                window.start_input_recording(&input_recording_path);
+                println!("Recording input to {:?}", input_recording_path);
+            }
+            recording_input = !recording_input;
+        }
+
+        // NOTE: the replay starts from wherever the camera is now, so it only
+        // ends up in the same place if you go back to where the recording
+        // started first
+        if window.get_frame_info().action_active("replay_input")
+            && !recording_input
+            && !window.is_replaying_input()
+        {
+            println!("Replaying input from {:?}", input_recording_path);
+            window.replay_input(&input_recording_path);
+        }
+
+        if window.get_frame_info().action_active("toggle_vsync") {
+            let mode = if window.present_mode() == PresentMode::Fifo {
+                PresentMode::Immediate
+            } else {
+                PresentMode::Fifo
+            };
+            window.set_present_mode(mode);
+            println!("Present mode: {:?}", window.present_mode());
+        }
+
+        // the frame drawn after the press has been kept by now, save it
+        if screenshot_pending {
+            let path = relative_path(&format!("screenshot_{:03}.png", screenshot_num));
+            match window.capture_to_file(&path) {
+                Ok(()) => println!("Saved screenshot to {:?}", path),
+                Err(e) => println!("Couldn't save screenshot: {}", e),
+            }
+            screenshot_num += 1;
+            screenshot_pending = false;
+            window.set_keep_last_frame(false);
+        }
+
+        if window.get_frame_info().action_active("screenshot") && !screenshot_pending {
+            window.set_keep_last_frame(true);
+            screenshot_pending = true;
+        }
+
         if update_view {
             match view_mode {
                 0 => {
@@ -481,9 +1065,142 @@ fn main() {
                 _ => { panic!("bad view mode") }
             }
 
+            // the view modes only exist for forward rendering, deferred always
+            // draws the G-buffer and lights it like view mode 0
+            if system.render_path() == RenderPath::Deferred {
+                geo_objects.iter_mut().for_each(|obj| {
+                    obj.pipeline_spec.vs_path =
+                        relative_path("shaders/pretty/deferred_geo_vert.glsl");
+                    obj.pipeline_spec.fs_path =
+                        relative_path("shaders/pretty/deferred_geo_frag.glsl");
+                });
+            } else {
+                geo_objects.iter_mut().for_each(|obj| {
+                    obj.pipeline_spec.vs_path = relative_path("shaders/pretty/vert.glsl");
+                });
+            }
+
+            // god rays and fog get composited onto color in the last passes,
+            // then it gets upscaled
+            if system.output_tag == "color" {
+                system.output_tag = "upscale";
+            }
+
             update_view = false;
         }
 
+        if texture_viewer_on {
+            system.output_tag = "texture_viewer";
+        }
+
+        // left click casts a ray from the camera against the meshes' bounds,
+        // and marks where it hit for a couple seconds
+        let clicked = window
+            .get_frame_info()
+            .all_events
+            .iter()
+            .any(|event| match event {
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::MouseInput {
+                            state: ElementState::Pressed,
+                            button: MouseButton::Left,
+                            ..
+                        },
+                    ..
+                } => true,
+                _ => false,
+            });
+        if clicked {
+            // through the center of the screen while flying around, otherwise
+            // through the cursor
+            let dir = if cursor_grabbed {
+                camera.front
+            } else {
+                let (x, y) = window.get_frame_info().cursor_ndc();
+                let view: Mat4 = camera_data.view.into();
+                let proj: Mat4 = camera_data.proj.into();
+                let inv_view_proj = inverse(&(proj * view));
+                // two points on the ray, any depths inside the frustum work
+                let near = inv_view_proj * vec4(x, y, 0.0, 1.0);
+                let far = inv_view_proj * vec4(x, y, 0.5, 1.0);
+                normalize(&(far.xyz() / far.w - near.xyz() / near.w))
+            };
+            let hit = geo_bounds
+                .iter()
+                .filter_map(|bounds| ray_aabb(&camera.position, &dir, bounds))
+                .fold(None, |closest: Option<f32>, t| {
+                    Some(closest.map_or(t, |c| c.min(t)))
+                });
+            match hit {
+                Some(t) => {
+                    let point: [f32; 3] = (camera.position + dir * t).into();
+                    // starts a bit below the camera, otherwise it'd be a dot
+                    let start: [f32; 3] = (camera.position - vec3(0.0, 0.1, 0.0)).into();
+                    system.debug_line_persistent(start, point, [1.0, 0.0, 0.0], 2.0);
+                    system.debug_box_persistent(point, [0.1, 0.1, 0.1], [1.0, 0.0, 0.0], 2.0);
+                }
+                None => println!("Raycast missed"),
+            }
+        }
+
+        system.set_debug_camera(camera_data.view, camera_data.proj);
+        let (cull_view, cull_proj) = frozen_frustum.unwrap_or((camera_data.view, camera_data.proj));
+        system.set_cull_camera(cull_view, cull_proj);
+        if frozen_frustum.is_some() {
+            let cull_view: Mat4 = cull_view.into();
+            let cull_proj: Mat4 = cull_proj.into();
+            let view_proj = cull_proj * cull_view;
+            let inv_view_proj = inverse(&view_proj);
+
+            // the corners of clip space, in the same order as Aabb::corners
+            let corners: Vec<[f32; 3]> = Aabb {
+                min: [-1.0, -1.0, -1.0],
+                max: [1.0, 1.0, 1.0],
+            }
+            .corners()
+            .iter()
+            .map(|c| {
+                let world = inv_view_proj * vec4(c[0], c[1], c[2], 1.0);
+                (world.xyz() / world.w).into()
+            })
+            .collect();
+            for i in 0..8 {
+                for axis in 0..3 {
+                    let j = i | (1 << axis);
+                    if j != i {
+                        system.debug_line(corners[i], corners[j], [1.0, 1.0, 0.0]);
+                    }
+                }
+            }
+
+            // green if drawn, red if culled
+            for bounds in geo_bounds.iter() {
+                let half_extents = [
+                    (bounds.max[0] - bounds.min[0]) / 2.0,
+                    (bounds.max[1] - bounds.min[1]) / 2.0,
+                    (bounds.max[2] - bounds.min[2]) / 2.0,
+                ];
+                let color = if bounds.outside_frustum(&view_proj) {
+                    [1.0, 0.0, 0.0]
+                } else {
+                    [0.0, 1.0, 0.0]
+                };
+                system.debug_box(bounds.center(), half_extents, color);
+            }
+
+            let object_stats = system.object_stats();
+            if object_stats != last_object_stats {
+                println!(
+                    "Objects: {} submitted, {} culled, {} drawn",
+                    object_stats.objects_submitted,
+                    object_stats.objects_culled,
+                    object_stats.objects_drawn
+                );
+                last_object_stats = object_stats;
+            }
+        }
+
         // start drawing!
         system.start_window(&mut window);
 
@@ -499,8 +1216,12 @@ fn main() {
 
         system.next_pass();
 
-        // depth_prepass
-        system.add_object(&depth_prepass_object);
+        // depth_prepass. left empty with System's prepass, geometry fills in
+        // the depth buffer itself then.
+        if !engine_prepass {
+            system.add_object(&depth_prepass_object);
+            system.add_object(&light_object_prepass);
+        }
 
         system.next_pass();
 
@@ -511,34 +1232,146 @@ fn main() {
 
         // geometry
 
-        if draw_wireframe {
-            system.add_object(&wireframe_object.clone());
+        if system.render_path() == RenderPath::Deferred {
+            // no wireframe, normals or light sphere here, the lighting subpass
+            // has no depth buffer to test them against
+            // TODO: no culling either, draw_subpasses takes every object
+            let geometry: Vec<&dyn Drawcall> =
+                geo_objects.iter().map(|obj| obj as &dyn Drawcall).collect();
+            let lighting: Vec<&dyn Drawcall> = vec![&deferred_light_object];
+            system.draw_subpasses(&[geometry, lighting]);
         } else {
-            for geo_object in geo_objects.iter() {
-                system.add_object(&geo_object);
+            for (geo_object, bounds) in geo_objects.iter().zip(geo_bounds.iter()) {
+                system.add_object_bounded(geo_object, bounds);
             }
+
+            // drawn on top of the geometry, the depth bias keeps it from
+            // z-fighting
+            if draw_wireframe {
+                system.add_object(&wireframe_object);
+            }
+
+            if draw_normals {
+                system.add_object(&normals_object);
+            }
+
+            system.add_object(&light_object_geo);
         }
 
-        system.add_object(&light_object_geo);
+        system.next_pass();
+
+        // decals
+        system.add_object(&decal_object);
+
+        system.next_pass();
+
+        // particles
+        sparks.add_to(&mut system);
+        light_trail.add_to(&mut system);
+
+        system.next_pass();
+
+        // god_rays
+        system.add_object(&god_rays_object);
+
+        system.next_pass();
+
+        // fog
+        system.add_object(&fog_object);
+
+        system.next_pass();
+
+        // upscale
+        system.draw_fullscreen(
+            "upscale",
+            &upscale_shader,
+            &[("color", "fog")],
+            upscale_params.get_data(),
+        );
+
+        // texture_viewer
+        if texture_viewer_on {
+            system.next_pass();
+            texture_viewer.add_to(&mut system);
+        }
 
-        timer_setup.stop();
+        timers.get(timer_setup).stop();
 
         // draw
-        timer_draw.start();
-        system.finish_to_window(&mut window);
-        timer_draw.stop();
+        {
+            let _t = timers.get(timer_draw).scope();
+            system.finish_to_window(&mut window);
+        }
+
+        // the images for each pass don't exist until the first frame
+        if frame_count == 0 {
+            system.memory_report().print();
+        }
+        frame_count += 1;
     }
 
     system.print_stats();
+    system.memory_report().print();
     println!("FPS: {}", window.get_fps());
     println!("Avg. delta: {} ms", window.get_avg_delta() * 1_000.0);
-    timer_setup.print();
-    timer_draw.print();
+    timers.print_tree();
     
     // Print pipeline cache stats
     println!("\nPipeline cache stats:");
     pipeline_cache_main.print_stats();
     pipeline_cache_shadow.print_stats();
+    pipeline_cache_decals.print_stats();
+    pipeline_cache_particles.print_stats();
+}
+
+fn scaled(bounds: &Aabb, factor: f32) -> Aabb {
+    let mut scaled = *bounds;
+    for axis in 0..3 {
+        scaled.min[axis] *= factor;
+        scaled.max[axis] *= factor;
+    }
+    scaled
+}
+
+// adds the texture viewer's pass to the end of both render paths' passes, or
+// takes it out again. it's only there while the viewer is on, since it keeps
+// the image it shows alive (and unaliased) until the end of the frame.
+fn set_viewer_pass<'a>(
+    system: &mut System<'a>,
+    forward_passes: &[Pass<'a>],
+    deferred_passes: &[Pass<'a>],
+    viewer_pass: Option<&Pass<'a>>,
+) {
+    let with_viewer = |passes: &[Pass<'a>]| {
+        let mut passes = passes.to_vec();
+        passes.extend(viewer_pass.cloned());
+        passes
+    };
+    system.add_render_path(RenderPath::Forward, with_viewer(forward_passes));
+    system.add_render_path(RenderPath::Deferred, with_viewer(deferred_passes));
+}
+
+// distance along dir from origin to where the ray enters bounds, if it does.
+// the slab method: the ray is inside the box where it's between the planes of
+// all 3 axes at once. if origin is already inside, it's where the ray leaves
+// instead, since the big meshes' boxes (floor, walls) contain the camera most
+// of the time.
+fn ray_aabb(origin: &Vec3, dir: &Vec3, bounds: &Aabb) -> Option<f32> {
+    let mut t_min = 0.0f32;
+    let mut t_max = std::f32::MAX;
+    for axis in 0..3 {
+        let inv = 1.0 / dir[axis];
+        let t0 = (bounds.min[axis] - origin[axis]) * inv;
+        let t1 = (bounds.max[axis] - origin[axis]) * inv;
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+    }
+
+    if t_min <= t_max {
+        Some(if t_min > 0.0 { t_min } else { t_max })
+    } else {
+        None
+    }
 }
 
 #[allow(dead_code)]
@@ -551,18 +1384,18 @@ struct Light {
 impl Data for Light {}
 
 struct MovingLight {
-    start_time: std::time::Instant,
+    speed: f32,
 }
 
 impl MovingLight {
     fn new() -> Self {
-        Self {
-            start_time: std::time::Instant::now(),
-        }
+        Self { speed: 1.0 / 16.0 }
     }
 
-    fn get_data(&self) -> Light {
-        let time = get_elapsed(self.start_time) / 16.0;
+    // elapsed should come from FrameInfo, so the light respects the window's
+    // fixed timestep if one is set
+    fn get_data(&self, elapsed: f32) -> Light {
+        let time = elapsed * self.speed;
         Light {
             position: [time.sin() * 100.0, 10.0, 0.0, 0.0],
             strength: 1.0,
@@ -570,11 +1403,91 @@ impl MovingLight {
     }
 }
 
+// controls for the light shaft pass
+#[derive(Clone, Copy)]
+struct GodRayParams {
+    // number of steps taken along each view ray
+    samples: f32,
+    // how much light each step scatters towards the camera
+    density: f32,
+    // how much each step's contribution falls off compared to the last one
+    decay: f32,
+}
+
+impl GodRayParams {
+    fn get_data(&self) -> GodRayData {
+        GodRayData {
+            params: [self.samples, self.density, self.decay, 0.0],
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone)]
+struct GodRayData {
+    params: [f32; 4],
+}
+
+impl Data for GodRayData {}
+
+// controls for the height fog pass
+#[derive(Clone, Copy)]
+struct FogParams {
+    // height at which fog has its full density
+    base_height: f32,
+    // how quickly fog thins out above base_height
+    falloff: f32,
+    density: f32,
+    color: [f32; 3],
+}
+
+impl FogParams {
+    fn get_data(&self) -> FogData {
+        FogData {
+            color: [self.color[0], self.color[1], self.color[2], 1.0],
+            params: [self.base_height, self.falloff, self.density, 0.0],
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone)]
+struct FogData {
+    color: [f32; 4],
+    params: [f32; 4],
+}
+
+impl Data for FogData {}
+
+// controls for the upscaling pass
+#[derive(Clone, Copy)]
+struct UpscaleParams {
+    // how much to sharpen on top of the lanczos filter
+    sharpness: f32,
+}
+
+impl UpscaleParams {
+    fn get_data(&self) -> UpscaleData {
+        UpscaleData {
+            params: [self.sharpness, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone)]
+struct UpscaleData {
+    params: [f32; 4],
+}
+
+impl Data for UpscaleData {}
+
 fn convert_to_shadow_casters<V: Vertex>(
     queue: Queue,
     base_object: ObjectPrototype<V, ()>,
     light_data: Light,
     pipeline_cache: &mut PipelineCache,
+    patch_size: f32,
 ) -> Vec<Object<(Set<(Matrix4,)>, Set<(Matrix4,)>, Set<(Matrix4,)>, Set<(Light,)>)>> {
     // if you want to make point lamps cast shadows, you need shadow cubemaps
     // render-engine doesn't support geometry shaders, so the easiest way to do
@@ -628,12 +1541,12 @@ fn convert_to_shadow_casters<V: Vertex>(
             // of the patched texture we draw to
             let margin = 0.0;
             let origin = [
-                patch_pos[0] * PATCH_DIMS[0] + margin,
-                patch_pos[1] * PATCH_DIMS[1] + margin,
+                patch_pos[0] * patch_size + margin,
+                patch_pos[1] * patch_size + margin,
             ];
             let dynamic_state = dynamic_state_for_bounds(
                 origin,
-                [PATCH_DIMS[0] - margin * 2.0, PATCH_DIMS[1] - margin * 2.0],
+                [patch_size - margin * 2.0, patch_size - margin * 2.0],
             );
 
             ObjectPrototype {
@@ -644,6 +1557,12 @@ fn convert_to_shadow_casters<V: Vertex>(
                     (light_data.clone(),),
                 ),
                 custom_dynamic_state: Some(dynamic_state),
+                // geometry right next to the light would otherwise get
+                // clipped by the near plane and leave holes in the shadow
+                pipeline_options: PipelineOptions {
+                    depth_clamp: true,
+                    ..base_object.pipeline_options
+                },
 
                 vs_path: base_object.vs_path.clone(),
                 fs_path: base_object.fs_path.clone(),
@@ -669,6 +1588,40 @@ fn dynamic_state_for_bounds(origin: [f32; 2], dimensions: [f32; 2]) -> DynamicSt
     }
 }
 
+// a ring with a dot in the middle, transparent everywhere else
+fn logo_texture(queue: Queue) -> Image {
+    let size = 256;
+    let data: Vec<u8> = (0..size * size)
+        .flat_map(|i| {
+            let x = (i % size) as f32 / size as f32 * 2.0 - 1.0;
+            let y = (i / size) as f32 / size as f32 * 2.0 - 1.0;
+            let r = (x * x + y * y).sqrt();
+            let inside = (r > 0.7 && r < 0.9) || r < 0.3;
+            let alpha = if inside { 220 } else { 0 };
+            vec![200, 30, 30, alpha]
+        })
+        .collect();
+
+    let (texture, future) = ImmutableImage::from_iter(
+        data.into_iter(),
+        Dimensions::Dim2d {
+            width: size,
+            height: size,
+        },
+        Format::R8G8B8A8Unorm,
+        queue,
+    )
+    .unwrap();
+
+    future
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    texture
+}
+
 #[allow(dead_code)]
 #[derive(Clone)]
 struct Material {
@@ -1,4 +1,4 @@
-use render_engine::object::ObjectPrototype;
+use render_engine::object::{ObjectPrototype, PipelineOptions};
 use render_engine::render_passes;
 use render_engine::system::{Pass, System};
 use render_engine::window::Window;
@@ -58,6 +58,7 @@ fn main() {
         },
         collection: (),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build(queue.clone(), render_pass.clone());
 
@@ -75,8 +76,13 @@ fn main() {
 
         // draw
         start_window_timer.start();
-        system.start_window(&mut window);
+        let started = system.start_window(&mut window);
         start_window_timer.stop();
+        if !started {
+            // minimized, idle until the window is back
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
 
         add_object_timer.start();
         system.add_object(&object);
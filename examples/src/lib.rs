@@ -2,6 +2,7 @@ use render_engine::input::{FrameInfo, get_elapsed};
 use render_engine::utils::upload_data;
 use render_engine::{Buffer, Device};
 use render_engine::collection::Data;
+use render_engine::mesh::Aabb;
 
 use nalgebra_glm::*;
 
@@ -43,6 +44,14 @@ pub struct OrbitCamera {
     pub yaw: f32,
     pub orbit_distance: f32,
     mouse_sens: f32,
+    pub near: f32,
+    pub far: f32,
+    // if set, near and far are recomputed every update to fit these bounds
+    pub scene_bounds: Option<Aabb>,
+    // near maps to 1.0 and far to 0.0, for float depth buffers. the passes
+    // drawing with this camera need a GreaterOrEqual depth compare and a depth
+    // clear of 0.0 to go with it.
+    pub reverse_depth: bool,
     view_mat: CameraMatrix,
     proj_mat: CameraMatrix,
 }
@@ -77,11 +86,20 @@ impl OrbitCamera {
             yaw,
             orbit_distance,
             mouse_sens,
+            near: 1.0,
+            far: 10_000.,
+            scene_bounds: None,
+            reverse_depth: false,
             view_mat,
             proj_mat,
         }
     }
 
+    pub fn set_scene_bounds(&mut self, bounds: Option<Aabb>) {
+        // call again whenever the scene changes
+        self.scene_bounds = bounds;
+    }
+
     pub fn update(&mut self, frame_info: FrameInfo) {
         // check for scroll wheel
         let scroll: f32 = frame_info
@@ -130,26 +148,21 @@ impl OrbitCamera {
 
         // recompute view and projection matrices
         let farther_front = self.front * self.orbit_distance;
-        self.view_mat = look_at(
-            &(self.center_position + farther_front),
-            &self.center_position,
-            &self.up,
-        )
-        .into();
+        let eye = self.center_position + farther_front;
+        self.view_mat = look_at(&eye, &self.center_position, &self.up).into();
+
+        if let Some(bounds) = &self.scene_bounds {
+            let forward = -self.front;
+            let (near, far) = bounds.clip_planes(eye.into(), forward.into(), self.reverse_depth);
+            self.near = near;
+            self.far = far;
+        }
 
         let dims = frame_info.dimensions;
         let aspect_ratio = (dims[0] as f32) / (dims[1] as f32);
         // TODO: idk why i have to flip it vertically
         self.proj_mat = scale(
-            &perspective(
-                aspect_ratio,
-                // fov
-                1.0,
-                // near
-                1.0,
-                // far
-                10_000.,
-            ),
+            &projection(aspect_ratio, self.near, self.far, self.reverse_depth),
             &vec3(1.0, -1.0, 1.0),
         )
         .into();
@@ -164,6 +177,15 @@ impl OrbitCamera {
     }
 }
 
+// fov is 1 radian
+fn projection(aspect_ratio: f32, near: f32, far: f32, reverse_depth: bool) -> Mat4 {
+    if reverse_depth {
+        reversed_perspective_rh_zo(aspect_ratio, 1.0, near, far)
+    } else {
+        perspective(aspect_ratio, 1.0, near, far)
+    }
+}
+
 pub struct FlyCamera {
     pub position: Vec3,
     pub front: Vec3,
@@ -175,6 +197,14 @@ pub struct FlyCamera {
     pub yaw: f32,
     movement_speed: f32,
     mouse_sens: f32,
+    pub near: f32,
+    pub far: f32,
+    // if set, near and far are recomputed every update to fit these bounds
+    pub scene_bounds: Option<Aabb>,
+    // near maps to 1.0 and far to 0.0, for float depth buffers. the passes
+    // drawing with this camera need a GreaterOrEqual depth compare and a depth
+    // clear of 0.0 to go with it.
+    pub reverse_depth: bool,
     view_mat: CameraMatrix,
     proj_mat: CameraMatrix,
 }
@@ -205,11 +235,20 @@ impl FlyCamera {
             yaw,
             movement_speed,
             mouse_sens,
+            near: 1.0,
+            far: 10_000.,
+            scene_bounds: None,
+            reverse_depth: false,
             view_mat: Mat4::identity().into(),
             proj_mat: Mat4::identity().into(),
         }
     }
 
+    pub fn set_scene_bounds(&mut self, bounds: Option<Aabb>) {
+        // call again whenever the scene changes
+        self.scene_bounds = bounds;
+    }
+
     pub fn move_forward(&mut self, delta: f32) {
         self.position += self.front * self.movement_speed * delta;
     }
@@ -272,19 +311,18 @@ impl FlyCamera {
 
         self.view_mat = look_at(&self.position, &(self.position + self.front), &self.up).into();
 
+        if let Some(bounds) = &self.scene_bounds {
+            let (near, far) =
+                bounds.clip_planes(self.position.into(), self.front.into(), self.reverse_depth);
+            self.near = near;
+            self.far = far;
+        }
+
         let dims = frame_info.dimensions;
         let aspect_ratio = (dims[0] as f32) / (dims[1] as f32);
         // TODO: idk why i have to flip it vertically
         self.proj_mat = scale(
-            &perspective(
-                aspect_ratio,
-                // fov
-                1.0,
-                // near
-                1.0,
-                // far
-                10_000.,
-            ),
+            &projection(aspect_ratio, self.near, self.far, self.reverse_depth),
             &vec3(1.0, -1.0, 1.0),
         )
         .into();
@@ -299,12 +337,11 @@ impl FlyCamera {
     }
 }
 
-#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct CameraData {
-    view: CameraMatrix,
-    proj: CameraMatrix,
-    pos: [f32; 3],
+    pub view: CameraMatrix,
+    pub proj: CameraMatrix,
+    pub pos: [f32; 3],
 }
 impl Data for CameraData {}
 
@@ -4,27 +4,107 @@ Mesh: vertices and indices, nothing else
 Object: mesh + other stuff.
  */
 
-use render_engine::mesh::{Mesh, PrimitiveTopology, Vertex};
-use render_engine::utils::load_texture;
+use render_engine::mesh::{orthonormalize, Mesh, PrimitiveTopology, Vertex, VertexAttributes};
+use render_engine::utils::{image_size_bytes, load_texture, try_load_texture};
 use render_engine::{Format, Queue, Image, RenderPass};
-use render_engine::object::{ObjectPrototype, Object};
+use render_engine::object::{ObjectPrototype, Object, PipelineOptions};
+use render_engine::streams::SplitMesh;
 use render_engine::impl_vertex;
 
 use crate::relative_path;
 
 use nalgebra_glm::*;
 
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub fn load_obj(path: &Path) -> Result<(Vec<tobj::Model>, Vec<tobj::Material>), tobj::LoadError> {
+    // loads an OBJ file with tobj, then fixes up what tobj doesn't handle:
+    // - faces with more than 3 vertices. tobj splits those into a fan around
+    //   their first vertex, which is fine for convex faces but covers the
+    //   wrong area (and folds over itself) for concave ones, so those get ear
+    //   clipped instead.
+    // - models without normals get them generated, smoothed within each
+    //   smoothing group (s statements) and flat everywhere else, so hard
+    //   edges stay hard. normals from the file are kept as they are.
+    let (mut models, materials) = tobj::load_obj(path)?;
+
+    // tobj doesn't keep the faces it triangulated, so read how many vertices
+    // each face had from the file itself
+    let faces = match std::fs::read_to_string(path) {
+        Ok(text) => obj_faces(&text),
+        Err(_) => return Ok((models, materials)),
+    };
+    let num_triangles: usize = models.iter().map(|m| m.mesh.indices.len() / 3).sum();
+    let expected: usize = faces.iter().map(|face| face.size - 2).sum();
+    if num_triangles != expected {
+        // something tobj does differently than we expect, the fans are
+        // better than garbage
+        println!(
+            "{:?}: expected {} triangles from the faces in the file, tobj made {}. Not fixing up concave faces.",
+            path, expected, num_triangles
+        );
+        return Ok((models, materials));
+    }
 
-pub use tobj::load_obj;
+    // faces are in the same order in the models as in the file
+    let mut faces = faces.into_iter();
+    for model in models.iter_mut() {
+        let mesh = &mut model.mesh;
+        let mut indices = Vec::with_capacity(mesh.indices.len());
+        // smoothing group of every triangle, and which face it's from
+        let mut triangle_faces = Vec::with_capacity(mesh.indices.len() / 3);
+        let mut tri = 0;
+        let mut face_idx = 0;
+        while tri * 3 < mesh.indices.len() {
+            let face = faces.next().unwrap();
+            let fan = &mesh.indices[tri * 3..(tri + face.size - 2) * 3];
+            indices.extend(retriangulate_fan(fan, &mesh.positions));
+            for _ in 0..face.size - 2 {
+                triangle_faces.push((face.smoothing_group, face_idx));
+            }
+            tri += face.size - 2;
+            face_idx += 1;
+        }
+        mesh.indices = indices;
+
+        if mesh.normals.is_empty() && !mesh.positions.is_empty() {
+            generate_normals(mesh, &triangle_faces);
+        }
+    }
+
+    Ok((models, materials))
+}
 
 pub fn convert_meshes(models: &[tobj::Model]) -> Vec<Mesh<VPosTexNorm>> {
     // converts all provided into meshes of type VPosTexNorm, which includes all
     // information commonly incldued in obj files: positions, texture
     // coordinates and normals.
+    // faces with more than 3 vertices have already been split into triangles
+    // by load_obj.
     models
         .iter()
-        .map(|model| convert_mesh(&model.mesh))
+        .map(|model| {
+            let mesh = convert_mesh(&model.mesh);
+            if mesh.normals_point_inward() {
+                println!(
+                    "Warning: most normals of {} point inward, it might be inside out. Mesh::flip_normals and Mesh::flip_winding can fix that.",
+                    model.name
+                );
+            }
+            // only the first, broken assets tend to have thousands
+            let warnings = mesh.validate();
+            if !warnings.is_empty() {
+                println!(
+                    "Warning: {} has {} problems, the first: {}",
+                    model.name,
+                    warnings.len(),
+                    warnings[0]
+                );
+            }
+            mesh
+        })
         .collect()
 }
 
@@ -32,76 +112,173 @@ pub fn load_textures(
     queue: Queue,
     root_path: &Path,
     materials: &[tobj::Material],
-) -> Vec<(Image, Image, Image)> {
+) -> (Vec<(Image, Image, Image)>, Vec<String>) {
     // loads all textures for all materials provided by returning 3 images for
     // each material: a diffuse texture, a specular texture, and a normal
-    // texture, in that order
-    materials
+    // texture, in that order.
+    // textures that are missing or can't be loaded are replaced with
+    // placeholders instead of failing the whole load, and what went wrong is
+    // returned as a list of warnings.
+    let mut warnings = vec![];
+
+    let mut load = |mat: &tobj::Material, kind: &str, file: &str, placeholder: &str, format| {
+        let placeholder = relative_path(placeholder);
+
+        // if the texture path is empty, use a placeholder. that's not a
+        // problem, plenty of materials just don't have every kind of texture.
+        if file == "" {
+            println!("{} has no {} texture", mat.name, kind);
+            return load_texture(queue.clone(), &placeholder, format);
+        }
+
+        let path = root_path.join(Path::new(file));
+        match try_load_texture(queue.clone(), &path, format) {
+            Ok(texture) => texture,
+            Err(e) => {
+                warnings.push(format!("{} {} texture: {}", mat.name, kind, e));
+                load_texture(queue.clone(), &placeholder, format)
+            }
+        }
+    };
+
+    let textures = materials
         .iter()
         .map(|mat| {
-            // diffuse
-            let diff_path = {
-                let maybe_path = root_path.join(Path::new(&mat.diffuse_texture));
-
-                // if the diffuse texture path is empty or the file doesn't
-                // exist, use a placeholder
-                if mat.diffuse_texture == "" {
-                    println!("{} has no diffuse texture", mat.name);
-                    relative_path("textures/missing.png")
-                } else if !maybe_path.exists() {
-                    println!(
-                        "{} diffuse texture does not exist: {:?}",
-                        mat.name, maybe_path
-                    );
-                    relative_path("textures/missing.png")
-                } else {
-                    maybe_path
-                }
-            };
+            let diff_tex = load(
+                mat,
+                "diffuse",
+                &mat.diffuse_texture,
+                "textures/missing.png",
+                Format::R8G8B8A8Srgb,
+            );
+            let spec_tex = load(
+                mat,
+                "specular",
+                &mat.specular_texture,
+                "textures/missing-spec.png",
+                Format::R8G8B8A8Unorm,
+            );
+            let norm_tex = load(
+                mat,
+                "normal",
+                &mat.normal_texture,
+                "textures/missing-normal.png",
+                Format::R8G8B8A8Unorm,
+            );
 
-            // specular
-            let spec_path = {
-                let maybe_path = root_path.join(Path::new(&mat.specular_texture));
-
-                if mat.specular_texture == "" {
-                    println!("{} has no specular texture", mat.name);
-                    relative_path("textures/missing-spec.png")
-                } else if !maybe_path.exists() {
-                    println!(
-                        "{} specular texture does not exist: {:?}",
-                        mat.name, maybe_path
-                    );
-                    relative_path("textures/missing-spec.png")
-                } else {
-                    maybe_path
-                }
-            };
+            (diff_tex, spec_tex, norm_tex)
+        })
+        .collect();
+
+    (textures, warnings)
+}
 
-            // normal
-            let normal_path = {
-                let maybe_path = root_path.join(Path::new(&mat.normal_texture));
-
-                if mat.normal_texture == "" {
-                    println!("{} has no normal texture", mat.name);
-                    relative_path("textures/missing-normal.png")
-                } else if !maybe_path.exists() {
-                    println!(
-                        "{} normal texture does not exist: {:?}",
-                        mat.name, maybe_path
-                    );
-                    relative_path("textures/missing-normal.png")
-                } else {
-                    maybe_path
+// a summary of a loaded OBJ file, to check everything made it in and to find
+// meshes that will render black: ones without usable normals, tex coords or
+// tangents.
+pub struct SceneStats {
+    pub meshes: usize,
+    pub triangles: usize,
+    pub vertices: usize,
+    // materials used by at least one mesh, out of all the file has
+    pub materials_used: usize,
+    pub materials: usize,
+    // textures are counted once even if several materials share them
+    pub textures: usize,
+    pub texture_bytes: u64,
+    // names of meshes with vertices that have zero or NaN normals
+    pub bad_normals: Vec<String>,
+    // names of meshes the file has no tex coords for
+    pub missing_tex_coords: Vec<String>,
+    // names of meshes with vertices that have zero or NaN tangents, usually
+    // because of missing or degenerate tex coords
+    pub bad_tangents: Vec<String>,
+}
+
+impl SceneStats {
+    // meshes have to be the converted models, in the same order. textures is
+    // what load_textures returned for the materials.
+    pub fn new<V: VertexAttributes>(
+        models: &[tobj::Model],
+        meshes: &[Mesh<V>],
+        materials: &[tobj::Material],
+        textures: &[(Image, Image, Image)],
+    ) -> Self {
+        let mut material_ids: Vec<usize> = models
+            .iter()
+            .filter_map(|model| model.mesh.material_id)
+            .collect();
+        material_ids.sort();
+        material_ids.dedup();
+
+        let mut unique_textures: Vec<&Image> = vec![];
+        for (diffuse, specular, normal) in textures.iter() {
+            for &texture in [diffuse, specular, normal].iter() {
+                if !unique_textures
+                    .iter()
+                    .any(|&counted| Arc::ptr_eq(counted, texture))
+                {
+                    unique_textures.push(texture);
                 }
-            };
+            }
+        }
 
-            let diff_tex = load_texture(queue.clone(), &diff_path, Format::R8G8B8A8Srgb);
-            let spec_tex = load_texture(queue.clone(), &spec_path, Format::R8G8B8A8Unorm);
-            let norm_tex = load_texture(queue.clone(), &normal_path, Format::R8G8B8A8Unorm);
+        let is_bad = |v: Option<[f32; 3]>| match v {
+            Some(v) => v.iter().any(|c| !c.is_finite()) || v.iter().all(|&c| c == 0.0),
+            // the vertex type doesn't have one, nothing to check
+            None => false,
+        };
+        let names_where = |bad: &dyn Fn(&Mesh<V>, &tobj::Model) -> bool| -> Vec<String> {
+            meshes
+                .iter()
+                .zip(models.iter())
+                .filter(|(mesh, model)| bad(*mesh, *model))
+                .map(|(_, model)| model.name.clone())
+                .collect()
+        };
 
-            (diff_tex, spec_tex, norm_tex)
-        })
-        .collect()
+        Self {
+            meshes: meshes.len(),
+            triangles: meshes.iter().map(|mesh| mesh.indices.len() / 3).sum(),
+            vertices: meshes.iter().map(|mesh| mesh.vertices.len()).sum(),
+            materials_used: material_ids.len(),
+            materials: materials.len(),
+            textures: unique_textures.len(),
+            texture_bytes: unique_textures
+                .iter()
+                .map(|&texture| image_size_bytes(texture))
+                .sum(),
+            bad_normals: names_where(&|mesh, _| mesh.vertices.iter().any(|v| is_bad(v.normal()))),
+            missing_tex_coords: names_where(&|_, model| model.mesh.texcoords.is_empty()),
+            bad_tangents: names_where(&|mesh, _| mesh.vertices.iter().any(|v| is_bad(v.tangent()))),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Scene stats:");
+        println!(
+            "    {} meshes, {} triangles, {} vertices",
+            self.meshes, self.triangles, self.vertices
+        );
+        println!(
+            "    {} of {} materials used",
+            self.materials_used, self.materials
+        );
+        println!(
+            "    {} textures using {:.1} MiB",
+            self.textures,
+            self.texture_bytes as f32 / 1_048_576.0
+        );
+
+        let warn = |what: &str, names: &[String]| {
+            if !names.is_empty() {
+                println!("    {} meshes {}: {:?}", names.len(), what, names);
+            }
+        };
+        warn("have bad normals", &self.bad_normals);
+        warn("have no tex coords", &self.missing_tex_coords);
+        warn("have bad tangents", &self.bad_tangents);
+    }
 }
 
 pub fn convert_mesh(mesh: &tobj::Mesh) -> Mesh<VPosTexNorm> {
@@ -151,6 +328,7 @@ pub fn add_tangents(mesh: &Mesh<VPosTexNorm>) -> Mesh<VPosTexNormTan> {
     let (vertices, indices) = (&mesh.vertices, &mesh.indices);
 
     let mut tangents: Vec<Vec3> = vec![vec3(0.0, 0.0, 0.0); vertices.len()];
+    let mut bitangents: Vec<Vec3> = vec![vec3(0.0, 0.0, 0.0); vertices.len()];
 
     for i in 0..indices.len() / 3 {
         let face = [
@@ -158,23 +336,34 @@ pub fn add_tangents(mesh: &Mesh<VPosTexNorm>) -> Mesh<VPosTexNormTan> {
             vertices[indices[i * 3 + 1] as usize],
             vertices[indices[i * 3 + 2] as usize],
         ];
-        let (tangent, _bitangent) = tangent_bitangent_for_face(&face);
-        tangents[indices[i * 3] as usize] += tangent;
-        tangents[indices[i * 3 + 1] as usize] += tangent;
-        tangents[indices[i * 3 + 2] as usize] += tangent;
+        let (tangent, bitangent) = tangent_bitangent_for_face(&face);
+        for j in 0..3 {
+            tangents[indices[i * 3 + j] as usize] += tangent;
+            bitangents[indices[i * 3 + j] as usize] += bitangent;
+        }
     }
 
     let new_vertices: Vec<VPosTexNormTan> = vertices
         .iter()
         .enumerate()
         .map(|(idx, v)| {
-            let t = normalize(&tangents[idx]);
+            // the summed face tangents usually aren't quite perpendicular to
+            // the vertex normal, so fix that up
+            let (normal, tangent) = orthonormalize(v.normal, tangents[idx].into());
+
+            // shaders get the bitangent as cross(normal, tangent) * w, so w
+            // says whether that points along the texture's v axis or against
+            // it (mirrored UVs). convert_meshes flips v on load, so the
+            // bitangent we want is the opposite of the one the flipped tex
+            // coords give.
+            let crossed = make_vec3(&normal).cross(&make_vec3(&tangent));
+            let w = if dot(&crossed, &-bitangents[idx]) < 0.0 { -1.0 } else { 1.0 };
 
             VPosTexNormTan {
                 position: v.position,
                 tex_coord: v.tex_coord,
-                normal: v.normal,
-                tangent: t.into(),
+                normal,
+                tangent: [tangent[0], tangent[1], tangent[2], w],
             }
         })
         .collect();
@@ -192,29 +381,36 @@ pub fn fullscreen_quad(queue: Queue, render_pass: RenderPass, vs_path: PathBuf,
         fill_type: PrimitiveTopology::TriangleStrip,
         read_depth: false,
         write_depth: false,
-        mesh: Mesh {
-            vertices: vec![
-                VPos2D {
-                    position: [-1.0, -1.0],
-                },
-                VPos2D {
-                    position: [-1.0, 1.0],
-                },
-                VPos2D {
-                    position: [1.0, -1.0],
-                },
-                VPos2D {
-                    position: [1.0, 1.0],
-                },
-            ],
-            indices: vec![0, 1, 2, 3],
-        },
+        mesh: fullscreen_quad_mesh(),
         collection: (),
         custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
     }
     .build_direct(queue, render_pass, 0)
 }
 
+// a quad covering the whole screen, drawn as a triangle strip. useful on its
+// own when the fullscreen object needs a collection.
+pub fn fullscreen_quad_mesh() -> Mesh<VPos2D> {
+    Mesh {
+        vertices: vec![
+            VPos2D {
+                position: [-1.0, -1.0],
+            },
+            VPos2D {
+                position: [-1.0, 1.0],
+            },
+            VPos2D {
+                position: [1.0, -1.0],
+            },
+            VPos2D {
+                position: [1.0, 1.0],
+            },
+        ],
+        indices: vec![0, 1, 2, 3],
+    }
+}
+
 pub fn wireframe(mesh: &Mesh<VPos>) -> Mesh<VPos> {
     // converts a mesh of triangles into one with lines for every edge, suitable
     // for drawing a wireframe version of a mesh
@@ -243,25 +439,228 @@ pub fn wireframe(mesh: &Mesh<VPos>) -> Mesh<VPos> {
     Mesh { vertices, indices }
 }
 
-pub fn merge<V: Vertex + Clone>(meshes: &[Mesh<V>]) -> Mesh<V> {
-    // merges a list of meshes into a single mesh
+struct ObjFace {
+    // number of vertices
+    size: usize,
+    // 0 if smoothing is off for the face
+    smoothing_group: u32,
+}
 
-    // you could probably write this as an iterator, i'm just too lazy
-    let mut vertices = vec![];
-    let mut indices = vec![];
-    // we need to offset some indices because the vertices are being merged into
-    // one giant list
-    let mut index_offset = 0;
-    for mesh in meshes.iter() {
-        for vertex in mesh.vertices.iter().cloned() {
-            vertices.push(vertex);
+// every face with at least 3 vertices in an OBJ file, in order. points and
+// lines are f statements with less.
+fn obj_faces(text: &str) -> Vec<ObjFace> {
+    let mut faces = vec![];
+    // smoothing is off until the first s statement
+    let mut smoothing_group = 0;
+
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("s") => {
+                // "s off" is the same as "s 0"
+                smoothing_group = words.next().and_then(|w| w.parse().ok()).unwrap_or(0);
+            }
+            Some("f") => {
+                let size = words.count();
+                if size >= 3 {
+                    faces.push(ObjFace {
+                        size,
+                        smoothing_group,
+                    });
+                }
+            }
+            _ => {}
         }
+    }
+
+    faces
+}
 
-        for index in mesh.indices.iter() {
-            indices.push(index + index_offset);
+// fills in mesh.normals, given the smoothing group and face of every triangle.
+// vertices of faces in the same smoothing group get the average of those
+// faces' normals, weighted by area. faces without a smoothing group, or in
+// different ones, don't share vertices: they're split so each side of a hard
+// edge has its own normal.
+fn generate_normals(mesh: &mut tobj::Mesh, triangle_faces: &[(u32, usize)]) {
+    let position = |idx: u32| -> Vec3 {
+        let i = idx as usize * 3;
+        vec3(mesh.positions[i], mesh.positions[i + 1], mesh.positions[i + 2])
+    };
+    // vertices are shared by position, not by index, since tobj makes
+    // separate vertices for the same position with different tex coords
+    let position_key = |idx: u32| -> [u32; 3] {
+        let p = position(idx);
+        [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]
+    };
+    // what normals are averaged over. faces without a group only share with
+    // themselves.
+    let smoothing_key = |tri: usize, idx: u32| -> ([u32; 3], u32, usize) {
+        let (group, face) = triangle_faces[tri];
+        let face = if group == 0 { face } else { std::usize::MAX };
+        (position_key(idx), group, face)
+    };
+
+    let mut normal_sums: HashMap<([u32; 3], u32, usize), Vec3> = HashMap::new();
+    for (tri, corners) in mesh.indices.chunks(3).enumerate() {
+        // not normalized, so bigger triangles count for more
+        let (a, b, c) = (
+            position(corners[0]),
+            position(corners[1]),
+            position(corners[2]),
+        );
+        let normal = (b - a).cross(&(c - a));
+        for &idx in corners.iter() {
+            *normal_sums
+                .entry(smoothing_key(tri, idx))
+                .or_insert_with(|| vec3(0.0, 0.0, 0.0)) += normal;
         }
+    }
+
+    // a new vertex for every old vertex and smoothing key it's used with
+    let mut new_vertices: HashMap<(u32, ([u32; 3], u32, usize)), u32> = HashMap::new();
+    let mut positions = vec![];
+    let mut texcoords = vec![];
+    let mut normals = vec![];
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    for (i, &idx) in mesh.indices.iter().enumerate() {
+        let key = smoothing_key(i / 3, idx);
+        let new_idx = *new_vertices.entry((idx, key)).or_insert_with(|| {
+            let old = idx as usize;
+            positions.extend_from_slice(&mesh.positions[old * 3..old * 3 + 3]);
+            if mesh.texcoords.len() >= old * 2 + 2 {
+                texcoords.extend_from_slice(&mesh.texcoords[old * 2..old * 2 + 2]);
+            }
+            let sum = normal_sums[&key];
+            let normal = if length(&sum) > 0.0 {
+                normalize(&sum)
+            } else {
+                vec3(0.0, 1.0, 0.0)
+            };
+            normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+            (positions.len() / 3 - 1) as u32
+        });
+        indices.push(new_idx);
+    }
+
+    mesh.positions = positions;
+    // convert_mesh falls back to dummy tex coords for all vertices past the
+    // end, so missing ones only work if all of them are missing
+    if texcoords.len() * 3 == mesh.positions.len() * 2 {
+        mesh.texcoords = texcoords;
+    } else {
+        mesh.texcoords = vec![];
+    }
+    mesh.normals = normals;
+    mesh.indices = indices;
+}
+
+// takes the triangles tobj made for one face, a fan around its first vertex,
+// and triangulates the face again with ear clipping. only a lone triangle
+// can't go wrong as a fan, a concave quad already can.
+fn retriangulate_fan(fan: &[u32], positions: &[f32]) -> Vec<u32> {
+    if fan.len() <= 3 {
+        return fan.to_vec();
+    }
+
+    // the fan is (0, 1, 2), (0, 2, 3), ..., so the face's outline is the
+    // first triangle and then the last vertex of every other one
+    let mut polygon = fan[0..3].to_vec();
+    polygon.extend(fan[3..].chunks(3).map(|tri| tri[2]));
 
-        index_offset += mesh.vertices.len() as u32;
+    let points: Vec<Vec3> = polygon
+        .iter()
+        .map(|&idx| {
+            let i = idx as usize * 3;
+            vec3(positions[i], positions[i + 1], positions[i + 2])
+        })
+        .collect();
+
+    match ear_clip(&points) {
+        Some(triangles) => triangles
+            .iter()
+            .flat_map(|tri| tri.iter().map(|&corner| polygon[corner]))
+            .collect(),
+        None => fan.to_vec(),
+    }
+}
+
+// splits a simple polygon into triangles, as indices into points. the polygon
+// doesn't have to be convex, and only has to be roughly flat. returns None if
+// it's degenerate or self-intersecting enough that there's no ear to clip.
+fn ear_clip(points: &[Vec3]) -> Option<Vec<[usize; 3]>> {
+    // Newell's method, which gives a sensible normal even for concave and
+    // slightly non-planar polygons
+    let mut normal = vec3(0.0, 0.0, 0.0);
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        normal.x += (a.y - b.y) * (a.z + b.z);
+        normal.y += (a.z - b.z) * (a.x + b.x);
+        normal.z += (a.x - b.x) * (a.y + b.y);
+    }
+    if length(&normal) < 1e-12 {
+        return None;
+    }
+
+    // whether b is a convex corner going from a to c, given the winding
+    let convex = |a: &Vec3, b: &Vec3, c: &Vec3| dot(&(b - a).cross(&(c - b)), &normal) > 0.0;
+    let inside = |p: &Vec3, a: &Vec3, b: &Vec3, c: &Vec3| {
+        convex(a, b, p) && convex(b, c, p) && convex(c, a, p)
+    };
+
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            convex(&points[a], &points[b], &points[c])
+                && remaining
+                    .iter()
+                    .filter(|&&other| other != a && other != b && other != c)
+                    .all(|&other| !inside(&points[other], &points[a], &points[b], &points[c]))
+        })?;
+
+        triangles.push([
+            remaining[(ear + n - 1) % n],
+            remaining[ear],
+            remaining[(ear + 1) % n],
+        ]);
+        remaining.remove(ear);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+
+    Some(triangles)
+}
+
+pub fn merge<V: Vertex + Clone>(meshes: &[Mesh<V>]) -> Mesh<V> {
+    // merges a list of meshes into a single mesh
+    Mesh::merge(meshes)
+}
+
+// the same as only_pos_from_ptnt(&merge(&add_tangents_multi(&convert_meshes(models)))),
+// but straight from the models, without building all the vertex data that
+// would just be thrown away. for depth prepasses and shadow casters.
+// triangles stay the same (optimize_vertex_cache only reorders whole
+// triangles), so it still lines up exactly with the full meshes.
+pub fn merged_positions(models: &[tobj::Model]) -> Mesh<VPos> {
+    let num_vertices: usize = models
+        .iter()
+        .map(|model| model.mesh.positions.len() / 3)
+        .sum();
+    let num_indices: usize = models.iter().map(|model| model.mesh.indices.len()).sum();
+    let mut vertices = Vec::with_capacity(num_vertices);
+    let mut indices = Vec::with_capacity(num_indices);
+
+    for model in models.iter() {
+        let index_offset = vertices.len() as u32;
+        vertices.extend(model.mesh.positions.chunks_exact(3).map(|pos| VPos {
+            position: [pos[0], pos[1], pos[2]],
+        }));
+        indices.extend(model.mesh.indices.iter().map(|index| index + index_offset));
     }
 
     Mesh { vertices, indices }
@@ -293,15 +692,15 @@ fn tangent_bitangent_for_face(face: &[VPosTexNorm; 3]) -> (Vec3, Vec3) {
     let duv1 = uv2 - uv1;
     let duv2 = uv3 - uv1;
 
-    // compute and bitangent
-    let mut tangent = normalize(&vec3(
-        duv2.y * edge1.x - duv1.y * edge2.x,
-        duv2.y * edge1.y - duv1.y * edge2.y,
-        duv2.y * edge1.z - duv1.y * edge2.z,
-    ));
+    // compute tangent and bitangent, the directions u and v increase in.
+    // they should be divided by the UV determinant, but they're normalized
+    // anyway so only its sign matters. without it faces with mirrored UVs get
+    // tangents pointing backwards.
+    let det_sign = if duv1.x * duv2.y - duv2.x * duv1.y < 0.0 { -1.0 } else { 1.0 };
+    let mut tangent = normalize(&((edge1 * duv2.y - edge2 * duv1.y) * det_sign));
+    let bitangent = normalize(&((edge2 * duv1.x - edge1 * duv2.x) * det_sign));
 
     tangent = normalize(&(tangent - dot(&tangent, &normal) * normal));
-    let bitangent = tangent.cross(&normal);
 
     (tangent, bitangent)
 }
@@ -343,6 +742,16 @@ pub struct VPos {
 }
 impl_vertex!(VPos, position);
 
+impl VertexAttributes for VPos {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn set_position(&mut self, position: [f32; 3]) {
+        self.position = position;
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy)]
 pub struct VPos2D {
     pub position: [f32; 2],
@@ -364,11 +773,128 @@ pub struct VPosTexNorm {
 }
 impl_vertex!(VPosTexNorm, position, tex_coord, normal);
 
+impl VertexAttributes for VPosTexNorm {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn set_position(&mut self, position: [f32; 3]) {
+        self.position = position;
+    }
+
+    fn normal(&self) -> Option<[f32; 3]> {
+        Some(self.normal)
+    }
+
+    fn set_normal(&mut self, normal: [f32; 3]) {
+        self.normal = normal;
+    }
+
+    fn tex_coord(&self) -> Option<[f32; 2]> {
+        Some(self.tex_coord)
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy)]
 pub struct VPosTexNormTan {
     pub position: [f32; 3],
     pub tex_coord: [f32; 2],
     pub normal: [f32; 3],
-    pub tangent: [f32; 3],
+    // w is the bitangent sign, see add_tangents
+    pub tangent: [f32; 4],
 }
 impl_vertex!(VPosTexNormTan, position, tex_coord, normal, tangent);
+
+// VPosTexNormTan without the position, for the attribute stream of a
+// SplitMesh
+#[derive(Default, Debug, Clone, Copy)]
+pub struct VTexNormTan {
+    pub tex_coord: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+}
+impl_vertex!(VTexNormTan, tex_coord, normal, tangent);
+
+pub fn split_positions(mesh: &Mesh<VPosTexNormTan>) -> SplitMesh<VTexNormTan> {
+    SplitMesh::from_mesh(mesh, |vertex| VTexNormTan {
+        tex_coord: vertex.tex_coord,
+        normal: vertex.normal,
+        tangent: vertex.tangent,
+    })
+}
+
+impl VertexAttributes for VPosTexNormTan {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn set_position(&mut self, position: [f32; 3]) {
+        self.position = position;
+    }
+
+    fn normal(&self) -> Option<[f32; 3]> {
+        Some(self.normal)
+    }
+
+    fn set_normal(&mut self, normal: [f32; 3]) {
+        self.normal = normal;
+    }
+
+    fn tex_coord(&self) -> Option<[f32; 2]> {
+        Some(self.tex_coord)
+    }
+
+    fn tangent(&self) -> Option<[f32; 3]> {
+        Some([self.tangent[0], self.tangent[1], self.tangent[2]])
+    }
+
+    fn set_tangent(&mut self, tangent: [f32; 3]) {
+        self.tangent = [tangent[0], tangent[1], tangent[2], self.tangent[3]];
+    }
+
+    fn bitangent_sign(&self) -> f32 {
+        self.tangent[3]
+    }
+
+    fn set_bitangent_sign(&mut self, sign: f32) {
+        self.tangent[3] = sign;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a dart whose first vertex is opposite the concave corner, so the fan
+    // tobj makes covers 3 times the area the face does
+    const CONCAVE_QUAD: &str = "v 0 0 0
+v 2 -1 0
+v 1 0 0
+v 2 1 0
+f 2 3 4 1
+";
+
+    #[test]
+    fn concave_quad_is_ear_clipped() {
+        let path = std::env::temp_dir().join("render-engine-concave-quad.obj");
+        std::fs::write(&path, CONCAVE_QUAD).unwrap();
+        let (models, _) = load_obj(&path).unwrap();
+        let mesh = &models[0].mesh;
+
+        assert_eq!(mesh.indices.len() / 3, 2);
+
+        let position = |idx: u32| {
+            let i = idx as usize * 3;
+            vec3(mesh.positions[i], mesh.positions[i + 1], mesh.positions[i + 2])
+        };
+        let area: f32 = mesh
+            .indices
+            .chunks(3)
+            .map(|tri| {
+                let (a, b, c) = (position(tri[0]), position(tri[1]), position(tri[2]));
+                length(&(b - a).cross(&(c - a))) / 2.0
+            })
+            .sum();
+        assert!((area - 1.0).abs() < 1e-5, "triangles cover {} instead of 1", area);
+    }
+}
@@ -1,20 +1,42 @@
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
 use vulkano::framebuffer::RenderPassAbstract;
 use vulkano::image::SwapchainImage;
 use vulkano::swapchain::{
-    AcquireError, Capabilities, PresentMode, Surface, SurfaceTransform, Swapchain,
+    AcquireError, Capabilities, ColorSpace, PresentMode, Surface, SurfaceTransform, Swapchain,
     SwapchainCreationError,
 };
 use vulkano::sync;
-use vulkano::sync::{FlushError, GpuFuture};
+use vulkano::sync::{FenceSignalFuture, FlushError, GpuFuture};
 
 use winit::Window;
 
+use std::collections::VecDeque;
 use std::sync::Arc;
 
+// what to ask for when creating the swapchain. anything left as None, or that
+// the surface doesn't support, gets picked automatically.
+#[derive(Clone, Debug, Default)]
+pub struct SwapchainConfig {
+    pub format: Option<Format>,
+    pub image_count: Option<u32>,
+    // NOTE: vulkano 0.14 always creates swapchains in SrgbNonLinear, so
+    // that's the only one that can be picked. anything else falls back with a
+    // warning.
+    pub color_space: Option<ColorSpace>,
+    // if format isn't set, prefer an sRGB format so the hardware converts the
+    // linear colors we render to sRGB when writing to the swapchain
+    pub srgb: bool,
+    // Immediate if not set
+    pub present_mode: Option<PresentMode>,
+}
+
 // TODO: store queue instead of device
 pub struct VkWindow {
     device: Arc<Device>,
+    queue: Arc<Queue>,
     swapchain: Arc<Swapchain<Window>>,
     images: Vec<Arc<SwapchainImage<Window>>>,
     surface: Arc<Surface<Window>>,
@@ -22,7 +44,44 @@ pub struct VkWindow {
     image_num: Option<usize>,
     future: Option<Box<dyn GpuFuture>>,
     previous_frame_end: Option<Box<dyn GpuFuture>>,
+    // fences of presented frames the GPU might still be working on, oldest
+    // first. see set_max_frames_in_flight.
+    in_flight: VecDeque<Arc<dyn FrameFence>>,
+    max_frames_in_flight: usize,
     dimensions: [u32; 2],
+    // see set_keep_last_frame
+    keep_last_frame: bool,
+    last_frame: Option<FrameCopy>,
+    // whether the surface lets swapchain images be copied from
+    can_copy_images: bool,
+    // what the swapchain gets recreated with. after creation it holds what
+    // was picked, so rebuilds don't warn about the same fallbacks again.
+    caps: Capabilities,
+    config: SwapchainConfig,
+    // the config changed while the window was minimized, which can't be
+    // rebuilt right away
+    needs_rebuild: bool,
+}
+
+// a presented frame, copied into a buffer right before it was presented. the
+// bytes are in the swapchain's format, rows from the top left.
+pub struct FrameCopy {
+    pub buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    pub dimensions: [u32; 2],
+    pub format: Format,
+}
+
+// lets frames of different future types be waited on from one list
+trait FrameFence {
+    fn wait_done(&self);
+}
+
+impl<F: GpuFuture> FrameFence for FenceSignalFuture<F> {
+    fn wait_done(&self) {
+        if let Err(e) = self.wait(None) {
+            println!("Error waiting for frame: {:?}", e);
+        }
+    }
 }
 
 impl VkWindow {
@@ -33,16 +92,47 @@ impl VkWindow {
         render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
         caps: Capabilities,
     ) -> Self {
+        Self::new_with_config(
+            device,
+            queue,
+            surface,
+            render_pass,
+            caps,
+            SwapchainConfig::default(),
+        )
+    }
+
+    pub fn new_with_config(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        surface: Arc<Surface<Window>>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        caps: Capabilities,
+        config: SwapchainConfig,
+    ) -> Self {
+        let can_copy_images = caps.supported_usage_flags.transfer_source;
+
         // create swapchain
-        let (swapchain, images) = create_swapchain_and_images_from_scratch(
-            device.clone(),
-            queue.clone(),
+        let config = pick_config(&caps, &config);
+        // TODO: try using other get_dimensions implementation
+        let dimensions = caps.current_extent.unwrap_or([1024, 768]);
+        let (swapchain, images) = match create_swapchain_and_images(
             surface.clone(),
-            caps,
-        );
+            queue.clone(),
+            &caps,
+            &config,
+            dimensions,
+            None,
+        ) {
+            Ok(r) => r,
+            // TODO: add dimensions to err msg
+            Err(SwapchainCreationError::UnsupportedDimensions) => panic!("SwapchainCreationError::UnsupportedDimensions when creating initial swapchain. Should never happen."),
+            Err(err) => panic!("{:?}", err),
+        };
 
         Self {
             device: device.clone(),
+            queue,
             swapchain,
             images,
             surface,
@@ -53,50 +143,158 @@ impl VkWindow {
             // TODO: maybe PFE and future can be joined into one, constantly
             // updating future
             previous_frame_end: Some(Box::new(sync::now(device.clone()))),
+            in_flight: VecDeque::new(),
+            max_frames_in_flight: 2,
             dimensions: [0, 0],
+            keep_last_frame: false,
+            last_frame: None,
+            can_copy_images,
+            caps,
+            config,
+            needs_rebuild: false,
         }
     }
 
+    // how many frames the CPU can get ahead of the GPU. 1 waits for every
+    // frame to finish before starting the next one, for the lowest input
+    // latency, more lets the CPU record the next frame while the GPU is still
+    // drawing the last ones. lowering it waits for frames until there are few
+    // enough in flight.
+    pub fn set_max_frames_in_flight(&mut self, max: usize) {
+        assert!(max >= 1, "At least 1 frame has to be in flight");
+        self.max_frames_in_flight = max;
+        self.wait_for_frames(max - 1);
+    }
+
+    pub fn max_frames_in_flight(&self) -> usize {
+        self.max_frames_in_flight
+    }
+
+    // blocks until every frame presented so far is done, which also releases
+    // whatever the frames were holding on to
+    pub fn wait_for_all_frames(&mut self) {
+        self.wait_for_frames(0);
+    }
+
+    // blocks until the GPU is working on at most max_outstanding frames
+    fn wait_for_frames(&mut self, max_outstanding: usize) {
+        while self.in_flight.len() > max_outstanding {
+            self.in_flight.pop_front().unwrap().wait_done();
+        }
+    }
+
+    // copies every frame as it's presented, so last_frame has it afterwards.
+    // swapchain images can't be touched once they're presented, this is the
+    // only way to get at what's on screen. costs a copy of the whole window
+    // per frame, so it's off by default.
+    pub fn set_keep_last_frame(&mut self, keep: bool) {
+        if keep && !self.can_copy_images {
+            println!("Swapchain images can't be copied from on this surface, not keeping frames");
+            return;
+        }
+
+        self.keep_last_frame = keep;
+        if !keep {
+            self.last_frame = None;
+        }
+    }
+
+    // the copy of the last presented frame, if set_keep_last_frame is on.
+    // blocks until the GPU is done with it.
+    // NOTE: frames presented with then_present aren't waited for, their
+    // buffer stays locked until whoever flushed them cleans up.
+    pub fn last_frame(&self) -> Option<&FrameCopy> {
+        if let Some(fence) = self.in_flight.back() {
+            fence.wait_done();
+        }
+
+        self.last_frame.as_ref()
+    }
+
     pub fn set_render_pass(&mut self, new_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>) {
         self.render_pass = new_render_pass;
     }
 
-    pub fn next_image(&mut self) -> Arc<SwapchainImage<Window>> {
+    // None while the window is minimized, there's nothing to draw to then.
+    // skip the frame and try again later.
+    pub fn next_image(&mut self) -> Option<Arc<SwapchainImage<Window>>> {
         // TODO: this does more than the name suggests, which is not so great
-        let mut idx_and_future = None;
-        while idx_and_future.is_none() {
-            idx_and_future = match vulkano::swapchain::acquire_next_image(
-                self.swapchain.clone(),
-                // timeout
-                None,
-            ) {
-                Ok(r) => Some(r),
-                Err(AcquireError::OutOfDate) => {
-                    self.rebuild();
-                    None
-                }
+        loop {
+            if self.is_minimized() {
+                return None;
+            }
+
+            match self.try_next_image() {
+                Ok(image) => return Some(image),
+                Err(AcquireError::OutOfDate) => self.rebuild(),
                 Err(err) => panic!("{:?}", err),
-            };
+            }
+        }
+    }
+
+    // next_image, but errors are returned instead of handled. when the
+    // swapchain is out of date, call rebuild and try again.
+    pub fn try_next_image(&mut self) -> Result<Arc<SwapchainImage<Window>>, AcquireError> {
+        if self.needs_rebuild {
+            self.rebuild();
         }
 
-        let (image_num, acquire_future) = idx_and_future.unwrap();
+        let (image_num, acquire_future) = vulkano::swapchain::acquire_next_image(
+            self.swapchain.clone(),
+            // timeout
+            None,
+        )?;
+
+        let previous_frame_end = self
+            .previous_frame_end
+            .take()
+            .unwrap_or_else(|| Box::new(sync::now(self.device.clone())));
         self.image_num = Some(image_num);
-        self.future = Some(Box::new(
-            self.previous_frame_end.take().unwrap().join(acquire_future),
-        ));
+        self.future = Some(Box::new(previous_frame_end.join(acquire_future)));
+
+        Ok(self.images[image_num].clone())
+    }
 
-        self.images[image_num].clone()
+    // gives up on the frame started with next_image, for when drawing it
+    // failed. if its future wasn't taken with get_future yet the image is
+    // presented as it is, so the swapchain gets it back.
+    // NOTE: otherwise the image stays acquired until the swapchain is
+    // rebuilt, so rebuild it after this if it keeps happening.
+    pub fn abandon_frame(&mut self, queue: Arc<Queue>) {
+        match self.future.take() {
+            Some(future) => self.present_image(queue, future),
+            None => {
+                self.image_num = None;
+                if self.previous_frame_end.is_none() {
+                    self.previous_frame_end = Some(Box::new(sync::now(self.device.clone())));
+                }
+            }
+        }
     }
 
     pub fn update_dimensions(&mut self) {
-        let dims: (u32, u32) = self
-            .surface
-            .window()
-            .get_inner_size()
-            .unwrap()
-            .to_physical(self.surface.window().get_hidpi_factor())
-            .into();
-        self.dimensions = [dims.0, dims.1]
+        self.dimensions = self.current_dimensions();
+    }
+
+    // the size of the window right now, which is different from
+    // get_dimensions (the swapchain's size) until the next rebuild
+    pub fn current_dimensions(&self) -> [u32; 2] {
+        let window = self.surface.window();
+        match window.get_inner_size() {
+            Some(size) => {
+                let dims: (u32, u32) = size.to_physical(window.get_hidpi_factor()).into();
+                [dims.0, dims.1]
+            }
+            // the window doesn't exist anymore
+            None => [0, 0],
+        }
+    }
+
+    // a minimized window has no area to draw to, so there's no swapchain that
+    // fits it. most platforms report it as [0, 0].
+    pub fn is_minimized(&self) -> bool {
+        let dims = self.current_dimensions();
+        dims[0] == 0 || dims[1] == 0
     }
 
     pub fn get_dimensions(&self) -> [u32; 2] {
@@ -104,8 +302,20 @@ impl VkWindow {
     }
 
     pub fn rebuild(&mut self) {
+        // the old swapchain is kept while minimized, it gets rebuilt once the
+        // window is back
+        if self.is_minimized() {
+            return;
+        }
         self.update_dimensions();
-        let result = match self.swapchain.recreate_with_dimension(self.dimensions) {
+        let result = match create_swapchain_and_images(
+            self.surface.clone(),
+            self.queue.clone(),
+            &self.caps,
+            &self.config,
+            self.dimensions,
+            Some(self.swapchain.clone()),
+        ) {
             Ok(r) => r,
             Err(SwapchainCreationError::UnsupportedDimensions) => {
                 panic!("Unsupported dimensions: {:?}", self.dimensions);
@@ -115,6 +325,33 @@ impl VkWindow {
 
         self.swapchain = result.0;
         self.images = result.1;
+        self.needs_rebuild = false;
+    }
+
+    // switches between vsync (Fifo), uncapped without tearing (Mailbox) and
+    // uncapped (Immediate), recreating the swapchain. modes the surface
+    // doesn't support fall back to the closest one it does, with a warning.
+    // waits for every frame in flight first, they're still using the old
+    // swapchain's images. while minimized the switch happens once the window
+    // is back, at the next next_image.
+    pub fn set_present_mode(&mut self, mode: PresentMode) {
+        if self.future.is_some() {
+            panic!(
+                "Can't change the present mode in the middle of a frame, do it before next_image"
+            );
+        }
+
+        self.config.present_mode = Some(pick_present_mode(&self.caps, Some(mode)));
+        self.needs_rebuild = true;
+        self.wait_for_all_frames();
+        self.rebuild();
+    }
+
+    // the one in use, which isn't the one asked for if that was unsupported
+    pub fn present_mode(&self) -> PresentMode {
+        self.config
+            .present_mode
+            .expect("The present mode is always set after creation")
     }
 
     pub fn get_future(&mut self) -> Box<dyn GpuFuture> {
@@ -122,6 +359,19 @@ impl VkWindow {
     }
 
     pub fn present_image<F>(&mut self, queue: Arc<Queue>, future: F)
+    where
+        F: GpuFuture + 'static,
+    {
+        match self.try_present_image(queue, future) {
+            // rebuilt in next_image
+            Ok(()) | Err(FlushError::OutOfDate) => {}
+            Err(e) => println!("{:?}", e),
+        }
+    }
+
+    // present_image, but errors are returned instead of printed. the window
+    // is ready for the next frame either way.
+    pub fn try_present_image<F>(&mut self, queue: Arc<Queue>, future: F) -> Result<(), FlushError>
     where
         F: GpuFuture + 'static,
     {
@@ -129,59 +379,255 @@ impl VkWindow {
             panic!("Image_num was none when trying to submit command buffer to swapchain. next_image was probably not called before.");
         }
 
+        let image_num = self.image_num.unwrap();
+        let future = self.then_copy_frame(queue.clone(), future, image_num);
         let result = future
-            .then_swapchain_present(queue, self.swapchain.clone(), self.image_num.unwrap())
+            .then_swapchain_present(queue, self.swapchain.clone(), image_num)
             .then_signal_fence_and_flush();
 
-        let mut new_fut: Box<dyn GpuFuture> = match result {
-            Ok(new_fut) => Box::new(new_fut),
-            Err(FlushError::OutOfDate) => Box::new(sync::now(self.device.clone())),
-            Err(e) => {
-                println!("{:?}", e);
-                Box::new(sync::now(self.device.clone()))
+        let (mut new_fut, error): (Box<dyn GpuFuture>, _) = match result {
+            Ok(new_fut) => {
+                // shared so it can be waited on here and joined by the next
+                // frame
+                let new_fut = Arc::new(new_fut);
+                self.in_flight.push_back(new_fut.clone());
+                (Box::new(new_fut), None)
             }
+            Err(e) => (Box::new(sync::now(self.device.clone())), Some(e)),
         };
 
         new_fut.cleanup_finished();
 
         self.previous_frame_end = Some(new_fut);
+
+        // the frame after this one counts as in flight too
+        self.wait_for_frames(self.max_frames_in_flight - 1);
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // chains presenting the current image onto future without flushing it,
+    // for presenting to several windows with one submission. whoever flushes
+    // the final future has to keep it around until the next frame, this
+    // window doesn't wait on it.
+    pub fn then_present(
+        &mut self,
+        queue: Arc<Queue>,
+        future: Box<dyn GpuFuture>,
+    ) -> Box<dyn GpuFuture> {
+        let image_num = self.image_num.expect(
+            "Image_num was none when trying to present. next_image was probably not called before.",
+        );
+
+        self.previous_frame_end = Some(Box::new(sync::now(self.device.clone())));
+
+        let future = self.then_copy_frame(queue.clone(), future, image_num);
+        Box::new(future.then_swapchain_present(queue, self.swapchain.clone(), image_num))
+    }
+
+    // chains copying swapchain image image_num into last_frame onto future,
+    // if frames are being kept
+    fn then_copy_frame<F: GpuFuture + 'static>(
+        &mut self,
+        queue: Arc<Queue>,
+        future: F,
+        image_num: usize,
+    ) -> Box<dyn GpuFuture> {
+        if !self.keep_last_frame {
+            return Box::new(future);
+        }
+
+        let dimensions = self.swapchain.dimensions();
+        let format = self.swapchain.format();
+        let num_bytes = dimensions[0] as usize * dimensions[1] as usize * format.size().unwrap();
+
+        // a new buffer every frame, the last one might still be locked by a
+        // frame in flight
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::all(),
+            (0..num_bytes).map(|_| 0u8),
+        )
+        .unwrap();
+        let cmd_buf =
+            AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), queue.family())
+                .unwrap()
+                .copy_image_to_buffer(self.images[image_num].clone(), buffer.clone())
+                .expect("Couldn't record copy of presented frame")
+                .build()
+                .unwrap();
+
+        self.last_frame = Some(FrameCopy {
+            buffer,
+            dimensions,
+            format,
+        });
+
+        Box::new(
+            future
+                .then_execute(queue, cmd_buf)
+                .expect("Couldn't submit copy of presented frame"),
+        )
     }
 
     pub fn get_surface(&self) -> Arc<Surface<Window>> {
         self.surface.clone()
     }
+
+    pub fn get_format(&self) -> Format {
+        self.swapchain.format()
+    }
 }
 
-fn create_swapchain_and_images_from_scratch(
-    device: Arc<Device>,
-    queue: Arc<Queue>,
+// old is the swapchain being replaced, if there is one. its images can't be
+// acquired anymore afterwards.
+fn create_swapchain_and_images(
     surface: Arc<Surface<Window>>,
-    caps: Capabilities,
-) -> SwapchainAndImages {
-    let image_format = caps.supported_formats[0].0;
-    // TODO: try using other get_dimensions implementation
-    let dimensions = caps.current_extent.unwrap_or([1024, 768]);
+    queue: Arc<Queue>,
+    caps: &Capabilities,
+    config: &SwapchainConfig,
+    dimensions: [u32; 2],
+    old: Option<Arc<Swapchain<Window>>>,
+) -> Result<SwapchainAndImages, SwapchainCreationError> {
+    // pick_config filled all of these in
+    let format = config.format.unwrap();
+    let image_count = config.image_count.unwrap();
+    let present_mode = config.present_mode.unwrap();
+    let alpha = caps.supported_composite_alpha.iter().next().unwrap();
 
-    match Swapchain::new(
-        device,
+    Swapchain::new(
+        queue.device().clone(),
         surface,
-        caps.min_image_count,
-        image_format,
+        image_count,
+        format,
         dimensions,
         1,
         caps.supported_usage_flags,
         &queue,
         SurfaceTransform::Identity,
-        caps.supported_composite_alpha.iter().next().unwrap(),
-        PresentMode::Immediate,
+        alpha,
+        present_mode,
         true,
-        None,
-    ) {
-        Ok(r) => r,
-        // TODO: add dimensions to err msg
-        Err(SwapchainCreationError::UnsupportedDimensions) => panic!("SwapchainCreationError::UnsupportedDimensions when creating initial swapchain. Should never happen."),
-        Err(err) => panic!("{:?}", err),
+        old.as_ref(),
+    )
+}
+
+// config with everything picked, falling back to what the surface supports
+fn pick_config(caps: &Capabilities, config: &SwapchainConfig) -> SwapchainConfig {
+    let (format, color_space) = pick_format(caps, config);
+
+    SwapchainConfig {
+        format: Some(format),
+        image_count: Some(pick_image_count(caps, config)),
+        color_space: Some(color_space),
+        srgb: config.srgb,
+        present_mode: Some(pick_present_mode(caps, config.present_mode)),
     }
 }
 
+pub fn is_srgb_format(format: Format) -> bool {
+    match format {
+        Format::B8G8R8A8Srgb
+        | Format::R8G8B8A8Srgb
+        | Format::A8B8G8R8SrgbPack32
+        | Format::B8G8R8Srgb
+        | Format::R8G8B8Srgb => true,
+        _ => false,
+    }
+}
+
+fn pick_format(caps: &Capabilities, config: &SwapchainConfig) -> (Format, ColorSpace) {
+    // Swapchain::new doesn't take a color space, see SwapchainConfig
+    let creatable = |color_space: ColorSpace| color_space == ColorSpace::SrgbNonLinear;
+    let matches = |format: Format, color_space: ColorSpace| {
+        config.format.map_or(true, |wanted| wanted == format)
+            && config.color_space.map_or(true, |wanted| wanted == color_space)
+            && creatable(color_space)
+    };
+
+    let preferred = if config.srgb && config.format.is_none() {
+        caps.supported_formats
+            .iter()
+            .find(|(format, color_space)| is_srgb_format(*format) && matches(*format, *color_space))
+    } else {
+        None
+    };
+    if config.srgb && config.format.is_none() && preferred.is_none() {
+        println!("No sRGB swapchain format is supported, output will need gamma correcting in a shader");
+    }
+
+    let found = preferred.or_else(|| {
+        caps.supported_formats
+            .iter()
+            .find(|(format, color_space)| matches(*format, *color_space))
+    });
+
+    match found {
+        Some(&pair) => pair,
+        None => {
+            let fallback = caps
+                .supported_formats
+                .iter()
+                .cloned()
+                .find(|&(_, color_space)| creatable(color_space))
+                .unwrap_or(caps.supported_formats[0]);
+            println!(
+                "Swapchain format {:?} with color space {:?} isn't supported, using {:?} instead",
+                config.format, config.color_space, fallback
+            );
+            fallback
+        }
+    }
+}
+
+fn pick_image_count(caps: &Capabilities, config: &SwapchainConfig) -> u32 {
+    let wanted = match config.image_count {
+        Some(count) => count,
+        None => return caps.min_image_count,
+    };
+
+    // max_image_count is None if there's no limit
+    let max = caps.max_image_count.unwrap_or(std::u32::MAX);
+    let count = wanted.max(caps.min_image_count).min(max);
+    if count != wanted {
+        println!(
+            "Swapchain image count {} isn't supported, using {} instead",
+            wanted, count
+        );
+    }
+
+    count
+}
+
+fn pick_present_mode(caps: &Capabilities, wanted: Option<PresentMode>) -> PresentMode {
+    // what it's always been, and only warn about modes that were asked for
+    let mode = wanted.unwrap_or(PresentMode::Immediate);
+    if caps.present_modes.supports(mode) {
+        return mode;
+    }
+
+    // the other uncapped mode for uncapped ones, otherwise Fifo, which every
+    // surface has to support
+    let fallbacks = match mode {
+        PresentMode::Immediate => vec![PresentMode::Mailbox, PresentMode::Fifo],
+        PresentMode::Mailbox => vec![PresentMode::Immediate, PresentMode::Fifo],
+        _ => vec![PresentMode::Fifo],
+    };
+    let fallback = fallbacks
+        .into_iter()
+        .find(|&fallback| caps.present_modes.supports(fallback))
+        .unwrap_or(PresentMode::Fifo);
+    if wanted.is_some() {
+        println!(
+            "Present mode {:?} isn't supported, using {:?} instead",
+            mode, fallback
+        );
+    }
+
+    fallback
+}
+
 type SwapchainAndImages = (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>);
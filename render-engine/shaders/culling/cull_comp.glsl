@@ -0,0 +1,135 @@
+#version 450
+
+// frustum culling for culling.rs: every invocation tests one instance's
+// bounding sphere and appends the ones that might be visible to visible,
+// counting them in the draw command's instance_count. with a depth pyramid
+// (hiz.rs) the ones that pass are also tested against that.
+
+layout(local_size_x = 64) in;
+
+struct Instance {
+  mat4 model;
+  // xyz is the center of the bounding sphere in model space, w its radius
+  vec4 bounds;
+};
+
+// same layout as VkDrawIndexedIndirectCommand
+struct DrawCommand {
+  uint index_count;
+  uint instance_count;
+  uint first_index;
+  int vertex_offset;
+  uint first_instance;
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer Instances {
+  Instance instances[];
+};
+
+layout(std430, set = 0, binding = 1) writeonly buffer Visible {
+  uint visible[];
+};
+
+layout(std430, set = 0, binding = 2) buffer Commands {
+  DrawCommand command;
+};
+
+layout(push_constant) uniform Frustum {
+  // a, b, c, d with the inside where dot(abc, p) + d >= 0, normalized
+  vec4 planes[6];
+  uint instance_count;
+} frustum;
+
+// every level side by side, r is the nearest depth and g the farthest
+layout(set = 0, binding = 3) uniform sampler2D pyramid;
+
+layout(set = 0, binding = 4) uniform Occlusion {
+  mat4 view_proj;
+  // xy is where the level is in pyramid, zw its size
+  vec4 levels[16];
+  // size of the depth image the pyramid was built from
+  vec2 depth_size;
+  // 0 turns occlusion culling off
+  int num_levels;
+  int reverse_depth;
+} occlusion;
+
+// whether the box around the sphere is behind what's in the pyramid
+bool occluded(vec3 center, float radius) {
+  if (occlusion.num_levels == 0) {
+    return false;
+  }
+  bool reverse = occlusion.reverse_depth != 0;
+
+  vec2 rect_min = vec2(1.0);
+  vec2 rect_max = vec2(-1.0);
+  float nearest = reverse ? 0.0 : 1.0;
+  for (int i = 0; i < 8; i++) {
+    vec3 corner = center + radius * vec3((i & 1) != 0 ? 1.0 : -1.0,
+                                         (i & 2) != 0 ? 1.0 : -1.0,
+                                         (i & 4) != 0 ? 1.0 : -1.0);
+    vec4 clip = occlusion.view_proj * vec4(corner, 1.0);
+    // crosses the camera plane, so it's right in front of the camera and
+    // the projection doesn't mean much
+    if (clip.w <= 0.0) {
+      return false;
+    }
+    vec3 ndc = clip.xyz / clip.w;
+    rect_min = min(rect_min, ndc.xy);
+    rect_max = max(rect_max, ndc.xy);
+    nearest = reverse ? max(nearest, ndc.z) : min(nearest, ndc.z);
+  }
+
+  vec2 px_min = clamp(rect_min * 0.5 + 0.5, 0.0, 1.0) * occlusion.depth_size;
+  vec2 px_max = clamp(rect_max * 0.5 + 0.5, 0.0, 1.0) * occlusion.depth_size;
+  vec2 extent = px_max - px_min;
+
+  // a texel of level k covers 2^(k+1) pixels, so this is the first level
+  // where the rect covers at most 2x2 texels
+  int level = int(ceil(log2(max(max(extent.x, extent.y), 1.0)))) - 1;
+  level = clamp(level, 0, occlusion.num_levels - 1);
+
+  ivec2 offset = ivec2(occlusion.levels[level].xy);
+  ivec2 size = ivec2(occlusion.levels[level].zw);
+  // the last row and column of a level also cover the leftover pixels, so
+  // clamping is right for any size
+  ivec2 texel_min = min(ivec2(px_min) >> (level + 1), size - 1);
+  ivec2 texel_max = min(ivec2(px_max) >> (level + 1), size - 1);
+
+  float farthest = reverse ? 1.0 : 0.0;
+  for (int y = texel_min.y; y <= texel_max.y; y++) {
+    for (int x = texel_min.x; x <= texel_max.x; x++) {
+      vec2 depth = texelFetch(pyramid, offset + ivec2(x, y), 0).rg;
+      farthest = reverse ? min(farthest, depth.r) : max(farthest, depth.g);
+    }
+  }
+
+  return reverse ? nearest < farthest : nearest > farthest;
+}
+
+void main() {
+  uint idx = gl_GlobalInvocationID.x;
+  if (idx >= frustum.instance_count) {
+    return;
+  }
+
+  Instance instance = instances[idx];
+  vec3 center = (instance.model * vec4(instance.bounds.xyz, 1.0)).xyz;
+  // non-uniform scale stretches the sphere, so take the biggest axis
+  float scale = max(length(instance.model[0].xyz),
+                    max(length(instance.model[1].xyz), length(instance.model[2].xyz)));
+  float radius = instance.bounds.w * scale;
+
+  for (int i = 0; i < 6; i++) {
+    if (dot(frustum.planes[i].xyz, center) + frustum.planes[i].w < -radius) {
+      return;
+    }
+  }
+
+  if (occluded(center, radius)) {
+    return;
+  }
+
+  uint slot = atomicAdd(command.instance_count, 1);
+  visible[slot] = idx;
+}
@@ -0,0 +1,18 @@
+#version 450
+
+// System's debug lines, see System::debug_line
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+
+layout(location = 0) out vec3 v_color;
+
+layout(set = 0, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+} camera;
+
+void main() {
+  v_color = color;
+  gl_Position = camera.proj * camera.view * vec4(position, 1.0);
+}
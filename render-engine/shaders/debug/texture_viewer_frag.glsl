@@ -0,0 +1,28 @@
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+// the tag being viewed, the viewer's pass needs it
+layout(set = 0, binding = 0) uniform sampler2D image;
+
+layout(set = 1, binding = 0) uniform Params {
+  // all 0 shows rgb, otherwise picks the channel to show in gray
+  vec4 channel;
+  // x is shown as black, y as white
+  vec4 range;
+} params;
+
+void main() {
+  vec4 texel = texture(image, v_uv);
+
+  vec3 color;
+  if (params.channel == vec4(0.0)) {
+    color = texel.rgb;
+  } else {
+    color = vec3(dot(texel, params.channel));
+  }
+
+  color = (color - params.range.x) / (params.range.y - params.range.x);
+  f_color = vec4(clamp(color, 0.0, 1.0), 1.0);
+}
@@ -0,0 +1,57 @@
+#version 450
+
+// projects decal_texture onto whatever's in the depth buffer inside the box,
+// along the box's z axis. see decal.rs.
+
+layout(location = 0) in vec3 v_local;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D depth_map;
+
+layout(set = 1, binding = 0) uniform Decal {
+  mat4 model;
+  mat4 inv_model;
+} decal;
+
+layout(set = 2, binding = 0) uniform sampler2D decal_texture;
+
+layout(set = 3, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+} camera;
+
+void main() {
+  // only draw the far side of the box, so every pixel gets the decal once
+  // even if the camera is inside it. the face normal is whichever axis the
+  // local position is on the edge of, which works regardless of winding.
+  vec3 a = abs(v_local);
+  vec3 n_local;
+  if (a.x > a.y && a.x > a.z) {
+    n_local = vec3(sign(v_local.x), 0.0, 0.0);
+  } else if (a.y > a.z) {
+    n_local = vec3(0.0, sign(v_local.y), 0.0);
+  } else {
+    n_local = vec3(0.0, 0.0, sign(v_local.z));
+  }
+  vec3 n_world = transpose(mat3(decal.inv_model)) * n_local;
+  vec3 frag_world = (decal.model * vec4(v_local, 1.0)).xyz;
+  vec3 cam_pos = inverse(camera.view)[3].xyz;
+  if (dot(n_world, frag_world - cam_pos) < 0.0) {
+    discard;
+  }
+
+  // reconstruct the world position of the surface behind this pixel
+  vec2 uv = gl_FragCoord.xy / vec2(textureSize(depth_map, 0));
+  float depth = texture(depth_map, uv).r;
+  vec4 ndc = vec4(uv * 2.0 - 1.0, depth, 1.0);
+  vec4 world = inverse(camera.proj * camera.view) * ndc;
+  world /= world.w;
+
+  // and skip it if it's outside the box
+  vec3 local = (decal.inv_model * world).xyz;
+  if (any(greaterThan(abs(local), vec3(1.0)))) {
+    discard;
+  }
+
+  f_color = texture(decal_texture, local.xy * 0.5 + 0.5);
+}
@@ -0,0 +1,19 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 0) out vec3 v_local;
+
+layout(set = 1, binding = 0) uniform Decal {
+  mat4 model;
+  mat4 inv_model;
+} decal;
+
+layout(set = 3, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+} camera;
+
+void main() {
+  v_local = position;
+  gl_Position = camera.proj * camera.view * decal.model * vec4(position, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// for System::draw_fullscreen, v_uv is 0, 0 at the top left and 1, 1 at the
+// bottom right
+
+layout(location = 0) in vec3 position;
+layout(location = 0) out vec2 v_uv;
+
+void main() {
+  v_uv = position.xy * 0.5 + 0.5;
+  gl_Position = vec4(position.xy, 0.0, 1.0);
+}
@@ -0,0 +1,57 @@
+#version 450
+
+// builds one level of the depth pyramid in hiz.rs from the level before it,
+// or from the depth image for the first one. r is the nearest depth (min) of
+// the texels below, g the farthest (max).
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform sampler2D depth;
+// every level side by side, see hiz.rs
+layout(set = 0, binding = 1, rgba32f) uniform image2D pyramid;
+
+layout(push_constant) uniform Level {
+  ivec2 src_offset;
+  ivec2 src_size;
+  ivec2 dst_offset;
+  ivec2 dst_size;
+  // whether to read the depth image instead of the pyramid
+  int from_depth;
+} level;
+
+vec2 load(ivec2 p) {
+  if (level.from_depth != 0) {
+    return vec2(texelFetch(depth, p, 0).r);
+  }
+  return imageLoad(pyramid, level.src_offset + p).rg;
+}
+
+void main() {
+  ivec2 dst = ivec2(gl_GlobalInvocationID.xy);
+  if (any(greaterThanEqual(dst, level.dst_size))) {
+    return;
+  }
+
+  // each texel covers 2x2 below it. sizes are rounded down, so with an odd
+  // size the last row or column also takes the one left over, otherwise it
+  // wouldn't be in the pyramid at all.
+  ivec2 start = dst * 2;
+  ivec2 end = start + 1;
+  if (dst.x == level.dst_size.x - 1) {
+    end.x = level.src_size.x - 1;
+  }
+  if (dst.y == level.dst_size.y - 1) {
+    end.y = level.src_size.y - 1;
+  }
+  end = min(end, level.src_size - 1);
+
+  vec2 result = load(start);
+  for (int y = start.y; y <= end.y; y++) {
+    for (int x = start.x; x <= end.x; x++) {
+      vec2 v = load(ivec2(x, y));
+      result = vec2(min(result.x, v.x), max(result.y, v.y));
+    }
+  }
+
+  imageStore(pyramid, level.dst_offset + dst, vec4(result, 0.0, 0.0));
+}
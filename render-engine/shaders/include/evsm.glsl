@@ -0,0 +1,71 @@
+// exponential variance shadow maps, see shadow.rs for the CPU side. include
+// with
+//
+// #include "evsm.glsl"
+//
+// the shadow map stores evsm_warp of the caster's depth instead of the depth
+// itself, in a 4 channel float image (render_passes::evsm_shadow). the caster
+// fragment shader is just
+//
+// layout(location = 0) out vec4 moments;
+// ...
+// moments = evsm_warp(gl_FragCoord.z, vec2(evsm.positive_exponent, evsm.negative_exponent));
+//
+// and the receiver samples the map with a normal linear sampler (not a
+// comparison one), so it can be filtered, mipmapped and blurred like a texture:
+//
+// vec2 exponents = vec2(evsm.positive_exponent, evsm.negative_exponent);
+// float lit = evsm_shadow(shadow_map, coord, exponents, evsm.light_bleed_reduction,
+//                         evsm.variance_bias);
+//
+// with the params in a uniform that's laid out like EvsmParams:
+//
+// layout(set = 1, binding = 0) uniform Evsm {
+//   float positive_exponent;
+//   float negative_exponent;
+//   float light_bleed_reduction;
+//   float variance_bias;
+// } evsm;
+
+// depth (0-1) remapped to -1-1 and warped by both exponents. xy are the
+// positive warp and its square, zw the negative ones.
+vec4 evsm_warp(float depth, vec2 exponents) {
+  float d = depth * 2.0 - 1.0;
+  float pos = exp(exponents.x * d);
+  float neg = -exp(-exponents.y * d);
+  return vec4(pos, pos * pos, neg, neg * neg);
+}
+
+// upper bound on how much of the filtered area is in front of depth, from the
+// mean and mean of squares. light_bleed_reduction cuts off the lowest
+// probabilities, which is where bleeding between overlapping casters shows up.
+float evsm_chebyshev(vec2 moments, float depth, float min_variance, float light_bleed_reduction) {
+  if (depth <= moments.x) {
+    return 1.0;
+  }
+
+  float variance = max(moments.y - moments.x * moments.x, min_variance);
+  float d = depth - moments.x;
+  float p_max = variance / (variance + d * d);
+
+  return clamp((p_max - light_bleed_reduction) / (1.0 - light_bleed_reduction), 0.0, 1.0);
+}
+
+// coord is (uv, depth to compare against) like shadow_filtering.glsl, returns
+// how lit the point is, 0 to 1. each warp gives its own bound and both only
+// ever overestimate, so the smaller one is used.
+float evsm_shadow(sampler2D map, vec3 coord, vec2 exponents, float light_bleed_reduction,
+                  float variance_bias) {
+  vec4 moments = texture(map, coord.xy);
+  vec4 warped = evsm_warp(coord.z, exponents);
+
+  // the bias is in depth units, the derivative of the warp scales it to warped
+  // ones
+  vec2 depth_scale = variance_bias * exponents * warped.xz;
+  vec2 min_variance = depth_scale * depth_scale;
+
+  float pos = evsm_chebyshev(moments.xy, warped.x, min_variance.x, light_bleed_reduction);
+  float neg = evsm_chebyshev(moments.zw, warped.z, min_variance.y, light_bleed_reduction);
+
+  return min(pos, neg);
+}
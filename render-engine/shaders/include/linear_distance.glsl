@@ -0,0 +1,40 @@
+// point light shadows that store linear distance to the light instead of
+// post-projection depth, for render_passes::linear_distance. depth from a
+// perspective projection bunches its precision up near the near plane, which
+// with a far plane of a couple hundred units leaves little for anything more
+// than a few units from the light. distance / far is spread evenly. include
+// with
+//
+// #include "linear_distance.glsl"
+//
+// the caster fragment shader writes
+//
+// layout(location = 0) out float distance;
+// ...
+// distance = linear_distance(world_pos, light.position, far);
+//
+// and the receiver, with the map bound as a plain sampler2D:
+//
+// float lit = linear_distance_shadow(shadow_map, uv, linear_distance(world_pos, light.position, far) - bias / far);
+
+// 0 at the light, 1 at far
+float linear_distance(vec3 world_pos, vec3 light_pos, float far) {
+  return length(world_pos - light_pos) / far;
+}
+
+// how lit a point dist (already divided by far) from the light is, 0 to 1.
+// compares against the 4 texels a linear sampler would read and blends the
+// results with the same weights, so it's the 2x2 PCF a linear comparison
+// sampler gives depth images. textureGather doesn't filter, so this works
+// whether or not the format supports linear filtering.
+float linear_distance_shadow(sampler2D map, vec2 uv, float dist) {
+  vec2 weights = fract(uv * vec2(textureSize(map, 0)) - 0.5);
+
+  // the texels at (0, 1), (1, 1), (1, 0), (0, 0) of the 2x2 block
+  vec4 occluders = textureGather(map, uv, 0);
+  vec4 lit = step(vec4(dist), occluders);
+
+  float top = mix(lit.w, lit.z, weights.x);
+  float bottom = mix(lit.x, lit.y, weights.x);
+  return mix(top, bottom, weights.y);
+}
@@ -0,0 +1,25 @@
+// morph targets for vertex shaders, see morph.rs. include with
+//
+// #include "morph.glsl"
+//
+// vulkano matches vertex inputs to MorphVertex's fields by name, so declare
+// them like this:
+//
+// layout(location = 0) in vec3 position;
+// layout(location = 1) in vec3 normal;
+// layout(location = 2) in vec3 delta_0;
+// layout(location = 3) in vec3 delta_1;
+// layout(location = 4) in vec3 delta_2;
+// layout(location = 5) in vec3 delta_3;
+//
+// and the weights (MorphWeights) wherever they fit in your layout:
+//
+// layout(set = 1, binding = 0) uniform MorphWeights {
+//   vec4 weights;
+// } morph;
+
+// the base position plus each target's offset scaled by its weight. weights
+// don't have to add up to 1, two targets at 1 apply both fully.
+vec3 morph_position(vec3 base, vec3 d0, vec3 d1, vec3 d2, vec3 d3, vec4 weights) {
+  return base + d0 * weights.x + d1 * weights.y + d2 * weights.z + d3 * weights.w;
+}
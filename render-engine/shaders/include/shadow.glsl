@@ -0,0 +1,113 @@
+// shadow lookups for directional and spot lights that work out their own
+// bias. include with
+//
+// #include "shadow.glsl"
+//
+// the map is a depth image rendered with light_view_proj, bound with a
+// comparison sampler (SamplerSpec::shadow()):
+//
+// layout(set = 0, binding = 0) uniform sampler2DShadow shadow_map;
+// ...
+// float lit = shadow_factor(shadow_map, v_world_pos, light.view_proj, v_normal);
+//
+// the bias is in shadow map texels instead of depth units, so it doesn't need
+// retuning when the light's range or the map's size change:
+// - the lookup moves out along the normal, more the further the surface turns
+//   away from the light (normal offset)
+// - the depth compared against is then moved towards the light, more the more
+//   the surface slopes in the map (slope scaled)
+// both are measured from the matrix, which works for ortho and perspective
+// projections. depth is assumed to grow away from the light, with reverse
+// depth flip the compare op (GreaterOrEqual) and SHADOW_REVERSE_DEPTH.
+//
+// for softer edges pass shadow_coord to shadow_filtering.glsl's shadow_pcf or
+// shadow_poisson instead of using shadow_factor.
+//
+// the amounts can be changed by defining these before the include, but the
+// defaults should be fine almost everywhere:
+
+// texels to move along the normal at grazing angles
+#ifndef SHADOW_NORMAL_OFFSET
+#define SHADOW_NORMAL_OFFSET 1.0
+#endif
+
+// texels towards the light, always
+#ifndef SHADOW_CONSTANT_BIAS
+#define SHADOW_CONSTANT_BIAS 0.5
+#endif
+
+// texels towards the light per unit of tan(angle to the light)
+#ifndef SHADOW_SLOPE_BIAS
+#define SHADOW_SLOPE_BIAS 1.0
+#endif
+
+// tan blows up at grazing angles, which would push the depth right through
+// whatever is casting
+#ifndef SHADOW_MAX_SLOPE
+#define SHADOW_MAX_SLOPE 10.0
+#endif
+
+// 4d cross product, the vector orthogonal to a, b and c
+vec4 shadow_cross4(vec4 a, vec4 b, vec4 c) {
+  return vec4(determinant(mat3(a.yzw, b.yzw, c.yzw)), -determinant(mat3(a.xzw, b.xzw, c.xzw)),
+              determinant(mat3(a.xyw, b.xyw, c.xyw)), -determinant(mat3(a.xyz, b.xyz, c.xyz)));
+}
+
+// normalized direction from world_pos to the light. the light is the point
+// where clip x, y and w are all 0, which for an ortho projection is at
+// infinity (w = 0) and then it's a direction.
+vec3 shadow_to_light(vec3 world_pos, mat4 light_view_proj) {
+  mat4 rows = transpose(light_view_proj);
+  vec4 light = shadow_cross4(rows[0], rows[1], rows[3]);
+
+  if (abs(light.w) > 1e-6) {
+    return normalize(light.xyz / light.w - world_pos);
+  }
+
+  // depth grows away from the light, so the light is where it shrinks
+  vec3 dir = normalize(light.xyz);
+#ifdef SHADOW_REVERSE_DEPTH
+  return dot(rows[2].xyz, dir) < 0.0 ? -dir : dir;
+#else
+  return dot(rows[2].xyz, dir) > 0.0 ? -dir : dir;
+#endif
+}
+
+// (uv, depth to compare against) for world_pos, with the bias applied.
+// map_size is textureSize of the shadow map.
+vec3 shadow_coord(vec3 world_pos, mat4 light_view_proj, vec3 normal, vec2 map_size) {
+  vec3 n = normalize(normal);
+  vec3 l = shadow_to_light(world_pos, light_view_proj);
+
+  float cos_angle = clamp(dot(n, l), 0.0, 1.0);
+  float sin_angle = sqrt(1.0 - cos_angle * cos_angle);
+  float tan_angle = min(sin_angle / max(cos_angle, 1e-4), SHADOW_MAX_SLOPE);
+
+  // how big a texel is in world units here, x of the clip space changes by
+  // length(row 0) per world unit and a texel is 2 / size of ndc
+  vec4 clip = light_view_proj * vec4(world_pos, 1.0);
+  vec3 row_x = vec3(light_view_proj[0][0], light_view_proj[1][0], light_view_proj[2][0]);
+  float texel = 2.0 * abs(clip.w) / (max(map_size.x, map_size.y) * length(row_x));
+
+  vec3 offset_pos = world_pos + n * texel * SHADOW_NORMAL_OFFSET * sin_angle;
+  vec3 biased_pos =
+      offset_pos + l * texel * (SHADOW_CONSTANT_BIAS + SHADOW_SLOPE_BIAS * tan_angle);
+
+  vec4 offset_clip = light_view_proj * vec4(offset_pos, 1.0);
+  vec4 biased_clip = light_view_proj * vec4(biased_pos, 1.0);
+  vec2 uv = offset_clip.xy / offset_clip.w * 0.5 + 0.5;
+
+  return vec3(uv, biased_clip.z / biased_clip.w);
+}
+
+// how lit world_pos is, 0 to 1, with a single comparison (2x2 PCF with a
+// linear sampler). anything outside the light's frustum is lit.
+float shadow_factor(sampler2DShadow map, vec3 world_pos, mat4 light_view_proj, vec3 normal) {
+  vec3 coord = shadow_coord(world_pos, light_view_proj, normal, vec2(textureSize(map, 0)));
+
+  if (coord.z < 0.0 || coord.z > 1.0) {
+    return 1.0;
+  }
+
+  return texture(map, coord);
+}
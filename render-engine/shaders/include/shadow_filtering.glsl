@@ -0,0 +1,61 @@
+// soft shadow filtering for shadow maps bound with a comparison sampler (see
+// SamplerSpec::compare). include with
+//
+// #include "shadow_filtering.glsl"
+//
+// coord is (uv, depth to compare against) for all of these, and they return
+// how lit the point is, 0 to 1. fragment shaders only, poisson uses
+// gl_FragCoord.
+//
+// the poisson disk comes from the CPU, see shadow.rs. declare its uniform
+// wherever it fits in your layout:
+//
+// layout(set = 1, binding = 0) uniform ShadowFilter {
+//   vec4 poisson_disk[16];
+//   int num_samples;
+//   float radius;
+//   int pcf_size;
+// } shadow_filter;
+
+// averages a pcf_size x pcf_size grid of comparisons around coord, one texel
+// apart. with a linear comparison sampler each of those is already 2x2 PCF,
+// so 3 looks about like a 4x4 kernel.
+float shadow_pcf(sampler2DShadow map, vec3 coord, int pcf_size) {
+  vec2 texel = 1.0 / vec2(textureSize(map, 0));
+  float half_size = float(pcf_size - 1) / 2.0;
+
+  float lit = 0.0;
+  for (int x = 0; x < pcf_size; x++) {
+    for (int y = 0; y < pcf_size; y++) {
+      vec2 offset = (vec2(x, y) - half_size) * texel;
+      lit += texture(map, vec3(coord.xy + offset, coord.z));
+    }
+  }
+
+  return lit / float(pcf_size * pcf_size);
+}
+
+// per-pixel noise in 0-1 from Jorge Jimenez's "interleaved gradient noise",
+// used to rotate the disk so the sample pattern turns into noise instead of
+// banding
+float shadow_ign(vec2 pixel) {
+  return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+// samples num_samples points of the poisson disk, scaled to radius texels and
+// rotated randomly per pixel. softer than PCF for the same number of taps.
+float shadow_poisson(sampler2DShadow map, vec3 coord, vec4 disk[16], int num_samples, float radius) {
+  vec2 texel = 1.0 / vec2(textureSize(map, 0));
+
+  float angle = shadow_ign(gl_FragCoord.xy) * 6.2831853;
+  mat2 rotation = mat2(cos(angle), sin(angle), -sin(angle), cos(angle));
+
+  int count = min(num_samples, 16);
+  float lit = 0.0;
+  for (int i = 0; i < count; i++) {
+    vec2 offset = rotation * disk[i].xy * radius * texel;
+    lit += texture(map, vec3(coord.xy + offset, coord.z));
+  }
+
+  return lit / float(max(count, 1));
+}
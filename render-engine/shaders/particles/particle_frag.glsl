@@ -0,0 +1,43 @@
+#version 450
+
+// a soft round blob, faded out where it gets close to the scene behind it
+
+layout(location = 0) in vec2 v_corner;
+layout(location = 1) in vec4 v_color;
+layout(location = 2) in vec3 v_world;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D depth_map;
+
+layout(set = 2, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+} camera;
+
+// distance over which particles fade out in front of the scene, in world
+// units
+const float SOFTNESS = 0.5;
+
+void main() {
+  float edge = 1.0 - smoothstep(0.5, 1.0, length(v_corner));
+
+  // reconstruct the world position of the surface behind this pixel, like
+  // decal_frag.glsl
+  vec2 uv = gl_FragCoord.xy / vec2(textureSize(depth_map, 0));
+  float depth = texture(depth_map, uv).r;
+  vec4 scene = inverse(camera.proj * camera.view) * vec4(uv * 2.0 - 1.0, depth, 1.0);
+  scene /= scene.w;
+
+  // the overlay pass has no depth buffer, so this is the depth test too
+  vec3 cam_pos = inverse(camera.view)[3].xyz;
+  float behind = length(scene.xyz - cam_pos) - length(v_world - cam_pos);
+  float soft = clamp(behind / SOFTNESS, 0.0, 1.0);
+
+  float alpha = v_color.a * edge * soft;
+  if (alpha <= 0.0) {
+    discard;
+  }
+
+  f_color = vec4(v_color.rgb, alpha);
+}
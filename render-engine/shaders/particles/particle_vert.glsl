@@ -0,0 +1,33 @@
+#version 450
+
+// one quad per particle, turned to face the camera. see particles.rs.
+
+layout(location = 0) in vec3 position;
+
+layout(location = 0) out vec2 v_corner;
+layout(location = 1) out vec4 v_color;
+layout(location = 2) out vec3 v_world;
+
+layout(set = 1, binding = 0) uniform Particles {
+  // xyz is the position, w the size
+  vec4 pos_size[512];
+  vec4 color[512];
+} particles;
+
+layout(set = 2, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+} camera;
+
+void main() {
+  vec4 pos_size = particles.pos_size[gl_InstanceIndex];
+
+  // the view matrix's first two rows are the camera's right and up
+  vec3 right = vec3(camera.view[0][0], camera.view[1][0], camera.view[2][0]);
+  vec3 up = vec3(camera.view[0][1], camera.view[1][1], camera.view[2][1]);
+
+  v_corner = position.xy;
+  v_color = particles.color[gl_InstanceIndex];
+  v_world = pos_size.xyz + (right * position.x + up * position.y) * pos_size.w;
+  gl_Position = camera.proj * camera.view * vec4(v_world, 1.0);
+}
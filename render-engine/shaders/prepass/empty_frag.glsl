@@ -0,0 +1,7 @@
+#version 450
+
+// used for the depth-only draws of System::set_depth_prepass, color writes
+// are masked off anyway
+
+void main() {
+}
@@ -0,0 +1,14 @@
+#version 450
+
+// see resolve.rs. keeps the nearest sample, so edges belong to whatever is in
+// front.
+layout(set = 0, binding = 0) uniform sampler2DMS depth_ms;
+
+void main() {
+  ivec2 coord = ivec2(gl_FragCoord.xy);
+  float depth = 1.0;
+  for (int i = 0; i < textureSamples(depth_ms); i++) {
+    depth = min(depth, texelFetch(depth_ms, coord, i).r);
+  }
+  gl_FragDepth = depth;
+}
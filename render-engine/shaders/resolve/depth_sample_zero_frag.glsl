@@ -0,0 +1,10 @@
+#version 450
+
+// see resolve.rs. takes the first sample, which is what a resolve would do
+// if it wasn't told anything else.
+layout(set = 0, binding = 0) uniform sampler2DMS depth_ms;
+
+void main() {
+  ivec2 coord = ivec2(gl_FragCoord.xy);
+  gl_FragDepth = texelFetch(depth_ms, coord, 0).r;
+}
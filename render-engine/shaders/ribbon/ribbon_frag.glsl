@@ -0,0 +1,36 @@
+#version 450
+
+layout(location = 0) in float v_along;
+layout(location = 1) in vec3 v_world;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D depth_map;
+
+layout(set = 1, binding = 0) uniform Ribbon {
+  vec4 color;
+  // x at the head, y at the end of the tail
+  vec4 fade;
+} ribbon;
+
+layout(set = 2, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+} camera;
+
+void main() {
+  // the pass has no depth buffer, so test against the scene by hand like
+  // particle_frag.glsl
+  vec2 uv = gl_FragCoord.xy / vec2(textureSize(depth_map, 0));
+  float depth = texture(depth_map, uv).r;
+  vec4 scene = inverse(camera.proj * camera.view) * vec4(uv * 2.0 - 1.0, depth, 1.0);
+  scene /= scene.w;
+
+  vec3 cam_pos = inverse(camera.view)[3].xyz;
+  if (length(v_world - cam_pos) > length(scene.xyz - cam_pos)) {
+    discard;
+  }
+
+  float alpha = ribbon.color.a * mix(ribbon.fade.x, ribbon.fade.y, v_along);
+  f_color = vec4(ribbon.color.rgb, alpha);
+}
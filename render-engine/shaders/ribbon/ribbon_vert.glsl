@@ -0,0 +1,20 @@
+#version 450
+
+// the strip is already built facing the camera on the CPU, see ribbon.rs
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in float along;
+
+layout(location = 0) out float v_along;
+layout(location = 1) out vec3 v_world;
+
+layout(set = 2, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 proj;
+} camera;
+
+void main() {
+  v_along = along;
+  v_world = position;
+  gl_Position = camera.proj * camera.view * vec4(position, 1.0);
+}
@@ -0,0 +1,19 @@
+#version 450
+
+// fills an EVSM shadow map with the moments of the far plane, see
+// shadow::evsm_clear_object
+
+#include "evsm.glsl"
+
+layout(location = 0) out vec4 moments;
+
+layout(set = 0, binding = 0) uniform Evsm {
+  float positive_exponent;
+  float negative_exponent;
+  float light_bleed_reduction;
+  float variance_bias;
+} evsm;
+
+void main() {
+  moments = evsm_warp(1.0, vec2(evsm.positive_exponent, evsm.negative_exponent));
+}
@@ -0,0 +1,196 @@
+/*
+What the device can actually do, so callers can pick a fallback up front
+instead of finding out from a validation error or a panic in pipeline
+creation.
+
+let caps = Capabilities::new(queue.device().clone());
+let texture = if caps.supports_bc7() {
+    load_bc7(...)
+} else {
+    load_rgba8(...)
+};
+
+Features are the ones the device was created with, which for devices from
+Window are everything the physical device supports. Format support is for
+optimal tiling, which is what every image the engine creates uses.
+
+vulkano 0.14 doesn't expose vkGetPhysicalDeviceFormatProperties, so format
+support is found by creating a 1x1 image (without memory) for each usage and
+seeing whether vulkano rejects it. vulkano checks the usage against the
+format's features before creating anything, so this is cheap.
+ */
+
+use vulkano::device::{Device, Features};
+use vulkano::format::Format;
+use vulkano::image::sys::UnsafeImage;
+use vulkano::image::{ImageDimensions, ImageUsage};
+use vulkano::sync::Sharing;
+
+use std::sync::Arc;
+
+pub struct Capabilities {
+    device: Arc<Device>,
+}
+
+// what a format can be used for with optimal tiling
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FormatSupport {
+    pub sampled: bool,
+    pub filter_linear: bool,
+    pub storage: bool,
+    pub color_attachment: bool,
+    pub depth_stencil_attachment: bool,
+    pub blit_src: bool,
+    pub blit_dst: bool,
+}
+
+impl Capabilities {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self { device }
+    }
+
+    pub fn features(&self) -> &Features {
+        self.device.enabled_features()
+    }
+
+    pub fn format_support(&self, format: Format) -> FormatSupport {
+        let sampled = self.probe_image(
+            format,
+            ImageUsage {
+                sampled: true,
+                ..ImageUsage::none()
+            },
+        );
+
+        FormatSupport {
+            sampled: sampled.is_some(),
+            // the image remembers all of the format's features, not just the
+            // ones for its usage
+            filter_linear: sampled
+                .as_ref()
+                .map_or(false, |image| image.supports_linear_filtering()),
+            storage: self
+                .probe_image(
+                    format,
+                    ImageUsage {
+                        storage: true,
+                        ..ImageUsage::none()
+                    },
+                )
+                .is_some(),
+            color_attachment: self
+                .probe_image(
+                    format,
+                    ImageUsage {
+                        color_attachment: true,
+                        ..ImageUsage::none()
+                    },
+                )
+                .is_some(),
+            depth_stencil_attachment: self
+                .probe_image(
+                    format,
+                    ImageUsage {
+                        depth_stencil_attachment: true,
+                        ..ImageUsage::none()
+                    },
+                )
+                .is_some(),
+            blit_src: sampled
+                .as_ref()
+                .map_or(false, |image| image.supports_blit_source()),
+            blit_dst: sampled
+                .as_ref()
+                .map_or(false, |image| image.supports_blit_destination()),
+        }
+    }
+
+    // a 1x1 image with only the given usage, or None if the format doesn't
+    // support it. nothing is allocated, so the image is useless apart from
+    // the format features it carries.
+    fn probe_image(&self, format: Format, usage: ImageUsage) -> Option<UnsafeImage> {
+        let dimensions = ImageDimensions::Dim2d {
+            width: 1,
+            height: 1,
+            array_layers: 1,
+            cubemap_compatible: false,
+        };
+
+        unsafe {
+            UnsafeImage::new(
+                self.device.clone(),
+                usage,
+                format,
+                dimensions,
+                1,
+                1,
+                Sharing::Exclusive::<std::iter::Empty<u32>>,
+                false,
+                false,
+            )
+        }
+        .ok()
+        .map(|(image, _)| image)
+    }
+
+    // can be sampled with linear filtering, which is all a texture needs
+    pub fn supports_texture(&self, format: Format) -> bool {
+        let support = self.format_support(format);
+        support.sampled && support.filter_linear
+    }
+
+    pub fn supports_color_attachment(&self, format: Format) -> bool {
+        self.format_support(format).color_attachment
+    }
+
+    pub fn supports_depth_attachment(&self, format: Format) -> bool {
+        self.format_support(format).depth_stencil_attachment
+    }
+
+    // the BC formats are all or nothing behind texture_compression_bc, but
+    // check the format too in case a driver is lying
+    pub fn supports_bc7(&self) -> bool {
+        self.features().texture_compression_bc
+            && self.supports_texture(Format::BC7UnormBlock)
+            && self.supports_texture(Format::BC7SrgbBlock)
+    }
+
+    // for HDR passes, like render_passes::with_depth_hdr. blending can't be
+    // probed, but the spec requires it for every float format that can be a
+    // color attachment.
+    pub fn supports_float_attachments(&self) -> bool {
+        let support = self.format_support(crate::render_passes::HDR_COLOR_FORMAT);
+        support.color_attachment && support.sampled
+    }
+
+    pub fn supports_geometry_shaders(&self) -> bool {
+        self.features().geometry_shader
+    }
+
+    pub fn supports_multi_viewport(&self) -> bool {
+        self.features().multi_viewport
+    }
+
+    pub fn supports_depth_clamp(&self) -> bool {
+        self.features().depth_clamp
+    }
+
+    pub fn supports_anisotropy(&self) -> bool {
+        self.features().sampler_anisotropy
+    }
+
+    // the highest sample count that works for both color and depth
+    // attachments, for picking a factor for render_passes::multisampled
+    pub fn max_msaa_samples(&self) -> u32 {
+        let limits = self.device.physical_device().limits();
+        let counts =
+            limits.framebuffer_color_sample_counts() & limits.framebuffer_depth_sample_counts();
+
+        // the counts are a bitmask of powers of two, 1 is always there
+        (0..7)
+            .rev()
+            .map(|bit| 1 << bit)
+            .find(|&count| counts & count != 0)
+            .unwrap_or(1)
+    }
+}
@@ -0,0 +1,267 @@
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::ImageAccess;
+use vulkano::sync::{self, GpuFuture};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use re_ll::vk_window::is_srgb_format;
+
+// A capture is a copy of an image into a CPU-accessible buffer that gets
+// written to disk once the GPU is done with it. Because command buffers
+// execute asynchronously, the buffer usually isn't readable until a frame or
+// two later, so captures are polled instead of waited on.
+pub struct PendingCapture {
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    dimensions: [u32; 2],
+    format: Format,
+    encoding: CaptureEncoding,
+    path: PathBuf,
+}
+
+// how the bytes of a captured image become the values in the png, which
+// viewers always treat as sRGB. see the color space notes in render_passes.rs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptureEncoding {
+    // the bytes as they are. right for sRGB images, where the hardware already
+    // encoded them, and for UNORM ones whose last pass gamma corrected, which
+    // is what a UNORM window shows.
+    AsStored,
+    // the image is UNORM but holds linear colors, apply the sRGB transfer
+    // function so the png isn't too dark. sRGB images are left alone since
+    // they're already encoded.
+    LinearToSrgb,
+}
+
+impl Default for CaptureEncoding {
+    fn default() -> Self {
+        CaptureEncoding::AsStored
+    }
+}
+
+impl PendingCapture {
+    // records a copy of image into a new buffer at the end of cmd_buf. must
+    // be called outside of a render pass.
+    pub fn record(
+        device: Arc<Device>,
+        cmd_buf: AutoCommandBufferBuilder,
+        image: Arc<dyn ImageAccess + Send + Sync>,
+        encoding: CaptureEncoding,
+        path: &Path,
+    ) -> (AutoCommandBufferBuilder, Self) {
+        let vk_dims = image.dimensions();
+        let dimensions = [vk_dims.width(), vk_dims.height()];
+        let format = image.format();
+
+        // 4 bytes per pixel, which is true for every format we know how to
+        // encode (see to_rgba8)
+        let buffer = CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage::all(),
+            (0..dimensions[0] * dimensions[1] * 4).map(|_| 0u8),
+        )
+        .unwrap();
+
+        let cmd_buf = cmd_buf
+            .copy_image_to_buffer(image, buffer.clone())
+            .expect("Couldn't record copy of image for capture");
+
+        let capture = Self {
+            buffer,
+            dimensions,
+            format,
+            encoding,
+            path: path.to_path_buf(),
+        };
+
+        (cmd_buf, capture)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // writes the capture to disk if the GPU is done with it. returns whether
+    // it was written.
+    pub fn try_write(&self) -> bool {
+        let data = match self.buffer.read() {
+            Ok(data) => data,
+            // still in use by the GPU
+            Err(_) => return false,
+        };
+
+        let mut rgba = to_rgba8(&data, self.format);
+        if self.encoding == CaptureEncoding::LinearToSrgb && !is_srgb_format(self.format) {
+            encode_srgb(&mut rgba);
+        }
+        if let Err(e) = image::save_buffer(
+            &self.path,
+            &rgba,
+            self.dimensions[0],
+            self.dimensions[1],
+            image::RGBA(8),
+        ) {
+            println!("Couldn't write capture to {:?}: {:?}", self.path, e);
+        }
+
+        true
+    }
+}
+
+// copies image back to the CPU right away, as 8-bit RGBA rows from the top
+// left, waiting for the GPU to finish everything queued before it. meant for
+// comparing pixels in tests (see System::render_headless), not for every
+// frame: it stalls until the copy is done.
+// the format is checked before anything is copied, so only 8-bit RGBA/BGRA
+// images work, anything else is CaptureError::UnsupportedFormat.
+pub fn read_rgba8(
+    queue: Arc<Queue>,
+    image: Arc<dyn ImageAccess + Send + Sync>,
+) -> Result<Vec<u8>, CaptureError> {
+    let device = queue.device().clone();
+    let format = image.format();
+    if !supports_rgba8(format) {
+        return Err(CaptureError::UnsupportedFormat(format));
+    }
+
+    // 4 bytes per pixel, true for every format supports_rgba8 accepts
+    let vk_dims = image.dimensions();
+    let num_bytes = vk_dims.width() * vk_dims.height() * 4;
+
+    let buffer = CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::all(),
+        (0..num_bytes).map(|_| 0u8),
+    )
+    .unwrap();
+
+    let cmd_buf = AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
+        .unwrap()
+        .copy_image_to_buffer(image, buffer.clone())
+        .expect("Couldn't record copy of image for reading")
+        .build()
+        .unwrap();
+
+    sync::now(device)
+        .then_execute(queue, cmd_buf)
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .expect("Couldn't submit copy of image for reading")
+        .wait(None)
+        .expect("Couldn't wait for copy of image for reading");
+
+    let data = buffer.read().map_err(|_| CaptureError::InUse)?;
+    Ok(to_rgba8(&data, format))
+}
+
+// whether try_to_rgba8 knows format. converting no pixels is free.
+pub fn supports_rgba8(format: Format) -> bool {
+    try_to_rgba8(&[], format).is_some()
+}
+
+// converts raw pixels of the given format to 8-bit RGBA
+pub fn to_rgba8(data: &[u8], format: Format) -> Vec<u8> {
+    try_to_rgba8(data, format)
+        .unwrap_or_else(|| panic!("Capturing images of format {:?} is unsupported!", format))
+}
+
+// to_rgba8, but None for formats it doesn't know. rows stay in the same
+// order: Vulkan images start at the top left like pngs do, so unlike GL
+// readbacks nothing needs flipping.
+pub fn try_to_rgba8(data: &[u8], format: Format) -> Option<Vec<u8>> {
+    match format {
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => Some(
+            data.chunks(4)
+                .flat_map(|px| vec![px[2], px[1], px[0], px[3]])
+                .collect(),
+        ),
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => Some(data.to_vec()),
+        _ => None,
+    }
+}
+
+// what can go wrong in Window::capture_to_file and read_rgba8
+#[derive(Debug)]
+pub enum CaptureError {
+    // nothing was presented since Window::set_keep_last_frame(true)
+    NoFrame,
+    // the GPU is still using the copy, happens with frames presented through
+    // System::finish_to_windows until the next frame
+    InUse,
+    // the image's format isn't one to_rgba8 knows
+    UnsupportedFormat(Format),
+    Write(std::io::Error),
+}
+
+impl std::fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CaptureError::NoFrame => write!(
+                f,
+                "no frame to capture, call set_keep_last_frame(true) before presenting"
+            ),
+            CaptureError::InUse => write!(f, "the captured frame is still in use by the GPU"),
+            CaptureError::UnsupportedFormat(format) => {
+                write!(f, "can't capture images of format {:?}", format)
+            }
+            CaptureError::Write(e) => write!(f, "couldn't write capture: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+// applies the sRGB transfer function to the color channels of 8-bit RGBA
+// pixels in place. alpha is always linear.
+pub fn encode_srgb(rgba: &mut [u8]) {
+    // only 256 possible inputs, no point doing the pow for every pixel
+    let table: Vec<u8> = (0..256)
+        .map(|v| (linear_to_srgb(v as f32 / 255.0) * 255.0).round() as u8)
+        .collect();
+
+    for px in rgba.chunks_mut(4) {
+        for channel in &mut px[..3] {
+            *channel = table[*channel as usize];
+        }
+    }
+}
+
+// the exact sRGB curve rather than pow(x, 1 / 2.2), so captures match what
+// an sRGB swapchain would have shown
+fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// state for dumping every frame to a numbered png
+pub struct FrameDump {
+    pub dir: PathBuf,
+    pub fps: f32,
+    pub frame_num: u32,
+}
+
+impl FrameDump {
+    pub fn new(dir: &Path, fps: f32) -> Self {
+        std::fs::create_dir_all(dir).expect("Couldn't create frame dump directory");
+
+        Self {
+            dir: dir.to_path_buf(),
+            fps,
+            frame_num: 0,
+        }
+    }
+
+    // returns the path to write the next frame to
+    pub fn next_path(&mut self) -> PathBuf {
+        let path = self.dir.join(format!("frame_{:05}.png", self.frame_num));
+        self.frame_num += 1;
+
+        path
+    }
+}
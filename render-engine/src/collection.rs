@@ -29,6 +29,9 @@ let mut set = Set::new(
 set.data.0 = updated_struct;
 set.upload(device);
 
+Images in the set are sampled with set.sampler, SamplerSpec::texture() unless
+you change it (set_sampler changes it and re-uploads).
+
 Ta-da! Now to collections. Collection is a trait implemented for all* tuples of
 sets that allows converting them into Vec<Arc<DescriptorSet>>, which is most
 concrete form of a collection: it is the type taken by draw and draw_indexed
@@ -41,15 +44,35 @@ So that's it: how you can go from tuples of images and arbitrary structs to a
 type that can be used in draw and draw_indexed. How magnificently mediocre.
  */
 
+use vulkano::buffer::cpu_access::WriteLockError;
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, TypedBufferAccess};
 use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
 use vulkano::device::Device;
 use vulkano::image::ImageViewAccess;
 use vulkano::pipeline::GraphicsPipelineAbstract;
 
-use crate::utils::{upload_data, default_sampler};
+use crate::utils::{upload_data, SamplerSpec};
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+// descriptor sets come out of vulkano's standard pool for the device, which
+// makes another pool whenever the ones it has are full, so there's no running
+// out of them and no pool size to configure. what does cost something is
+// making lots of sets every frame (every Set::upload makes a new one), so they
+// get counted, see descriptor_sets_created.
+static DESCRIPTOR_SETS_CREATED: AtomicUsize = AtomicUsize::new(0);
+
+// how many descriptor sets have been made by Sets and the collection cache so
+// far. see System::descriptor_set_stats for how many per frame.
+pub fn descriptor_sets_created() -> usize {
+    DESCRIPTOR_SETS_CREATED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn count_descriptor_sets(count: usize) {
+    DESCRIPTOR_SETS_CREATED.fetch_add(count, Ordering::Relaxed);
+}
+
 pub trait Collection {
     fn get(&self) -> Vec<Arc<dyn DescriptorSet + Send + Sync>>;
 }
@@ -235,6 +258,9 @@ pub struct Set<T: SetUpload> {
     cached: Arc<dyn DescriptorSet + Send + Sync>,
     pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
     set_idx: usize,
+    // used for every image in the set. change it with set_sampler, or change
+    // it directly and call upload.
+    pub sampler: SamplerSpec,
 }
 
 impl<T: SetUpload> Set<T> {
@@ -245,12 +271,15 @@ impl<T: SetUpload> Set<T> {
         set_idx: usize,
     ) -> Self {
         // creates a new set and immediately uploads the data to the GPU
-        let gpu_data = data.upload(device, pipeline.clone(), set_idx);
+        let sampler = SamplerSpec::texture();
+        let gpu_data = data.upload(device, pipeline.clone(), set_idx, &sampler);
+        count_descriptor_sets(1);
         Self {
             data,
             cached: gpu_data,
             pipeline,
             set_idx,
+            sampler,
         }
     }
 
@@ -259,7 +288,17 @@ impl<T: SetUpload> Set<T> {
     }
 
     pub fn upload(&mut self, device: Arc<Device>) {
-        self.cached = self.data.upload(device, self.pipeline.clone(), self.set_idx);
+        self.cached = self
+            .data
+            .upload(device, self.pipeline.clone(), self.set_idx, &self.sampler);
+        count_descriptor_sets(1);
+    }
+
+    // swaps the sampler without touching the data, the images stay where they
+    // are and only the descriptor set is made again
+    pub fn set_sampler(&mut self, device: Arc<Device>, sampler: SamplerSpec) {
+        self.sampler = sampler;
+        self.upload(device);
     }
 }
 
@@ -269,6 +308,7 @@ pub trait SetUpload: Clone {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync>;
 }
 
@@ -279,6 +319,7 @@ impl<T: Data> SetUpload for (T,) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        _sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
         let buffer = upload_data(device.clone(), self.0.clone());
 
@@ -298,8 +339,9 @@ impl SetUpload for (Image,) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         Arc::new(
             PersistentDescriptorSet::start(pipeline, set_idx)
                 .add_sampled_image(self.0.clone(), sampler)
@@ -317,6 +359,7 @@ impl<T1: Data, T2: Data> SetUpload for (T1, T2) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        _sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
         let buffer1 = upload_data(device.clone(), self.0.clone());
         let buffer2 = upload_data(device.clone(), self.1.clone());
@@ -339,8 +382,9 @@ impl<T: Data> SetUpload for (Image, T) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         let buffer2 = upload_data(device.clone(), self.1.clone());
 
         Arc::new(
@@ -361,8 +405,9 @@ impl<T: Data> SetUpload for (T, Image) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         let buffer1 = upload_data(device.clone(), self.0.clone());
 
         Arc::new(
@@ -383,8 +428,9 @@ impl SetUpload for (Image, Image) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
 
         Arc::new(
             PersistentDescriptorSet::start(pipeline, set_idx)
@@ -405,6 +451,7 @@ impl<T1: Data, T2: Data, T3: Data> SetUpload for (T1, T2, T3) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        _sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
         let buffer1 = upload_data(device.clone(), self.0.clone());
         let buffer2 = upload_data(device.clone(), self.1.clone());
@@ -430,8 +477,9 @@ impl<T1: Data, T2: Data> SetUpload for (Image, T1, T2) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         let buffer2 = upload_data(device.clone(), self.1.clone());
         let buffer3 = upload_data(device.clone(), self.2.clone());
 
@@ -455,8 +503,9 @@ impl<T1: Data, T2: Data> SetUpload for (T1, Image, T2) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         let buffer1 = upload_data(device.clone(), self.0.clone());
         let buffer3 = upload_data(device.clone(), self.2.clone());
 
@@ -480,8 +529,9 @@ impl<T1: Data, T2: Data> SetUpload for (T1, T2, Image) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         let buffer1 = upload_data(device.clone(), self.0.clone());
         let buffer2 = upload_data(device.clone(), self.1.clone());
 
@@ -505,8 +555,9 @@ impl<T: Data> SetUpload for (T, Image, Image) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         let buffer1 = upload_data(device.clone(), self.0.clone());
 
         Arc::new(
@@ -529,8 +580,9 @@ impl<T: Data> SetUpload for (Image, T, Image) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         let buffer2 = upload_data(device.clone(), self.1.clone());
 
         Arc::new(
@@ -553,8 +605,9 @@ impl<T: Data> SetUpload for (Image, Image, T) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
         let buffer3 = upload_data(device.clone(), self.2.clone());
 
         Arc::new(
@@ -577,8 +630,9 @@ impl SetUpload for (Image, Image, Image) {
         device: Arc<Device>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         set_idx: usize,
+        sampler: &SamplerSpec,
     ) -> Arc<dyn DescriptorSet + Send + Sync> {
-        let sampler = default_sampler(device.clone());
+        let sampler = sampler.build(device.clone());
 
         Arc::new(
             PersistentDescriptorSet::start(pipeline, set_idx)
@@ -596,4 +650,196 @@ impl SetUpload for (Image, Image, Image) {
 
 pub type Image = Arc<dyn ImageViewAccess + Send + Sync>;
 
+/*
+UniformArray
+
+Lots of objects with the same kind of data (model matrices for every Sponza
+submesh, for example) can share one uniform buffer instead of each uploading
+their own. Every object gets an ArrayElement pointing to its part of the
+buffer, which can be used in a set like any struct, as long as it's alone:
+
+let models = UniformArray::new(device, &model_matrices);
+collection: ((models.element(idx),), ...)
+models.write(idx, new_matrix)?;  // no re-upload needed
+
+NOTE: ideally this would be a single set with a dynamic offset per draw, but
+vulkano doesn't let us pass dynamic offsets when binding sets. So each object
+still has its own set, they just all point into the same buffer.
+ */
+
+// uniform buffer offsets have to be a multiple of
+// minUniformBufferOffsetAlignment, which is at most 256 bytes
+#[derive(Clone, Copy)]
+#[repr(C, align(256))]
+pub struct Aligned<T>(pub T);
+
+pub struct UniformArray<T: Data> {
+    buffer: Arc<CpuAccessibleBuffer<[Aligned<T>]>>,
+}
+
+impl<T: Data> UniformArray<T> {
+    pub fn new(device: Arc<Device>, data: &[T]) -> Self {
+        let buffer = CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage::all(),
+            data.iter().cloned().map(Aligned),
+        )
+        .unwrap();
+
+        Self { buffer }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // changes an element in place. sets pointing to it see the new value
+    // without having to be re-uploaded. fails while a frame that uses the
+    // buffer is still in flight, Window::wait_for_all_frames before writing
+    // makes sure it doesn't.
+    pub fn write(&self, idx: usize, value: T) -> Result<(), WriteLockError> {
+        let mut contents = self.buffer.write()?;
+        contents[idx] = Aligned(value);
+
+        Ok(())
+    }
+
+    pub fn element(&self, idx: usize) -> ArrayElement<T> {
+        assert!(idx < self.len(), "Uniform array index {} is out of bounds (len {})", idx, self.len());
+
+        ArrayElement {
+            buffer: self.buffer.clone(),
+            idx,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ArrayElement<T: Data> {
+    buffer: Arc<CpuAccessibleBuffer<[Aligned<T>]>>,
+    idx: usize,
+}
+
+impl<T: Data> SetUpload for (ArrayElement<T>,) {
+    fn upload(
+        &self,
+        _device: Arc<Device>,
+        pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        set_idx: usize,
+        _sampler: &SamplerSpec,
+    ) -> Arc<dyn DescriptorSet + Send + Sync> {
+        let element = self
+            .0
+            .buffer
+            .clone()
+            .into_buffer_slice()
+            .index(self.0.idx)
+            .unwrap();
+
+        Arc::new(
+            PersistentDescriptorSet::start(pipeline, set_idx)
+                .add_buffer(element)
+                .expect(&format!("Panic adding array element at set idx {}", set_idx))
+                .build()
+                .expect(&format!("Panic finalizing set at set idx {}", set_idx)),
+        )
+    }
+}
+
+/*
+DataArray
+
+Several structs of the same type bound to one binding as an array, like four
+cascade matrices for
+
+layout(set = 1, binding = 0) uniform Cascade {
+    mat4 view_proj;
+} cascades[4];
+
+Each element gets its own buffer. Use it alone in a set:
+
+collection: ((DataArray(cascade_matrices),), ...)
+
+*: up to 4 elements, because vulkano's set builder changes type with every
+buffer added, so each length has to be written out. Sorry again.
+ */
+#[derive(Clone)]
+pub struct DataArray<T: Data>(pub Vec<T>);
+
+impl<T: Data> SetUpload for (DataArray<T>,) {
+    fn upload(
+        &self,
+        device: Arc<Device>,
+        pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        set_idx: usize,
+        _sampler: &SamplerSpec,
+    ) -> Arc<dyn DescriptorSet + Send + Sync> {
+        let buffers: Vec<_> = (self.0)
+            .0
+            .iter()
+            .map(|data| upload_data(device.clone(), data.clone()))
+            .collect();
+        let err = format!("Panic adding buffer array at set idx {}", set_idx);
+        let builder = PersistentDescriptorSet::start(pipeline, set_idx)
+            .enter_array()
+            .expect(&err);
+
+        match buffers.len() {
+            1 => Arc::new(
+                builder
+                    .add_buffer(buffers[0].clone())
+                    .expect(&err)
+                    .leave_array()
+                    .expect(&err)
+                    .build()
+                    .expect(&format!("Panic finalizing set at set idx {}", set_idx)),
+            ),
+            2 => Arc::new(
+                builder
+                    .add_buffer(buffers[0].clone())
+                    .expect(&err)
+                    .add_buffer(buffers[1].clone())
+                    .expect(&err)
+                    .leave_array()
+                    .expect(&err)
+                    .build()
+                    .expect(&format!("Panic finalizing set at set idx {}", set_idx)),
+            ),
+            3 => Arc::new(
+                builder
+                    .add_buffer(buffers[0].clone())
+                    .expect(&err)
+                    .add_buffer(buffers[1].clone())
+                    .expect(&err)
+                    .add_buffer(buffers[2].clone())
+                    .expect(&err)
+                    .leave_array()
+                    .expect(&err)
+                    .build()
+                    .expect(&format!("Panic finalizing set at set idx {}", set_idx)),
+            ),
+            4 => Arc::new(
+                builder
+                    .add_buffer(buffers[0].clone())
+                    .expect(&err)
+                    .add_buffer(buffers[1].clone())
+                    .expect(&err)
+                    .add_buffer(buffers[2].clone())
+                    .expect(&err)
+                    .add_buffer(buffers[3].clone())
+                    .expect(&err)
+                    .leave_array()
+                    .expect(&err)
+                    .build()
+                    .expect(&format!("Panic finalizing set at set idx {}", set_idx)),
+            ),
+            len => panic!("DataArray can have 1 to 4 elements, not {}", len),
+        }
+    }
+}
+
 pub trait Data: Send + Sync + Clone + 'static {}
+
+// for functions that take Data when there's nothing to pass, like
+// System::draw_fullscreen. not uploaded, there'd be nothing to upload.
+impl Data for () {}
@@ -9,6 +9,7 @@ use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::collection::count_descriptor_sets;
 use crate::input::get_elapsed;
 use crate::pipeline_cache::PipelineSpec;
 use crate::system::Pass;
@@ -16,6 +17,13 @@ use crate::system::Pass;
 pub struct CollectionCache {
     c_collections: Vec<CachedCollection>,
     sampler: Arc<Sampler>,
+    // samplers for specific tags, used instead of sampler. see set_sampler
+    tag_samplers: HashMap<String, Arc<Sampler>>,
+    // same, but only in one pass, keyed by (pass name, tag). these win over
+    // tag_samplers.
+    pass_tag_samplers: HashMap<(String, String), Arc<Sampler>>,
+    // see set_max_cached
+    max_cached: Option<usize>,
     stats: CacheStats,
 }
 
@@ -51,10 +59,62 @@ impl CollectionCache {
         Self {
             c_collections: vec![],
             sampler,
+            tag_samplers: HashMap::new(),
+            pass_tag_samplers: HashMap::new(),
+            max_cached: None,
             stats: CacheStats::default(),
         }
     }
 
+    // makes images_needed sets sample the image with tag using sampler
+    // instead of the default clamp-to-edge linear one
+    pub fn set_sampler(&mut self, tag: &str, sampler: Arc<Sampler>) {
+        self.tag_samplers.insert(tag.to_string(), sampler);
+        // cached collections could have the old sampler
+        self.clear();
+    }
+
+    // like set_sampler, but only for the pass called pass_name
+    pub fn set_pass_sampler(&mut self, pass_name: &str, tag: &str, sampler: Arc<Sampler>) {
+        self.pass_tag_samplers.insert((pass_name.to_string(), tag.to_string()), sampler);
+        self.clear();
+    }
+
+    // caps how many collections are kept around, dropping the oldest ones
+    // (and their descriptor sets) when there'd be more. None, the default,
+    // keeps every one. there's one per pipeline spec that was drawn with, so
+    // this only matters if specs keep changing, like when shaders are swapped
+    // out all the time.
+    pub fn set_max_cached(&mut self, max_cached: Option<usize>) {
+        self.max_cached = max_cached;
+        self.evict();
+    }
+
+    pub fn len(&self) -> usize {
+        self.c_collections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.c_collections.is_empty()
+    }
+
+    fn evict(&mut self) {
+        if let Some(max_cached) = self.max_cached {
+            if self.c_collections.len() > max_cached {
+                let excess = self.c_collections.len() - max_cached;
+                self.c_collections.drain(0..excess);
+            }
+        }
+    }
+
+    pub(crate) fn sampler_for(&self, pass_name: &str, tag: &str) -> Arc<Sampler> {
+        self.pass_tag_samplers
+            .get(&(pass_name.to_string(), tag.to_string()))
+            .or_else(|| self.tag_samplers.get(tag))
+            .unwrap_or(&self.sampler)
+            .clone()
+    }
+
     // TODO: replace with a struct that defines a uniform buffer: what spec
     // pipeline is belongs to, what resources it needs, etc.
     pub fn get(
@@ -90,15 +150,33 @@ impl CollectionCache {
                             .clone()
                     })
                     .collect();
+                let samplers: Vec<Arc<Sampler>> = pass
+                    .images_needed_tags
+                    .iter()
+                    .map(|tag| self.sampler_for(pass.name, tag))
+                    .collect();
+
+                let mut collection =
+                    collection_from_images(&samplers, pipeline.clone(), &images_needed);
+
+                // input attachments get their own set, right after the one
+                // for images_needed (if there is one)
+                let input_images = input_attachments_for_subpass(pass, spec.subpass, images);
+                let input_set_idx = collection.len();
+                if let Some(input_set) =
+                    pds_for_input_attachments(pipeline.clone(), &input_images, input_set_idx)
+                {
+                    collection.push(input_set);
+                }
 
-                let collection =
-                    collection_from_images(self.sampler.clone(), pipeline.clone(), &images_needed);
+                count_descriptor_sets(collection.len());
 
                 let c_collection = CachedCollection {
                     spec: spec.clone(),
                     collection: collection.clone(),
                 };
                 self.c_collections.push(c_collection);
+                self.evict();
 
                 self.stats.gen_times.push(get_elapsed(start_time));
 
@@ -117,28 +195,33 @@ impl CollectionCache {
         let percent =
             (self.stats.hits as f32) / ((self.stats.hits + self.stats.misses) as f32) * 100.0;
         println!(
-            "Hits: {}, misses: {}, {}%, avg. time taken to gen collection: {}",
-            self.stats.hits, self.stats.misses, percent, avg
+            "Hits: {}, misses: {}, {}%, avg. time taken to gen collection: {}, {} cached",
+            self.stats.hits,
+            self.stats.misses,
+            percent,
+            avg,
+            self.c_collections.len()
         );
     }
 }
 
 // TODO: this is kinda obsolete now
 fn collection_from_images(
-    sampler: Arc<Sampler>,
+    samplers: &[Arc<Sampler>],
     pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
     images: &[Arc<dyn ImageViewAccess + Send + Sync>],
 ) -> Vec<Arc<dyn DescriptorSet + Send + Sync>> {
     // assumes set idx should be 0
-    if let Some(image_set) = pds_for_images(sampler, pipeline.clone(), &images, 0) {
+    if let Some(image_set) = pds_for_images(samplers, pipeline.clone(), &images, 0) {
         vec![image_set]
     } else {
         vec![]
     }
 }
 
+// samplers has one sampler for each image
 pub fn pds_for_images(
-    sampler: Arc<Sampler>,
+    samplers: &[Arc<Sampler>],
     pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
     images: &[Arc<dyn ImageViewAccess + Send + Sync>],
     set_idx: usize,
@@ -147,40 +230,40 @@ pub fn pds_for_images(
         0 => None,
         1 => Some(Arc::new(
             PersistentDescriptorSet::start(pipeline, set_idx)
-                .add_sampled_image(images[0].clone(), sampler)
+                .add_sampled_image(images[0].clone(), samplers[0].clone())
                 .unwrap()
                 .build()
                 .unwrap(),
         )),
         2 => Some(Arc::new(
             PersistentDescriptorSet::start(pipeline, set_idx)
-                .add_sampled_image(images[0].clone(), sampler.clone())
+                .add_sampled_image(images[0].clone(), samplers[0].clone())
                 .unwrap()
-                .add_sampled_image(images[1].clone(), sampler.clone())
+                .add_sampled_image(images[1].clone(), samplers[1].clone())
                 .unwrap()
                 .build()
                 .unwrap(),
         )),
         3 => Some(Arc::new(
             PersistentDescriptorSet::start(pipeline, set_idx)
-                .add_sampled_image(images[0].clone(), sampler.clone())
+                .add_sampled_image(images[0].clone(), samplers[0].clone())
                 .unwrap()
-                .add_sampled_image(images[1].clone(), sampler.clone())
+                .add_sampled_image(images[1].clone(), samplers[1].clone())
                 .unwrap()
-                .add_sampled_image(images[2].clone(), sampler.clone())
+                .add_sampled_image(images[2].clone(), samplers[2].clone())
                 .unwrap()
                 .build()
                 .unwrap(),
         )),
         4 => Some(Arc::new(
             PersistentDescriptorSet::start(pipeline, set_idx)
-                .add_sampled_image(images[0].clone(), sampler.clone())
+                .add_sampled_image(images[0].clone(), samplers[0].clone())
                 .unwrap()
-                .add_sampled_image(images[1].clone(), sampler.clone())
+                .add_sampled_image(images[1].clone(), samplers[1].clone())
                 .unwrap()
-                .add_sampled_image(images[2].clone(), sampler.clone())
+                .add_sampled_image(images[2].clone(), samplers[2].clone())
                 .unwrap()
-                .add_sampled_image(images[3].clone(), sampler.clone())
+                .add_sampled_image(images[3].clone(), samplers[3].clone())
                 .unwrap()
                 .build()
                 .unwrap(),
@@ -189,6 +272,81 @@ pub fn pds_for_images(
     }
 }
 
+// finds the images a subpass of pass reads as input attachments, going by the
+// render pass's description
+fn input_attachments_for_subpass(
+    pass: &Pass,
+    subpass: u32,
+    images: &HashMap<String, Arc<dyn ImageViewAccess + Send + Sync>>,
+) -> Vec<Arc<dyn ImageViewAccess + Send + Sync>> {
+    let desc = match pass.render_pass.subpass_desc(subpass as usize) {
+        Some(desc) => desc,
+        None => return vec![],
+    };
+
+    desc.input_attachments
+        .iter()
+        .map(|(attachment_idx, _layout)| {
+            let tag = pass.images_created_tags[*attachment_idx];
+            images
+                .get(tag)
+                .unwrap_or_else(|| panic!("missing image {} for input attachment", tag))
+                .clone()
+        })
+        .collect()
+}
+
+pub fn pds_for_input_attachments(
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    images: &[Arc<dyn ImageViewAccess + Send + Sync>],
+    set_idx: usize,
+) -> Option<Arc<dyn DescriptorSet + Send + Sync>> {
+    match images.len() {
+        0 => None,
+        1 => Some(Arc::new(
+            PersistentDescriptorSet::start(pipeline, set_idx)
+                .add_image(images[0].clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        )),
+        2 => Some(Arc::new(
+            PersistentDescriptorSet::start(pipeline, set_idx)
+                .add_image(images[0].clone())
+                .unwrap()
+                .add_image(images[1].clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        )),
+        3 => Some(Arc::new(
+            PersistentDescriptorSet::start(pipeline, set_idx)
+                .add_image(images[0].clone())
+                .unwrap()
+                .add_image(images[1].clone())
+                .unwrap()
+                .add_image(images[2].clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        )),
+        4 => Some(Arc::new(
+            PersistentDescriptorSet::start(pipeline, set_idx)
+                .add_image(images[0].clone())
+                .unwrap()
+                .add_image(images[1].clone())
+                .unwrap()
+                .add_image(images[2].clone())
+                .unwrap()
+                .add_image(images[3].clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        )),
+        _ => panic!("pds_for_input_attachments does not support more than 4 images!"),
+    }
+}
+
 // rename to set for buffers? idk
 pub fn pds_for_buffers(
     pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
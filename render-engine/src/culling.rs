@@ -0,0 +1,335 @@
+/*
+Culling lots of instances of one mesh on the GPU. A compute shader tests every
+instance's bounding sphere against the camera's frustum and writes the indices
+of the ones that survive to a buffer, counting them in the instance_count of a
+single indirect draw command. Drawing them is then one add_object_indirect,
+without the CPU looking at a single instance:
+
+let culler = GpuCuller::new(queue.clone(), &instances, mesh_range);
+let object = culler.attach(object, &mut pipeline_cache, 2);
+...
+// every frame, before the pass that draws object. record_custom ends the
+// current pass, so this goes at the end of the one before it.
+system.record_custom(|cmd_buf| culler.record(cmd_buf, &view_proj));
+system.next_pass();
+system.add_object_indirect(&object, culler.commands());
+
+attach adds a set with the instances and the visible list to the object, at
+the set index given, which has to come after the object's own sets. The vertex
+shader looks its instance up through the visible list:
+
+struct Instance {
+  mat4 model;
+  vec4 bounds;
+};
+layout(std430, set = 2, binding = 0) readonly buffer Instances {
+  Instance instances[];
+};
+layout(std430, set = 2, binding = 1) readonly buffer Visible {
+  uint visible[];
+};
+...
+mat4 model = instances[visible[gl_InstanceIndex]].model;
+
+For occlusion culling as well, build a DepthPyramid (see hiz.rs) from a depth
+image first and use record_with_occlusion instead of record. Instances that
+survive the frustum test are then also tested against the pyramid: the box
+around the bounding sphere is projected, and the instance is dropped if it's
+farther away than everything in the texels covering it. This only helps if
+the depth image already has the big occluders in it, and if it's last frame's
+depth things can pop in for a frame when the camera moves fast.
+
+NOTE: vulkano doesn't have vkCmdDrawIndexedIndirectCount, so instead of one
+command per surviving instance there's one command with the survivors packed
+at the front of the visible list. Same result with one mesh, but different
+meshes each need their own culler.
+ */
+
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DrawIndexedIndirectCommand};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor::pipeline_layout::PipelineLayout;
+use vulkano::descriptor::DescriptorSet;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, ImageUsage, ImageViewAccess, StorageImage};
+use vulkano::pipeline::ComputePipeline;
+use vulkano::sampler::Sampler;
+
+use nalgebra_glm::{length, row, vec4_to_vec3, Mat4};
+
+use crate::collection::Collection;
+use crate::hiz::{nearest_sampler, DepthPyramid, MAX_PYRAMID_LEVELS, PYRAMID_FORMAT};
+use crate::mesh::DrawRange;
+use crate::object::Object;
+use crate::pipeline_cache::PipelineCache;
+use crate::utils::{immutable_slice, upload_data};
+
+use std::sync::Arc;
+
+mod cull_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "shaders/culling/cull_comp.glsl"
+    }
+}
+
+// has to match the compute shader's workgroup size
+const GROUP_SIZE: u32 = 64;
+
+// one instance to cull, laid out like Instance in the shaders
+#[derive(Clone, Copy, Debug)]
+pub struct CullInstance {
+    pub model: [[f32; 4]; 4],
+    // xyz is the center of the bounding sphere in model space, w the radius
+    pub bounds: [f32; 4],
+}
+
+pub struct GpuCuller {
+    instances: Arc<dyn BufferAccess + Send + Sync>,
+    num_instances: u32,
+    // indices into instances of the ones that survived, packed at the front
+    visible: Arc<DeviceLocalBuffer<[u32]>>,
+    commands: Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+    // the command with instance_count 0, copied over commands before culling
+    reset: Arc<CpuAccessibleBuffer<[DrawIndexedIndirectCommand]>>,
+    pipeline: Arc<ComputePipeline<PipelineLayout<cull_cs::Layout>>>,
+    // bound instead of a pyramid when there isn't one, the shader doesn't
+    // read it then
+    empty_pyramid: Arc<StorageImage<Format>>,
+    sampler: Arc<Sampler>,
+    queue: Arc<Queue>,
+}
+
+impl GpuCuller {
+    // range is the part of the index buffer every instance draws, use
+    // DrawRange { first_index: 0, index_count: mesh.indices.len() as u32,
+    // vertex_offset: 0 } for a whole mesh
+    pub fn new(queue: Arc<Queue>, instances: &[CullInstance], range: DrawRange) -> Self {
+        if instances.is_empty() {
+            panic!("Can't make a GPU culler with no instances");
+        }
+
+        let device = queue.device().clone();
+        let num_instances = instances.len() as u32;
+
+        let instances = immutable_slice(queue.clone(), instances);
+        let visible = DeviceLocalBuffer::<[u32]>::array(
+            device.clone(),
+            num_instances as usize,
+            BufferUsage::all(),
+            vec![queue.family()],
+        )
+        .expect("Couldn't create visible instance buffer");
+        let commands = DeviceLocalBuffer::<[DrawIndexedIndirectCommand]>::array(
+            device.clone(),
+            1,
+            BufferUsage::all(),
+            vec![queue.family()],
+        )
+        .expect("Couldn't create indirect command buffer");
+
+        let mut command = range.indirect_command();
+        command.instance_count = 0;
+        let reset = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_source(),
+            std::iter::once(command),
+        )
+        .unwrap();
+
+        let shader = cull_cs::Shader::load(device.clone()).expect("Couldn't load culling shader");
+        let pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &shader.main_entry_point(), &())
+                .expect("Couldn't create culling pipeline"),
+        );
+
+        let empty_pyramid = StorageImage::with_usage(
+            device.clone(),
+            Dimensions::Dim2d {
+                width: 1,
+                height: 1,
+            },
+            PYRAMID_FORMAT,
+            ImageUsage {
+                storage: true,
+                sampled: true,
+                ..ImageUsage::none()
+            },
+            vec![queue.family()],
+        )
+        .expect("Couldn't create empty depth pyramid");
+        let sampler = nearest_sampler(queue.clone());
+
+        Self {
+            instances,
+            num_instances,
+            visible,
+            commands,
+            reset,
+            pipeline,
+            empty_pyramid,
+            sampler,
+            queue,
+        }
+    }
+
+    // records the culling for a camera with view_proj, see the top of the
+    // file for where. has to be outside of a render pass.
+    pub fn record(
+        &self,
+        cmd_buf: AutoCommandBufferBuilder,
+        view_proj: &Mat4,
+    ) -> AutoCommandBufferBuilder {
+        self.record_inner(cmd_buf, view_proj, None)
+    }
+
+    // same, but also culls against pyramid, which has to have been recorded
+    // before this
+    pub fn record_with_occlusion(
+        &self,
+        cmd_buf: AutoCommandBufferBuilder,
+        view_proj: &Mat4,
+        pyramid: &DepthPyramid,
+    ) -> AutoCommandBufferBuilder {
+        self.record_inner(cmd_buf, view_proj, Some(pyramid))
+    }
+
+    fn record_inner(
+        &self,
+        cmd_buf: AutoCommandBufferBuilder,
+        view_proj: &Mat4,
+        pyramid: Option<&DepthPyramid>,
+    ) -> AutoCommandBufferBuilder {
+        let mut occlusion = cull_cs::ty::Occlusion {
+            view_proj: (*view_proj).into(),
+            levels: [[0.0; 4]; MAX_PYRAMID_LEVELS],
+            depth_size: [0.0; 2],
+            num_levels: 0,
+            reverse_depth: 0,
+        };
+        let pyramid_image: Arc<dyn ImageViewAccess + Send + Sync> = match pyramid {
+            Some(pyramid) => {
+                for (idx, level) in pyramid.levels().iter().enumerate() {
+                    occlusion.levels[idx] = [
+                        level.offset[0] as f32,
+                        level.offset[1] as f32,
+                        level.size[0] as f32,
+                        level.size[1] as f32,
+                    ];
+                }
+                let depth_dims = pyramid.depth_dims();
+                occlusion.depth_size = [depth_dims[0] as f32, depth_dims[1] as f32];
+                occlusion.num_levels = pyramid.levels().len() as i32;
+                occlusion.reverse_depth = pyramid.reverse_depth as i32;
+                pyramid.image()
+            }
+            None => self.empty_pyramid.clone(),
+        };
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                .add_buffer(self.instances.clone())
+                .unwrap()
+                .add_buffer(self.visible.clone())
+                .unwrap()
+                .add_buffer(self.commands.clone())
+                .unwrap()
+                .add_sampled_image(pyramid_image, self.sampler.clone())
+                .unwrap()
+                .add_buffer(upload_data(self.queue.device().clone(), occlusion))
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let frustum = cull_cs::ty::Frustum {
+            planes: frustum_planes(view_proj),
+            instance_count: self.num_instances,
+        };
+        let groups = (self.num_instances + GROUP_SIZE - 1) / GROUP_SIZE;
+
+        cmd_buf
+            .copy_buffer(self.reset.clone(), self.commands.clone())
+            .expect("Couldn't record reset of culling command")
+            .dispatch([groups, 1, 1], self.pipeline.clone(), set, frustum)
+            .expect("Couldn't record culling dispatch")
+    }
+
+    // the draw command for add_object_indirect, filled in by record
+    pub fn commands(&self) -> Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>> {
+        self.commands.clone()
+    }
+
+    // gives object the set with the instances and the visible list, see the
+    // top of the file. pipeline_cache has to be for the pass object is drawn
+    // in.
+    pub fn attach<C: Collection>(
+        &self,
+        object: Object<C>,
+        pipeline_cache: &mut PipelineCache,
+        set_idx: usize,
+    ) -> Object<CulledCollection<C>> {
+        let pipeline = pipeline_cache.get(&object.pipeline_spec);
+        let instance_set = Arc::new(
+            PersistentDescriptorSet::start(pipeline, set_idx)
+                .add_buffer(self.instances.clone())
+                .expect(&format!(
+                    "Panic adding instance buffer at set idx {}",
+                    set_idx
+                ))
+                .add_buffer(self.visible.clone())
+                .expect(&format!(
+                    "Panic adding visible buffer at set idx {}",
+                    set_idx
+                ))
+                .build()
+                .expect(&format!("Panic finalizing set at set idx {}", set_idx)),
+        );
+
+        Object {
+            pipeline_spec: object.pipeline_spec,
+            vbufs: object.vbufs,
+            ibuf: object.ibuf,
+            collection: CulledCollection {
+                inner: object.collection,
+                instance_set,
+            },
+            custom_dynamic_state: object.custom_dynamic_state,
+        }
+    }
+}
+
+// an object's own sets followed by the one from GpuCuller::attach
+#[derive(Clone)]
+pub struct CulledCollection<C: Collection> {
+    pub inner: C,
+    pub instance_set: Arc<dyn DescriptorSet + Send + Sync>,
+}
+
+impl<C: Collection> Collection for CulledCollection<C> {
+    fn get(&self) -> Vec<Arc<dyn DescriptorSet + Send + Sync>> {
+        let mut sets = self.inner.get();
+        sets.push(self.instance_set.clone());
+        sets
+    }
+}
+
+// the planes of the frustum of view_proj, pointing inwards and normalized so
+// distances to them are in world units. like Aabb::outside_frustum the near
+// and far planes are at -w and w, which is conservative for Vulkan's depth
+// range and works with reverse depth.
+pub fn frustum_planes(view_proj: &Mat4) -> [[f32; 4]; 6] {
+    let w = row(view_proj, 3);
+
+    let mut planes = [[0.0; 4]; 6];
+    for axis in 0..3 {
+        for (side, sign) in [1.0, -1.0].iter().enumerate() {
+            let plane = w + row(view_proj, axis) * *sign;
+            let plane = plane / length(&vec4_to_vec3(&plane));
+            planes[axis * 2 + side] = [plane.x, plane.y, plane.z, plane.w];
+        }
+    }
+
+    planes
+}
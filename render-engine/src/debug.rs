@@ -0,0 +1,482 @@
+// Helpers for drawing things that help with debugging, like wireframes.
+
+use vulkano::device::Queue;
+use vulkano::framebuffer::RenderPassAbstract;
+
+use crate::collection::{CollectionData, Data, Set};
+use crate::impl_vertex;
+use crate::mesh::{Aabb, Mesh, PrimitiveTopology, VertexAttributes};
+use crate::object::{Object, ObjectPrototype, PipelineOptions};
+use crate::pipeline_cache::PipelineCache;
+use crate::shaders::relative_path;
+use crate::system::{Pass, System};
+
+use nalgebra_glm::{vec4, Mat4, Vec3};
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// how far wireframe overlays get pulled towards the camera, see
+// PipelineSpec::depth_bias
+pub const WIREFRAME_DEPTH_BIAS: f32 = -0.0001;
+
+// vertex type for debug geometry, which only ever needs positions
+#[derive(Default, Debug, Clone, Copy)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+}
+impl_vertex!(DebugVertex, position);
+
+impl VertexAttributes for DebugVertex {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn set_position(&mut self, position: [f32; 3]) {
+        self.position = position;
+    }
+}
+
+// vertex type for debug lines that need to be told apart, like normals and
+// tangents
+#[derive(Default, Debug, Clone, Copy)]
+pub struct DebugColorVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+impl_vertex!(DebugColorVertex, position, color);
+
+impl VertexAttributes for DebugColorVertex {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn set_position(&mut self, position: [f32; 3]) {
+        self.position = position;
+    }
+}
+
+// what System's debug lines are seen through, see System::set_debug_camera
+#[derive(Clone)]
+pub struct DebugCamera {
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+}
+
+impl Data for DebugCamera {}
+
+// colors for normal_lines, the usual tangent space axes colors
+pub const NORMAL_COLOR: [f32; 3] = [0.0, 0.0, 1.0];
+pub const TANGENT_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
+pub const BITANGENT_COLOR: [f32; 3] = [0.0, 1.0, 0.0];
+
+// creates a line list with a line of the given length along every vertex's
+// normal, and if tangents is set, its tangent and bitangent too. vertices
+// without a normal (or tangent) are skipped.
+pub fn normal_lines<V: VertexAttributes>(
+    mesh: &Mesh<V>,
+    length: f32,
+    tangents: bool,
+) -> Mesh<DebugColorVertex> {
+    let mut vertices = vec![];
+
+    let mut add_line = |start: [f32; 3], dir: [f32; 3], color: [f32; 3]| {
+        let end = [
+            start[0] + dir[0] * length,
+            start[1] + dir[1] * length,
+            start[2] + dir[2] * length,
+        ];
+        vertices.push(DebugColorVertex {
+            position: start,
+            color,
+        });
+        vertices.push(DebugColorVertex {
+            position: end,
+            color,
+        });
+    };
+
+    for vertex in mesh.vertices.iter() {
+        let position = vertex.position();
+        let normal = match vertex.normal() {
+            Some(normal) => normal,
+            None => continue,
+        };
+        add_line(position, normal, NORMAL_COLOR);
+
+        if !tangents {
+            continue;
+        }
+
+        if let Some(tangent) = vertex.tangent() {
+            let bitangent = Vec3::from(normal).cross(&Vec3::from(tangent));
+            let bitangent: [f32; 3] = (bitangent * vertex.bitangent_sign()).into();
+            add_line(position, tangent, TANGENT_COLOR);
+            add_line(position, bitangent, BITANGENT_COLOR);
+        }
+    }
+
+    let indices = (0..vertices.len() as u32).collect();
+
+    Mesh { vertices, indices }
+}
+
+// converts a triangle list into a line list with a line for every edge
+pub fn wireframe_lines<V: VertexAttributes>(mesh: &Mesh<V>) -> Mesh<DebugVertex> {
+    let vertices = mesh
+        .vertices
+        .iter()
+        .map(|vertex| DebugVertex {
+            position: vertex.position(),
+        })
+        .collect();
+
+    let mut indices = Vec::with_capacity(mesh.indices.len() * 2);
+    for face in mesh.indices.chunks(3) {
+        if face.len() < 3 {
+            break;
+        }
+
+        indices.extend_from_slice(&[face[0], face[1], face[1], face[2], face[2], face[0]]);
+    }
+
+    Mesh { vertices, indices }
+}
+
+// creates an object drawing mesh's edges as lines, biased towards the camera
+// so they sit on top of the solid version of the mesh instead of z-fighting
+// with it. the vertex shader should take a vec3 position and is given the
+// collection as usual.
+pub fn wireframe_overlay<V: VertexAttributes, D: CollectionData + 'static>(
+    queue: Arc<Queue>,
+    pipeline_cache: &mut PipelineCache,
+    mesh: &Mesh<V>,
+    vs_path: PathBuf,
+    fs_path: PathBuf,
+    collection: D,
+    set_start_idx: usize,
+) -> Object<D::Sets> {
+    ObjectPrototype {
+        vs_path,
+        fs_path,
+        fill_type: PrimitiveTopology::LineList,
+        read_depth: true,
+        write_depth: true,
+        mesh: wireframe_lines(mesh),
+        collection,
+        custom_dynamic_state: None,
+        pipeline_options: PipelineOptions {
+            depth_bias: WIREFRAME_DEPTH_BIAS,
+            ..PipelineOptions::default()
+        },
+    }
+    .build(queue, pipeline_cache, set_start_idx)
+}
+
+// creates an object drawing mesh's normals (and tangents) as lines, see
+// normal_lines. flipped normals point into the mesh, bad tangents don't follow
+// the texture's u direction. the vertex shader should take a vec3 position and
+// a vec3 color and is given the collection as usual.
+pub fn draw_normals<V: VertexAttributes, D: CollectionData + 'static>(
+    queue: Arc<Queue>,
+    pipeline_cache: &mut PipelineCache,
+    mesh: &Mesh<V>,
+    length: f32,
+    tangents: bool,
+    vs_path: PathBuf,
+    fs_path: PathBuf,
+    collection: D,
+    set_start_idx: usize,
+) -> Object<D::Sets> {
+    ObjectPrototype {
+        vs_path,
+        fs_path,
+        fill_type: PrimitiveTopology::LineList,
+        read_depth: true,
+        write_depth: true,
+        mesh: normal_lines(mesh, length, tangents),
+        collection,
+        custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
+    }
+    .build(queue, pipeline_cache, set_start_idx)
+}
+
+// a line list outlining bounds, 8 corners and 12 edges
+pub fn bounds_lines(bounds: &Aabb) -> Mesh<DebugVertex> {
+    box_lines(bounds.corners())
+}
+
+// same as bounds_lines, but with the corners moved by transform first, so the
+// box turns and scales with whatever it's bounding instead of staying lined up
+// with the axes. use this when the shader doesn't apply the model matrix
+// itself, otherwise bounds_lines with the model matrix gives the same thing.
+pub fn oriented_bounds_lines(bounds: &Aabb, transform: &Mat4) -> Mesh<DebugVertex> {
+    let mut corners = bounds.corners();
+    for corner in corners.iter_mut() {
+        let moved = transform * vec4(corner[0], corner[1], corner[2], 1.0);
+        *corner = [moved.x / moved.w, moved.y / moved.w, moved.z / moved.w];
+    }
+
+    box_lines(corners)
+}
+
+// creates an object outlining mesh's bounds, for checking culling and the
+// like. the vertex shader should take a vec3 position and is given the
+// collection as usual.
+pub fn bounds_box<V: VertexAttributes, D: CollectionData + 'static>(
+    queue: Arc<Queue>,
+    pipeline_cache: &mut PipelineCache,
+    mesh: &Mesh<V>,
+    vs_path: PathBuf,
+    fs_path: PathBuf,
+    collection: D,
+    set_start_idx: usize,
+) -> Object<D::Sets> {
+    lines_object(
+        queue,
+        pipeline_cache,
+        bounds_lines(&mesh.bounds()),
+        vs_path,
+        fs_path,
+        collection,
+        set_start_idx,
+    )
+}
+
+// bounds_box with the corners moved by transform, see oriented_bounds_lines
+pub fn oriented_bounds_box<V: VertexAttributes, D: CollectionData + 'static>(
+    queue: Arc<Queue>,
+    pipeline_cache: &mut PipelineCache,
+    mesh: &Mesh<V>,
+    transform: &Mat4,
+    vs_path: PathBuf,
+    fs_path: PathBuf,
+    collection: D,
+    set_start_idx: usize,
+) -> Object<D::Sets> {
+    lines_object(
+        queue,
+        pipeline_cache,
+        oriented_bounds_lines(&mesh.bounds(), transform),
+        vs_path,
+        fs_path,
+        collection,
+        set_start_idx,
+    )
+}
+
+fn box_lines(corners: [[f32; 3]; 8]) -> Mesh<DebugVertex> {
+    let vertices = corners
+        .iter()
+        .map(|&position| DebugVertex { position })
+        .collect();
+    let indices = box_edges().iter().flat_map(|edge| edge.iter().cloned()).collect();
+
+    Mesh { vertices, indices }
+}
+
+// the 12 edges of a box, as pairs of indices into its corners in the order
+// Aabb::corners gives them, where corner i is at max on the axes whose bit is
+// set in i
+pub(crate) fn box_edges() -> Vec<[u32; 2]> {
+    // every pair of corners that differ in exactly one axis is an edge
+    let mut edges = Vec::with_capacity(12);
+    for i in 0..8 {
+        for axis in 0..3 {
+            let j = i | (1 << axis);
+            if j != i {
+                edges.push([i, j]);
+            }
+        }
+    }
+
+    edges
+}
+
+fn lines_object<D: CollectionData + 'static>(
+    queue: Arc<Queue>,
+    pipeline_cache: &mut PipelineCache,
+    mesh: Mesh<DebugVertex>,
+    vs_path: PathBuf,
+    fs_path: PathBuf,
+    collection: D,
+    set_start_idx: usize,
+) -> Object<D::Sets> {
+    ObjectPrototype {
+        vs_path,
+        fs_path,
+        fill_type: PrimitiveTopology::LineList,
+        read_depth: true,
+        write_depth: true,
+        mesh,
+        collection,
+        custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
+    }
+    .build(queue, pipeline_cache, set_start_idx)
+}
+
+/*
+Shows any image of a System fullscreen, by tag, for when something's black and
+it's not clear which pass is at fault. It needs a pass of its own at the end,
+which viewer.pass(render_passes::basic(device.clone())) makes. That pass needs
+the tag being viewed, so the image isn't aliased with another one before it's
+drawn and the System binds it in set 0. So only add it to the System's passes
+while the viewer is on (set_passes), make it again whenever the tag changes,
+and leave it out when the viewer is turned off.
+
+While it's on, call viewer.add_to(&mut system) while drawing its pass, and set
+system.output_tag to "texture_viewer" to see it. Integer and multisampled
+images can't be sampled like this, so they show up as garbage (or not at all).
+ */
+pub struct TextureViewer<'a> {
+    pub tags: Vec<&'a str>,
+    pub tag_idx: usize,
+    pub channel: ViewerChannel,
+    // values at range[0] are black, at range[1] white. depth is mostly close
+    // to 1, so something like [0.99, 1.0] shows it better.
+    pub range: [f32; 2],
+    queue: Arc<Queue>,
+    object: Object<(Set<(ViewerParams,)>,)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewerChannel {
+    Rgb,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl ViewerChannel {
+    pub fn next(&self) -> Self {
+        match self {
+            ViewerChannel::Rgb => ViewerChannel::Red,
+            ViewerChannel::Red => ViewerChannel::Green,
+            ViewerChannel::Green => ViewerChannel::Blue,
+            ViewerChannel::Blue => ViewerChannel::Alpha,
+            ViewerChannel::Alpha => ViewerChannel::Rgb,
+        }
+    }
+
+    fn mask(&self) -> [f32; 4] {
+        match self {
+            ViewerChannel::Rgb => [0.0, 0.0, 0.0, 0.0],
+            ViewerChannel::Red => [1.0, 0.0, 0.0, 0.0],
+            ViewerChannel::Green => [0.0, 1.0, 0.0, 0.0],
+            ViewerChannel::Blue => [0.0, 0.0, 1.0, 0.0],
+            ViewerChannel::Alpha => [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+// only read by the shader
+#[allow(dead_code)]
+#[derive(Clone)]
+struct ViewerParams {
+    channel: [f32; 4],
+    range: [f32; 4],
+}
+
+impl Data for ViewerParams {}
+
+impl<'a> TextureViewer<'a> {
+    // pipeline_cache has to be for the viewer's pass
+    pub fn new(queue: Arc<Queue>, pipeline_cache: &mut PipelineCache, tags: Vec<&'a str>) -> Self {
+        assert!(!tags.is_empty(), "TextureViewer needs at least one tag");
+
+        let channel = ViewerChannel::Rgb;
+        let range = [0.0, 1.0];
+        let params = ViewerParams {
+            channel: channel.mask(),
+            range: [range[0], range[1], 0.0, 0.0],
+        };
+
+        let object = ObjectPrototype {
+            vs_path: relative_path("shaders/debug/texture_viewer_vert.glsl"),
+            fs_path: relative_path("shaders/debug/texture_viewer_frag.glsl"),
+            fill_type: PrimitiveTopology::TriangleStrip,
+            read_depth: false,
+            write_depth: false,
+            mesh: fullscreen_quad(),
+            collection: ((params,),),
+            custom_dynamic_state: None,
+            pipeline_options: PipelineOptions::default(),
+        }
+        .build(queue.clone(), pipeline_cache, 1);
+
+        Self {
+            tags,
+            tag_idx: 0,
+            channel,
+            range,
+            queue,
+            object,
+        }
+    }
+
+    // every tag created by a pass or provided as a custom image, except for
+    // the ones in except (like the viewer's own)
+    pub fn all_tags(system: &System<'a>, except: &[&str]) -> Vec<&'a str> {
+        let mut tags: Vec<&'a str> = vec![];
+        let pass_tags = system
+            .passes
+            .iter()
+            .flat_map(|pass| pass.images_created_tags.iter());
+        for &tag in pass_tags.chain(system.custom_images.keys()) {
+            if !tags.contains(&tag) && !except.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        tags
+    }
+
+    // the pass to draw the viewer in, for the current tag. see above
+    pub fn pass(&self, render_pass: Arc<dyn RenderPassAbstract + Send + Sync>) -> Pass<'a> {
+        Pass {
+            name: "texture_viewer",
+            images_created_tags: vec!["texture_viewer"],
+            images_needed_tags: vec![self.current_tag()],
+            render_pass,
+        }
+    }
+
+    pub fn current_tag(&self) -> &'a str {
+        self.tags[self.tag_idx]
+    }
+
+    pub fn next_tag(&mut self) {
+        self.tag_idx = (self.tag_idx + 1) % self.tags.len();
+    }
+
+    // draws the current tag's image for this frame, has to be called during
+    // the viewer's pass
+    pub fn add_to(&mut self, system: &mut System) {
+        let params = ViewerParams {
+            channel: self.channel.mask(),
+            range: [self.range[0], self.range[1], 0.0, 0.0],
+        };
+
+        self.object.collection.0.data = (params,);
+        self.object.collection.0.upload(self.queue.device().clone());
+
+        system.add_object(&self.object);
+    }
+}
+
+pub(crate) fn fullscreen_quad() -> Mesh<DebugVertex> {
+    let vertices = [[-1.0, -1.0], [-1.0, 1.0], [1.0, -1.0], [1.0, 1.0]]
+        .iter()
+        .map(|corner| DebugVertex {
+            position: [corner[0], corner[1], 0.0],
+        })
+        .collect();
+
+    Mesh {
+        vertices,
+        indices: vec![0, 1, 2, 3],
+    }
+}
@@ -0,0 +1,114 @@
+/*
+Deferred decals: a texture projected onto whatever is already in the depth
+buffer inside a box, like a sticker or a spray. They follow the surface instead
+of being flat quads, so they work on stairs, columns and so on.
+
+The box is the cube from -1 to 1 transformed by the projector matrix, and the
+texture is projected along its z axis. Decals are drawn in a pass after the
+geometry that draws over color, with the depth buffer as its first (and only)
+needed image:
+
+Pass {
+    name: "decals",
+    images_created_tags: vec!["color"],
+    images_needed_tags: vec!["depth"],
+    render_pass: render_passes::overlay(device.clone()),
+}
+
+let decal = Decal { projector, texture }.build(queue, &mut pipeline_cache, camera_data);
+...
+system.add_object(&decal);
+
+The camera data has to start with the view and projection matrices, so it
+matches `uniform Camera { mat4 view; mat4 proj; }`. The texture's alpha is
+used to blend with what's below.
+TODO: deferred renderers want decals on the normal buffer too. for that,
+decal_frag.glsl needs a second output and the render pass another attachment.
+ */
+
+use vulkano::device::Queue;
+
+use nalgebra_glm::{inverse, Mat4};
+
+use crate::collection::{Data, Image, Set};
+use crate::debug::DebugVertex;
+use crate::mesh::{Mesh, PrimitiveTopology};
+use crate::object::{Object, ObjectPrototype, PipelineOptions};
+use crate::pipeline_cache::PipelineCache;
+use crate::shaders::relative_path;
+
+use std::sync::Arc;
+
+pub struct Decal {
+    // transforms the unit cube to the box the decal is projected in
+    pub projector: [[f32; 4]; 4],
+    pub texture: Image,
+}
+
+#[derive(Clone)]
+pub struct DecalData {
+    pub model: [[f32; 4]; 4],
+    pub inv_model: [[f32; 4]; 4],
+}
+
+impl Data for DecalData {}
+
+impl Decal {
+    pub fn data(&self) -> DecalData {
+        let model: Mat4 = self.projector.into();
+
+        DecalData {
+            model: self.projector,
+            inv_model: inverse(&model).into(),
+        }
+    }
+
+    // pipeline_cache has to be for the decal pass
+    pub fn build<C: Data>(
+        &self,
+        queue: Arc<Queue>,
+        pipeline_cache: &mut PipelineCache,
+        camera: C,
+    ) -> Object<(Set<(DecalData,)>, Set<(Image,)>, Set<(C,)>)> {
+        ObjectPrototype {
+            vs_path: relative_path("shaders/decal/decal_vert.glsl"),
+            fs_path: relative_path("shaders/decal/decal_frag.glsl"),
+            fill_type: PrimitiveTopology::TriangleList,
+            read_depth: false,
+            write_depth: false,
+            mesh: unit_cube(),
+            collection: ((self.data(),), (self.texture.clone(),), (camera,)),
+            custom_dynamic_state: None,
+            pipeline_options: PipelineOptions {
+                alpha_blend: true,
+                ..PipelineOptions::default()
+            },
+        }
+        .build(queue, pipeline_cache, 1)
+    }
+}
+
+// the cube from -1 to 1. winding doesn't matter, the fragment shader figures
+// out which faces to draw by itself.
+fn unit_cube() -> Mesh<DebugVertex> {
+    let vertices = (0..8)
+        .map(|i| DebugVertex {
+            position: [
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            ],
+        })
+        .collect();
+
+    let indices = vec![
+        // -x, +x
+        0, 2, 4, 2, 6, 4, 1, 5, 3, 3, 5, 7,
+        // -y, +y
+        0, 4, 1, 1, 4, 5, 2, 3, 6, 3, 7, 6,
+        // -z, +z
+        0, 1, 2, 1, 3, 2, 4, 6, 5, 5, 6, 7,
+    ];
+
+    Mesh { vertices, indices }
+}
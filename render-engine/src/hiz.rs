@@ -0,0 +1,225 @@
+/*
+A depth pyramid (hierarchical Z) for occlusion culling: the depth image
+halved over and over, each texel keeping the nearest and farthest depth of the
+ones below it. A bounding box covering a few hundred pixels can then be tested
+against the depth with a couple of texel fetches, by picking the level where
+it only covers 2x2 texels.
+
+vulkano 0.14 can't make views of single mip levels to write to, so instead of
+a mip chain the levels are laid out side by side in one storage image, level 0
+(half the depth image's size) at the left. levels() has where each one is.
+Sizes are rounded down and the last row and column of each level cover the
+odd texel out, so it works for any size, not just powers of two.
+
+let depth = system.get_image("depth_prepass");
+system.record_custom(|cmd_buf| {
+    let cmd_buf = pyramid.record(cmd_buf, depth);
+    culler.record_with_occlusion(cmd_buf, &view_proj, &pyramid)
+});
+
+Something has to be in the depth image first, e.g. a depth prepass of the big
+occluders, or last frame's depth.
+ */
+
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor::pipeline_layout::PipelineLayout;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, ImageUsage, ImageViewAccess, StorageImage};
+use vulkano::pipeline::ComputePipeline;
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use std::sync::Arc;
+
+mod hiz_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "shaders/hiz/downsample_comp.glsl"
+    }
+}
+
+// has to match the compute shader's workgroup size
+const GROUP_SIZE: u32 = 8;
+
+// culling.rs has room for this many
+pub const MAX_PYRAMID_LEVELS: usize = 16;
+
+pub const PYRAMID_FORMAT: Format = Format::R32G32B32A32Sfloat;
+
+// where a level is in the pyramid image, in texels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PyramidLevel {
+    pub offset: [u32; 2],
+    pub size: [u32; 2],
+}
+
+pub struct DepthPyramid {
+    // whether nearer is bigger in the depth image, which flips what occludes
+    // what when culling
+    pub reverse_depth: bool,
+    depth_dims: [u32; 2],
+    levels: Vec<PyramidLevel>,
+    image: Arc<StorageImage<Format>>,
+    pipeline: Arc<ComputePipeline<PipelineLayout<hiz_cs::Layout>>>,
+    sampler: Arc<Sampler>,
+    queue: Arc<Queue>,
+}
+
+impl DepthPyramid {
+    // depth_dims is the size of the depth image, it's remade if that changes
+    pub fn new(queue: Arc<Queue>, depth_dims: [u32; 2], reverse_depth: bool) -> Self {
+        let device = queue.device().clone();
+
+        let shader = hiz_cs::Shader::load(device.clone()).expect("Couldn't load Hi-Z shader");
+        let pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &shader.main_entry_point(), &())
+                .expect("Couldn't create Hi-Z pipeline"),
+        );
+        let sampler = nearest_sampler(queue.clone());
+
+        let levels = pyramid_levels(depth_dims);
+        let image = create_pyramid_image(queue.clone(), &levels);
+
+        Self {
+            reverse_depth,
+            depth_dims,
+            levels,
+            image,
+            pipeline,
+            sampler,
+            queue,
+        }
+    }
+
+    // records building the pyramid from depth, which has to be a depth image
+    // that can be sampled (every image System creates can be). has to be
+    // outside of a render pass.
+    pub fn record(
+        &mut self,
+        cmd_buf: AutoCommandBufferBuilder,
+        depth: Arc<dyn ImageViewAccess + Send + Sync>,
+    ) -> AutoCommandBufferBuilder {
+        let dims = depth.dimensions();
+        let depth_dims = [dims.width(), dims.height()];
+        if depth_dims != self.depth_dims {
+            self.depth_dims = depth_dims;
+            self.levels = pyramid_levels(depth_dims);
+            self.image = create_pyramid_image(self.queue.clone(), &self.levels);
+        }
+
+        let mut cmd_buf = cmd_buf;
+        for (idx, level) in self.levels.iter().enumerate() {
+            let (src_offset, src_size) = if idx == 0 {
+                ([0, 0], depth_dims)
+            } else {
+                (self.levels[idx - 1].offset, self.levels[idx - 1].size)
+            };
+
+            let set = Arc::new(
+                PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                    .add_sampled_image(depth.clone(), self.sampler.clone())
+                    .unwrap()
+                    .add_image(self.image.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            let push_constants = hiz_cs::ty::Level {
+                src_offset: to_ivec2(src_offset),
+                src_size: to_ivec2(src_size),
+                dst_offset: to_ivec2(level.offset),
+                dst_size: to_ivec2(level.size),
+                from_depth: (idx == 0) as i32,
+            };
+            let groups = [
+                (level.size[0] + GROUP_SIZE - 1) / GROUP_SIZE,
+                (level.size[1] + GROUP_SIZE - 1) / GROUP_SIZE,
+                1,
+            ];
+
+            cmd_buf = cmd_buf
+                .dispatch(groups, self.pipeline.clone(), set, push_constants)
+                .expect("Couldn't record Hi-Z downsample");
+        }
+
+        cmd_buf
+    }
+
+    // every level side by side, r is the nearest depth and g the farthest
+    pub fn image(&self) -> Arc<StorageImage<Format>> {
+        self.image.clone()
+    }
+
+    pub fn levels(&self) -> &[PyramidLevel] {
+        &self.levels
+    }
+
+    pub fn depth_dims(&self) -> [u32; 2] {
+        self.depth_dims
+    }
+}
+
+// halves until both sides are 1, rounding down. stops early if there are more
+// than MAX_PYRAMID_LEVELS, which only happens past 2^16 texels.
+fn pyramid_levels(depth_dims: [u32; 2]) -> Vec<PyramidLevel> {
+    let mut levels: Vec<PyramidLevel> = vec![];
+    let mut size = depth_dims;
+    let mut x = 0;
+
+    while levels.len() < MAX_PYRAMID_LEVELS {
+        size = [(size[0] / 2).max(1), (size[1] / 2).max(1)];
+        levels.push(PyramidLevel {
+            offset: [x, 0],
+            size,
+        });
+        x += size[0];
+
+        if size == [1, 1] {
+            break;
+        }
+    }
+
+    levels
+}
+
+fn create_pyramid_image(queue: Arc<Queue>, levels: &[PyramidLevel]) -> Arc<StorageImage<Format>> {
+    let width = levels.iter().map(|level| level.size[0]).sum();
+    let height = levels[0].size[1];
+
+    StorageImage::with_usage(
+        queue.device().clone(),
+        Dimensions::Dim2d { width, height },
+        PYRAMID_FORMAT,
+        ImageUsage {
+            storage: true,
+            sampled: true,
+            ..ImageUsage::none()
+        },
+        vec![queue.family()],
+    )
+    .expect("Couldn't create Hi-Z image")
+}
+
+// texelFetch ignores filtering, but depth formats don't have to support linear
+// filtering at all, so don't ask for it
+pub(crate) fn nearest_sampler(queue: Arc<Queue>) -> Arc<Sampler> {
+    Sampler::new(
+        queue.device().clone(),
+        Filter::Nearest,
+        Filter::Nearest,
+        MipmapMode::Nearest,
+        SamplerAddressMode::ClampToEdge,
+        SamplerAddressMode::ClampToEdge,
+        SamplerAddressMode::ClampToEdge,
+        0.0,
+        1.0,
+        0.0,
+        0.0,
+    )
+    .expect("Couldn't create sampler")
+}
+
+fn to_ivec2(v: [u32; 2]) -> [i32; 2] {
+    [v[0] as i32, v[1] as i32]
+}
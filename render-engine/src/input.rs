@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
 use std::time::Instant;
 pub use winit::{Event, EventsLoop, KeyboardInput, VirtualKeyCode, WindowEvent};
 
@@ -9,6 +13,12 @@ pub struct EventHandler {
     last_frame_time: Instant,
     start_time: Instant,
     frames_drawn: u32,
+    // if set, every frame pretends exactly this much time has passed
+    fixed_timestep: Option<f32>,
+    // see start_recording and start_replay
+    recording: Option<BufWriter<File>>,
+    replay: Option<std::vec::IntoIter<RecordedFrame>>,
+    replay_frame: Option<RecordedFrame>,
 }
 
 // information about the current frame
@@ -19,8 +29,17 @@ pub struct FrameInfo {
     pub keyups: Vec<VirtualKeyCode>,
     pub keys_down: KeysDown,
     pub mouse_movement: [f32; 2],
+    // in pixels from the top left of the window, see cursor_pixels
+    pub cursor_position: [f32; 2],
     pub delta: f32,
+    // total time passed, as the sum of all deltas so far. use this instead of
+    // the wall clock for animations so they respect the fixed timestep.
+    pub elapsed: f32,
+    pub frame_num: u32,
     pub dimensions: [u32; 2],
+    // see action_active. kept from frame to frame, change it with
+    // Window::action_map_mut.
+    pub actions: ActionMap,
 }
 
 impl EventHandler {
@@ -31,15 +50,89 @@ impl EventHandler {
             last_frame_time: Instant::now(),
             start_time: Instant::now(),
             frames_drawn: 0,
+            fixed_timestep: None,
+            recording: None,
+            replay: None,
+            replay_frame: None,
         }
     }
 
+    pub fn set_fixed_timestep(&mut self, timestep: Option<f32>) {
+        self.fixed_timestep = timestep;
+    }
+
+    // writes every frame's input to path from now on, for replaying it with
+    // start_replay later. overwrites whatever's at path.
+    pub fn start_recording(&mut self, path: &Path) {
+        let file = File::create(path)
+            .unwrap_or_else(|e| panic!("Couldn't create input recording {:?}: {}", path, e));
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "{}", RECORDING_HEADER).expect("Couldn't write input recording");
+        self.recording = Some(writer);
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(mut writer) = self.recording.take() {
+            writer.flush().expect("Couldn't write input recording");
+        }
+    }
+
+    // plays back input recorded with start_recording, one recorded frame per
+    // update, instead of the live input. the recorded deltas are used too, so
+    // the replay takes exactly the same steps as the recording did. goes back
+    // to live input when the recording runs out.
+    // NOTE: only keys, the mouse and delta are recorded. while
+    // replaying, all_events only has close requests, so anything reading raw
+    // winit events (mouse clicks, for example) won't see them.
+    pub fn start_replay(&mut self, path: &Path) {
+        let file = File::open(path)
+            .unwrap_or_else(|e| panic!("Couldn't open input recording {:?}: {}", path, e));
+        let mut lines = BufReader::new(file).lines();
+
+        match lines.next() {
+            Some(Ok(ref header)) if header == RECORDING_HEADER => {}
+            _ => panic!("{:?} isn't an input recording", path),
+        }
+
+        let frames: Vec<RecordedFrame> = lines
+            .enumerate()
+            .map(|(idx, line)| {
+                let line = line.expect("Couldn't read input recording");
+                RecordedFrame::parse(&line)
+                    .unwrap_or_else(|| panic!("Bad frame on line {} of {:?}", idx + 2, path))
+            })
+            .collect();
+
+        self.replay = Some(frames.into_iter());
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
     pub fn update(&mut self, dimensions: [u32; 2]) -> bool {
         // call this before drawing every frame
-        self.frame_info.delta = get_elapsed(self.last_frame_time);
+        let real_delta = get_elapsed(self.last_frame_time);
+
+        self.replay_frame = self.replay.as_mut().and_then(|frames| frames.next());
+        if self.replay.is_some() && self.replay_frame.is_none() {
+            println!("Input replay finished, back to live input");
+            self.replay = None;
+        }
+
+        self.frame_info.delta = match &self.replay_frame {
+            Some(frame) => frame.delta,
+            None => self.fixed_timestep.unwrap_or(real_delta),
+        };
         self.last_frame_time = Instant::now();
         self.frames_drawn += 1;
 
+        // the first frame is skipped so loading times don't count
+        if self.frames_drawn > 1 {
+            self.frame_info.elapsed += self.frame_info.delta;
+        }
+        self.frame_info.frame_num = self.frames_drawn - 1;
+
         // if this is our first frame, reset the start time so that loading
         // times don't affect FPS calculations
         if self.frames_drawn == 1 {
@@ -47,7 +140,14 @@ impl EventHandler {
         }
 
         self.frame_info.dimensions = dimensions;
-        self.collect_events()
+        let done = self.collect_events();
+
+        if let Some(writer) = &mut self.recording {
+            let frame = RecordedFrame::from_frame_info(&self.frame_info);
+            writeln!(writer, "{}", frame.to_line()).expect("Couldn't write input recording");
+        }
+
+        done
     }
 
     pub fn get_fps(&self) -> f32 {
@@ -108,6 +208,21 @@ impl EventHandler {
             all_events.push(ev.clone());
         });
 
+        // while replaying, live input is thrown away
+        let (keydowns, keyups) = match &self.replay_frame {
+            Some(frame) => {
+                all_events.retain(|ev| match ev {
+                    Event::WindowEvent {
+                        event: WindowEvent::CloseRequested,
+                        ..
+                    } => true,
+                    _ => false,
+                });
+                (frame.keydowns.clone(), frame.keyups.clone())
+            }
+            None => (keydowns, keyups),
+        };
+
         // for avoiding problems with borrow checker
         // append all new keydown events to the list, as well as updating keys_down
         keydowns.iter().for_each(|&keycode| {
@@ -180,11 +295,15 @@ impl EventHandler {
         self.frame_info.keyups = keyups;
 
         // calculate mouse movement, assuming it used to be at the center of the screen
-        if let Some(pos) = cursor_pos {
+        if let Some(frame) = &self.replay_frame {
+            self.frame_info.mouse_movement = frame.mouse_movement;
+            self.frame_info.cursor_position = frame.cursor_position;
+        } else if let Some(pos) = cursor_pos {
             let x_diff = pos.x - ((self.frame_info.dimensions[0] / 2) as f64);
             let y_diff = pos.y - ((self.frame_info.dimensions[1] / 2) as f64);
 
             self.frame_info.mouse_movement = [x_diff as f32, y_diff as f32];
+            self.frame_info.cursor_position = [pos.x as f32, pos.y as f32];
         }
 
         self.frame_info.all_events = all_events;
@@ -194,6 +313,32 @@ impl EventHandler {
 }
 
 impl FrameInfo {
+    // where the cursor was last seen, in pixels from the top left of the
+    // window. it doesn't change on frames where the cursor didn't move.
+    // NOTE: with the cursor grabbed (see Window::set_recenter) this is
+    // wherever the mouse got to before being put back in the center, which
+    // isn't very useful.
+    // NOTE: winit gives logical positions, which are only the same as pixels
+    // of the framebuffer if the window's hidpi factor is 1. everything else
+    // in here assumes that too.
+    pub fn cursor_pixels(&self) -> (f32, f32) {
+        (self.cursor_position[0], self.cursor_position[1])
+    }
+
+    // the cursor position in normalized device coordinates, -1 to 1 from the
+    // top left to the bottom right of the window. y points down, same as in
+    // gl_Position, so this can go straight through the inverse view
+    // projection matrix to get a picking ray.
+    pub fn cursor_ndc(&self) -> (f32, f32) {
+        let width = (self.dimensions[0] as f32).max(1.0);
+        let height = (self.dimensions[1] as f32).max(1.0);
+
+        (
+            self.cursor_position[0] / width * 2.0 - 1.0,
+            self.cursor_position[1] / height * 2.0 - 1.0,
+        )
+    }
+
     pub fn empty() -> Self {
         Self {
             all_events: vec![],
@@ -201,10 +346,92 @@ impl FrameInfo {
             keyups: vec![],
             keys_down: KeysDown::all_false(),
             mouse_movement: [0.0, 0.0],
+            cursor_position: [0.0, 0.0],
             delta: 0.0,
+            elapsed: 0.0,
+            frame_num: 0,
             dimensions: [0, 0],
+            actions: ActionMap::new(),
+        }
+    }
+
+    // whether any key bound to action was pressed this frame. panics if
+    // nothing was ever bound to action, it's probably a typo.
+    pub fn action_active(&self, action: &str) -> bool {
+        self.actions
+            .keys(action)
+            .iter()
+            .any(|key| self.keydowns.contains(key))
+    }
+
+    // same as action_active, but for keys let go of this frame
+    pub fn action_released(&self, action: &str) -> bool {
+        self.actions
+            .keys(action)
+            .iter()
+            .any(|key| self.keyups.contains(key))
+    }
+}
+
+// names for things you can do with the keyboard, so code can ask whether
+// "toggle_wireframe" happened instead of whether R was pressed, and keys can
+// be changed in one place:
+//
+// let mut actions = ActionMap::new();
+// actions.bind("next_view", VirtualKeyCode::C);
+// actions.bind("next_view", VirtualKeyCode::Right);
+// window.set_action_map(actions);
+// ...
+// if window.get_frame_info().action_active("next_view") { ... }
+#[derive(Clone, Debug, Default)]
+pub struct ActionMap {
+    bindings: HashMap<String, Vec<VirtualKeyCode>>,
+}
+
+impl ActionMap {
+    pub fn new() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+
+    // adds key to the keys that trigger action, an action can have as many as
+    // you want
+    pub fn bind(&mut self, action: &str, key: VirtualKeyCode) {
+        let keys = self.bindings.entry(action.to_string()).or_default();
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    // replaces whatever keys action had with keys
+    pub fn rebind(&mut self, action: &str, keys: &[VirtualKeyCode]) {
+        self.bindings.insert(action.to_string(), keys.to_vec());
+    }
+
+    // takes all keys away from action. it still exists afterwards, it just
+    // never happens.
+    pub fn unbind(&mut self, action: &str) {
+        self.rebind(action, &[]);
+    }
+
+    pub fn keys(&self, action: &str) -> &[VirtualKeyCode] {
+        match self.bindings.get(action) {
+            Some(keys) => keys,
+            None => panic!("No action called {}", action),
         }
     }
+
+    // every action and its keys, sorted by name. for printing controls.
+    pub fn actions(&self) -> Vec<(&str, &[VirtualKeyCode])> {
+        let mut actions: Vec<(&str, &[VirtualKeyCode])> = self
+            .bindings
+            .iter()
+            .map(|(action, keys)| (action.as_str(), keys.as_slice()))
+            .collect();
+        actions.sort_by_key(|(action, _)| *action);
+        actions
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -270,6 +497,97 @@ impl KeysDown {
     }
 }
 
+const RECORDING_HEADER: &str = "render-engine input recording v2";
+
+// one frame of input, as written by EventHandler::start_recording. the rest of
+// FrameInfo either follows from this (keys_down, elapsed) or can't be written
+// out (all_events, which are winit's).
+#[derive(Clone, Debug)]
+struct RecordedFrame {
+    delta: f32,
+    mouse_movement: [f32; 2],
+    cursor_position: [f32; 2],
+    keydowns: Vec<VirtualKeyCode>,
+    keyups: Vec<VirtualKeyCode>,
+}
+
+impl RecordedFrame {
+    fn from_frame_info(frame_info: &FrameInfo) -> Self {
+        Self {
+            delta: frame_info.delta,
+            mouse_movement: frame_info.mouse_movement,
+            cursor_position: frame_info.cursor_position,
+            keydowns: frame_info.keydowns.clone(),
+            keyups: frame_info.keyups.clone(),
+        }
+    }
+
+    // "delta mouse_x mouse_y cursor_x cursor_y;keydowns;keyups", keys as
+    // comma-separated numbers. floats are printed with {:?} so they read back
+    // exactly.
+    fn to_line(&self) -> String {
+        let keys = |keys: &[VirtualKeyCode]| -> String {
+            keys.iter()
+                .map(|&key| (key as u32).to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        format!(
+            "{:?} {:?} {:?} {:?} {:?};{};{}",
+            self.delta,
+            self.mouse_movement[0],
+            self.mouse_movement[1],
+            self.cursor_position[0],
+            self.cursor_position[1],
+            keys(&self.keydowns),
+            keys(&self.keyups)
+        )
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let parts: Vec<&str> = line.split(';').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+
+        let numbers: Vec<f32> = parts[0]
+            .split_whitespace()
+            .map(|n| n.parse().ok())
+            .collect::<Option<_>>()?;
+        if numbers.len() != 5 {
+            return None;
+        }
+
+        let keys = |part: &str| -> Option<Vec<VirtualKeyCode>> {
+            part.split(',')
+                .filter(|n| !n.is_empty())
+                .map(|n| keycode_from_u32(n.parse().ok()?))
+                .collect()
+        };
+
+        Some(Self {
+            delta: numbers[0],
+            mouse_movement: [numbers[1], numbers[2]],
+            cursor_position: [numbers[3], numbers[4]],
+            keydowns: keys(parts[1])?,
+            keyups: keys(parts[2])?,
+        })
+    }
+}
+
+// winit's VirtualKeyCode is repr(u32) and has no way to go back from a
+// number, so this transmutes. Cut is the last variant in winit 0.19.
+// NOTE: recordings made with a different winit version might have different
+// numbers for the same keys.
+fn keycode_from_u32(n: u32) -> Option<VirtualKeyCode> {
+    if n <= VirtualKeyCode::Cut as u32 {
+        Some(unsafe { std::mem::transmute::<u32, VirtualKeyCode>(n) })
+    } else {
+        None
+    }
+}
+
 pub fn get_elapsed(start: std::time::Instant) -> f32 {
     start.elapsed().as_secs() as f32 + start.elapsed().subsec_nanos() as f32 / 1_000_000_000.0
 }
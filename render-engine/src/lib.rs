@@ -12,6 +12,8 @@ pub mod input;
 
 pub mod mesh;
 
+pub mod vertex_cache;
+
 pub mod utils;
 
 pub mod window;
@@ -24,6 +26,34 @@ pub mod collection;
 
 pub mod object;
 
+pub mod capture;
+
+pub mod testing;
+
+pub mod debug;
+
+pub mod quality;
+
+pub mod decal;
+
+pub mod shadow;
+
+pub mod particles;
+
+pub mod ribbon;
+
+pub mod resolve;
+
+pub mod capabilities;
+
+pub mod morph;
+
+pub mod streams;
+
+pub mod culling;
+
+pub mod hiz;
+
 // re-exports of vulkano's stuff
 use std::sync::Arc;
 pub type RenderPass = Arc<dyn vulkano::framebuffer::RenderPassAbstract + Send + Sync>;
@@ -2,17 +2,30 @@ pub use vulkano::pipeline::input_assembly::PrimitiveTopology;
 pub use vulkano::impl_vertex;
 
 use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
 use vulkano::buffer::{ImmutableBuffer, BufferAccess};
+use vulkano::command_buffer::DrawIndexedIndirectCommand;
 use vulkano::framebuffer::{RenderPassAbstract, Subpass};
+use vulkano::descriptor::pipeline_layout::PipelineLayoutDesc;
 use vulkano::pipeline::{GraphicsPipelineAbstract, GraphicsPipeline};
-use vulkano::pipeline::depth_stencil::{DepthStencil, Compare};
+use vulkano::pipeline::blend::AttachmentBlend;
+use vulkano::pipeline::depth_stencil::{Compare, DepthStencil, Stencil, StencilOp};
+use vulkano::pipeline::vertex::{SingleBufferDefinition, VertexDefinition, VertexMemberInfo, VertexSource};
+use vulkano::pipeline::shader::{GraphicsEntryPointAbstract, ShaderInterfaceDef};
+
+use nalgebra_glm::{
+    determinant, dot, inverse_transpose, length, mat4_to_mat3, normalize, vec3, vec4, Mat4, Vec3,
+};
 
 use crate::utils::immutable_slice;
 use crate::shaders::ShaderSystem;
+use crate::pipeline_cache::{PipelineSpec, PrepassStage};
 
-use std::sync::Arc;
+use std::sync::{Arc, Once};
 use std::marker::PhantomData;
-use std::any::Any;
+use std::any::{Any, TypeId};
+
+use shade_runner::VertInput;
 
 #[derive(Clone)]
 pub struct Mesh<V: Vertex> {
@@ -20,10 +33,649 @@ pub struct Mesh<V: Vertex> {
     pub indices: Vec<u32>,
 }
 
+impl<V: Vertex> Mesh<V> {
+    pub fn merge(meshes: &[Mesh<V>]) -> Mesh<V> {
+        // merges a list of meshes into a single mesh
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        // we need to offset some indices because the vertices are being merged
+        // into one giant list
+        let mut index_offset = 0;
+        for mesh in meshes.iter() {
+            vertices.extend(mesh.vertices.iter().cloned());
+            indices.extend(mesh.indices.iter().map(|index| index + index_offset));
+
+            index_offset += mesh.vertices.len() as u32;
+        }
+
+        Mesh { vertices, indices }
+    }
+
+    // like merge, but indices aren't offset and the range each mesh ends up
+    // in is returned, so one vertex and index buffer can be bound and each
+    // submesh drawn separately with its own range.
+    pub fn merge_indexed(meshes: &[Mesh<V>]) -> (Mesh<V>, Vec<DrawRange>) {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut ranges = Vec::with_capacity(meshes.len());
+
+        for mesh in meshes.iter() {
+            ranges.push(DrawRange {
+                first_index: indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+
+            vertices.extend(mesh.vertices.iter().cloned());
+            indices.extend(mesh.indices.iter().cloned());
+        }
+
+        (Mesh { vertices, indices }, ranges)
+    }
+
+    // reverses the order of every triangle's vertices, which turns front
+    // faces into back faces. for meshes modeled inside out, together with
+    // flip_normals. assumes a triangle list.
+    pub fn flip_winding(&mut self) {
+        for triangle in self.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+}
+
+// one submesh in a mesh created with merge_indexed. indices in the range are
+// relative to vertex_offset, same as the arguments to vkCmdDrawIndexed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawRange {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+}
+
+impl DrawRange {
+    // for filling the command buffer given to System::add_object_indirect
+    pub fn indirect_command(&self) -> DrawIndexedIndirectCommand {
+        DrawIndexedIndirectCommand {
+            index_count: self.index_count,
+            instance_count: 1,
+            first_index: self.first_index,
+            // vulkano has this as a u32 even though vulkan's is signed, the
+            // bits are the same
+            vertex_offset: self.vertex_offset as u32,
+            first_instance: 0,
+        }
+    }
+}
+
 pub trait Vertex: vulkano::pipeline::vertex::Vertex + Clone {}
 
 impl<V: vulkano::pipeline::vertex::Vertex + Clone> Vertex for V {}
 
+// Vertex only tells vulkano how to feed the vertex to a shader, it doesn't let
+// us read anything back on the CPU. Vertex types that want to use the CPU-side
+// mesh utilities (bounds and so on) need to implement this too.
+pub trait VertexAttributes: Vertex {
+    fn position(&self) -> [f32; 3];
+    fn set_position(&mut self, position: [f32; 3]);
+
+    // vertices without normals or tangents can leave these alone
+    fn normal(&self) -> Option<[f32; 3]> {
+        None
+    }
+    fn set_normal(&mut self, _normal: [f32; 3]) {}
+
+    fn tex_coord(&self) -> Option<[f32; 2]> {
+        None
+    }
+
+    fn tangent(&self) -> Option<[f32; 3]> {
+        None
+    }
+    fn set_tangent(&mut self, _tangent: [f32; 3]) {}
+
+    // which way the bitangent points: cross(normal, tangent) * sign. it's -1
+    // where the texture is mirrored, since mirrored UVs flip tangent space.
+    fn bitangent_sign(&self) -> f32 {
+        1.0
+    }
+    fn set_bitangent_sign(&mut self, _sign: f32) {}
+}
+
+impl<V: VertexAttributes> Mesh<V> {
+    pub fn bounds(&self) -> Aabb {
+        // an empty mesh gets an empty box at the origin
+        if self.vertices.is_empty() {
+            return Aabb {
+                min: [0.0, 0.0, 0.0],
+                max: [0.0, 0.0, 0.0],
+            };
+        }
+
+        let mut min = [std::f32::INFINITY; 3];
+        let mut max = [std::f32::NEG_INFINITY; 3];
+
+        for vertex in self.vertices.iter() {
+            let pos = vertex.position();
+            for axis in 0..3 {
+                min[axis] = min[axis].min(pos[axis]);
+                max[axis] = max[axis].max(pos[axis]);
+            }
+        }
+
+        Aabb { min, max }
+    }
+
+    // applies a transformation matrix to every vertex. positions get the
+    // matrix itself, normals get its inverse-transpose so they stay
+    // perpendicular to the surface under non-uniform scales. tangents lie in
+    // the surface, so they get the matrix without translation.
+    // normals and tangents aren't renormalized, so scales will change their
+    // length. matrices that mirror the mesh flip the bitangent sign too.
+    pub fn transformed(&self, matrix: &Mat4) -> Mesh<V> {
+        let tangent_matrix = mat4_to_mat3(matrix);
+        let normal_matrix = mat4_to_mat3(&inverse_transpose(*matrix));
+        let mirrors = determinant(&tangent_matrix) < 0.0;
+
+        let vertices = self
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let mut vertex = vertex.clone();
+                let pos = vertex.position();
+                let new_pos = matrix * vec4(pos[0], pos[1], pos[2], 1.0);
+                vertex.set_position([new_pos.x, new_pos.y, new_pos.z]);
+
+                if let Some(normal) = vertex.normal() {
+                    let new_normal = normal_matrix * vec3(normal[0], normal[1], normal[2]);
+                    vertex.set_normal([new_normal.x, new_normal.y, new_normal.z]);
+                }
+
+                if let Some(tangent) = vertex.tangent() {
+                    let new_tangent = tangent_matrix * vec3(tangent[0], tangent[1], tangent[2]);
+                    vertex.set_tangent([new_tangent.x, new_tangent.y, new_tangent.z]);
+                    if mirrors {
+                        let sign = vertex.bitangent_sign();
+                        vertex.set_bitangent_sign(-sign);
+                    }
+                }
+
+                vertex
+            })
+            .collect();
+
+        Mesh {
+            vertices,
+            indices: self.indices.clone(),
+        }
+    }
+
+    // makes every normal unit length and every tangent a unit vector
+    // perpendicular to its normal. run it after transformed, since scales
+    // stretch normals and skew tangents.
+    pub fn renormalize(&mut self) {
+        for vertex in self.vertices.iter_mut() {
+            match (vertex.normal(), vertex.tangent()) {
+                (Some(normal), Some(tangent)) => {
+                    let (normal, tangent) = orthonormalize(normal, tangent);
+                    vertex.set_normal(normal);
+                    vertex.set_tangent(tangent);
+                }
+                (Some(normal), None) => {
+                    vertex.set_normal(normalize_or_keep(normal));
+                }
+                (None, Some(tangent)) => {
+                    vertex.set_tangent(normalize_or_keep(tangent));
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    // points every normal the other way. the bitangent sign is flipped too,
+    // so the bitangent (cross(normal, tangent) * sign) and with it normal
+    // mapping stay the same.
+    pub fn flip_normals(&mut self) {
+        for vertex in self.vertices.iter_mut() {
+            if let Some(normal) = vertex.normal() {
+                vertex.set_normal([-normal[0], -normal[1], -normal[2]]);
+                let sign = vertex.bitangent_sign();
+                vertex.set_bitangent_sign(-sign);
+            }
+        }
+    }
+
+    // guesses whether the mesh was modeled inside out: true if more normals
+    // point towards the center of its bounding box than away from it.
+    // only makes sense for closed, roughly convex meshes, the walls of a room
+    // point inwards on purpose. always false without normals.
+    pub fn normals_point_inward(&self) -> bool {
+        let center = self.bounds().center();
+        let mut inward = 0;
+        let mut outward = 0;
+
+        for vertex in self.vertices.iter() {
+            let normal = match vertex.normal() {
+                Some(normal) => normal,
+                None => return false,
+            };
+            let pos = vertex.position();
+            let d: f32 = (0..3).map(|i| normal[i] * (pos[i] - center[i])).sum();
+
+            // vertices at the center or with a sideways normal don't say much
+            if d < -0.000_001 {
+                inward += 1;
+            } else if d > 0.000_001 {
+                outward += 1;
+            }
+        }
+
+        inward > outward
+    }
+
+    // looks for things that make a mesh render wrong, like an imported asset
+    // that comes out black or with holes. indices are read as a triangle
+    // list. an empty list means nothing was found, not that the mesh looks
+    // right: inside out meshes are normals_point_inward's job.
+    pub fn validate(&self) -> Vec<MeshWarning> {
+        let mut warnings = vec![];
+        let num_vertices = self.vertices.len();
+
+        for (idx, vertex) in self.vertices.iter().enumerate() {
+            if vertex.position().iter().any(|c| !c.is_finite()) {
+                warnings.push(MeshWarning::InvalidPosition { vertex: idx });
+            }
+
+            if let Some(normal) = vertex.normal() {
+                let len =
+                    (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+                if len < 0.000_001 {
+                    warnings.push(MeshWarning::ZeroNormal { vertex: idx });
+                } else if !((len - 1.0).abs() <= NORMAL_LENGTH_TOLERANCE) {
+                    // written so NaN lengths end up here too
+                    warnings.push(MeshWarning::NonUnitNormal {
+                        vertex: idx,
+                        length: len,
+                    });
+                }
+            }
+        }
+
+        for (idx, &index) in self.indices.iter().enumerate() {
+            if index as usize >= num_vertices {
+                warnings.push(MeshWarning::IndexOutOfRange {
+                    index: idx,
+                    value: index,
+                    num_vertices,
+                });
+            }
+        }
+
+        for (idx, tri) in self.indices.chunks_exact(3).enumerate() {
+            // already reported above, and there's nothing to compute with
+            if tri.iter().any(|&i| i as usize >= num_vertices) {
+                continue;
+            }
+
+            let p: Vec<Vec3> = tri
+                .iter()
+                .map(|&i| {
+                    let pos = self.vertices[i as usize].position();
+                    vec3(pos[0], pos[1], pos[2])
+                })
+                .collect();
+            if p.iter().any(|pos| pos.iter().any(|c| !c.is_finite())) {
+                continue;
+            }
+
+            // relative to the edges, so tiny triangles in a tiny mesh are
+            // fine. catches repeated indices and collinear vertices.
+            let e1 = p[1] - p[0];
+            let e2 = p[2] - p[0];
+            if length(&e1.cross(&e2)) <= 0.000_001 * length(&e1) * length(&e2) {
+                warnings.push(MeshWarning::DegenerateTriangle { triangle: idx });
+            }
+        }
+
+        // tangents come from how the tex coords change across each
+        // triangle, without any change they're zero or NaN
+        let has_tangents = self.vertices.iter().any(|v| v.tangent().is_some());
+        if has_tangents {
+            let tex_coords: Vec<Option<[f32; 2]>> =
+                self.vertices.iter().map(|v| v.tex_coord()).collect();
+            let missing = match tex_coords.first() {
+                Some(Some(first)) => tex_coords.iter().all(|tc| *tc == Some(*first)),
+                Some(None) => true,
+                None => false,
+            };
+            if missing {
+                warnings.push(MeshWarning::MissingTexCoords);
+            }
+        }
+
+        warnings
+    }
+}
+
+// normals this far from unit length get a MeshWarning::NonUnitNormal, which
+// is well past float error but catches scaled ones
+pub const NORMAL_LENGTH_TOLERANCE: f32 = 0.01;
+
+// a problem Mesh::validate found. vertex and triangle are indices into the
+// mesh's vertices and its triangles (every 3 indices), index one into indices.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshWarning {
+    // a coordinate is NaN or infinite, the triangles using it disappear or
+    // streak across the screen
+    InvalidPosition {
+        vertex: usize,
+    },
+    // lighting with it gives NaN, usually black or missing pixels
+    ZeroNormal {
+        vertex: usize,
+    },
+    // lighting comes out too bright or dark. Mesh::renormalize fixes it.
+    NonUnitNormal {
+        vertex: usize,
+        length: f32,
+    },
+    // a triangle with no area, which has no normal or tangent of its own and
+    // spreads NaNs to its vertices when they're generated
+    DegenerateTriangle {
+        triangle: usize,
+    },
+    // the vertices have tangents but no tex coords to derive them from, or
+    // all the same one (what an OBJ without any gets converted to)
+    MissingTexCoords,
+    IndexOutOfRange {
+        index: usize,
+        value: u32,
+        num_vertices: usize,
+    },
+}
+
+impl std::fmt::Display for MeshWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MeshWarning::InvalidPosition { vertex } => {
+                write!(f, "vertex {} has a NaN or infinite position", vertex)
+            }
+            MeshWarning::ZeroNormal { vertex } => {
+                write!(f, "vertex {} has a zero length normal", vertex)
+            }
+            MeshWarning::NonUnitNormal { vertex, length } => write!(
+                f,
+                "vertex {} has a normal of length {}, not 1",
+                vertex, length
+            ),
+            MeshWarning::DegenerateTriangle { triangle } => {
+                write!(f, "triangle {} has no area", triangle)
+            }
+            MeshWarning::MissingTexCoords => {
+                write!(
+                    f,
+                    "the mesh has tangents but no tex coords to compute them from"
+                )
+            }
+            MeshWarning::IndexOutOfRange {
+                index,
+                value,
+                num_vertices,
+            } => write!(
+                f,
+                "index {} is {}, but there are only {} vertices",
+                index, value, num_vertices
+            ),
+        }
+    }
+}
+
+// normalizes the normal, then uses Gram-Schmidt to make the tangent a unit
+// vector perpendicular to it. if the tangent is parallel to the normal (or
+// zero), an arbitrary perpendicular one is picked so we never return NaNs.
+pub fn orthonormalize(normal: [f32; 3], tangent: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    let n = vec3(normal[0], normal[1], normal[2]);
+    if length(&n) < 0.000_001 {
+        // nothing sensible to do with a zero normal
+        return (normal, normalize_or_keep(tangent));
+    }
+    let n = n / length(&n);
+
+    let t = vec3(tangent[0], tangent[1], tangent[2]);
+    let mut t: Vec3 = t - n * dot(&n, &t);
+    if length(&t) < 0.000_001 {
+        // any axis that isn't close to the normal works
+        let axis = if n.x.abs() < 0.9 { vec3(1.0, 0.0, 0.0) } else { vec3(0.0, 1.0, 0.0) };
+        t = n.cross(&axis);
+    }
+    let t = t / length(&t);
+
+    ([n.x, n.y, n.z], [t.x, t.y, t.z])
+}
+
+fn normalize_or_keep(v: [f32; 3]) -> [f32; 3] {
+    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if len < 0.000_001 {
+        v
+    } else {
+        [v[0] / len, v[1] / len, v[2] / len]
+    }
+}
+
+// axis-aligned bounding box, in whatever space the vertices are in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut min = self.min;
+        let mut max = self.max;
+        for axis in 0..3 {
+            min[axis] = min[axis].min(other.min[axis]);
+            max[axis] = max[axis].max(other.max[axis]);
+        }
+
+        Aabb { min, max }
+    }
+
+    // combined bounds of several boxes, for example every mesh in a scene
+    pub fn union_all(boxes: &[Aabb]) -> Option<Aabb> {
+        let first = *boxes.first()?;
+        Some(boxes.iter().fold(first, |acc, b| acc.union(b)))
+    }
+
+    pub fn center(&self) -> [f32; 3] {
+        [
+            (self.min[0] + self.max[0]) / 2.0,
+            (self.min[1] + self.max[1]) / 2.0,
+            (self.min[2] + self.max[2]) / 2.0,
+        ]
+    }
+
+    pub fn corners(&self) -> [[f32; 3]; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            [a[0], a[1], a[2]],
+            [b[0], a[1], a[2]],
+            [a[0], b[1], a[2]],
+            [b[0], b[1], a[2]],
+            [a[0], a[1], b[2]],
+            [b[0], a[1], b[2]],
+            [a[0], b[1], b[2]],
+            [b[0], b[1], b[2]],
+        ]
+    }
+
+    // distance from a point to the closest point of the box, 0 if the point
+    // is inside it
+    pub fn distance_to(&self, point: [f32; 3]) -> f32 {
+        let mut sq_dist = 0.0;
+        for axis in 0..3 {
+            let d = (self.min[axis] - point[axis])
+                .max(0.0)
+                .max(point[axis] - self.max[axis]);
+            sq_dist += d * d;
+        }
+
+        sq_dist.sqrt()
+    }
+
+    pub fn farthest_distance_to(&self, point: [f32; 3]) -> f32 {
+        self.corners()
+            .iter()
+            .map(|c| {
+                let d = [c[0] - point[0], c[1] - point[1], c[2] - point[2]];
+                (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    // picks near and far planes that tightly enclose the box for a camera at
+    // eye looking along forward (doesn't have to be normalized). the planes
+    // are perpendicular to forward, so what matters is how far along it the
+    // corners are, not how far they are from the eye.
+    // with a regular depth buffer, precision depends on far / near, so near is
+    // pushed out as far as possible and the ratio is capped. with reverse depth
+    // (near maps to 1.0, float depth buffer) precision is nearly uniform, so
+    // near can safely be much closer to the eye. pass whichever the camera's
+    // projection actually uses.
+    pub fn clip_planes(&self, eye: [f32; 3], forward: [f32; 3], reverse_depth: bool) -> (f32, f32) {
+        let forward = normalize(&vec3(forward[0], forward[1], forward[2]));
+        let depths: Vec<f32> = self
+            .corners()
+            .iter()
+            .map(|c| dot(&vec3(c[0] - eye[0], c[1] - eye[1], c[2] - eye[2]), &forward))
+            .collect();
+        let closest = depths.iter().cloned().fold(std::f32::MAX, f32::min);
+        let farthest = depths.iter().cloned().fold(std::f32::MIN, f32::max);
+
+        // small margins so geometry exactly on the bounds doesn't get clipped
+        let far = (farthest * 1.01).max(0.01);
+        let max_ratio = if reverse_depth { 1_000_000.0 } else { 10_000.0 };
+        // closest is negative if part of the box is behind the eye
+        let near = (closest * 0.99).max(far / max_ratio);
+
+        (near, far)
+    }
+
+    // the box around this one after moving it by transform, e.g. to get
+    // world space bounds from a mesh's bounds and its model matrix
+    pub fn transformed(&self, transform: &Mat4) -> Aabb {
+        let mut min = [std::f32::MAX; 3];
+        let mut max = [std::f32::MIN; 3];
+        for corner in self.corners().iter() {
+            let moved = transform * vec4(corner[0], corner[1], corner[2], 1.0);
+            for axis in 0..3 {
+                min[axis] = min[axis].min(moved[axis]);
+                max[axis] = max[axis].max(moved[axis]);
+            }
+        }
+
+        Aabb { min, max }
+    }
+
+    // whether the box is definitely out of view for a camera with the given
+    // view projection matrix. it's conservative: a box near a corner of the
+    // frustum can be out of view and still return false, but anything that
+    // returns true really can't be seen.
+    // works with both GL and Vulkan depth ranges, and with reverse depth, by
+    // only testing the near and far planes against -w and w.
+    pub fn outside_frustum(&self, view_proj: &Mat4) -> bool {
+        let clip: Vec<_> = self
+            .corners()
+            .iter()
+            .map(|c| view_proj * vec4(c[0], c[1], c[2], 1.0))
+            .collect();
+
+        // outside if every corner is on the wrong side of the same plane
+        (0..3).any(|axis| {
+            clip.iter().all(|c| c[axis] < -c.w) || clip.iter().all(|c| c[axis] > c.w)
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum VertexInputError {
+    // the shader reads an attribute the vertex type doesn't have
+    MissingAttribute { location: u32, name: String },
+    // the vertex type has the attribute, but its type doesn't fit the
+    // shader's, e.g. a vec2 where the shader wants a vec3
+    FormatMismatch {
+        location: u32,
+        name: String,
+        shader_format: Format,
+    },
+}
+
+impl std::fmt::Display for VertexInputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VertexInputError::MissingAttribute { location, name } => write!(
+                f,
+                "shader input `{}` at location {} isn't in the vertex type",
+                name, location
+            ),
+            VertexInputError::FormatMismatch {
+                location,
+                name,
+                shader_format,
+            } => write!(
+                f,
+                "shader input `{}` at location {} expects {:?}, which the vertex type's member doesn't fit",
+                name, location, shader_format
+            ),
+        }
+    }
+}
+
+// compares the inputs a vertex shader expects against the members of V.
+// returns the first mismatch, in location order.
+pub fn check_vertex_input<V: Vertex, I: ShaderInterfaceDef>(
+    shader_inputs: &I,
+) -> Result<(), VertexInputError> {
+    check_vertex_members(shader_inputs, |name| V::member(name))
+}
+
+// check_vertex_input for vertex types spread over several buffers, member
+// finds the attribute for a name in whichever buffer has it
+pub(crate) fn check_vertex_members<I, F>(
+    shader_inputs: &I,
+    find_member: F,
+) -> Result<(), VertexInputError>
+where
+    I: ShaderInterfaceDef,
+    F: Fn(&str) -> Option<VertexMemberInfo>,
+{
+    let mut inputs: Vec<_> = shader_inputs.elements().collect();
+    inputs.sort_by_key(|input| input.location.start);
+
+    for input in inputs {
+        let location = input.location.start;
+        let name = match &input.name {
+            Some(name) => name.to_string(),
+            None => "<unnamed>".to_string(),
+        };
+
+        let member = match find_member(&name) {
+            Some(member) => member,
+            None => return Err(VertexInputError::MissingAttribute { location, name }),
+        };
+
+        let num_locations = input.location.end - input.location.start;
+        if !member.ty.matches(member.array_size, input.format, num_locations) {
+            return Err(VertexInputError::FormatMismatch {
+                location,
+                name,
+                shader_format: input.format,
+            });
+        }
+    }
+
+    Ok(())
+}
+
 pub trait MeshAbstract {
     fn get_vbuf(&self, queue: Arc<Queue>) -> Arc<dyn BufferAccess + Send + Sync>;
     fn get_ibuf(&self, queue: Arc<Queue>) -> Arc<ImmutableBuffer<[u32]>>;
@@ -65,13 +717,164 @@ pub trait VertexTypeAbstract: Any {
         &self,
         device: Arc<Device>,
         shaders: ShaderSystem,
-        fill_type: PrimitiveTopology,
+        spec: &PipelineSpec,
         render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-        read_depth: bool,
-        write_depth: bool,
     ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
 
     fn clone(&self) -> Arc<dyn VertexTypeAbstract>;
+
+    // used to check that two vertex types are the same, since we can't
+    // compare the trait objects directly
+    fn vertex_type_id(&self) -> TypeId;
+
+    fn vertex_type_name(&self) -> &'static str;
+}
+
+// makes the pipeline for spec, taking vertices as described by vertex_input.
+// member looks up the vertex type's attribute for a shader input name, for
+// checking them against each other first: vulkano checks this too when
+// building, but only gives an unwrap panic with no context.
+pub(crate) fn build_pipeline<D, F>(
+    device: Arc<Device>,
+    shaders: ShaderSystem,
+    spec: &PipelineSpec,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    vertex_input: D,
+    vertex_type_name: &str,
+    member: F,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync>
+where
+    D: VertexDefinition<VertInput>
+        + VertexSource<Vec<Arc<dyn BufferAccess + Send + Sync>>>
+        + Send
+        + Sync
+        + 'static,
+    F: Fn(&str) -> Option<VertexMemberInfo>,
+{
+    let (vs_main, fs_main) = shaders.get_entry_points();
+
+    if let Err(e) = check_vertex_members(vs_main.input(), member) {
+        panic!(
+            "Vertex type {} doesn't match the inputs of vertex shader {:?}: {}",
+            vertex_type_name, spec.vs_path, e,
+        );
+    }
+
+    // depth clamp is an optional feature, so fall back to clipping if the
+    // device doesn't have it. every pipeline asking for it would warn
+    // otherwise, once is enough.
+    let depth_clamp = if spec.depth_clamp && !device.enabled_features().depth_clamp {
+        static WARN_DEPTH_CLAMP: Once = Once::new();
+        WARN_DEPTH_CLAMP.call_once(|| {
+            println!("Depth clamp was requested but isn't supported, clipping instead");
+        });
+        false
+    } else {
+        spec.depth_clamp
+    };
+
+    // NOTE: there's no conservative rasterization (every pixel a triangle
+    // touches, not just the ones whose center it covers, for voxelization and
+    // the like). vulkano 0.14 doesn't know VK_EXT_conservative_rasterization,
+    // so the rasterization state has nowhere to put it. once it does, it'd be
+    // a PipelineSpec flag that gets applied here.
+
+    let num_viewports = if spec.num_viewports > 1 && !device.enabled_features().multi_viewport {
+        println!(
+            "{} viewports were requested but multi_viewport isn't supported, using 1",
+            spec.num_viewports
+        );
+        1
+    } else {
+        spec.num_viewports
+    };
+
+    let subpass = Subpass::from(render_pass, spec.subpass).unwrap_or_else(|| {
+        panic!("Render pass has no subpass {} for pipeline with vertex shader {:?}", spec.subpass, spec.vs_path)
+    });
+
+    if !spec.read_depth && !spec.write_depth && spec.stencil.is_none() {
+        // no depth buffer at all
+        let builder = GraphicsPipeline::start()
+            .vertex_input(vertex_input)
+            .vertex_shader(vs_main, ())
+            .primitive_topology(spec.fill_type)
+            .viewports_dynamic_scissors_irrelevant(num_viewports)
+            .depth_clamp(depth_clamp)
+            .fragment_shader(fs_main, ());
+        let builder = if spec.alpha_blend {
+            builder.blend_alpha_blending()
+        } else {
+            builder
+        };
+
+        Arc::new(
+            builder
+                .render_pass(subpass.clone())
+                .build(device)
+                .unwrap()
+        )
+    } else {
+        let mut stencil = DepthStencil::disabled();
+        stencil.depth_compare = if spec.read_depth {
+            spec.depth_compare
+        } else {
+            Compare::Always
+        };
+        stencil.depth_write = spec.write_depth;
+
+        if let Some(stencil_spec) = spec.stencil {
+            let face = Stencil {
+                compare: stencil_spec.compare,
+                pass_op: stencil_spec.pass_op,
+                fail_op: StencilOp::Keep,
+                depth_fail_op: StencilOp::Keep,
+                compare_mask: Some(0xff),
+                write_mask: Some(0xff),
+                reference: Some(stencil_spec.reference),
+            };
+            stencil.stencil_front = face.clone();
+            stencil.stencil_back = face;
+        }
+
+        let builder = GraphicsPipeline::start()
+            .vertex_input(vertex_input)
+            .vertex_shader(vs_main, ())
+            .primitive_topology(spec.fill_type)
+            .viewports_dynamic_scissors_irrelevant(num_viewports)
+            .depth_clamp(depth_clamp)
+            .fragment_shader(fs_main, ())
+            .depth_stencil(stencil);
+        let builder = if spec.prepass_stage == Some(PrepassStage::DepthOnly) {
+            builder.blend_collective(AttachmentBlend {
+                mask_red: false,
+                mask_green: false,
+                mask_blue: false,
+                mask_alpha: false,
+                ..AttachmentBlend::pass_through()
+            })
+        } else if spec.alpha_blend {
+            builder.blend_alpha_blending()
+        } else {
+            builder
+        };
+        let builder = builder.render_pass(subpass.clone());
+
+        if let Some(layout_fs) = &shaders.layout_fs {
+            let layout = shaders
+                .vs
+                .entry
+                .vert_layout
+                .clone()
+                .union(layout_fs.entry.frag_layout.clone())
+                .build(device.clone())
+                .unwrap();
+
+            Arc::new(builder.with_pipeline_layout(device, layout).unwrap())
+        } else {
+            Arc::new(builder.build(device).unwrap())
+        }
+    }
 }
 
 impl<V: Vertex + Send + Sync + Clone + 'static> VertexTypeAbstract for VertexType<V> {
@@ -79,48 +882,20 @@ impl<V: Vertex + Send + Sync + Clone + 'static> VertexTypeAbstract for VertexTyp
         &self,
         device: Arc<Device>,
         shaders: ShaderSystem,
-        fill_type: PrimitiveTopology,
+        spec: &PipelineSpec,
         render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-        read_depth: bool,
-        write_depth: bool,
     ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
-        let (vs_main, fs_main) = shaders.get_entry_points();
-
-        if !read_depth && !write_depth {
-            // no depth buffer at all
-            Arc::new(
-                GraphicsPipeline::start()
-                    .vertex_input_single_buffer::<V>()
-                    .vertex_shader(vs_main, ())
-                    .primitive_topology(fill_type)
-                    .viewports_dynamic_scissors_irrelevant(1)
-                    .fragment_shader(fs_main, ())
-                    .render_pass(Subpass::from(render_pass, 0).unwrap())
-                    .build(device)
-                    .unwrap()
-            )
-        } else {
-            let mut stencil = DepthStencil::disabled();
-            stencil.depth_compare = if read_depth {
-                Compare::LessOrEqual
-            } else {
-                Compare::Always
-            };
-            stencil.depth_write = write_depth;
-
-            Arc::new(
-                GraphicsPipeline::start()
-                    .vertex_input_single_buffer::<V>()
-                    .vertex_shader(vs_main, ())
-                    .primitive_topology(fill_type)
-                    .viewports_dynamic_scissors_irrelevant(1)
-                    .fragment_shader(fs_main, ())
-                    .depth_stencil(stencil)
-                    .render_pass(Subpass::from(render_pass, 0).unwrap())
-                    .build(device)
-                    .unwrap()
-            )
-        }
+        // the vertex input layout comes straight from V, attributes are
+        // matched to the vertex shader's inputs by name
+        build_pipeline(
+            device,
+            shaders,
+            spec,
+            render_pass,
+            SingleBufferDefinition::<V>::new(),
+            std::any::type_name::<V>(),
+            |name| V::member(name),
+        )
     }
 
     fn clone(&self) -> Arc<dyn VertexTypeAbstract> {
@@ -130,4 +905,100 @@ impl<V: Vertex + Send + Sync + Clone + 'static> VertexTypeAbstract for VertexTyp
             }
         )
     }
+
+    fn vertex_type_id(&self) -> TypeId {
+        TypeId::of::<V>()
+    }
+
+    fn vertex_type_name(&self) -> &'static str {
+        std::any::type_name::<V>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impl_vertex;
+    use nalgebra_glm::scale;
+
+    #[derive(Default, Debug, Clone, Copy)]
+    struct TestVertex {
+        position: [f32; 3],
+        normal: [f32; 3],
+        tangent: [f32; 3],
+    }
+    impl_vertex!(TestVertex, position, normal, tangent);
+
+    impl VertexAttributes for TestVertex {
+        fn position(&self) -> [f32; 3] {
+            self.position
+        }
+        fn set_position(&mut self, position: [f32; 3]) {
+            self.position = position;
+        }
+        fn normal(&self) -> Option<[f32; 3]> {
+            Some(self.normal)
+        }
+        fn set_normal(&mut self, normal: [f32; 3]) {
+            self.normal = normal;
+        }
+        fn tangent(&self) -> Option<[f32; 3]> {
+            Some(self.tangent)
+        }
+        fn set_tangent(&mut self, tangent: [f32; 3]) {
+            self.tangent = tangent;
+        }
+    }
+
+    #[test]
+    fn normals_stay_unit_under_non_uniform_scale() {
+        // a triangle in the plane x + y = 1, tilted so the scale below
+        // changes both the length and the direction of its normal
+        let s = 1.0 / 2.0f32.sqrt();
+        let vertex = |position| TestVertex {
+            position,
+            normal: [s, s, 0.0],
+            tangent: [s, -s, 0.0],
+        };
+        let mesh = Mesh {
+            vertices: vec![vertex([1.0, 0.0, 0.0]), vertex([0.0, 1.0, 0.0]), vertex([1.0, 0.0, 1.0])],
+            indices: vec![0, 1, 2],
+        };
+
+        let mut scaled = mesh.transformed(&scale(&Mat4::identity(), &vec3(4.0, 1.0, 0.5)));
+        scaled.renormalize();
+
+        let p = |i: usize| {
+            let pos = scaled.vertices[i].position;
+            vec3(pos[0], pos[1], pos[2])
+        };
+        let edges = [p(1) - p(0), p(2) - p(0)];
+
+        for vertex in scaled.vertices.iter() {
+            let n = vec3(vertex.normal[0], vertex.normal[1], vertex.normal[2]);
+            let t = vec3(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            assert!((length(&n) - 1.0).abs() < 1e-5, "normal has length {}", length(&n));
+            assert!((length(&t) - 1.0).abs() < 1e-5, "tangent has length {}", length(&t));
+            assert!(dot(&n, &t).abs() < 1e-5);
+            for edge in edges.iter() {
+                assert!(dot(&n, edge).abs() < 1e-5, "normal isn't perpendicular to the surface");
+            }
+        }
+    }
+
+    #[test]
+    fn clip_planes_enclose_off_axis_corners() {
+        // a wide box in front of the eye, its near face at z = -10. the
+        // corners are much farther than 10 from the eye, but only 10 along
+        // the view direction, so a near plane at their distance would clip
+        // the middle of the face.
+        let bounds = Aabb {
+            min: [-50.0, -50.0, -20.0],
+            max: [50.0, 50.0, -10.0],
+        };
+        let (near, far) = bounds.clip_planes([0.0, 0.0, 0.0], [0.0, 0.0, -1.0], false);
+
+        assert!(near <= 10.0 && near > 9.0, "near is {}", near);
+        assert!(far >= 20.0 && far < 21.0, "far is {}", far);
+    }
 }
@@ -0,0 +1,144 @@
+/*
+Morph targets (blend shapes), for faces and other deformations that bones
+don't do well. Each target is a set of position offsets from the base mesh,
+and the vertex shader adds them up scaled by per-target weights:
+
+let mesh = morph_mesh(&positions, &normals, indices, &[smile, frown]);
+let mut object = ObjectPrototype {
+    vs_path: relative_path("shaders/face_vert.glsl"),
+    ...
+    mesh,
+    collection: ((camera,), (MorphWeights::new(&[0.0, 0.0]),)),
+    ...
+}
+.build(queue, &mut pipeline_cache, 0);
+
+// every frame
+object.collection.1.data.0 = MorphWeights::new(&[smile_amount, 1.0 - smile_amount]);
+object.collection.1.upload(device.clone());
+
+The offsets live in the vertex buffer next to the base position, so there's a
+fixed number of target slots (MAX_MORPH_TARGETS) and meshes with fewer leave
+the rest zeroed. See shaders/include/morph.glsl for the shader side.
+
+TODO: normals aren't morphed, which is fine for small deformations but makes
+lighting lag behind big ones. would need another MAX_MORPH_TARGETS attributes.
+ */
+
+use crate::collection::Data;
+use crate::impl_vertex;
+use crate::mesh::Mesh;
+
+// how many targets can be active on one mesh. limited by vertex attributes,
+// there's a guaranteed 16 and this already takes 6.
+pub const MAX_MORPH_TARGETS: usize = 4;
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MorphVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    // offsets from position for each target
+    pub delta_0: [f32; 3],
+    pub delta_1: [f32; 3],
+    pub delta_2: [f32; 3],
+    pub delta_3: [f32; 3],
+}
+impl_vertex!(
+    MorphVertex,
+    position,
+    normal,
+    delta_0,
+    delta_1,
+    delta_2,
+    delta_3
+);
+
+// one target's offsets, one per vertex of the base mesh
+#[derive(Clone, Debug)]
+pub struct MorphTarget {
+    pub name: String,
+    pub position_deltas: Vec<[f32; 3]>,
+}
+
+// weights for each target slot, upload it as a uniform
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MorphWeights {
+    pub weights: [f32; MAX_MORPH_TARGETS],
+}
+
+impl Data for MorphWeights {}
+
+impl MorphWeights {
+    // weights for the first weights.len() targets, the rest are 0
+    pub fn new(weights: &[f32]) -> Self {
+        if weights.len() > MAX_MORPH_TARGETS {
+            panic!(
+                "Got {} morph weights, at most {} targets are supported",
+                weights.len(),
+                MAX_MORPH_TARGETS
+            );
+        }
+
+        let mut all = [0.0; MAX_MORPH_TARGETS];
+        all[..weights.len()].copy_from_slice(weights);
+
+        Self { weights: all }
+    }
+}
+
+// builds a mesh with the targets' offsets baked into its vertices. targets
+// end up in the slots in the order given.
+pub fn morph_mesh(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: Vec<u32>,
+    targets: &[MorphTarget],
+) -> Mesh<MorphVertex> {
+    assert_eq!(
+        positions.len(),
+        normals.len(),
+        "Morph mesh needs one normal per position"
+    );
+    if targets.len() > MAX_MORPH_TARGETS {
+        panic!(
+            "Mesh has {} morph targets, at most {} are supported",
+            targets.len(),
+            MAX_MORPH_TARGETS
+        );
+    }
+    for target in targets {
+        if target.position_deltas.len() != positions.len() {
+            panic!(
+                "Morph target {} has {} deltas but the mesh has {} vertices",
+                target.name,
+                target.position_deltas.len(),
+                positions.len()
+            );
+        }
+    }
+
+    let delta = |target: usize, vertex: usize| {
+        targets
+            .get(target)
+            .map(|t| t.position_deltas[vertex])
+            .unwrap_or([0.0; 3])
+    };
+
+    let vertices = (0..positions.len())
+        .map(|i| MorphVertex {
+            position: positions[i],
+            normal: normals[i],
+            delta_0: delta(0, i),
+            delta_1: delta(1, i),
+            delta_2: delta(2, i),
+            delta_3: delta(3, i),
+        })
+        .collect();
+
+    Mesh { vertices, indices }
+}
+
+// finds a target's slot by name, e.g. to set the weight of "smile"
+pub fn target_index(targets: &[MorphTarget], name: &str) -> Option<usize> {
+    targets.iter().position(|target| target.name == name)
+}
@@ -3,19 +3,31 @@ use vulkano::command_buffer::DynamicState;
 use vulkano::descriptor::DescriptorSet;
 use vulkano::device::Queue;
 use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::pipeline::depth_stencil::Compare;
 use vulkano::pipeline::input_assembly::PrimitiveTopology;
 
 use crate::collection::{Collection, CollectionData};
-use crate::mesh::{Mesh, MeshAbstract, Vertex, VertexType};
-use crate::pipeline_cache::{PipelineCache, PipelineSpec};
+use crate::mesh::{Mesh, MeshAbstract, Vertex, VertexAttributes, VertexType, VertexTypeAbstract};
+use crate::pipeline_cache::{PipelineCache, PipelineSpec, StencilSpec};
+
+use nalgebra_glm::Mat4;
 
 use std::path::PathBuf;
 use std::sync::Arc;
 
+// Cloning an object is cheap: the vertex and index buffers and the uploaded
+// descriptor sets are behind Arcs, so the clone shares them with the original
+// and nothing new is allocated on the GPU. Only the CPU side gets copied, i.e.
+// the pipeline spec, dynamic state and the data in the collection's sets.
+// Because of that, changing a clone's collection data and calling upload on it
+// gives the clone a new descriptor set without touching the original's, so
+// cloning per frame and uploading different data to each clone works.
+// Set::upload does allocate a buffer, so that part isn't free.
 #[derive(Clone)]
 pub struct Object<C: Collection> {
     pub pipeline_spec: PipelineSpec,
-    pub vbuf: Arc<dyn BufferAccess + Send + Sync>,
+    // one per vertex stream, usually just the one. see streams.rs.
+    pub vbufs: Vec<Arc<dyn BufferAccess + Send + Sync>>,
     pub ibuf: Arc<ImmutableBuffer<[u32]>>,
     pub collection: C,
     pub custom_dynamic_state: Option<DynamicState>,
@@ -23,19 +35,39 @@ pub struct Object<C: Collection> {
 
 pub trait Drawcall {
     fn pipe_spec(&self) -> &PipelineSpec;
-    fn vbuf(&self) -> Arc<dyn BufferAccess + Send + Sync>;
+    fn vbufs(&self) -> Vec<Arc<dyn BufferAccess + Send + Sync>>;
     fn ibuf(&self) -> Arc<ImmutableBuffer<[u32]>>;
     fn collection(&self) -> Vec<Arc<dyn DescriptorSet + Send + Sync>>;
     fn custom_dynstate(&self) -> Option<DynamicState>;
 }
 
+impl<C: Collection> Object<C> {
+    // replaces the vertex and index buffers, keeping the pipeline and
+    // collection. the new mesh has to have the same vertex type as the one the
+    // object was built with, otherwise the pipeline's vertex input wouldn't
+    // match.
+    pub fn set_mesh<V: Vertex>(&mut self, queue: Arc<Queue>, mesh: &Mesh<V>) {
+        let new_vtype = mesh.get_vtype();
+        if new_vtype.vertex_type_id() != self.pipeline_spec.vtype.vertex_type_id() {
+            panic!(
+                "Can't set mesh with vertex type {} on an object built with vertex type {}",
+                new_vtype.vertex_type_name(),
+                self.pipeline_spec.vtype.vertex_type_name(),
+            );
+        }
+
+        self.vbufs = vec![mesh.get_vbuf(queue.clone())];
+        self.ibuf = mesh.get_ibuf(queue);
+    }
+}
+
 impl<C: Collection> Drawcall for Object<C> {
     fn pipe_spec(&self) -> &PipelineSpec {
         &self.pipeline_spec
     }
 
-    fn vbuf(&self) -> Arc<dyn BufferAccess + Send + Sync> {
-        self.vbuf.clone()
+    fn vbufs(&self) -> Vec<Arc<dyn BufferAccess + Send + Sync>> {
+        self.vbufs.clone()
     }
 
     fn ibuf(&self) -> Arc<ImmutableBuffer<[u32]>> {
@@ -56,8 +88,8 @@ impl<'a, C: Collection> Drawcall for &'a Object<C> {
         &self.pipeline_spec
     }
 
-    fn vbuf(&self) -> Arc<dyn BufferAccess + Send + Sync> {
-        self.vbuf.clone()
+    fn vbufs(&self) -> Vec<Arc<dyn BufferAccess + Send + Sync>> {
+        self.vbufs.clone()
     }
 
     fn ibuf(&self) -> Arc<ImmutableBuffer<[u32]>> {
@@ -73,6 +105,58 @@ impl<'a, C: Collection> Drawcall for &'a Object<C> {
     }
 }
 
+// an object that doesn't have its pipeline or descriptor sets yet, see
+// ObjectPrototype::build_deferred. it can't be drawn until it's finalized: the
+// pipeline comes from the cache of the pass it's drawn in, which System only
+// looks at once it's already got an object with sets.
+#[derive(Clone)]
+pub struct DeferredObject<D: CollectionData> {
+    // can still be changed, e.g. the subpass, since nothing's been made from
+    // it yet
+    pub pipeline_spec: PipelineSpec,
+    pub(crate) vbufs: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+    pub(crate) ibuf: Arc<ImmutableBuffer<[u32]>>,
+    pub(crate) collection: D,
+    pub(crate) set_start_idx: usize,
+    pub(crate) custom_dynamic_state: Option<DynamicState>,
+}
+
+impl<D: CollectionData + 'static> DeferredObject<D> {
+    // creates the pipeline (if the cache doesn't have it yet) and uploads the
+    // collection, giving an object that's ready to draw. pipeline_cache has
+    // to be for the pass the object is going to be drawn in.
+    pub fn finalize(
+        self,
+        queue: Arc<Queue>,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Object<D::Sets> {
+        let pipeline = pipeline_cache.get(&self.pipeline_spec);
+        let collection =
+            self.collection
+                .create_sets(queue.device().clone(), pipeline, self.set_start_idx);
+
+        Object {
+            pipeline_spec: self.pipeline_spec,
+            vbufs: self.vbufs,
+            ibuf: self.ibuf,
+            collection,
+            custom_dynamic_state: self.custom_dynamic_state,
+        }
+    }
+
+    // finalizes all of objects with the same cache
+    pub fn finalize_all(
+        objects: Vec<Self>,
+        queue: Arc<Queue>,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Vec<Object<D::Sets>> {
+        objects
+            .into_iter()
+            .map(|object| object.finalize(queue.clone(), pipeline_cache))
+            .collect()
+    }
+}
+
 #[derive(Clone)]
 pub struct ObjectPrototype<V: Vertex, D: CollectionData> {
     pub vs_path: PathBuf,
@@ -83,6 +167,7 @@ pub struct ObjectPrototype<V: Vertex, D: CollectionData> {
     pub mesh: Mesh<V>,
     pub collection: D,
     pub custom_dynamic_state: Option<DynamicState>,
+    pub pipeline_options: PipelineOptions,
 }
 
 impl<V: Vertex, D: CollectionData + 'static> ObjectPrototype<V, D> {
@@ -92,27 +177,34 @@ impl<V: Vertex, D: CollectionData + 'static> ObjectPrototype<V, D> {
         pipeline_cache: &mut PipelineCache,
         set_start_idx: usize,
     ) -> Object<D::Sets> {
-        let vbuf = self.mesh.get_vbuf(queue.clone());
-        let ibuf = self.mesh.get_ibuf(queue.clone());
-
-        let pipeline_spec = PipelineSpec {
-            vs_path: self.vs_path,
-            fs_path: self.fs_path,
-            fill_type: self.fill_type,
-            read_depth: self.read_depth,
-            write_depth: self.write_depth,
-            vtype: VertexType::<V>::new(),
-        };
-        let pipeline = pipeline_cache.get(&pipeline_spec);
+        self.build_subpass(queue, pipeline_cache, set_start_idx, 0)
+    }
 
-        let collection = self.collection.create_sets(queue.device().clone(), pipeline,
-            set_start_idx);
+    // build, but for a later subpass of the cache's render pass
+    pub fn build_subpass(
+        self,
+        queue: Arc<Queue>,
+        pipeline_cache: &mut PipelineCache,
+        set_start_idx: usize,
+        subpass: u32,
+    ) -> Object<D::Sets> {
+        let mut deferred = self.build_deferred(queue.clone(), set_start_idx);
+        deferred.pipeline_spec.subpass = subpass;
+        deferred.finalize(queue, pipeline_cache)
+    }
 
-        Object {
-            pipeline_spec,
-            vbuf,
-            ibuf,
-            collection,
+    // uploads the mesh, but leaves creating the pipeline (and the descriptor
+    // sets, which need it) for DeferredObject::finalize. compiling shaders is
+    // the slow part of building objects, so this lets you load everything
+    // first and then make all the pipelines in one go, e.g. behind a loading
+    // screen.
+    pub fn build_deferred(self, queue: Arc<Queue>, set_start_idx: usize) -> DeferredObject<D> {
+        DeferredObject {
+            pipeline_spec: self.pipeline_spec(),
+            vbufs: vec![self.mesh.get_vbuf(queue.clone())],
+            ibuf: self.mesh.get_ibuf(queue),
+            collection: self.collection,
+            set_start_idx,
             custom_dynamic_state: self.custom_dynamic_state,
         }
     }
@@ -123,17 +215,10 @@ impl<V: Vertex, D: CollectionData + 'static> ObjectPrototype<V, D> {
         render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
         set_start_idx: usize,
     ) -> Object<D::Sets> {
-        let vbuf = self.mesh.get_vbuf(queue.clone());
+        let vbufs = vec![self.mesh.get_vbuf(queue.clone())];
         let ibuf = self.mesh.get_ibuf(queue.clone());
 
-        let pipeline_spec = PipelineSpec {
-            vs_path: self.vs_path,
-            fs_path: self.fs_path,
-            fill_type: self.fill_type,
-            read_depth: self.read_depth,
-            write_depth: self.write_depth,
-            vtype: VertexType::<V>::new(),
-        };
+        let pipeline_spec = self.pipeline_spec();
 
         let pipeline = pipeline_spec.concrete(queue.device().clone(), render_pass);
 
@@ -142,10 +227,161 @@ impl<V: Vertex, D: CollectionData + 'static> ObjectPrototype<V, D> {
 
         Object {
             pipeline_spec,
-            vbuf,
+            vbufs,
             ibuf,
             collection,
             custom_dynamic_state: self.custom_dynamic_state,
         }
     }
+
+    pub fn pipeline_spec(&self) -> PipelineSpec {
+        default_pipeline_spec(
+            self.vs_path.clone(),
+            self.fs_path.clone(),
+            self.fill_type,
+            self.read_depth,
+            self.write_depth,
+            self.pipeline_options,
+            VertexType::<V>::new(),
+        )
+    }
+}
+
+// the parts of PipelineSpec prototypes don't have their own field for, see
+// PipelineSpec for what each one does. most objects want the defaults:
+// pipeline_options: PipelineOptions::default(),
+// and the rest only change what they need:
+// pipeline_options: PipelineOptions {
+//     stencil: Some(StencilSpec::equal(1)),
+//     ..PipelineOptions::default()
+// },
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PipelineOptions {
+    pub depth_compare: Compare,
+    pub depth_clamp: bool,
+    pub depth_bias: f32,
+    pub stencil: Option<StencilSpec>,
+    pub alpha_blend: bool,
+    pub num_viewports: u32,
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        Self {
+            depth_compare: Compare::LessOrEqual,
+            depth_clamp: false,
+            depth_bias: 0.0,
+            stencil: None,
+            alpha_blend: false,
+            num_viewports: 1,
+        }
+    }
+}
+
+// the spec prototypes start from, everything not given is off or the default
+pub(crate) fn default_pipeline_spec(
+    vs_path: PathBuf,
+    fs_path: PathBuf,
+    fill_type: PrimitiveTopology,
+    read_depth: bool,
+    write_depth: bool,
+    options: PipelineOptions,
+    vtype: Arc<dyn VertexTypeAbstract>,
+) -> PipelineSpec {
+    PipelineSpec {
+        vs_path,
+        fs_path,
+        fill_type,
+        read_depth,
+        write_depth,
+        depth_compare: options.depth_compare,
+        depth_clamp: options.depth_clamp,
+        subpass: 0,
+        depth_bias: options.depth_bias,
+        stencil: options.stencil,
+        alpha_blend: options.alpha_blend,
+        num_viewports: options.num_viewports,
+        prepass_stage: None,
+        vtype,
+    }
+}
+
+// A bunch of objects that never move, merged into a single vertex and index
+// buffer with their transforms baked into the vertices. Drawing it is one draw
+// call instead of one per object, but the parts can't be moved individually
+// anymore, so only use it for static scenery.
+#[derive(Clone)]
+pub struct StaticBatch<C: Collection> {
+    pub object: Object<C>,
+    pub num_parts: usize,
+}
+
+impl<C: Collection> StaticBatch<C> {
+    // every part must have the same pipeline spec, since they all get drawn
+    // with one pipeline. the collection of the first part is used for the
+    // whole batch, so it shouldn't contain anything per-object like a model
+    // matrix - that's what transforms is for.
+    pub fn new<V: VertexAttributes, D: CollectionData<Sets = C> + 'static>(
+        mut parts: Vec<ObjectPrototype<V, D>>,
+        transforms: &[Mat4],
+        queue: Arc<Queue>,
+        pipeline_cache: &mut PipelineCache,
+        set_start_idx: usize,
+    ) -> Self {
+        assert!(
+            !parts.is_empty(),
+            "You tried to create a static batch with no objects!"
+        );
+        assert!(
+            parts.len() == transforms.len(),
+            "There are a different number of parts ({}) than transforms ({}) in a static batch!",
+            parts.len(),
+            transforms.len(),
+        );
+
+        let spec = parts[0].pipeline_spec();
+        for part in parts.iter() {
+            assert!(
+                part.pipeline_spec() == spec,
+                "Every part of a static batch must use the same shaders and fill type!",
+            );
+        }
+
+        let baked: Vec<Mesh<V>> = parts
+            .iter()
+            .zip(transforms)
+            .map(|(part, transform)| part.mesh.transformed(transform))
+            .collect();
+
+        let num_parts = parts.len();
+        let mut base = parts.swap_remove(0);
+        base.mesh = Mesh::merge(&baked);
+
+        Self {
+            object: base.build(queue, pipeline_cache, set_start_idx),
+            num_parts,
+        }
+    }
+}
+
+impl<C: Collection> Drawcall for StaticBatch<C> {
+    fn pipe_spec(&self) -> &PipelineSpec {
+        self.object.pipe_spec()
+    }
+
+    fn vbufs(&self) -> Vec<Arc<dyn BufferAccess + Send + Sync>> {
+        self.object.vbufs()
+    }
+
+    fn ibuf(&self) -> Arc<ImmutableBuffer<[u32]>> {
+        self.object.ibuf()
+    }
+
+    fn collection(&self) -> Vec<Arc<dyn DescriptorSet + Send + Sync>> {
+        self.object.collection()
+    }
+
+    fn custom_dynstate(&self) -> Option<DynamicState> {
+        self.object.custom_dynstate()
+    }
 }
@@ -0,0 +1,325 @@
+/*
+Particles for sparks, smoke, fire and so on: lots of small camera-facing quads
+that get spawned by an emitter, move around for a while and then disappear.
+
+Particles are simulated on the CPU. Every frame their positions, sizes and
+colors go into one uniform buffer, and a single quad is drawn instanced, once
+per particle, with the vertex shader picking its particle by
+gl_InstanceIndex. They're drawn with alpha blending in a pass after the
+geometry that draws over color, with the depth buffer as its first (and only)
+needed image, same as decals:
+
+Pass {
+    name: "particles",
+    images_created_tags: vec!["color"],
+    images_needed_tags: vec!["depth"],
+    render_pass: render_passes::overlay(device.clone()),
+}
+
+let mut sparks = ParticleSystem::new(queue, &mut pipeline_cache, emitter, camera_data);
+...
+sparks.emitter.position = ...;
+sparks.update(frame_info.delta);
+sparks.add_to(&mut system);
+
+The fragment shader does the depth test itself against the depth buffer, and
+fades particles out close to whatever is behind them so they don't get cut
+off in a hard line where they intersect the scene. The camera data has to
+start with the view and projection matrices, like for decals.
+
+TODO: simulating in a compute shader would allow a lot more particles, but
+System doesn't run compute passes yet.
+TODO: alpha blended particles should be sorted back to front. it doesn't
+matter much for small bright things like sparks, but smoke looks wrong where
+particles overlap.
+ */
+
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, TypedBufferAccess};
+use vulkano::command_buffer::DrawIndexedIndirectCommand;
+use vulkano::device::Queue;
+
+use nalgebra_glm::*;
+
+use crate::collection::{Data, Set};
+use crate::debug::DebugVertex;
+use crate::mesh::{Mesh, PrimitiveTopology};
+use crate::object::{Object, ObjectPrototype, PipelineOptions};
+use crate::pipeline_cache::PipelineCache;
+use crate::shaders::relative_path;
+use crate::system::System;
+
+use std::sync::Arc;
+
+// how many particles one system can have alive at once. the instance data is
+// 32 bytes per particle and has to fit in a uniform buffer, which is only
+// guaranteed to be 16KB.
+pub const MAX_PARTICLES: usize = 512;
+
+// where and how particles are spawned, and how they change over their life.
+// ranges are [min, max], particles pick a random value in between.
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    pub position: [f32; 3],
+    // particles spawned per second
+    pub rate: f32,
+    pub direction: [f32; 3],
+    // angle in radians between direction and the furthest a particle can go
+    // from it. PI spawns particles going in every direction.
+    pub spread: f32,
+    pub speed: [f32; 2],
+    // in seconds
+    pub lifetime: [f32; 2],
+    pub gravity: [f32; 3],
+    // fraction of its velocity a particle loses per second
+    pub drag: f32,
+    // size at the start and end of a particle's life, in world units
+    pub size: [f32; 2],
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 0.0],
+            rate: 20.0,
+            direction: [0.0, 1.0, 0.0],
+            spread: 0.3,
+            speed: [1.0, 2.0],
+            lifetime: [1.0, 2.0],
+            gravity: [0.0, 0.0, 0.0],
+            drag: 0.0,
+            size: [0.1, 0.1],
+            start_color: [1.0, 1.0, 1.0, 1.0],
+            end_color: [1.0, 1.0, 1.0, 0.0],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Particle {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    // seconds since the particle was spawned
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+// what the vertex shader gets for every particle. repr(C) so the arrays stay
+// in the order the uniform block has them.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ParticleInstances {
+    // xyz is the position, w the size
+    pub pos_size: [[f32; 4]; MAX_PARTICLES],
+    pub color: [[f32; 4]; MAX_PARTICLES],
+}
+
+impl Data for ParticleInstances {}
+
+pub struct ParticleSystem<C: Data> {
+    pub emitter: Emitter,
+    particles: Vec<Particle>,
+    // particles that should have been spawned but weren't yet, because rate
+    // times delta usually isn't a whole number
+    spawn_debt: f32,
+    rng_state: u32,
+    queue: Arc<Queue>,
+    pub object: Object<(Set<(ParticleInstances,)>, Set<(C,)>)>,
+}
+
+impl<C: Data> ParticleSystem<C> {
+    // pipeline_cache has to be for the particle pass
+    pub fn new(
+        queue: Arc<Queue>,
+        pipeline_cache: &mut PipelineCache,
+        emitter: Emitter,
+        camera: C,
+    ) -> Self {
+        let instances = ParticleInstances {
+            pos_size: [[0.0; 4]; MAX_PARTICLES],
+            color: [[0.0; 4]; MAX_PARTICLES],
+        };
+
+        let object = ObjectPrototype {
+            vs_path: relative_path("shaders/particles/particle_vert.glsl"),
+            fs_path: relative_path("shaders/particles/particle_frag.glsl"),
+            fill_type: PrimitiveTopology::TriangleList,
+            read_depth: false,
+            write_depth: false,
+            mesh: unit_quad(),
+            collection: ((instances,), (camera,)),
+            custom_dynamic_state: None,
+            pipeline_options: PipelineOptions {
+                alpha_blend: true,
+                ..PipelineOptions::default()
+            },
+        }
+        .build(queue.clone(), pipeline_cache, 1);
+
+        Self {
+            emitter,
+            particles: Vec::with_capacity(MAX_PARTICLES),
+            spawn_debt: 0.0,
+            rng_state: 0x9e37_79b9,
+            queue,
+            object,
+        }
+    }
+
+    // spawns new particles and moves the old ones, delta is in seconds
+    pub fn update(&mut self, delta: f32) {
+        let gravity = make_vec3(&self.emitter.gravity);
+        let damping = (1.0 - self.emitter.drag * delta).max(0.0);
+        for particle in self.particles.iter_mut() {
+            particle.velocity = (particle.velocity + gravity * delta) * damping;
+            particle.position += particle.velocity * delta;
+            particle.age += delta;
+        }
+        self.particles
+            .retain(|particle| particle.age < particle.lifetime);
+
+        self.spawn_debt += self.emitter.rate * delta;
+        let count = self.spawn_debt.floor();
+        self.spawn_debt -= count;
+        self.burst(count as usize);
+    }
+
+    // spawns count particles right away, on top of the emitter's rate. stops
+    // at MAX_PARTICLES.
+    pub fn burst(&mut self, count: usize) {
+        let count = count.min(MAX_PARTICLES - self.particles.len());
+        for _ in 0..count {
+            let particle = self.spawn();
+            self.particles.push(particle);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.spawn_debt = 0.0;
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn num_alive(&self) -> usize {
+        self.particles.len()
+    }
+
+    // draws every live particle for this frame, has to be called during the
+    // particle pass. does nothing if there aren't any.
+    pub fn add_to(&mut self, system: &mut System) {
+        if self.particles.is_empty() {
+            return;
+        }
+
+        self.object.collection.0.data.0 = self.instances();
+        self.object.collection.0.upload(self.queue.device().clone());
+
+        let command = DrawIndexedIndirectCommand {
+            index_count: self.object.ibuf.len() as u32,
+            instance_count: self.particles.len() as u32,
+            first_index: 0,
+            vertex_offset: 0,
+            first_instance: 0,
+        };
+        let commands = CpuAccessibleBuffer::from_iter(
+            self.queue.device().clone(),
+            BufferUsage::indirect_buffer(),
+            std::iter::once(command),
+        )
+        .unwrap();
+
+        system.add_object_indirect(&self.object, commands);
+    }
+
+    fn instances(&self) -> ParticleInstances {
+        let mut instances = ParticleInstances {
+            pos_size: [[0.0; 4]; MAX_PARTICLES],
+            color: [[0.0; 4]; MAX_PARTICLES],
+        };
+
+        let start_color = make_vec4(&self.emitter.start_color);
+        let end_color = make_vec4(&self.emitter.end_color);
+        for (i, particle) in self.particles.iter().enumerate() {
+            let t = (particle.age / particle.lifetime).min(1.0);
+            let size = lerp_scalar(self.emitter.size[0], self.emitter.size[1], t);
+            let color = lerp(&start_color, &end_color, t);
+
+            instances.pos_size[i] = [
+                particle.position.x,
+                particle.position.y,
+                particle.position.z,
+                size,
+            ];
+            instances.color[i] = color.into();
+        }
+
+        instances
+    }
+
+    fn spawn(&mut self) -> Particle {
+        // a random direction in the cone around the emitter's direction:
+        // uniform in cos(angle) so particles don't bunch up in the middle
+        let cos_angle = lerp_scalar(1.0, self.emitter.spread.cos(), self.random());
+        let sin_angle = (1.0 - cos_angle * cos_angle).max(0.0).sqrt();
+        let around = self.random() * std::f32::consts::PI * 2.0;
+
+        let forward = normalize(&make_vec3(&self.emitter.direction));
+        // anything not parallel to forward works to build the other two axes
+        let helper = if forward.y.abs() < 0.99 {
+            vec3(0.0, 1.0, 0.0)
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        let right = normalize(&forward.cross(&helper));
+        let up = right.cross(&forward);
+        let direction =
+            forward * cos_angle + (right * around.cos() + up * around.sin()) * sin_angle;
+
+        let speed = lerp_scalar(self.emitter.speed[0], self.emitter.speed[1], self.random());
+        let lifetime = lerp_scalar(
+            self.emitter.lifetime[0],
+            self.emitter.lifetime[1],
+            self.random(),
+        );
+
+        Particle {
+            position: make_vec3(&self.emitter.position),
+            velocity: direction * speed,
+            age: 0.0,
+            lifetime,
+        }
+    }
+
+    // 0 to 1, xorshift32. doesn't need to be good, just fast and not visibly
+    // repeating.
+    fn random(&mut self) -> f32 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 17;
+        self.rng_state ^= self.rng_state << 5;
+        self.rng_state as f32 / std::u32::MAX as f32
+    }
+}
+
+fn lerp_scalar(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// the square from -1 to 1, the vertex shader scales it to the particle's size
+// and turns it to face the camera
+fn unit_quad() -> Mesh<DebugVertex> {
+    let vertices = [[-1.0, -1.0], [-1.0, 1.0], [1.0, -1.0], [1.0, 1.0]]
+        .iter()
+        .map(|corner| DebugVertex {
+            position: [corner[0], corner[1], 0.0],
+        })
+        .collect();
+
+    Mesh {
+        vertices,
+        indices: vec![0, 1, 2, 2, 1, 3],
+    }
+}
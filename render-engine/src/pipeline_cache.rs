@@ -1,5 +1,6 @@
 use vulkano::device::Device;
 use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::pipeline::depth_stencil::{Compare, StencilOp};
 use vulkano::pipeline::input_assembly::PrimitiveTopology;
 use vulkano::pipeline::GraphicsPipelineAbstract;
 
@@ -38,9 +39,93 @@ pub struct PipelineSpec {
     pub fill_type: PrimitiveTopology,
     pub read_depth: bool,
     pub write_depth: bool,
+    // the depth test used if read_depth is set, LessOrEqual by default.
+    // Equal only shades fragments exactly at the depth an earlier draw left,
+    // which is what System::set_depth_prepass uses. for that to work both
+    // draws have to compute gl_Position the exact same way, ideally with the
+    // same vertex shader, otherwise they'll z-fight.
+    pub depth_compare: Compare,
+    // clamps fragments outside the near and far planes instead of clipping
+    // them. useful for shadow casters, which would otherwise leave holes in
+    // the shadow when they're cut off by the near plane.
+    pub depth_clamp: bool,
+    // which subpass of the render pass the pipeline is used in. only matters
+    // for render passes with more than one, like render_passes::depth_input.
+    pub subpass: u32,
+    // pushes fragments' depth away from (positive) or towards (negative) the
+    // camera, so coplanar geometry like wireframe overlays doesn't z-fight.
+    // NOTE: vulkano doesn't let us set the rasterizer's depth bias, so this is
+    // done by shrinking the viewport's depth range instead. that means the
+    // offset is proportional to depth rather than constant (pulling towards
+    // the camera works best, pushing away does little to far geometry).
+    // it's applied per draw and doesn't change the pipeline, so it's not
+    // compared in PartialEq and objects with different biases share one
+    // pipeline. to stack several coplanar objects, give each its own
+    // layer_depth_bias.
+    // TODO: use real dynamic depth bias (vkCmdSetDepthBias) once vulkano
+    // exposes it. 0.14 can't enable it on pipelines or set it in DynamicState.
+    pub depth_bias: f32,
+    // stencil test and write, needs a render pass with a stencil buffer like
+    // render_passes::with_depth_stencil. None disables it.
+    pub stencil: Option<StencilSpec>,
+    // blends the fragment shader's output with what's already in the image
+    // using its alpha, instead of overwriting it
+    pub alpha_blend: bool,
+    // how many viewports the pipeline has. with more than one, a geometry
+    // shader picks one per primitive with gl_ViewportIndex, like when
+    // rendering all six faces of a cubemap at once. System's default dynamic
+    // state gives every one of them the whole framebuffer. needs the
+    // multi_viewport feature, falls back to 1 without it.
+    pub num_viewports: u32,
+    // set by System on the two draws it makes of each object in a pass with a
+    // depth prepass, see System::set_depth_prepass. None everywhere else.
+    pub prepass_stage: Option<PrepassStage>,
     pub vtype: Arc<dyn VertexTypeAbstract>
 }
 
+// the test passes if `reference compare stored_value`, and then pass_op says
+// what to do with the stored value (Replace writes reference). fragments that
+// fail the test are discarded and don't change the stencil buffer.
+// NOTE: vulkano's DynamicState can't set the stencil reference, so it's part
+// of the pipeline. objects with different references get different
+// pipelines, which the cache handles, it's just a few more of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StencilSpec {
+    pub compare: Compare,
+    pub pass_op: StencilOp,
+    pub reference: u32,
+}
+
+impl StencilSpec {
+    // marks every fragment drawn with reference
+    pub fn write(reference: u32) -> Self {
+        Self {
+            compare: Compare::Always,
+            pass_op: StencilOp::Replace,
+            reference,
+        }
+    }
+
+    // only draws where something was marked with reference before
+    pub fn equal(reference: u32) -> Self {
+        Self {
+            compare: Compare::Equal,
+            pass_op: StencilOp::Keep,
+            reference,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrepassStage {
+    // only writes depth: the fragment shader is swapped for an empty one and
+    // color writes are masked off
+    DepthOnly,
+    // only shades fragments whose depth equals what DepthOnly wrote (with
+    // depth_compare Equal), without writing depth
+    Shade,
+}
+
 impl PipelineCache {
     pub fn new(
         device: Arc<Device>,
@@ -101,28 +186,45 @@ impl PipelineCache {
 
 impl PipelineSpec {
     pub fn concrete(&self, device: Arc<Device>, render_pass: Arc<dyn RenderPassAbstract + Send + Sync>) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
-        let shader_sys =
-            ShaderSystem::load_from_file(device.clone(), &self.vs_path, &self.fs_path);
+        let shader_sys = if self.prepass_stage == Some(PrepassStage::DepthOnly) {
+            ShaderSystem::load_depth_only(device.clone(), &self.vs_path, &self.fs_path)
+        } else {
+            ShaderSystem::load_from_file(device.clone(), &self.vs_path, &self.fs_path)
+        };
 
-        self.vtype.create_pipeline(
-            device,
-            shader_sys,
-            self.fill_type,
-            render_pass,
-            self.read_depth,
-            self.write_depth,
-        )
+        self.vtype.create_pipeline(device, shader_sys, self, render_pass)
     }
 }
 
+// how much further towards the camera each layer of layer_depth_bias gets
+// pulled. a bit less than debug::WIREFRAME_DEPTH_BIAS, so a wireframe still
+// shows on top of the first layer.
+pub const DEPTH_LAYER_STEP: f32 = 0.00005;
+
+// depth_bias for stacking coplanar objects like markings on a floor: layer 0
+// is unbiased and each higher layer is drawn on top of the ones below it, no
+// matter what order they're drawn in.
+pub fn layer_depth_bias(layer: u32) -> f32 {
+    -(layer as f32) * DEPTH_LAYER_STEP
+}
+
 impl PartialEq for PipelineSpec {
     fn eq(&self, other: &Self) -> bool {
-        // TODO: compare vtype too
+        // the vertex type matters since the same shaders can be fed by one
+        // interleaved buffer or by separate streams, see streams.rs
         self.vs_path == other.vs_path
             && self.fs_path == other.fs_path
             && self.fill_type == other.fill_type
             && self.read_depth == other.read_depth
             && self.write_depth == other.write_depth
+            && self.depth_compare == other.depth_compare
+            && self.depth_clamp == other.depth_clamp
+            && self.subpass == other.subpass
+            && self.stencil == other.stencil
+            && self.alpha_blend == other.alpha_blend
+            && self.num_viewports == other.num_viewports
+            && self.prepass_stage == other.prepass_stage
+            && self.vtype.vertex_type_id() == other.vtype.vertex_type_id()
     }
 }
 
@@ -134,6 +236,14 @@ impl Clone for PipelineSpec {
             fill_type: self.fill_type,
             read_depth: self.read_depth,
             write_depth: self.write_depth,
+            depth_compare: self.depth_compare,
+            depth_clamp: self.depth_clamp,
+            subpass: self.subpass,
+            depth_bias: self.depth_bias,
+            stencil: self.stencil,
+            alpha_blend: self.alpha_blend,
+            num_viewports: self.num_viewports,
+            prepass_stage: self.prepass_stage,
             vtype: self.vtype.clone(),
         }
     }
@@ -0,0 +1,85 @@
+/*
+Graphics quality presets, for exposing a single "quality" setting to users
+instead of a dozen individual ones.
+
+let settings = QualityPreset::High.settings();
+system.apply_quality(&settings, &["shadow_map"]);
+
+System only handles the parts it owns (shadow map resolution). Anything else,
+like texture filtering or turning post-processing passes on and off, has to be
+done by whoever owns the objects, using the same settings:
+
+obj.collection.1.set_sampler(device, settings.texture_sampler());
+ */
+
+use crate::utils::SamplerSpec;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+// NOTE: there's no MSAA sample count in here. the sample count is baked into
+// each pass's render pass when it's made (see
+// render_passes::multisampled_with_depth), along with the resolve attachment
+// and the subpass layout, so System can't change it afterwards. to tie MSAA
+// to a preset, pick the render pass from the preset and set_passes again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualitySettings {
+    // height of the shadow map images. the width is scaled to keep the
+    // aspect ratio, so patched shadow maps keep their layout.
+    pub shadow_map_size: u32,
+    // max anisotropy for texture samplers, see texture_sampler
+    pub anisotropy: f32,
+    pub post_processing: bool,
+}
+
+impl QualityPreset {
+    pub fn settings(&self) -> QualitySettings {
+        match self {
+            QualityPreset::Low => QualitySettings {
+                shadow_map_size: 256,
+                anisotropy: 1.0,
+                post_processing: false,
+            },
+            QualityPreset::Medium => QualitySettings {
+                shadow_map_size: 512,
+                anisotropy: 4.0,
+                post_processing: true,
+            },
+            QualityPreset::High => QualitySettings {
+                shadow_map_size: 1024,
+                anisotropy: 8.0,
+                post_processing: true,
+            },
+            QualityPreset::Ultra => QualitySettings {
+                shadow_map_size: 2048,
+                anisotropy: 16.0,
+                post_processing: true,
+            },
+        }
+    }
+
+    // cycles through presets, wrapping around from Ultra to Low
+    pub fn next(&self) -> Self {
+        match self {
+            QualityPreset::Low => QualityPreset::Medium,
+            QualityPreset::Medium => QualityPreset::High,
+            QualityPreset::High => QualityPreset::Ultra,
+            QualityPreset::Ultra => QualityPreset::Low,
+        }
+    }
+}
+
+impl QualitySettings {
+    // SamplerSpec::texture() with this preset's anisotropy
+    pub fn texture_sampler(&self) -> SamplerSpec {
+        SamplerSpec {
+            max_anisotropy: self.anisotropy,
+            ..SamplerSpec::texture()
+        }
+    }
+}
@@ -1,14 +1,49 @@
 use vulkano::device::Device;
 use vulkano::format::{ClearValue, Format};
 use vulkano::framebuffer::{LoadOp, RenderPassAbstract, RenderPassDesc};
+use vulkano::pipeline::depth_stencil::Compare;
 
 use std::sync::Arc;
 
+/*
+Color spaces: all color images between passes hold linear colors, and shaders
+should do their lighting math on linear values. Textures loaded as *Srgb
+formats get decoded to linear when sampled.
+
+DEFAULT_COLOR_FORMAT is 8 bits per channel UNORM, which is fine for values from
+0 to 1 but bands and clips HDR values. Passes that produce HDR values (lighting
+before tonemapping, bloom...) should use HDR_COLOR_FORMAT, like the *_hdr
+render passes here.
+
+Only the image written to the window is different. If the window was created
+with an sRGB swapchain (SwapchainConfig::srgb, check with Window::is_srgb) the
+hardware does the linear->sRGB conversion, and the last pass must NOT gamma
+correct. Otherwise the last pass has to do it in its shader, roughly
+pow(color, 1 / 2.2). Either way the render pass of the last pass has to use
+Window::get_format, System checks this.
+
+Dynamic rendering (VK_KHR_dynamic_rendering) would let passes skip these
+render pass objects entirely and just name their attachments. It's a Vulkan 1.3
+extension that vulkano 0.14 doesn't know about, and System leans on render
+passes to figure out what images to create (see images_for_passes), so for now
+every Pass needs one of the functions below.
+TODO: revisit when we move to a vulkano that has it. Pass would get an enum of
+either a render pass or a list of attachment descriptions.
+ */
+
 type RenderPass = Arc<dyn RenderPassAbstract + Send + Sync>;
 
 // TODO: let user provide own format for color buffers
 const DEFAULT_COLOR_FORMAT: Format = vulkano::format::Format::B8G8R8A8Unorm;
 const DEFAULT_DEPTH_FORMAT: Format = vulkano::format::Format::D32Sfloat;
+pub const HDR_COLOR_FORMAT: Format = vulkano::format::Format::R16G16B16A16Sfloat;
+// 32 bits because the warp in evsm.glsl overflows 16 bit floats at anything
+// but tiny exponents
+pub const EVSM_FORMAT: Format = vulkano::format::Format::R32G32B32A32Sfloat;
+// distance to the light divided by far, for point light shadows, see
+// linear_distance
+pub const DISTANCE_FORMAT: Format = vulkano::format::Format::R32Sfloat;
+const DEPTH_STENCIL_FORMAT: Format = vulkano::format::Format::D32Sfloat_S8Uint;
 
 // TODO: resolve_depth is not needed. I think, at least - programs run without
 // it, but make sure no jaggedness in introduced by removing it.
@@ -47,6 +82,65 @@ pub fn multisampled_with_depth(device: Arc<Device>, factor: u32) -> RenderPass {
     )
 }
 
+// multisampled_with_depth, but the multisampled depth is stored so a
+// depth_resolve pass can turn it into a normal depth image, see resolve.rs
+pub fn multisampled_with_depth_resolve(device: Arc<Device>, factor: u32) -> RenderPass {
+    Arc::new(
+        vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                resolve_color: {
+                    load: Clear,
+                    store: Store,
+                    format: DEFAULT_COLOR_FORMAT,
+                    samples: 1,
+                },
+                multisampled_color: {
+                    load: Clear,
+                    store: DontCare,
+                    format: DEFAULT_COLOR_FORMAT,
+                    samples: factor,
+                },
+                multisampled_depth: {
+                    load: Clear,
+                    store: Store,
+                    format: DEFAULT_DEPTH_FORMAT,
+                    samples: factor,
+                }
+            },
+            pass: {
+                color: [multisampled_color],
+                depth_stencil: {multisampled_depth},
+                resolve: [resolve_color]
+            }
+        )
+        .unwrap(),
+    )
+}
+
+// a single-sampled depth image that resolve::depth_resolve_object writes the
+// resolved depth of a multisampled one to. whatever was in it is overwritten.
+pub fn depth_resolve(device: Arc<Device>) -> RenderPass {
+    Arc::new(
+        vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                depth: {
+                    load: DontCare,
+                    store: Store,
+                    format: DEFAULT_DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [],
+                depth_stencil: {depth}
+            }
+        )
+        .unwrap(),
+    )
+}
+
 pub fn multisampled(device: Arc<Device>, factor: u32) -> RenderPass {
     Arc::new(
         vulkano::single_pass_renderpass!(
@@ -102,7 +196,8 @@ pub fn with_depth(device: Arc<Device>) -> RenderPass {
     )
 }
 
-pub fn read_depth(device: Arc<Device>) -> RenderPass {
+// with_depth, but with a linear float color image for HDR
+pub fn with_depth_hdr(device: Arc<Device>) -> RenderPass {
     Arc::new(
         vulkano::single_pass_renderpass!(
             device.clone(),
@@ -110,11 +205,11 @@ pub fn read_depth(device: Arc<Device>) -> RenderPass {
                 color: {
                     load: Clear,
                     store: Store,
-                    format: DEFAULT_COLOR_FORMAT,
+                    format: HDR_COLOR_FORMAT,
                     samples: 1,
                 },
                 depth: {
-                    load: Load,
+                    load: Clear,
                     store: Store,
                     format: DEFAULT_DEPTH_FORMAT,
                     samples: 1,
@@ -129,6 +224,145 @@ pub fn read_depth(device: Arc<Device>) -> RenderPass {
     )
 }
 
+// with_depth, but the depth buffer has a stencil component too, see
+// PipelineSpec::stencil
+pub fn with_depth_stencil(device: Arc<Device>) -> RenderPass {
+    Arc::new(
+        vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: DEFAULT_COLOR_FORMAT,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: Store,
+                    format: DEPTH_STENCIL_FORMAT,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth}
+            }
+        )
+        .unwrap(),
+    )
+}
+
+// how a pass that draws on top of an earlier pass's depth buffer (see
+// read_depth) treats it. the render pass only cares about load, compare is
+// for the objects drawn in it, put it in their PipelineSpec::depth_compare.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReadDepthConfig {
+    // Load keeps the depth that's already in the image, Clear resets it to
+    // 1.0 when the pass starts like a normal pass would
+    pub load: LoadOp,
+    pub compare: Compare,
+}
+
+impl ReadDepthConfig {
+    // depth comes from a prepass that drew the same objects, so only the
+    // frontmost fragment of each pixel passes and gets shaded. the prepass
+    // has to compute gl_Position exactly like the pass does, otherwise
+    // nothing is drawn (see PipelineSpec::depth_compare).
+    pub fn after_prepass() -> Self {
+        Self {
+            load: LoadOp::Load,
+            compare: Compare::Equal,
+        }
+    }
+
+    // depth is kept, but objects can still be drawn in front of it. for
+    // adding to an earlier pass's depth instead of repeating it.
+    pub fn preserve() -> Self {
+        Self {
+            load: LoadOp::Load,
+            compare: Compare::LessOrEqual,
+        }
+    }
+
+    // starts from an empty depth buffer, like with_depth
+    pub fn clear() -> Self {
+        Self {
+            load: LoadOp::Clear,
+            compare: Compare::LessOrEqual,
+        }
+    }
+}
+
+impl Default for ReadDepthConfig {
+    fn default() -> Self {
+        Self::after_prepass()
+    }
+}
+
+// a color image and a depth image that's loaded instead of cleared and stored
+// afterwards, same as read_depth_with(device, ReadDepthConfig::after_prepass()).
+// objects drawn in it should use ReadDepthConfig::after_prepass().compare.
+pub fn read_depth(device: Arc<Device>) -> RenderPass {
+    read_depth_with(device, ReadDepthConfig::after_prepass())
+}
+
+// read_depth, but with the depth load behavior from config. DontCare is
+// treated like Clear, there's no point in a pass reading undefined depth.
+pub fn read_depth_with(device: Arc<Device>, config: ReadDepthConfig) -> RenderPass {
+    // the macro needs the load op spelled out
+    match config.load {
+        LoadOp::Load => Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: DEFAULT_COLOR_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Load,
+                        store: Store,
+                        format: DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        ),
+        LoadOp::Clear | LoadOp::DontCare => Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: DEFAULT_COLOR_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        ),
+    }
+}
+
 pub fn only_depth(device: Arc<Device>) -> RenderPass {
     Arc::new(
         vulkano::single_pass_renderpass!(
@@ -150,7 +384,104 @@ pub fn only_depth(device: Arc<Device>) -> RenderPass {
     )
 }
 
+// a shadow map pass for exponential variance shadow maps: the warped moments
+// go in the color image and the depth image is only for depth testing. the
+// color gets cleared to 0, which isn't what empty space looks like in an EVSM,
+// so draw shadow::evsm_clear_object first.
+pub fn evsm_shadow(device: Arc<Device>) -> RenderPass {
+    Arc::new(
+        vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                moments: {
+                    load: Clear,
+                    store: Store,
+                    format: EVSM_FORMAT,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: DEFAULT_DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [moments],
+                depth_stencil: {depth}
+            }
+        )
+        .unwrap(),
+    )
+}
+
+// a shadow map pass that stores linear distance to the light in a color image
+// instead of post-projection depth, see shaders/include/linear_distance.glsl.
+// the depth image is only for depth testing, so the nearest caster wins.
+// distance should be cleared to 1 (far away), so nothing drawn means lit. that
+// isn't what clear_values_for_pass picks for R32Sfloat, so use
+// System::set_clear_color on the distance image's tag.
+pub fn linear_distance(device: Arc<Device>) -> RenderPass {
+    Arc::new(
+        vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                distance: {
+                    load: Clear,
+                    store: Store,
+                    format: DISTANCE_FORMAT,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: DEFAULT_DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [distance],
+                depth_stencil: {depth}
+            }
+        )
+        .unwrap(),
+    )
+}
+
+// a single color image that keeps what's already in it, for passes that draw
+// on top of an earlier pass's output, like decal::Decal
+pub fn overlay(device: Arc<Device>) -> RenderPass {
+    Arc::new(
+        vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: Load,
+                    store: Store,
+                    format: DEFAULT_COLOR_FORMAT,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )
+        .unwrap(),
+    )
+}
+
 pub fn basic(device: Arc<Device>) -> RenderPass {
+    basic_with_format(device, DEFAULT_COLOR_FORMAT)
+}
+
+pub fn basic_hdr(device: Arc<Device>) -> RenderPass {
+    basic_with_format(device, HDR_COLOR_FORMAT)
+}
+
+// for drawing to a window whose swapchain isn't DEFAULT_COLOR_FORMAT, use
+// Window::get_format
+pub fn basic_with_format(device: Arc<Device>, format: Format) -> RenderPass {
     Arc::new(
         vulkano::single_pass_renderpass!(
             device.clone(),
@@ -158,7 +489,7 @@ pub fn basic(device: Arc<Device>) -> RenderPass {
                 color: {
                     load: Clear,
                     store: Store,
-                    format: DEFAULT_COLOR_FORMAT,
+                    format: format,
                     samples: 1,
                 }
             },
@@ -171,6 +502,189 @@ pub fn basic(device: Arc<Device>) -> RenderPass {
     )
 }
 
+// with_depth, but the color image holds unsigned integers instead of colors.
+// meant for data like object ids for picking, which can't be blended or
+// filtered: write them from the fragment shader to a uint output
+// (layout(location = 0) out uint id) and read them back with a copy instead
+// of sampling them with a linear sampler.
+pub fn uint_color(device: Arc<Device>) -> RenderPass {
+    integer_color_with_depth(device, Format::R32Uint)
+}
+
+// same as uint_color but for signed integers (out int in GLSL)
+pub fn int_color(device: Arc<Device>) -> RenderPass {
+    integer_color_with_depth(device, Format::R32Sint)
+}
+
+fn integer_color_with_depth(device: Arc<Device>, format: Format) -> RenderPass {
+    Arc::new(
+        vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: format,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: Store,
+                    format: DEFAULT_DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth}
+            }
+        )
+        .unwrap(),
+    )
+}
+
+// two subpasses: the first draws color and depth like with_depth, the second
+// draws more color and can read the first's depth as an input attachment
+// (subpassInput in GLSL), for stuff like soft particles. the depth buffer can't
+// be written in the second subpass.
+pub fn depth_input(device: Arc<Device>) -> RenderPass {
+    Arc::new(
+        vulkano::ordered_passes_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: DEFAULT_COLOR_FORMAT,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: Store,
+                    format: DEFAULT_DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            passes: [
+                {
+                    color: [color],
+                    depth_stencil: {depth},
+                    input: []
+                },
+                {
+                    color: [color],
+                    depth_stencil: {},
+                    input: [depth]
+                }
+            ]
+        )
+        .unwrap(),
+    )
+}
+
+// G-buffer fill and lighting in one render pass. the first subpass writes
+// albedo, normals and depth, the second reads them as input attachments and
+// writes the lit result to final_color. the G-buffer is never stored, so on
+// tiled GPUs it can stay in tile memory the whole time.
+// image tags need to be in attachment order: final_color, albedo, normal,
+// depth.
+pub fn deferred(device: Arc<Device>) -> RenderPass {
+    Arc::new(
+        vulkano::ordered_passes_renderpass!(
+            device.clone(),
+            attachments: {
+                final_color: {
+                    load: Clear,
+                    store: Store,
+                    format: DEFAULT_COLOR_FORMAT,
+                    samples: 1,
+                },
+                albedo: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::R8G8B8A8Unorm,
+                    samples: 1,
+                },
+                normal: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::R16G16B16A16Sfloat,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: DEFAULT_DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            passes: [
+                {
+                    color: [albedo, normal],
+                    depth_stencil: {depth},
+                    input: []
+                },
+                {
+                    color: [final_color],
+                    depth_stencil: {},
+                    input: [albedo, normal, depth]
+                }
+            ]
+        )
+        .unwrap(),
+    )
+}
+
+// deferred, but like read_depth the depth buffer is loaded instead of cleared
+// and stored afterwards, so it can come from a depth prepass and later passes
+// can read it
+pub fn deferred_read_depth(device: Arc<Device>) -> RenderPass {
+    Arc::new(
+        vulkano::ordered_passes_renderpass!(
+            device.clone(),
+            attachments: {
+                final_color: {
+                    load: Clear,
+                    store: Store,
+                    format: DEFAULT_COLOR_FORMAT,
+                    samples: 1,
+                },
+                albedo: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::R8G8B8A8Unorm,
+                    samples: 1,
+                },
+                normal: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::R16G16B16A16Sfloat,
+                    samples: 1,
+                },
+                depth: {
+                    load: Load,
+                    store: Store,
+                    format: DEFAULT_DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            passes: [
+                {
+                    color: [albedo, normal],
+                    depth_stencil: {depth},
+                    input: []
+                },
+                {
+                    color: [final_color],
+                    depth_stencil: {},
+                    input: [albedo, normal, depth]
+                }
+            ]
+        )
+        .unwrap(),
+    )
+}
+
 // TODO: add every format to this
 pub fn clear_values_for_pass(
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
@@ -181,12 +695,25 @@ pub fn clear_values_for_pass(
             LoadOp::Clear => match desc.format {
                 Format::B8G8R8A8Unorm => [0.0, 0.0, 0.0, 1.0].into(),
                 Format::R8G8B8A8Unorm => [0.0, 0.0, 0.0, 1.0].into(),
+                Format::B8G8R8A8Srgb => [0.0, 0.0, 0.0, 1.0].into(),
+                Format::R8G8B8A8Srgb => [0.0, 0.0, 0.0, 1.0].into(),
+                Format::A2B10G10R10UnormPack32 => [0.0, 0.0, 0.0, 1.0].into(),
                 Format::R32G32B32A32Sfloat => [0.0, 0.0, 0.0, 0.0].into(),
                 Format::R16G16B16A16Sfloat => [0.0, 0.0, 0.0, 0.0].into(),
+                // linear_distance targets want 1.0 instead, see
+                // System::set_clear_color
+                Format::R32Sfloat => [0.0, 0.0, 0.0, 0.0].into(),
+                // integer images have to be cleared with integer values. 0
+                // is usually "nothing here", so ids should start at 1.
+                Format::R32Uint => ClearValue::Uint([0, 0, 0, 0]),
+                Format::R32Sint => ClearValue::Int([0, 0, 0, 0]),
                 Format::D16Unorm => 1f32.into(),
                 Format::D32Sfloat => 1f32.into(),
-                // TODO: make the panic print the bad format
-                _ => panic!("You provided a format that the clear values couldn't be guessed for!"),
+                Format::D32Sfloat_S8Uint => ClearValue::DepthStencil((1.0, 0)),
+                format => panic!(
+                    "You provided a format that the clear values couldn't be guessed for: {:?}",
+                    format
+                ),
             },
             LoadOp::DontCare => ClearValue::None,
             LoadOp::Load => ClearValue::None,
@@ -0,0 +1,84 @@
+/*
+Resolving multisampled depth to a normal depth image, so passes after an MSAA
+pass can sample it like any other depth buffer (for SSAO, fog, decals...).
+
+Color gets resolved by the render pass itself, but depth can't be: averaging
+depths makes values that belong to no surface, and the Vulkan way of picking
+a sample (VK_KHR_depth_stencil_resolve's resolve modes) isn't exposed by
+vulkano 0.14. So depth is resolved by a fullscreen draw in its own pass that
+reads every sample and writes gl_FragDepth:
+
+Pass {
+    name: "geometry",
+    images_created_tags: vec!["color", "multisampled_color", "multisampled_depth"],
+    images_needed_tags: vec![],
+    render_pass: render_passes::multisampled_with_depth_resolve(device.clone(), 4),
+}
+Pass {
+    name: "depth_resolve",
+    images_created_tags: vec!["depth"],
+    images_needed_tags: vec!["multisampled_depth"],
+    render_pass: render_passes::depth_resolve(device.clone()),
+}
+
+let resolve = depth_resolve_object(queue, &mut resolve_pipeline_cache, DepthResolveMode::Min);
+...
+system.add_object(&resolve);
+
+TODO: use the real resolve modes when vulkano has them, that would save the
+extra pass and the stored multisampled depth.
+ */
+
+use vulkano::device::Queue;
+
+use crate::debug::fullscreen_quad;
+use crate::mesh::PrimitiveTopology;
+use crate::object::{Object, ObjectPrototype, PipelineOptions};
+use crate::pipeline_cache::PipelineCache;
+use crate::shaders::relative_path;
+
+use std::sync::Arc;
+
+// which of a pixel's samples ends up in the resolved depth
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthResolveMode {
+    // the first one, like a resolve that isn't told anything else does
+    SampleZero,
+    // the nearest one, so edges count as the surface in front. usually what
+    // post-processing wants.
+    Min,
+    // the farthest one, so edges count as the surface behind
+    Max,
+}
+
+impl DepthResolveMode {
+    fn fs_path(&self) -> &'static str {
+        match self {
+            DepthResolveMode::SampleZero => "shaders/resolve/depth_sample_zero_frag.glsl",
+            DepthResolveMode::Min => "shaders/resolve/depth_min_frag.glsl",
+            DepthResolveMode::Max => "shaders/resolve/depth_max_frag.glsl",
+        }
+    }
+}
+
+// the fullscreen draw for a depth_resolve pass. pipeline_cache has to be for
+// that pass, and the multisampled depth has to be its first needed image.
+pub fn depth_resolve_object(
+    queue: Arc<Queue>,
+    pipeline_cache: &mut PipelineCache,
+    mode: DepthResolveMode,
+) -> Object<()> {
+    ObjectPrototype {
+        vs_path: relative_path("shaders/resolve/depth_vert.glsl"),
+        fs_path: relative_path(mode.fs_path()),
+        fill_type: PrimitiveTopology::TriangleStrip,
+        // written with gl_FragDepth, without testing against what was there
+        read_depth: false,
+        write_depth: true,
+        mesh: fullscreen_quad(),
+        collection: (),
+        custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
+    }
+    .build(queue, pipeline_cache, 0)
+}
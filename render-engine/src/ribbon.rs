@@ -0,0 +1,237 @@
+/*
+Trails behind moving things: a Ribbon remembers where its head has been and
+turns that into a strip that always faces the camera, rebuilt every frame and
+swapped into the object with Object::set_mesh.
+
+It's drawn like particles (see particles.rs), with alpha blending in a pass
+after the geometry that has the depth buffer as its only needed image, and
+does the depth test itself in the fragment shader:
+
+let mut trail = Ribbon::new(queue, &mut pipeline_cache, camera_data);
+trail.width = 0.2;
+...
+trail.update(head_position, camera_position);
+trail.add_to(&mut system);
+
+The camera data has to start with the view and projection matrices.
+ */
+
+use vulkano::device::Queue;
+
+use nalgebra_glm::*;
+
+use crate::collection::{Data, Set};
+use crate::impl_vertex;
+use crate::mesh::{Mesh, PrimitiveTopology};
+use crate::object::{Object, ObjectPrototype, PipelineOptions};
+use crate::pipeline_cache::PipelineCache;
+use crate::shaders::relative_path;
+use crate::system::System;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct RibbonVertex {
+    pub position: [f32; 3],
+    // 0 at the head, 1 at the end of the tail
+    pub along: f32,
+}
+impl_vertex!(RibbonVertex, position, along);
+
+#[derive(Clone, Copy)]
+pub struct RibbonParams {
+    pub color: [f32; 4],
+    // x is the alpha at the head, y at the end of the tail
+    pub fade: [f32; 4],
+}
+
+impl Data for RibbonParams {}
+
+pub struct Ribbon<C: Data> {
+    // in world units
+    pub width: f32,
+    // how long the trail gets before the oldest points are dropped, in world
+    // units
+    pub length: f32,
+    // how far the head has to move before a new point is recorded. smaller is
+    // smoother but makes more triangles.
+    pub spacing: f32,
+    pub color: [f32; 4],
+    // alpha at the head and the end of the tail, multiplied with color's
+    pub fade: [f32; 2],
+    // recorded points, newest first. the current head isn't in here until
+    // it's moved spacing away from the newest one.
+    points: VecDeque<Vec3>,
+    head: Option<Vec3>,
+    // whether the last update made at least one segment
+    has_strip: bool,
+    queue: Arc<Queue>,
+    pub object: Object<(Set<(RibbonParams,)>, Set<(C,)>)>,
+}
+
+impl<C: Data> Ribbon<C> {
+    // pipeline_cache has to be for the pass the ribbon is drawn in
+    pub fn new(queue: Arc<Queue>, pipeline_cache: &mut PipelineCache, camera: C) -> Self {
+        let width = 0.1;
+        let color = [1.0, 1.0, 1.0, 1.0];
+        let fade = [1.0, 0.0];
+
+        let object = ObjectPrototype {
+            vs_path: relative_path("shaders/ribbon/ribbon_vert.glsl"),
+            fs_path: relative_path("shaders/ribbon/ribbon_frag.glsl"),
+            fill_type: PrimitiveTopology::TriangleList,
+            read_depth: false,
+            write_depth: false,
+            // replaced in update, there's nothing to draw yet
+            mesh: Mesh {
+                vertices: vec![RibbonVertex::default(); 3],
+                indices: vec![0, 1, 2],
+            },
+            collection: ((ribbon_params(color, fade),), (camera,)),
+            custom_dynamic_state: None,
+            pipeline_options: PipelineOptions {
+                alpha_blend: true,
+                ..PipelineOptions::default()
+            },
+        }
+        .build(queue.clone(), pipeline_cache, 1);
+
+        Self {
+            width,
+            length: 5.0,
+            spacing: 0.1,
+            color,
+            fade,
+            points: VecDeque::new(),
+            head: None,
+            has_strip: false,
+            queue,
+            object,
+        }
+    }
+
+    // moves the head of the ribbon to head and rebuilds the strip so it faces
+    // camera_pos. call every frame, even if the head doesn't move.
+    pub fn update(&mut self, head: [f32; 3], camera_pos: [f32; 3]) {
+        let head = make_vec3(&head);
+        let should_record = match self.points.front() {
+            Some(newest) => distance(newest, &head) >= self.spacing,
+            None => true,
+        };
+        if should_record {
+            self.points.push_front(head);
+        }
+        self.head = Some(head);
+
+        // drop points past the end of the tail, keeping the one that crosses
+        // the length so the tail doesn't jump when it's dropped
+        let mut total = distance(&head, &self.points[0]);
+        let mut keep = self.points.len();
+        for i in 1..self.points.len() {
+            if total >= self.length {
+                keep = i;
+                break;
+            }
+            total += distance(&self.points[i - 1], &self.points[i]);
+        }
+        self.points.truncate(keep);
+
+        let strip = self.strip(&make_vec3(&camera_pos));
+        self.has_strip = strip.is_some();
+        if let Some(mesh) = strip {
+            self.object.set_mesh(self.queue.clone(), &mesh);
+        }
+    }
+
+    // forgets the whole trail, e.g. when the thing it follows teleports
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.head = None;
+        self.has_strip = false;
+    }
+
+    // draws the ribbon for this frame. does nothing until the head has moved
+    // far enough to make a segment.
+    pub fn add_to(&mut self, system: &mut System) {
+        if !self.has_strip {
+            return;
+        }
+
+        self.object.collection.0.data.0 = ribbon_params(self.color, self.fade);
+        self.object.collection.0.upload(self.queue.device().clone());
+
+        system.add_object(&self.object);
+    }
+
+    // head first, then the recorded points
+    fn strip(&self, camera_pos: &Vec3) -> Option<Mesh<RibbonVertex>> {
+        let mut points: Vec<Vec3> = vec![];
+        if let Some(head) = self.head {
+            points.push(head);
+        }
+        points.extend(
+            self.points
+                .iter()
+                .cloned()
+                .filter(|&p| Some(p) != self.head),
+        );
+        if points.len() < 2 {
+            return None;
+        }
+
+        // distance along the ribbon at each point, for the fade
+        let mut along = vec![0.0; points.len()];
+        for i in 1..points.len() {
+            along[i] = along[i - 1] + distance(&points[i - 1], &points[i]);
+        }
+        let total = along[points.len() - 1].max(0.0001);
+
+        let half_width = self.width / 2.0;
+        let mut vertices = Vec::with_capacity(points.len() * 2);
+        for i in 0..points.len() {
+            // direction of the ribbon at this point, averaged over the
+            // segments on both sides so corners don't pinch
+            let prev = if i == 0 { points[i] } else { points[i - 1] };
+            let next = if i + 1 == points.len() {
+                points[i]
+            } else {
+                points[i + 1]
+            };
+            let tangent = next - prev;
+            let to_camera = camera_pos - points[i];
+            let side = tangent.cross(&to_camera);
+            // looking straight down the ribbon, no side is better than another
+            let side = if length(&side) < 0.00001 {
+                vec3(0.0, 0.0, 0.0)
+            } else {
+                normalize(&side) * half_width
+            };
+
+            let t = along[i] / total;
+            vertices.push(RibbonVertex {
+                position: (points[i] + side).into(),
+                along: t,
+            });
+            vertices.push(RibbonVertex {
+                position: (points[i] - side).into(),
+                along: t,
+            });
+        }
+
+        let mut indices = Vec::with_capacity((points.len() - 1) * 6);
+        for i in 0..(points.len() as u32 - 1) {
+            let a = i * 2;
+            indices.extend_from_slice(&[a, a + 1, a + 2, a + 2, a + 1, a + 3]);
+        }
+
+        Some(Mesh { vertices, indices })
+    }
+}
+
+fn ribbon_params(color: [f32; 4], fade: [f32; 2]) -> RibbonParams {
+    RibbonParams {
+        color,
+        fade: [fade[0], fade[1], 0.0, 0.0],
+    }
+}
@@ -1,4 +1,5 @@
 use vulkano::device::Device;
+use vulkano::format::Format;
 use vulkano::pipeline::shader::GraphicsEntryPoint;
 use vulkano::pipeline::shader::ShaderModule;
 
@@ -16,15 +17,21 @@ pub struct Shader {
     pub entry: Entry,
 }
 
+// TODO: geometry shaders, shade_runner only compiles vertex and fragment
+// shaders. rendering all six faces of a cubemap in one pass (num_viewports 6
+// and a layered framebuffer) needs them.
 #[derive(Clone)]
 pub struct ShaderSystem {
     pub vs: Shader,
     pub fs: Shader,
+    // if set, the pipeline layout gets this shader's descriptor sets instead
+    // of fs's, see load_depth_only
+    pub layout_fs: Option<Shader>,
 }
 
 impl ShaderSystem {
     pub fn load_from_file(device: Arc<Device>, vs_path: &Path, fs_path: &Path) -> Self {
-        let shaders = load(vs_path, fs_path).expect(&format!("Couldn't load shaders: {:?} and {:?}", vs_path, fs_path));
+        let shaders = load(expand_includes(vs_path), expand_includes(fs_path)).expect(&format!("Couldn't load shaders: {:?} and {:?}", vs_path, fs_path));
         let entry = parse(&shaders).expect("Couldn't parse shaders");
 
         let vs_module =
@@ -45,7 +52,35 @@ impl ShaderSystem {
             entry: entry.clone(),
         };
 
-        Self { vs, fs }
+        Self { vs, fs, layout_fs: None }
+    }
+
+    // for the depth-only draws of System::set_depth_prepass: fs is an empty
+    // shader, but the layout still has every set of the real fragment shader.
+    // that way the object's descriptor sets match the pipeline's layout
+    // exactly and can be bound as they are.
+    pub fn load_depth_only(device: Arc<Device>, vs_path: &Path, fs_path: &Path) -> Self {
+        let real = Self::load_from_file(device.clone(), vs_path, fs_path);
+        let mut shaders = Self::load_from_file(
+            device,
+            vs_path,
+            &relative_path("shaders/prepass/empty_frag.glsl"),
+        );
+        shaders.layout_fs = Some(real.fs);
+
+        shaders
+    }
+
+    // the first location and format of each of the fragment shader's outputs.
+    // formats are what the shader writes, like R32G32B32A32Sfloat for a vec4.
+    pub fn fragment_outputs(&self) -> Vec<(u32, Format)> {
+        self.fs
+            .entry
+            .frag_output
+            .outputs
+            .iter()
+            .map(|output| (output.location.start, output.format))
+            .collect()
     }
 
     pub fn get_entry_points(&self) -> (VertEntry, FragEntry) {
@@ -80,5 +115,93 @@ pub fn relative_path(local_path: &str) -> PathBuf {
     [env!("CARGO_MANIFEST_DIR"), local_path].iter().collect()
 }
 
+// shade_runner doesn't give shaderc a way to find included files, so we
+// expand #include "file.glsl" lines ourselves. files are looked up next to
+// the shader including them first, then in render-engine/shaders/include,
+// where the engine's own includes live. each file is only included once, like
+// with #pragma once.
+// shaders without includes are loaded as they are, others get written out
+// expanded to a temp file and that gets compiled instead. line numbers in
+// compile errors are for the expanded file, which is left there for looking
+// at.
+fn expand_includes(path: &Path) -> PathBuf {
+    let source = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Couldn't read shader {:?}: {}", path, e));
+    if !source.lines().any(|line| include_target(line).is_some()) {
+        return path.to_path_buf();
+    }
+
+    let mut included = vec![];
+    let expanded = expand_source(path, &source, &mut included);
+
+    // named after the whole path, so shaders with the same file name in
+    // different directories don't overwrite each other
+    let name: String = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || c == '.' => c,
+            _ => '_',
+        })
+        .collect();
+    let dir = std::env::temp_dir().join("render-engine-shaders");
+    std::fs::create_dir_all(&dir).expect("Couldn't create directory for expanded shaders");
+    let expanded_path = dir.join(name);
+    std::fs::write(&expanded_path, expanded).expect("Couldn't write expanded shader");
+
+    expanded_path
+}
+
+fn expand_source(path: &Path, source: &str, included: &mut Vec<PathBuf>) -> String {
+    let mut out = String::new();
+
+    for line in source.lines() {
+        match include_target(line) {
+            Some(target) => {
+                let include_path = find_include(path, target);
+                if !included.contains(&include_path) {
+                    included.push(include_path.clone());
+                    let include_source =
+                        std::fs::read_to_string(&include_path).unwrap_or_else(|e| {
+                            panic!("Couldn't read include {:?}: {}", include_path, e)
+                        });
+                    out.push_str(&expand_source(&include_path, &include_source, included));
+                }
+            }
+            None => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+
+    out
+}
+
+// the file name in an #include "file" line
+fn include_target(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if !line.starts_with("#include") {
+        return None;
+    }
+    let rest = line["#include".len()..].trim();
+    if rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"') {
+        Some(&rest[1..rest.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn find_include(including: &Path, target: &str) -> PathBuf {
+    let next_to = including.parent().map(|dir| dir.join(target));
+    let engine = relative_path("shaders/include").join(target);
+
+    match next_to {
+        Some(path) if path.exists() => path,
+        _ if engine.exists() => engine,
+        _ => panic!("Couldn't find {} included from {:?}", target, including),
+    }
+}
+
 type VertEntry<'a> = GraphicsEntryPoint<'a, (), VertInput, VertOutput, VertLayout>;
 type FragEntry<'a> = GraphicsEntryPoint<'a, (), FragInput, FragOutput, FragLayout>;
@@ -0,0 +1,213 @@
+/*
+CPU side of shaders/include/shadow_filtering.glsl.
+
+ShadowFilterParams is laid out to match the ShadowFilter uniform block the
+include expects, so it can go in a collection like any other Data:
+
+let params = ShadowFilterParams::poisson(16, 2.5);
+collection: ((params,), ...)
+
+The poisson disk is generated once on the CPU instead of being hardcoded in
+the shader so the sample count can change without recompiling.
+
+EvsmParams is the same for shaders/include/evsm.glsl, exponential variance
+shadow maps. Those store warped depth moments instead of depth, which can be
+filtered like any texture (linear, anisotropic, blurred) instead of taking a
+pile of comparisons per pixel, and the exponential warp keeps the light
+bleeding of plain variance shadow maps down. It's the expensive option: a
+R32G32B32A32Sfloat map is 4 times the memory of a D32 one.
+
+Pass {
+    name: "shadow",
+    images_created_tags: vec!["shadow_moments", "shadow_depth"],
+    images_needed_tags: vec![],
+    render_pass: render_passes::evsm_shadow(device.clone()),
+}
+
+let params = EvsmParams::new(40.0, 5.0);
+let clear = evsm_clear_object(queue.clone(), &mut shadow_pipeline_cache, params);
+...
+system.add_object(&clear);
+// then the casters, writing evsm_warp in their fragment shaders
+ */
+
+use vulkano::device::Queue;
+
+use crate::collection::{Data, Set};
+use crate::debug::fullscreen_quad;
+use crate::mesh::PrimitiveTopology;
+use crate::object::{Object, ObjectPrototype, PipelineOptions};
+use crate::pipeline_cache::PipelineCache;
+use crate::shaders::relative_path;
+
+use std::sync::Arc;
+
+// the most samples the include's disk array has room for
+pub const MAX_POISSON_SAMPLES: usize = 16;
+
+// repr(C) so the fields stay in the order the uniform block has them
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowFilterParams {
+    // vec4s because std140 pads array elements to 16 bytes anyway, only xy
+    // are used
+    pub poisson_disk: [[f32; 4]; MAX_POISSON_SAMPLES],
+    pub num_samples: i32,
+    // in texels
+    pub radius: f32,
+    pub pcf_size: i32,
+}
+
+impl Data for ShadowFilterParams {}
+
+impl ShadowFilterParams {
+    // for shadow_poisson, num_samples points radius texels around the center
+    pub fn poisson(num_samples: usize, radius: f32) -> Self {
+        if num_samples > MAX_POISSON_SAMPLES {
+            panic!(
+                "Poisson shadows can use at most {} samples, not {}",
+                MAX_POISSON_SAMPLES, num_samples
+            );
+        }
+
+        let mut poisson_disk = [[0.0; 4]; MAX_POISSON_SAMPLES];
+        for (i, point) in poisson_disk_points(num_samples).iter().enumerate() {
+            poisson_disk[i] = [point[0], point[1], 0.0, 0.0];
+        }
+
+        Self {
+            poisson_disk,
+            num_samples: num_samples as i32,
+            radius,
+            pcf_size: 1,
+        }
+    }
+
+    // for shadow_pcf, a pcf_size x pcf_size grid
+    pub fn pcf(pcf_size: u32) -> Self {
+        Self {
+            poisson_disk: [[0.0; 4]; MAX_POISSON_SAMPLES],
+            num_samples: 0,
+            radius: 0.0,
+            pcf_size: pcf_size.max(1) as i32,
+        }
+    }
+}
+
+// past this exp() overflows 32 bit floats once it's squared for the second
+// moment
+pub const EVSM_MAX_EXPONENT: f32 = 42.0;
+
+// laid out like the Evsm uniform block in evsm.glsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EvsmParams {
+    // how steep the warps are. higher means less bleeding but more precision
+    // problems, the positive one matters more.
+    pub positive_exponent: f32,
+    pub negative_exponent: f32,
+    // 0 to 1, how much of the lowest shadow probability is cut off. higher
+    // means less bleeding but shadows that get hard and dark at the edges.
+    pub light_bleed_reduction: f32,
+    // minimum variance, in depth units, against acne
+    pub variance_bias: f32,
+}
+
+impl Data for EvsmParams {}
+
+impl Default for EvsmParams {
+    fn default() -> Self {
+        Self::new(40.0, 5.0)
+    }
+}
+
+impl EvsmParams {
+    // exponents over EVSM_MAX_EXPONENT get clamped to it
+    pub fn new(positive_exponent: f32, negative_exponent: f32) -> Self {
+        Self {
+            positive_exponent: clamp_exponent(positive_exponent),
+            negative_exponent: clamp_exponent(negative_exponent),
+            light_bleed_reduction: 0.25,
+            variance_bias: 0.0001,
+        }
+    }
+}
+
+fn clamp_exponent(exponent: f32) -> f32 {
+    if exponent > EVSM_MAX_EXPONENT {
+        println!(
+            "EVSM exponent {} is too big for 32 bit floats, using {}",
+            exponent, EVSM_MAX_EXPONENT
+        );
+        EVSM_MAX_EXPONENT
+    } else {
+        exponent.max(0.0)
+    }
+}
+
+// fills an evsm_shadow pass's moments with the far plane, so texels no caster
+// covers are lit. draw it before the casters. pipeline_cache has to be for
+// that pass, and the pass can't have any images_needed since the params are
+// in set 0.
+pub fn evsm_clear_object(
+    queue: Arc<Queue>,
+    pipeline_cache: &mut PipelineCache,
+    params: EvsmParams,
+) -> Object<(Set<(EvsmParams,)>,)> {
+    ObjectPrototype {
+        vs_path: relative_path("shaders/shadow/evsm_clear_vert.glsl"),
+        fs_path: relative_path("shaders/shadow/evsm_clear_frag.glsl"),
+        fill_type: PrimitiveTopology::TriangleStrip,
+        // the casters still need the cleared depth to test against
+        read_depth: false,
+        write_depth: false,
+        mesh: fullscreen_quad(),
+        collection: ((params,),),
+        custom_dynamic_state: None,
+        pipeline_options: PipelineOptions::default(),
+    }
+    .build(queue, pipeline_cache, 0)
+}
+
+// count points in the unit disk that are roughly evenly spread out but not in
+// a grid, using Mitchell's best candidate algorithm: each new point is the one
+// farthest from all others out of a bunch of random candidates. the random
+// numbers come from a fixed seed, so the disk is the same every run.
+pub fn poisson_disk_points(count: usize) -> Vec<[f32; 2]> {
+    const CANDIDATES: usize = 32;
+
+    let mut seed: u32 = 0x1234_5678;
+    let mut random = || {
+        // xorshift32
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed as f32 / std::u32::MAX as f32
+    };
+
+    let mut points: Vec<[f32; 2]> = Vec::with_capacity(count);
+    while points.len() < count {
+        let mut best = [0.0, 0.0];
+        let mut best_dist = -1.0;
+
+        for _ in 0..CANDIDATES {
+            // uniform in the disk, sqrt so points don't bunch up in the middle
+            let r = random().sqrt();
+            let angle = random() * std::f32::consts::PI * 2.0;
+            let candidate = [r * angle.cos(), r * angle.sin()];
+
+            let dist = points
+                .iter()
+                .map(|p| (p[0] - candidate[0]).powi(2) + (p[1] - candidate[1]).powi(2))
+                .fold(std::f32::MAX, f32::min);
+            if dist > best_dist {
+                best = candidate;
+                best_dist = dist;
+            }
+        }
+
+        points.push(best);
+    }
+
+    points
+}
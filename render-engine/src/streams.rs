@@ -0,0 +1,368 @@
+/*
+Vertex streams: a mesh's positions in one vertex buffer and the rest of its
+attributes in another, instead of everything interleaved in one. Passes that
+only need positions (depth prepasses, shadow maps) can then bind just the
+position buffer, sharing it with the full object instead of uploading a
+position-only copy of the mesh.
+
+let streams = SplitMesh::from_mesh(&mesh, |v| VTexNorm {
+    tex_coord: v.tex_coord,
+    normal: v.normal,
+})
+.upload(queue.clone());
+
+let geometry = StreamPrototype {
+    vs_path: relative_path("shaders/geo_vert.glsl"),
+    fs_path: relative_path("shaders/geo_frag.glsl"),
+    fill_type: PrimitiveTopology::TriangleList,
+    read_depth: true,
+    write_depth: false,
+    streams: streams.clone(),
+    positions_only: false,
+    collection: (),
+    custom_dynamic_state: None,
+    pipeline_options: PipelineOptions::default(),
+}
+.build(queue.clone(), &mut geo_cache, 0);
+
+let prepass = StreamPrototype {
+    vs_path: relative_path("shaders/prepass_vert.glsl"),
+    ...
+    streams,
+    positions_only: true,
+    ...
+}
+.build(queue.clone(), &mut prepass_cache, 0);
+
+Shader inputs are matched by name, first against PositionVertex and then
+against the attribute type, so the attribute type shouldn't have a position of
+its own. With positions_only the object is the same as one built from a
+Mesh<PositionVertex>.
+
+Interleaved is still the better layout for objects only drawn with shaders
+that read everything, since every vertex fetch then hits one buffer.
+ */
+
+use vulkano::buffer::{BufferAccess, ImmutableBuffer};
+use vulkano::command_buffer::DynamicState;
+use vulkano::device::{Device, Queue};
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::pipeline::shader::ShaderInterfaceDef;
+use vulkano::pipeline::vertex::{
+    AttributeInfo, IncompatibleVertexDefinitionError, InputRate, VertexDefinition,
+    VertexMemberInfo, VertexSource,
+};
+// for PositionVertex::member
+use vulkano::pipeline::vertex::Vertex as _;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+
+use crate::collection::CollectionData;
+use crate::impl_vertex;
+use crate::mesh::{
+    build_pipeline, Mesh, PrimitiveTopology, Vertex, VertexAttributes, VertexType,
+    VertexTypeAbstract,
+};
+use crate::object::{default_pipeline_spec, DeferredObject, Object, PipelineOptions};
+use crate::pipeline_cache::{PipelineCache, PipelineSpec};
+use crate::shaders::ShaderSystem;
+use crate::utils::immutable_slice;
+
+use std::any::TypeId;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// the first stream, which is all a position-only pass binds
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PositionVertex {
+    pub position: [f32; 3],
+}
+impl_vertex!(PositionVertex, position);
+
+// a mesh with its positions split off from the other attributes
+#[derive(Clone)]
+pub struct SplitMesh<A: Vertex> {
+    pub positions: Vec<PositionVertex>,
+    pub attributes: Vec<A>,
+    pub indices: Vec<u32>,
+}
+
+impl<A: Vertex + Send + Sync + 'static> SplitMesh<A> {
+    // splits an interleaved mesh, attributes picks out everything but the
+    // position from each vertex
+    pub fn from_mesh<V: VertexAttributes, F: Fn(&V) -> A>(mesh: &Mesh<V>, attributes: F) -> Self {
+        Self {
+            positions: mesh
+                .vertices
+                .iter()
+                .map(|vertex| PositionVertex {
+                    position: vertex.position(),
+                })
+                .collect(),
+            attributes: mesh.vertices.iter().map(attributes).collect(),
+            indices: mesh.indices.clone(),
+        }
+    }
+
+    pub fn upload(&self, queue: Arc<Queue>) -> VertexStreams<A> {
+        if self.positions.len() != self.attributes.len() {
+            panic!(
+                "Split mesh has {} positions but {} attributes, they need one each",
+                self.positions.len(),
+                self.attributes.len()
+            );
+        }
+
+        VertexStreams {
+            positions: immutable_slice(queue.clone(), &self.positions),
+            attributes: immutable_slice(queue.clone(), &self.attributes),
+            ibuf: immutable_slice(queue, &self.indices),
+            phantom: PhantomData,
+        }
+    }
+}
+
+// the uploaded buffers of a SplitMesh. cloning shares them.
+#[derive(Clone)]
+pub struct VertexStreams<A: Vertex> {
+    pub positions: Arc<dyn BufferAccess + Send + Sync>,
+    pub attributes: Arc<dyn BufferAccess + Send + Sync>,
+    pub ibuf: Arc<ImmutableBuffer<[u32]>>,
+    phantom: PhantomData<A>,
+}
+
+// ObjectPrototype, but for a mesh that's already been uploaded as streams
+#[derive(Clone)]
+pub struct StreamPrototype<A: Vertex, D: CollectionData> {
+    pub vs_path: PathBuf,
+    pub fs_path: PathBuf,
+    pub fill_type: PrimitiveTopology,
+    pub read_depth: bool,
+    pub write_depth: bool,
+    pub streams: VertexStreams<A>,
+    // only bind the position stream, for shaders that don't read anything
+    // else
+    pub positions_only: bool,
+    pub collection: D,
+    pub custom_dynamic_state: Option<DynamicState>,
+    pub pipeline_options: PipelineOptions,
+}
+
+impl<A: Vertex + Send + Sync + 'static, D: CollectionData + 'static> StreamPrototype<A, D> {
+    pub fn build(
+        self,
+        queue: Arc<Queue>,
+        pipeline_cache: &mut PipelineCache,
+        set_start_idx: usize,
+    ) -> Object<D::Sets> {
+        self.build_deferred(set_start_idx)
+            .finalize(queue, pipeline_cache)
+    }
+
+    pub fn build_direct(
+        self,
+        queue: Arc<Queue>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        set_start_idx: usize,
+    ) -> Object<D::Sets> {
+        let deferred = self.build_deferred(set_start_idx);
+        let pipeline = deferred
+            .pipeline_spec
+            .concrete(queue.device().clone(), render_pass);
+        let collection =
+            deferred
+                .collection
+                .create_sets(queue.device().clone(), pipeline, set_start_idx);
+
+        Object {
+            pipeline_spec: deferred.pipeline_spec,
+            vbufs: deferred.vbufs,
+            ibuf: deferred.ibuf,
+            collection,
+            custom_dynamic_state: deferred.custom_dynamic_state,
+        }
+    }
+
+    // nothing gets uploaded here, the streams already are
+    pub fn build_deferred(self, set_start_idx: usize) -> DeferredObject<D> {
+        let vbufs = if self.positions_only {
+            vec![self.streams.positions.clone()]
+        } else {
+            vec![
+                self.streams.positions.clone(),
+                self.streams.attributes.clone(),
+            ]
+        };
+
+        DeferredObject {
+            pipeline_spec: self.pipeline_spec(),
+            vbufs,
+            ibuf: self.streams.ibuf,
+            collection: self.collection,
+            set_start_idx,
+            custom_dynamic_state: self.custom_dynamic_state,
+        }
+    }
+
+    pub fn pipeline_spec(&self) -> PipelineSpec {
+        let vtype: Arc<dyn VertexTypeAbstract> = if self.positions_only {
+            VertexType::<PositionVertex>::new()
+        } else {
+            Arc::new(StreamsVertexType::<A> {
+                phantom: PhantomData,
+            })
+        };
+
+        default_pipeline_spec(
+            self.vs_path.clone(),
+            self.fs_path.clone(),
+            self.fill_type,
+            self.read_depth,
+            self.write_depth,
+            self.pipeline_options,
+            vtype,
+        )
+    }
+}
+
+// finds a shader input's attribute and which stream it's in
+fn stream_member<A: Vertex>(name: &str) -> Option<(VertexMemberInfo, u32)> {
+    PositionVertex::member(name)
+        .map(|member| (member, 0))
+        .or_else(|| A::member(name).map(|member| (member, 1)))
+}
+
+// vulkano has TwoBuffersDefinition for this, but it can't take the buffers as
+// a Vec (it's unimplemented), which is how System passes them for every
+// object
+pub struct StreamsDefinition<A: Vertex> {
+    phantom: PhantomData<A>,
+}
+
+unsafe impl<A: Vertex, I: ShaderInterfaceDef> VertexDefinition<I> for StreamsDefinition<A> {
+    type BuffersIter = std::vec::IntoIter<(u32, usize, InputRate)>;
+    type AttribsIter = std::vec::IntoIter<(u32, u32, AttributeInfo)>;
+
+    fn definition(
+        &self,
+        interface: &I,
+    ) -> Result<(Self::BuffersIter, Self::AttribsIter), IncompatibleVertexDefinitionError> {
+        let mut attribs = vec![];
+        for element in interface.elements() {
+            let name = match &element.name {
+                Some(name) => name.to_string(),
+                None => "<unnamed>".to_string(),
+            };
+            let (member, binding) = match stream_member::<A>(&name) {
+                Some(found) => found,
+                None => {
+                    return Err(IncompatibleVertexDefinitionError::MissingAttribute {
+                        attribute: name,
+                    })
+                }
+            };
+
+            let num_locations = element.location.end - element.location.start;
+            if !member
+                .ty
+                .matches(member.array_size, element.format, num_locations)
+            {
+                return Err(IncompatibleVertexDefinitionError::FormatMismatch {
+                    attribute: name,
+                    shader: (element.format, num_locations as usize),
+                    definition: (member.ty, member.array_size),
+                });
+            }
+
+            // attributes bigger than a location (matrices) take one per
+            // column
+            let mut offset = member.offset;
+            for location in element.location.clone() {
+                attribs.push((
+                    location,
+                    binding,
+                    AttributeInfo {
+                        offset,
+                        format: element.format,
+                    },
+                ));
+                offset += element.format.size().unwrap();
+            }
+        }
+
+        let buffers = vec![
+            (0, std::mem::size_of::<PositionVertex>(), InputRate::Vertex),
+            (1, std::mem::size_of::<A>(), InputRate::Vertex),
+        ];
+
+        Ok((buffers.into_iter(), attribs.into_iter()))
+    }
+}
+
+unsafe impl<A: Vertex> VertexSource<Vec<Arc<dyn BufferAccess + Send + Sync>>>
+    for StreamsDefinition<A>
+{
+    fn decode(
+        &self,
+        source: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+    ) -> (Vec<Box<dyn BufferAccess + Send + Sync>>, usize, usize) {
+        if source.len() != 2 {
+            panic!(
+                "Objects with vertex streams need 2 vertex buffers, got {}",
+                source.len()
+            );
+        }
+
+        let num_vertices = std::cmp::min(
+            source[0].size() / std::mem::size_of::<PositionVertex>(),
+            source[1].size() / std::mem::size_of::<A>(),
+        );
+        let buffers = source
+            .into_iter()
+            .map(|buffer| Box::new(buffer) as Box<dyn BufferAccess + Send + Sync>)
+            .collect();
+
+        (buffers, num_vertices, 1)
+    }
+}
+
+// VertexType for objects built from both streams
+struct StreamsVertexType<A: Vertex> {
+    phantom: PhantomData<A>,
+}
+
+impl<A: Vertex + Send + Sync + 'static> VertexTypeAbstract for StreamsVertexType<A> {
+    fn create_pipeline(
+        &self,
+        device: Arc<Device>,
+        shaders: ShaderSystem,
+        spec: &PipelineSpec,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        build_pipeline(
+            device,
+            shaders,
+            spec,
+            render_pass,
+            StreamsDefinition::<A> {
+                phantom: PhantomData,
+            },
+            self.vertex_type_name(),
+            |name| stream_member::<A>(name).map(|(member, _)| member),
+        )
+    }
+
+    fn clone(&self) -> Arc<dyn VertexTypeAbstract> {
+        Arc::new(Self {
+            phantom: PhantomData,
+        })
+    }
+
+    fn vertex_type_id(&self) -> TypeId {
+        TypeId::of::<StreamsDefinition<A>>()
+    }
+
+    fn vertex_type_name(&self) -> &'static str {
+        std::any::type_name::<StreamsDefinition<A>>()
+    }
+}
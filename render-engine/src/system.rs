@@ -1,20 +1,37 @@
-use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::buffer::{BufferAccess, ImmutableBuffer, TypedBufferAccess};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DrawIndexedIndirectCommand, DynamicState};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor::DescriptorSet;
 use vulkano::device::{Device, Queue};
+use vulkano::format::{ClearValue, Format, FormatTy};
 use vulkano::framebuffer::{
     AttachmentDescription, Framebuffer, FramebufferAbstract, RenderPassAbstract,
 };
-use vulkano::image::{AttachmentImage, ImageViewAccess};
+use vulkano::image::{AttachmentImage, ImageAccess, ImageUsage, ImageViewAccess, SwapchainImage};
+use vulkano::pipeline::depth_stencil::Compare;
 use vulkano::pipeline::viewport::Viewport;
-use vulkano::sync::GpuFuture;
+use vulkano::sampler::Filter;
+use vulkano::command_buffer::AutoCommandBuffer;
+use vulkano::sync::{self, GpuFuture};
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::collection_cache::CollectionCache;
+use nalgebra_glm::Mat4;
+
+use crate::capabilities::Capabilities;
+use crate::capture::{CaptureEncoding, FrameDump, PendingCapture};
+use crate::collection::{count_descriptor_sets, descriptor_sets_created, Data};
+use crate::collection_cache::{pds_for_images, CollectionCache};
+use crate::debug::{box_edges, fullscreen_quad, DebugCamera, DebugColorVertex, DebugVertex};
+use crate::mesh::{Aabb, Mesh, MeshAbstract, PrimitiveTopology, VertexType};
 use crate::object::Drawcall;
-use crate::pipeline_cache::PipelineCache;
+use crate::pipeline_cache::{PipelineCache, PipelineSpec, PrepassStage};
+use crate::quality::QualitySettings;
 use crate::render_passes::clear_values_for_pass;
-use crate::utils::Timer;
+use crate::shaders::{relative_path, ShaderSystem};
+use crate::utils::{blit_image, image_size_bytes, solid_texture, upload_data, SamplerSpec, Timer};
 use crate::window::Window;
 
 // TODO: make the whole thing less prone to runtime panics. vecs of strings are
@@ -28,12 +45,84 @@ pub struct System<'a> {
     pipeline_caches: Vec<PipelineCache>,
     collection_cache: CollectionCache,
     // stores the vbuf of the screen-filling square used for non-geometry passes
+    // (see draw_fullscreen), made the first time it's needed
+    fullscreen_quad: Option<(
+        Arc<dyn BufferAccess + Send + Sync>,
+        Arc<ImmutableBuffer<[u32]>>,
+    )>,
     device: Arc<Device>,
     queue: Arc<Queue>,
     pub output_tag: &'a str,
-    cached_images: Option<HashMap<String, Arc<dyn ImageViewAccess + Send + Sync>>>,
+    // kept as AttachmentImages instead of ImageViewAccess so they can be used
+    // as the source of blits (see get_attachment_image)
+    cached_images: Option<HashMap<String, Arc<AttachmentImage>>>,
+    // the output tag cached_images was made for. the output image is kept
+    // out of reuse, so the cache is stale once output_tag changes.
+    cached_output_tag: &'a str,
     pub custom_images: HashMap<&'a str, Arc<dyn ImageViewAccess + Send + Sync>>,
     state: DrawState,
+    // set by record_custom, which has to leave the current render pass early
+    // since most commands can't be recorded inside one
+    render_pass_ended: bool,
+    // the destination image, if we know how to copy from it. only set when
+    // drawing to a window.
+    output_image: Option<Arc<dyn ImageAccess + Send + Sync>>,
+    frame_dump: Option<FrameDump>,
+    // see set_render_scale
+    render_scale: f32,
+    // the window image to upscale the output to at the end of the frame, if
+    // render_scale isn't 1
+    upscale_dest: Option<Arc<dyn ImageAccess + Send + Sync>>,
+    // see set_full_res_output
+    full_res_output: bool,
+    // dimensions of each pass's framebuffer this frame
+    pass_dims: Vec<[u32; 2]>,
+    // see run_on_async_queue
+    async_passes: Option<AsyncPasses>,
+    // see set_read_only
+    read_only_tags: Vec<&'a str>,
+    // see set_clear_color
+    clear_colors: HashMap<&'a str, [f32; 4]>,
+    // see set_max_frames_in_flight
+    frames_in_flight: usize,
+    // see set_render_path. holds the passes of every path but the current one.
+    render_path: RenderPath,
+    other_path_passes: HashMap<RenderPath, Vec<Pass<'a>>>,
+    // the async passes of the other paths, so switching back restores them
+    other_path_async_passes: HashMap<RenderPath, AsyncPasses>,
+    // indices of the passes set_depth_prepass was called for
+    depth_prepass_passes: Vec<usize>,
+    // opaque objects added to a pass with a depth prepass, drawn when the
+    // pass ends
+    deferred_draws: Vec<DeferredDraw>,
+    // everything else added to such a pass (blended, not writing depth...),
+    // drawn after the opaque ones in the order it was added
+    deferred_late_draws: Vec<DeferredDraw>,
+    pending_captures: Vec<PendingCapture>,
+    capture_encoding: CaptureEncoding,
+    // see debug_line. two vertices per line, cleared every frame.
+    debug_lines: Vec<DebugColorVertex>,
+    persistent_debug_lines: Vec<PersistentDebugLine>,
+    // whether the persistent lines have been drawn yet this frame, the
+    // debug lines pass can be flushed more than once
+    persistent_debug_lines_drawn: bool,
+    debug_lines_pass: Option<&'a str>,
+    debug_camera: Option<DebugCamera>,
+    // see set_cull_camera
+    cull_view_proj: Option<Mat4>,
+    // counted as objects are added, then moved to last_object_stats when the
+    // next frame starts
+    object_stats: ObjectStats,
+    last_object_stats: ObjectStats,
+    // descriptor_sets_created() when the current frame started, and how many
+    // were made during the last one
+    descriptor_sets_at_frame_start: usize,
+    descriptor_sets_last_frame: usize,
+    // see render_pass_only
+    isolated_pass: Option<IsolatedPass<'a>>,
+    // the outputs of every fragment shader objects have been added with, for
+    // check_object_fits_pass. by path, since loading them means compiling.
+    fragment_outputs: HashMap<PathBuf, Vec<(u32, Format)>>,
     pass_timers: Vec<Timer>,
     cmd_buf_timer: Timer,
     present_timer: Timer,
@@ -41,6 +130,143 @@ pub struct System<'a> {
     setup_timer: Timer,
 }
 
+// which set of passes a System draws with, see System::set_render_path
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RenderPath {
+    Forward,
+    Deferred,
+}
+
+// what can go wrong while drawing a frame, returned by the try_ versions of
+// System's frame methods. the plain versions panic instead.
+// after any of these but InvalidObject the frame is lost, call abandon_frame
+// (try_finish_to_window does it by itself) and start the next one.
+#[derive(Debug)]
+pub enum RenderError {
+    // the window changed (resized, minimized...) and its swapchain doesn't
+    // fit it anymore. call Window::rebuild_swapchain and draw the frame again.
+    SwapchainOutOfDate,
+    // the window is minimized, so there's nothing to draw to. skip the frame,
+    // it's not an error as such.
+    Minimized,
+    // no image could be acquired from the swapchain
+    Acquire(String),
+    // a command couldn't be recorded into the frame's command buffer
+    Recording(String),
+    // the frame couldn't be submitted or presented
+    Submit(String),
+    // the object can't be drawn in the current pass. nothing was recorded,
+    // so the frame can go on without it.
+    InvalidObject(String),
+    // the methods were called in the wrong order, like add_object before
+    // start_window
+    InvalidState(String),
+    // waiting for the GPU failed, usually because the device was lost
+    Wait(String),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RenderError::SwapchainOutOfDate => write!(f, "the swapchain is out of date"),
+            RenderError::Minimized => write!(f, "the window is minimized"),
+            RenderError::Acquire(e) => write!(f, "couldn't acquire a swapchain image: {}", e),
+            RenderError::Recording(e) => write!(f, "couldn't record command: {}", e),
+            RenderError::Submit(e) => write!(f, "couldn't submit frame: {}", e),
+            RenderError::InvalidObject(e) => write!(f, "{}", e),
+            RenderError::InvalidState(e) => write!(f, "{}", e),
+            RenderError::Wait(e) => write!(f, "couldn't wait for the GPU to go idle: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+// the first num_passes passes are recorded into their own command buffer and
+// submitted on queue as soon as they're done, before the rest of the frame is
+// recorded.
+struct AsyncPasses {
+    queue: Arc<Queue>,
+    num_passes: usize,
+    // signals a semaphore when the async passes are done, the main queue
+    // waits on it in finish
+    future: Option<Box<dyn GpuFuture>>,
+    // set when the async passes couldn't be submitted on their own (see
+    // submit_async_passes) and have to run on the main queue instead
+    fallback: Option<Arc<AutoCommandBuffer>>,
+    warned: bool,
+}
+
+// a System with just one of the passes of another one, see
+// System::render_pass_only. rebuilt when the pass or the window size changes.
+struct IsolatedPass<'a> {
+    name: &'a str,
+    dims: [u32; 2],
+    // what the pass draws its output to, blitted to the window afterwards
+    target: Arc<AttachmentImage>,
+    system: Box<System<'a>>,
+}
+
+// how many objects were added in a frame and what happened to them, see
+// System::object_stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ObjectStats {
+    // every add_object, add_object_indirect and add_object_bounded call
+    pub objects_submitted: u32,
+    // objects add_object_bounded skipped because they were out of view
+    pub objects_culled: u32,
+    pub objects_drawn: u32,
+}
+
+// see System::descriptor_set_stats
+#[derive(Clone, Copy, Debug)]
+pub struct DescriptorSetStats {
+    pub created_last_frame: usize,
+    pub created_total: usize,
+    // collections for passes' images_needed the collection cache holds on to
+    pub cached_collections: usize,
+}
+
+// see System::debug_line_persistent
+struct PersistentDebugLine {
+    vertices: [DebugColorVertex; 2],
+    duration: f32,
+    // set the first time start_window sees the line
+    expires_at: Option<f32>,
+}
+
+// everything add_object needs to draw an object later
+#[derive(Clone)]
+struct DeferredDraw {
+    spec: PipelineSpec,
+    vbufs: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+    ibuf: Arc<ImmutableBuffer<[u32]>>,
+    collection: Vec<Arc<dyn DescriptorSet + Send + Sync>>,
+    custom_dynstate: Option<DynamicState>,
+}
+
+impl Drawcall for DeferredDraw {
+    fn pipe_spec(&self) -> &PipelineSpec {
+        &self.spec
+    }
+
+    fn vbufs(&self) -> Vec<Arc<dyn BufferAccess + Send + Sync>> {
+        self.vbufs.clone()
+    }
+
+    fn ibuf(&self) -> Arc<ImmutableBuffer<[u32]>> {
+        self.ibuf.clone()
+    }
+
+    fn collection(&self) -> Vec<Arc<dyn DescriptorSet + Send + Sync>> {
+        self.collection.clone()
+    }
+
+    fn custom_dynstate(&self) -> Option<DynamicState> {
+        self.custom_dynstate.clone()
+    }
+}
+
 enum DrawState {
     Uninitialized,
     Drawing {
@@ -67,6 +293,7 @@ enum DrawState {
 
 // Often drawing a frame requires multiple vertex and fragment shaders operating
 // in sequence. This what System is for.
+#[derive(Clone)]
 pub struct Pass<'a> {
     pub name: &'a str,
     pub images_created_tags: Vec<&'a str>,
@@ -74,15 +301,53 @@ pub struct Pass<'a> {
     pub render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
 }
 
+impl<'a> Pass<'a> {
+    pub fn num_subpasses(&self) -> usize {
+        self.render_pass.num_subpasses()
+    }
+}
+
+// Images you create yourself instead of letting System create them, for
+// example because they need a different resolution than the screen or have to
+// keep their contents between frames. They're checked against the passes that
+// create them when the System is created, so a mismatched format shows up as a
+// clear error then instead of a framebuffer creation failure later.
+// A plain HashMap works too, it gets converted.
+pub struct CustomImages<'a> {
+    images: HashMap<&'a str, Arc<dyn ImageViewAccess + Send + Sync>>,
+}
+
+impl<'a> CustomImages<'a> {
+    pub fn new() -> Self {
+        Self {
+            images: HashMap::new(),
+        }
+    }
+
+    pub fn insert(mut self, tag: &'a str, image: Arc<dyn ImageViewAccess + Send + Sync>) -> Self {
+        self.images.insert(tag, image);
+        self
+    }
+}
+
+impl<'a> From<HashMap<&'a str, Arc<dyn ImageViewAccess + Send + Sync>>> for CustomImages<'a> {
+    fn from(images: HashMap<&'a str, Arc<dyn ImageViewAccess + Send + Sync>>) -> Self {
+        Self { images }
+    }
+}
+
 impl<'a> System<'a> {
-    pub fn new(
+    pub fn new<C: Into<CustomImages<'a>>>(
         queue: Arc<Queue>,
         passes: Vec<Pass<'a>>,
-        custom_images: HashMap<&'a str, Arc<dyn ImageViewAccess + Send + Sync>>,
+        custom_images: C,
         output_tag: &'a str,
     ) -> Self {
         let device = queue.device().clone();
 
+        let custom_images = custom_images.into().images;
+        validate_custom_images(&passes, &custom_images);
+
         let pipeline_caches = pipe_caches_for_passes(device.clone(), &passes);
         let collection_cache = CollectionCache::new(device.clone());
         let pass_timers = passes.iter().map(|pass| Timer::new(pass.name)).collect();
@@ -91,12 +356,45 @@ impl<'a> System<'a> {
             passes,
             pipeline_caches,
             collection_cache,
+            fullscreen_quad: None,
             device,
             queue,
             output_tag,
             cached_images: None,
+            cached_output_tag: output_tag,
             custom_images,
             state: DrawState::Uninitialized,
+            render_pass_ended: false,
+            output_image: None,
+            frame_dump: None,
+            render_scale: 1.0,
+            upscale_dest: None,
+            full_res_output: false,
+            pass_dims: vec![],
+            async_passes: None,
+            read_only_tags: vec![],
+            clear_colors: HashMap::new(),
+            frames_in_flight: 2,
+            render_path: RenderPath::Forward,
+            other_path_passes: HashMap::new(),
+            other_path_async_passes: HashMap::new(),
+            depth_prepass_passes: vec![],
+            deferred_draws: vec![],
+            deferred_late_draws: vec![],
+            pending_captures: vec![],
+            capture_encoding: CaptureEncoding::default(),
+            debug_lines: vec![],
+            persistent_debug_lines: vec![],
+            persistent_debug_lines_drawn: false,
+            debug_lines_pass: None,
+            debug_camera: None,
+            cull_view_proj: None,
+            object_stats: ObjectStats::default(),
+            last_object_stats: ObjectStats::default(),
+            descriptor_sets_at_frame_start: descriptor_sets_created(),
+            descriptor_sets_last_frame: 0,
+            isolated_pass: None,
+            fragment_outputs: HashMap::new(),
             pass_timers,
             cmd_buf_timer: Timer::new("command buffer"),
             present_timer: Timer::new("present to window"),
@@ -105,22 +403,638 @@ impl<'a> System<'a> {
         }
     }
 
+    // runs the given passes on a separate queue, for example one from
+    // Window::new_with_async_queue, so they can overlap with whatever the main
+    // queue is still doing from the last frame. only passes at the start of
+    // the list can be async, since everything after them waits on them anyway.
+    // NOTE: if a pass on the main queue is still reading something an async
+    // pass writes (like last frame's geometry reading the shadow map), the
+    // async passes can't be submitted early and run at the start of the main
+    // queue's work instead. to get real overlap, anything they write that's
+    // read later has to be double buffered.
+    // TODO: vulkano doesn't expose timestamp queries, so there's no way to
+    // measure the overlap on the GPU. the pass timers only measure recording.
+    pub fn run_on_async_queue(&mut self, pass_names: &[&str], queue: Arc<Queue>) {
+        let num_passes = pass_names.len();
+
+        assert!(
+            num_passes < self.passes.len(),
+            "At least one pass has to run on the main queue"
+        );
+        assert!(
+            queue.family().id() == self.queue.family().id(),
+            "The async queue has to be from the same queue family as the main queue"
+        );
+        for (pass, name) in self.passes.iter().zip(pass_names) {
+            assert!(
+                pass.name == *name,
+                "Async passes have to be the first passes in the system, but {} isn't",
+                name
+            );
+        }
+
+        self.async_passes = Some(AsyncPasses {
+            queue,
+            num_passes,
+            future: None,
+            fallback: None,
+            warned: false,
+        });
+
+        self.check_async_reads();
+    }
+
+    // changes the sampler passes that need the image with tag sample it with.
+    // everything else uses SamplerSpec::default().
+    pub fn set_sampler(&mut self, tag: &str, spec: SamplerSpec) {
+        let sampler = spec.build(self.device.clone());
+        self.collection_cache.set_sampler(tag, sampler);
+    }
+
+    // like set_sampler, but only for the pass called pass_name. other passes
+    // keep using whatever sampler they did before. this is how one pass can
+    // read a shadow map with a comparison sampler while others read the
+    // depths themselves.
+    pub fn set_pass_sampler(&mut self, pass_name: &str, tag: &str, spec: SamplerSpec) {
+        if !self.passes.iter().any(|pass| pass.name == pass_name) {
+            panic!("Can't set a sampler for pass {}, there's no pass called that", pass_name);
+        }
+        let sampler = spec.build(self.device.clone());
+        self.collection_cache.set_pass_sampler(pass_name, tag, sampler);
+    }
+
+    // what the image with tag gets cleared to when a pass that clears it
+    // starts, instead of the guess clear_values_for_pass makes from its
+    // format. only for float and normalized color images. for example a
+    // render_passes::linear_distance target wants 1.0, i.e. nothing in the way:
+    // system.set_clear_color("shadow_map", [1.0, 0.0, 0.0, 0.0]);
+    pub fn set_clear_color(&mut self, tag: &'a str, color: [f32; 4]) {
+        self.clear_colors.insert(tag, color);
+    }
+
+    // clear_values_for_pass, with the colors from set_clear_color
+    fn clear_values(&self, pass_idx: usize) -> Vec<ClearValue> {
+        let pass = &self.passes[pass_idx];
+        let mut values = clear_values_for_pass(pass.render_pass.clone());
+        for (value, tag) in values.iter_mut().zip(pass.images_created_tags.iter()) {
+            if let ClearValue::Float(_) = value {
+                if let Some(color) = self.clear_colors.get(tag) {
+                    *value = ClearValue::Float(*color);
+                }
+            }
+        }
+
+        values
+    }
+
+    // declares that no pass ever writes to a custom image, so any number of
+    // passes can sample it at the same time, even on different queues.
+    // vulkano already puts barriers between a pass writing an image and a
+    // later one reading it, and none between passes that only read, so within
+    // one queue this is just checked. it matters for async passes, which can
+    // only safely read images the main queue doesn't write (or ones written
+    // by earlier async passes).
+    pub fn set_read_only(&mut self, tag: &'a str) {
+        if !self.custom_images.contains_key(tag) {
+            panic!("Only custom images can be read-only, {} isn't one", tag);
+        }
+
+        if let Some(writer) = self
+            .passes
+            .iter()
+            .find(|pass| pass.images_created_tags.contains(&tag))
+        {
+            panic!(
+                "Image {} can't be read-only, pass {} writes to it",
+                tag, writer.name
+            );
+        }
+
+        self.read_only_tags.push(tag);
+        self.check_async_reads();
+    }
+
+    // makes a pass draw its opaque objects (ones that write depth and don't
+    // blend) twice: first only their depth, then again shading only the
+    // fragments whose depth equals what the first draw left. that way every
+    // pixel is shaded once, no matter what order objects are added in. the
+    // pass needs a depth attachment.
+    // both draws use the object's own vertex shader so the depths match
+    // exactly, the depth-only one swaps the fragment shader for an empty one.
+    // that also means objects whose fragment shader discards (alpha testing)
+    // don't work here, the depth-only draw doesn't know what they discard.
+    // the depth-only pipeline's layout still has all of the real fragment
+    // shader's sets (see ShaderSystem::load_depth_only), so the object's
+    // descriptor sets are bound to both draws as they are.
+    pub fn set_depth_prepass(&mut self, pass_name: &str, enabled: bool) {
+        let pass_idx = self
+            .passes
+            .iter()
+            .position(|pass| pass.name == pass_name)
+            .unwrap_or_else(|| panic!("No pass named {}", pass_name));
+
+        let render_pass = &self.passes[pass_idx].render_pass;
+        let has_depth = (0..render_pass.num_attachments()).any(|idx| {
+            match render_pass.attachment_desc(idx).unwrap().format.ty() {
+                FormatTy::Depth | FormatTy::DepthStencil => true,
+                _ => false,
+            }
+        });
+        if !has_depth {
+            panic!(
+                "Pass {} has no depth attachment, so it can't have a depth prepass",
+                pass_name
+            );
+        }
+
+        self.depth_prepass_passes.retain(|&idx| idx != pass_idx);
+        if enabled {
+            self.depth_prepass_passes.push(pass_idx);
+        }
+    }
+
+    // draws what add_object held back in a pass with a depth prepass, see
+    // set_depth_prepass. has to be called before the pass's render pass ends.
+    // objects that aren't opaque come last, so blended ones end up on top of
+    // the opaque geometry like they would without the prepass.
+    fn flush_deferred_draws(&mut self) -> Result<(), RenderError> {
+        if self.deferred_draws.is_empty() && self.deferred_late_draws.is_empty() {
+            return Ok(());
+        }
+
+        // the depth-only draws keep the direction of the object's own depth
+        // test, so reverse depth (Greater) still works
+        let depth_compares: Vec<Compare> = self
+            .deferred_draws
+            .iter()
+            .map(|draw| match draw.spec.depth_compare {
+                Compare::Greater | Compare::GreaterOrEqual => Compare::GreaterOrEqual,
+                _ => Compare::LessOrEqual,
+            })
+            .collect();
+
+        let shade_draws: Vec<DeferredDraw> = std::mem::replace(&mut self.deferred_draws, vec![])
+            .into_iter()
+            .map(|mut draw| {
+                draw.spec.read_depth = true;
+                draw.spec.write_depth = false;
+                draw.spec.depth_compare = Compare::Equal;
+                draw.spec.prepass_stage = Some(PrepassStage::Shade);
+                draw
+            })
+            .collect();
+
+        for (draw, &depth_compare) in shade_draws.iter().zip(depth_compares.iter()) {
+            // fs_path stays the real fragment shader: DepthOnly makes the
+            // pipeline cache swap it for an empty one, but keep its layout
+            let mut depth_draw = draw.clone();
+            // the depth test has to be on for both draws, or objects that
+            // don't read depth would write whatever is drawn last and the
+            // shading draw's Equal would miss everything behind it
+            depth_draw.spec.read_depth = true;
+            depth_draw.spec.write_depth = true;
+            depth_draw.spec.depth_compare = depth_compare;
+            depth_draw.spec.prepass_stage = Some(PrepassStage::DepthOnly);
+
+            // set 0 (images_needed) comes from the shading draw's spec, the
+            // layouts are the same but its collection is already cached
+            self.draw_object(&depth_draw, &draw.spec)?;
+        }
+
+        for draw in shade_draws.iter() {
+            self.draw_object(draw, &draw.spec)?;
+        }
+
+        let late_draws = std::mem::replace(&mut self.deferred_late_draws, vec![]);
+        for draw in late_draws.iter() {
+            self.draw_object(draw, &draw.spec)?;
+        }
+
+        Ok(())
+    }
+
+    // lines added with debug_line and debug_box are drawn at the end of
+    // pass_name, on top of whatever the pass drew and depth tested against it
+    // if it has a depth buffer. they only last for the frame they're added in,
+    // for throwaway visualizations that aren't worth making an Object for.
+    pub fn set_debug_lines_pass(&mut self, pass_name: &'a str) {
+        if !self.passes.iter().any(|pass| pass.name == pass_name) {
+            panic!("Can't draw debug lines in pass {}, there's no such pass", pass_name);
+        }
+        self.debug_lines_pass = Some(pass_name);
+    }
+
+    // the camera debug lines are drawn with. has to be set again whenever the
+    // camera moves.
+    pub fn set_debug_camera(&mut self, view: [[f32; 4]; 4], proj: [[f32; 4]; 4]) {
+        self.debug_camera = Some(DebugCamera { view, proj });
+    }
+
+    // draws a line from a to b this frame, see set_debug_lines_pass
+    pub fn debug_line(&mut self, a: [f32; 3], b: [f32; 3], color: [f32; 3]) {
+        self.debug_lines.push(DebugColorVertex { position: a, color });
+        self.debug_lines.push(DebugColorVertex { position: b, color });
+    }
+
+    // draws the edges of an axis-aligned box this frame
+    pub fn debug_box(&mut self, center: [f32; 3], half_extents: [f32; 3], color: [f32; 3]) {
+        let corners = box_corners(center, half_extents);
+        for &[i, j] in box_edges().iter() {
+            self.debug_line(corners[i as usize], corners[j as usize], color);
+        }
+    }
+
+    // like debug_line, but the line stays for duration seconds or until
+    // clear_debug_lines, for showing things that only happen on one frame
+    // like a raycast. time is the window's FrameInfo::elapsed, so lines only
+    // expire when drawing with start_window. std::f32::INFINITY keeps them
+    // until they're cleared.
+    pub fn debug_line_persistent(
+        &mut self,
+        a: [f32; 3],
+        b: [f32; 3],
+        color: [f32; 3],
+        duration: f32,
+    ) {
+        self.persistent_debug_lines.push(PersistentDebugLine {
+            vertices: [
+                DebugColorVertex { position: a, color },
+                DebugColorVertex { position: b, color },
+            ],
+            duration,
+            expires_at: None,
+        });
+    }
+
+    // debug_box, but persistent like debug_line_persistent
+    pub fn debug_box_persistent(
+        &mut self,
+        center: [f32; 3],
+        half_extents: [f32; 3],
+        color: [f32; 3],
+        duration: f32,
+    ) {
+        let corners = box_corners(center, half_extents);
+        for &[i, j] in box_edges().iter() {
+            self.debug_line_persistent(corners[i as usize], corners[j as usize], color, duration);
+        }
+    }
+
+    // removes every persistent debug line
+    pub fn clear_debug_lines(&mut self) {
+        self.persistent_debug_lines.clear();
+    }
+
+    // starts the clock on lines added since the last frame and drops the ones
+    // whose time is up
+    fn expire_debug_lines(&mut self, elapsed: f32) {
+        for line in self.persistent_debug_lines.iter_mut() {
+            if line.expires_at.is_none() {
+                line.expires_at = Some(elapsed + line.duration);
+            }
+        }
+        self.persistent_debug_lines
+            .retain(|line| line.expires_at.map_or(true, |time| elapsed < time));
+    }
+
+    // draws the debug lines if the current pass is the one they're meant for.
+    // called whenever a pass ends, like flush_deferred_draws.
+    fn flush_debug_lines(&mut self) {
+        let draw_persistent =
+            !self.persistent_debug_lines_drawn && !self.persistent_debug_lines.is_empty();
+        if self.debug_lines.is_empty() && !draw_persistent {
+            return;
+        }
+        if self.render_pass_ended {
+            return;
+        }
+        let pass_idx = match &self.state {
+            DrawState::Drawing { pass_idx, .. } => *pass_idx,
+            DrawState::Uninitialized => return,
+        };
+        if self.debug_lines_pass != Some(self.passes[pass_idx].name) {
+            return;
+        }
+        let camera = self
+            .debug_camera
+            .clone()
+            .expect("Call set_debug_camera before drawing debug lines");
+
+        let mut vertices = std::mem::replace(&mut self.debug_lines, vec![]);
+        if draw_persistent {
+            for line in self.persistent_debug_lines.iter() {
+                vertices.extend_from_slice(&line.vertices);
+            }
+            self.persistent_debug_lines_drawn = true;
+        }
+        let indices = (0..vertices.len() as u32).collect();
+        let mesh = Mesh { vertices, indices };
+
+        // by the time the pass ends we're in its last subpass
+        let render_pass = self.passes[pass_idx].render_pass.clone();
+        let subpass = render_pass.num_subpasses() as u32 - 1;
+        let has_depth = render_pass
+            .subpass_desc(subpass as usize)
+            .map(|desc| desc.depth_stencil.is_some())
+            .unwrap_or(false);
+
+        let spec = PipelineSpec {
+            vs_path: relative_path("shaders/debug/lines_vert.glsl"),
+            fs_path: relative_path("shaders/debug/lines_frag.glsl"),
+            fill_type: PrimitiveTopology::LineList,
+            read_depth: has_depth,
+            write_depth: false,
+            depth_compare: Compare::LessOrEqual,
+            depth_clamp: false,
+            subpass,
+            depth_bias: 0.0,
+            stencil: None,
+            alpha_blend: false,
+            num_viewports: 1,
+            prepass_stage: None,
+            vtype: VertexType::<DebugColorVertex>::new(),
+        };
+        let pipeline = self.pipeline_caches[pass_idx].get(&spec);
+
+        // the lines' shaders only have the camera in set 0, so they don't go
+        // through the collection cache and don't get the pass's images
+        let camera_set: Arc<dyn DescriptorSet + Send + Sync> = Arc::new(
+            PersistentDescriptorSet::start(pipeline.clone(), 0)
+                .add_buffer(upload_data(self.device.clone(), camera))
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+        if let DrawState::Drawing {
+            cmd_buf,
+            pass_idx,
+            images,
+            framebuffers,
+            cur_dims,
+        } = state
+        {
+            let cmd_buf = cmd_buf
+                .draw_indexed(
+                    pipeline,
+                    &dynamic_state_for_dimensions(cur_dims, 1),
+                    vec![mesh.get_vbuf(self.queue.clone())],
+                    mesh.get_ibuf(self.queue.clone()),
+                    vec![camera_set],
+                    (),
+                )
+                .expect(&format!(
+                    "error drawing debug lines in pass {}",
+                    self.passes[pass_idx].name
+                ));
+
+            self.state = DrawState::Drawing {
+                cmd_buf,
+                pass_idx,
+                images,
+                framebuffers,
+                cur_dims,
+            };
+        }
+    }
+
+    // warns about images async passes read that the main queue writes, since
+    // the last frame's main queue work might still be writing them
+    fn check_async_reads(&self) {
+        let num_async = match &self.async_passes {
+            Some(async_passes) => async_passes.num_passes,
+            None => return,
+        };
+
+        let (async_passes, main_passes) = self.passes.split_at(num_async);
+        for (pass_idx, pass) in async_passes.iter().enumerate() {
+            for tag in pass.images_needed_tags.iter() {
+                let written_by_async = async_passes[..pass_idx]
+                    .iter()
+                    .any(|other| other.images_created_tags.contains(tag));
+                if written_by_async || self.read_only_tags.contains(tag) {
+                    continue;
+                }
+
+                if let Some(writer) = main_passes
+                    .iter()
+                    .find(|other| other.images_created_tags.contains(tag))
+                {
+                    println!(
+                        "Warning: async pass {} reads {}, which pass {} on the main queue writes",
+                        pass.name, tag, writer.name
+                    );
+                }
+            }
+        }
+    }
+
+    // replaces every pass. images are recreated next frame and pipelines are
+    // rebuilt as objects are drawn, so this is slow, but objects themselves
+    // don't care since they're added again every frame anyway. settings
+    // made per pass (depth prepasses, async passes) carry over to passes with
+    // the same names.
+    pub fn set_passes(&mut self, passes: Vec<Pass<'a>>) {
+        if let DrawState::Drawing { .. } = self.state {
+            panic!("Can't change passes in the middle of a frame");
+        }
+        validate_custom_images(&passes, &self.custom_images);
+        if !passes
+            .iter()
+            .any(|pass| pass.images_created_tags.contains(&self.output_tag))
+        {
+            panic!(
+                "None of the new passes create the output image {}",
+                self.output_tag
+            );
+        }
+        for tag in self.read_only_tags.iter() {
+            if let Some(writer) = passes
+                .iter()
+                .find(|pass| pass.images_created_tags.contains(tag))
+            {
+                panic!(
+                    "Image {} is read-only, but new pass {} writes to it",
+                    tag, writer.name
+                );
+            }
+        }
+
+        let prepass_names: Vec<&str> = self
+            .depth_prepass_passes
+            .iter()
+            .map(|&idx| self.passes[idx].name)
+            .collect();
+        self.depth_prepass_passes = passes
+            .iter()
+            .enumerate()
+            .filter(|(_, pass)| prepass_names.contains(&pass.name))
+            .map(|(idx, _)| idx)
+            .collect();
+
+        // async passes have to stay at the start, otherwise they're dropped
+        if let Some(async_passes) = &self.async_passes {
+            let num = async_passes.num_passes;
+            let same_start = passes.len() > num
+                && self.passes[..num]
+                    .iter()
+                    .zip(passes.iter())
+                    .all(|(old, new)| old.name == new.name);
+            if !same_start {
+                println!("Warning: the new passes don't start with the async ones, running everything on the main queue");
+                self.async_passes = None;
+            }
+        }
+
+        self.pipeline_caches = pipe_caches_for_passes(self.device.clone(), &passes);
+        self.pass_timers = passes.iter().map(|pass| Timer::new(pass.name)).collect();
+        self.passes = passes;
+        self.cached_images = None;
+        self.pass_dims = vec![];
+        self.collection_cache.clear();
+        self.check_async_reads();
+    }
+
+    // registers the passes to use for path. the passes the System was created
+    // with count as RenderPath::Forward.
+    pub fn add_render_path(&mut self, path: RenderPath, passes: Vec<Pass<'a>>) {
+        if path == self.render_path {
+            self.set_passes(passes);
+        } else {
+            self.other_path_passes.insert(path, passes);
+        }
+    }
+
+    // switches to the passes registered for path with add_render_path. the
+    // objects drawn in each pass change with the path too, so the code adding
+    // objects needs to check render_path() every frame.
+    // async passes (see run_on_async_queue) belong to the path that was
+    // current when they were set, and come back when switching back to it.
+    pub fn set_render_path(&mut self, path: RenderPath) {
+        if path == self.render_path {
+            return;
+        }
+
+        let new_passes = self
+            .other_path_passes
+            .remove(&path)
+            .unwrap_or_else(|| panic!("No passes for render path {:?}, see add_render_path", path));
+        let old_passes = self.passes.clone();
+        // taken out first so set_passes doesn't compare them with passes
+        // from a different path
+        let old_async = self.async_passes.take();
+        self.set_passes(new_passes);
+
+        self.other_path_passes.insert(self.render_path, old_passes);
+        if let Some(old_async) = old_async {
+            self.other_path_async_passes.insert(self.render_path, old_async);
+        }
+        self.async_passes = self.other_path_async_passes.remove(&path);
+        self.check_async_reads();
+        self.render_path = path;
+    }
+
+    pub fn render_path(&self) -> RenderPath {
+        self.render_path
+    }
+
+    // renders every image that isn't custom at scale times the window's
+    // resolution, then upscales the output to the window at the end of the
+    // frame. below 1 it trades sharpness for speed. only applies when
+    // drawing to a window with start_window.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        assert!(scale > 0.0, "Render scale has to be positive, got {}", scale);
+        self.render_scale = scale;
+    }
+
+    pub fn get_render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    // with a render scale, draws the pass that creates output_tag straight to
+    // the window at full resolution instead of blitting its output there. the
+    // images it reads are still at the lower resolution, so it can do its
+    // own, better upscaling (see shaders/pretty/upscale_frag.glsl in the
+    // examples). it can't create any images except the output.
+    pub fn set_full_res_output(&mut self, full_res: bool) {
+        self.full_res_output = full_res;
+    }
+
     pub fn start(&mut self, dest_image: Arc<dyn ImageViewAccess + Send + Sync>) {
+        if let Err(e) = self.start_inner(dest_image, None) {
+            panic!("{}", e);
+        }
+    }
+
+    // window_image is the same image as dest_image, but as something that
+    // can be blitted to for upscaling
+    fn start_inner(
+        &mut self,
+        dest_image: Arc<dyn ImageViewAccess + Send + Sync>,
+        window_image: Option<Arc<dyn ImageAccess + Send + Sync>>,
+    ) -> Result<(), RenderError> {
         self.setup_timer.start();
+        self.last_object_stats = std::mem::replace(&mut self.object_stats, ObjectStats::default());
+        let descriptor_sets = descriptor_sets_created();
+        self.descriptor_sets_last_frame = descriptor_sets - self.descriptor_sets_at_frame_start;
+        self.descriptor_sets_at_frame_start = descriptor_sets;
+
+        self.output_image = None;
+        self.render_pass_ended = false;
+        self.persistent_debug_lines_drawn = false;
+        self.write_finished_captures();
 
         // all images will be created with the same dimensions as the
-        // destination image. if you need to use an image with a different
-        // resolution, use custom_images in System.
-        let dimensions = [
+        // destination image (times the render scale). if you need to use an
+        // image with a different resolution, use custom_images in System.
+        let full_dimensions = [
             dest_image.dimensions().width(),
             dest_image.dimensions().height(),
         ];
 
+        let scaling = window_image.is_some() && self.render_scale != 1.0;
+        self.upscale_dest = if scaling && !self.full_res_output {
+            window_image
+        } else {
+            None
+        };
+        let dimensions = if scaling {
+            [
+                ((full_dimensions[0] as f32 * self.render_scale) as u32).max(1),
+                ((full_dimensions[1] as f32 * self.render_scale) as u32).max(1),
+            ]
+        } else {
+            full_dimensions
+        };
+
         // create all images and framebuffers
         let mut images = self.get_images(dimensions);
 
-        // replace destination image with the real one
-        images.insert(self.output_tag.to_string(), dest_image);
+        // replace destination image with the real one. when upscaling with a
+        // blit, the output is drawn to its own image and blitted later.
+        if self.upscale_dest.is_none() {
+            images.insert(self.output_tag.to_string(), dest_image);
+        }
+
+        let output_tag = self.output_tag;
+        self.pass_dims = self
+            .passes
+            .iter()
+            .map(|pass| {
+                let creates_output = pass.images_created_tags.contains(&output_tag);
+                if scaling && self.full_res_output && creates_output {
+                    assert!(
+                        pass.images_created_tags.len() == 1,
+                        "Pass {} draws the output at full resolution, so it can't create any other images",
+                        pass.name
+                    );
+                    full_dimensions
+                } else {
+                    dimensions
+                }
+            })
+            .collect();
 
         // use any custom images to replace existing ones
         for (tag, image) in self.custom_images.iter() {
@@ -132,180 +1046,1580 @@ impl<'a> System<'a> {
         // when you begin rendering, you automatically enter the first pass (for
         // which the first framebuffer is used)
         let first_framebuffer = framebuffers[0].clone();
-        let first_render_pass = self.passes[0].render_pass.clone();
-
-        let clear_values = clear_values_for_pass(first_render_pass);
+        let clear_values = self.clear_values(0);
 
         // create the command buffer and enter first render pass
         let cmd_buf_builder = AutoCommandBufferBuilder::primary_one_time_submit(
             self.device.clone(),
             self.queue.family(),
         )
-        .unwrap()
+        .map_err(recording_error)?
         .begin_render_pass(first_framebuffer, false, clear_values.clone())
-        .unwrap();
+        .map_err(recording_error)?;
 
         self.state = DrawState::Drawing {
             cmd_buf: cmd_buf_builder,
             pass_idx: 0,
             images,
             framebuffers,
-            // TODO: support passes with different dimensions
-            cur_dims: dimensions,
+            cur_dims: self.pass_dims[0],
+        };
+
+        self.setup_timer.stop();
+        self.cmd_buf_timer.start();
+        self.pass_timers[0].start();
+
+        Ok(())
+    }
+
+    // draws a frame without a window: every pass draws to offscreen images of
+    // dims and the output comes back once the GPU is done with it, to be read
+    // with capture::read_rgba8. draw adds the objects, the same as what goes
+    // between start_window and finish_to_window:
+    //
+    // let queue = window::headless_queue();
+    // let mut system = System::new(queue.clone(), passes, CustomImages::new(), "final");
+    // let image = system.render_headless([256, 256], |system| {
+    //     system.add_object(&object);
+    // });
+    // let pixels = capture::read_rgba8(queue, image).unwrap();
+    //
+    // images System creates (depth buffers too) are dims, custom images keep
+    // the size they were made with like always. the render scale is ignored,
+    // it only applies to windows.
+    pub fn render_headless<F: FnOnce(&mut Self)>(
+        &mut self,
+        dims: [u32; 2],
+        draw: F,
+    ) -> Arc<AttachmentImage> {
+        // the output gets its own image instead of the one System would make,
+        // since that one can share memory with other transient images and
+        // would be overwritten by the next frame anyway
+        let output = self.create_headless_output(dims);
+        if let Err(e) = self.start_inner(output.clone(), None) {
+            panic!("{}", e);
+        }
+        // so frame dumps work without a window too
+        self.output_image = Some(output.clone());
+
+        draw(self);
+
+        self.finish(sync::now(self.device.clone()))
+            .then_signal_fence_and_flush()
+            .expect("Couldn't submit headless frame")
+            .wait(None)
+            .expect("Couldn't wait for headless frame");
+
+        output
+    }
+
+    fn create_headless_output(&self, dims: [u32; 2]) -> Arc<AttachmentImage> {
+        let format = self
+            .passes
+            .iter()
+            .find_map(|pass| {
+                let idx = pass
+                    .images_created_tags
+                    .iter()
+                    .position(|&tag| tag == self.output_tag)?;
+                pass.render_pass
+                    .attachment_desc(idx)
+                    .map(|desc| desc.format)
+            })
+            .unwrap_or_else(|| panic!("No pass creates the output image {}", self.output_tag));
+
+        AttachmentImage::with_usage(
+            self.device.clone(),
+            dims,
+            format,
+            ImageUsage {
+                transfer_source: true,
+                sampled: true,
+                color_attachment: true,
+                ..ImageUsage::none()
+            },
+        )
+        .expect("Couldn't create headless output image")
+    }
+
+    // returns whether a frame was started. it isn't while the window is
+    // minimized, skip adding objects and finishing until it is:
+    // if !system.start_window(&mut window) {
+    //     continue;
+    // }
+    pub fn start_window(&mut self, window: &mut Window) -> bool {
+        // next_image rebuilds the swapchain by itself when it's out of date
+        self.acquire_timer.start();
+        let swapchain_image = window.next_image();
+        self.acquire_timer.stop();
+        let swapchain_image = match swapchain_image {
+            Some(image) => image,
+            None => return false,
         };
+        if let Err(e) = self.start_window_image(window, swapchain_image) {
+            panic!("{}", e);
+        }
+
+        true
+    }
+
+    // start_window, but errors are returned. RenderError::SwapchainOutOfDate
+    // means the window has to be rebuilt first, see Window::rebuild_swapchain.
+    pub fn try_start_window(&mut self, window: &mut Window) -> Result<(), RenderError> {
+        self.acquire_timer.start();
+        let swapchain_image = window.try_next_image();
+        self.acquire_timer.stop();
+        self.start_window_image(window, swapchain_image?)
+    }
+
+    fn start_window_image(
+        &mut self,
+        window: &mut Window,
+        swapchain_image: Arc<SwapchainImage<winit::Window>>,
+    ) -> Result<(), RenderError> {
+        self.check_output_format(window.get_format());
+        self.expire_debug_lines(window.get_frame_info().elapsed);
+        let started = self.start_inner(swapchain_image.clone(), Some(swapchain_image.clone()));
+        if let Err(e) = started {
+            self.abandon_frame(window);
+            return Err(e);
+        }
+        self.output_image = Some(swapchain_image);
+
+        Ok(())
+    }
+
+    // the pass that creates the output image has to draw in the same format
+    // as the destination, otherwise creating its framebuffer fails with a
+    // much less helpful error
+    fn check_output_format(&self, dest_format: Format) {
+        for pass in self.passes.iter() {
+            let idx = match pass
+                .images_created_tags
+                .iter()
+                .position(|&tag| tag == self.output_tag)
+            {
+                Some(idx) => idx,
+                None => continue,
+            };
+
+            let format = pass.render_pass.attachment_desc(idx).unwrap().format;
+            if format != dest_format {
+                panic!(
+                    "Pass {} draws output image {} in format {:?}, but the window's format is {:?}. Create its render pass with the window's format.",
+                    pass.name, self.output_tag, format, dest_format
+                );
+            }
+        }
+    }
+
+    pub fn add_object<T: Drawcall + ?Sized>(&mut self, object: &T) {
+        if let Err(e) = self.try_add_object(object) {
+            panic!("{}", e);
+        }
+    }
+
+    // the camera add_object_bounded culls against. has to be set again
+    // whenever the camera moves. it can be a different camera than the one
+    // the objects are drawn with, e.g. a frozen one to see what gets culled.
+    pub fn set_cull_camera(&mut self, view: [[f32; 4]; 4], proj: [[f32; 4]; 4]) {
+        let view: Mat4 = view.into();
+        let proj: Mat4 = proj.into();
+        self.cull_view_proj = Some(proj * view);
+    }
+
+    // adds object, unless bounds (in world space) are out of view of the
+    // camera from set_cull_camera. returns whether it was drawn. without a
+    // cull camera everything is drawn.
+    // see Aabb::transformed for getting world space bounds.
+    pub fn add_object_bounded<T: Drawcall + ?Sized>(&mut self, object: &T, bounds: &Aabb) -> bool {
+        if let Some(view_proj) = &self.cull_view_proj {
+            if bounds.outside_frustum(view_proj) {
+                self.object_stats.objects_submitted += 1;
+                self.object_stats.objects_culled += 1;
+                return false;
+            }
+        }
+
+        self.add_object(object);
+        true
+    }
+
+    // how many descriptor sets are being made. every Set::upload makes one, so
+    // uploading lots of objects' data every frame shows up here.
+    // NOTE: the counts are for every System (and Set) in the program, not just
+    // this one
+    pub fn descriptor_set_stats(&self) -> DescriptorSetStats {
+        DescriptorSetStats {
+            created_last_frame: self.descriptor_sets_last_frame,
+            created_total: descriptor_sets_created(),
+            cached_collections: self.collection_cache.len(),
+        }
+    }
+
+    // see CollectionCache::set_max_cached
+    pub fn set_max_cached_collections(&mut self, max_cached: Option<usize>) {
+        self.collection_cache.set_max_cached(max_cached);
+    }
+
+    // object counts for the last finished frame. objects_drawn counts
+    // objects System got to draw, not what the GPU ended up rasterizing.
+    // NOTE: indirect draws count as one object, however many draws or
+    // instances are in their buffer
+    pub fn object_stats(&self) -> ObjectStats {
+        self.last_object_stats
+    }
+
+    // like add_object, but returns an error instead of panicking if the
+    // object can't be drawn in the current pass, e.g. because it was made for
+    // a different one. the error says which passes it would work in.
+    pub fn try_add_object<T: Drawcall + ?Sized>(&mut self, object: &T) -> Result<(), RenderError> {
+        if self.render_pass_ended {
+            let msg = "Can't add objects after record_custom, call next_pass first!";
+            return Err(RenderError::InvalidState(msg.to_string()));
+        }
+
+        let pass_idx = match self.state {
+            DrawState::Drawing { pass_idx, .. } => pass_idx,
+            DrawState::Uninitialized => {
+                let msg = "You tried to render an object without calling begin_render first!";
+                return Err(RenderError::InvalidState(msg.to_string()));
+            }
+        };
+
+        self.object_stats.objects_submitted += 1;
+
+        let outputs = self.fragment_outputs_for(object.pipe_spec());
+        let pass = &self.passes[pass_idx];
+        if let Err(e) = check_object_fits_pass(object.pipe_spec(), &outputs, pass) {
+            let fitting: Vec<&str> = self
+                .passes
+                .iter()
+                .filter(|other| check_object_fits_pass(object.pipe_spec(), &outputs, other).is_ok())
+                .map(|other| other.name)
+                .collect();
+            return Err(RenderError::InvalidObject(format!(
+                "Object with vertex shader {:?} can't be drawn in pass {}: {}. Passes it would work in: {:?}",
+                object.pipe_spec().vs_path, pass.name, e, fitting
+            )));
+        }
+
+        if self.depth_prepass_passes.contains(&pass_idx) {
+            let draw = DeferredDraw {
+                spec: object.pipe_spec().clone(),
+                vbufs: object.vbufs(),
+                ibuf: object.ibuf(),
+                collection: object.collection(),
+                custom_dynstate: object.custom_dynstate(),
+            };
+            if is_opaque(object.pipe_spec()) {
+                self.deferred_draws.push(draw);
+            } else {
+                self.deferred_late_draws.push(draw);
+            }
+            self.object_stats.objects_drawn += 1;
+            return Ok(());
+        }
+
+        self.draw_object(object, object.pipe_spec())?;
+        self.object_stats.objects_drawn += 1;
+
+        Ok(())
+    }
+
+    fn fragment_outputs_for(&mut self, spec: &PipelineSpec) -> Vec<(u32, Format)> {
+        let device = self.device.clone();
+        self.fragment_outputs
+            .entry(spec.fs_path.clone())
+            .or_insert_with(|| {
+                ShaderSystem::load_from_file(device, &spec.vs_path, &spec.fs_path)
+                    .fragment_outputs()
+            })
+            .clone()
+    }
+
+    // the descriptor sets for images_needed and input attachments are made
+    // for collection_spec's pipeline, which has to have the same layout as
+    // object's. they're only different for the depth-only prepass draws.
+    fn draw_object<T: Drawcall + ?Sized>(
+        &mut self,
+        object: &T,
+        collection_spec: &PipelineSpec,
+    ) -> Result<(), RenderError> {
+        // we need to take ownership for a while
+        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+        match state {
+            DrawState::Uninitialized => Err(RenderError::InvalidState(
+                "You tried to render an object without calling begin_render first!".to_string(),
+            )),
+            DrawState::Drawing {
+                mut cmd_buf,
+                pass_idx,
+                images,
+                framebuffers,
+                cur_dims,
+            } => {
+                // TODO: dynamic state is re-created for every object, shouldn't be
+                let mut dynamic_state = if let Some(dynstate) = object.custom_dynstate() {
+                    dynstate
+                } else {
+                    // TODO: this is another spot preventing passes with
+                    // different dimensions
+                    dynamic_state_for_dimensions(
+                        cur_dims,
+                        supported_num_viewports(&self.device, object.pipe_spec().num_viewports),
+                    )
+                };
+                apply_depth_bias(&mut dynamic_state, object.pipe_spec().depth_bias);
+
+                let pipeline = self.pipeline_caches[pass_idx].get(object.pipe_spec());
+                let collection_pipeline = if collection_spec == object.pipe_spec() {
+                    pipeline.clone()
+                } else {
+                    self.pipeline_caches[pass_idx].get(collection_spec)
+                };
+
+                let mut collection = self.collection_cache.get(
+                    collection_spec,
+                    collection_pipeline,
+                    &self.passes[pass_idx],
+                    &images,
+                );
+
+                let mut obj_collection =
+                    object.collection();
+
+                collection.append(&mut obj_collection);
+
+                cmd_buf = cmd_buf
+                    .draw_indexed(
+                        pipeline,
+                        &dynamic_state,
+                        object.vbufs(),
+                        object.ibuf(),
+                        collection,
+                        (),
+                    )
+                    .map_err(|e| {
+                        RenderError::Recording(format!(
+                            "error building cmd buf, in pass {}: {:?}",
+                            self.passes[pass_idx].name, e
+                        ))
+                    })?;
+
+                // give state a real value again
+                self.state = DrawState::Drawing {
+                    cmd_buf,
+                    pass_idx,
+                    images,
+                    framebuffers,
+                    cur_dims,
+                };
+
+                Ok(())
+            }
+        }
+    }
+
+    // draws object once for every command in commands, e.g. one per
+    // DrawRange from Mesh::merge_indexed. commands can be written by a compute
+    // shader to do culling on the GPU: culled draws should set instance_count
+    // to 0.
+    // NOTE: ideally this would use vkCmdDrawIndexedIndirectCount, so a compute
+    // pass could also write how many draws there are and we'd skip the culled
+    // ones entirely. vulkano doesn't expose it (or the drawIndirectCount
+    // feature), so this falls back to vkCmdDrawIndexedIndirect and every
+    // command in the buffer is always issued.
+    pub fn add_object_indirect<T: Drawcall, B>(&mut self, object: &T, commands: Arc<B>)
+    where
+        B: TypedBufferAccess<Content = [DrawIndexedIndirectCommand]> + Send + Sync + 'static,
+    {
+        if let Err(e) = self.try_add_object_indirect(object, commands) {
+            panic!("{}", e);
+        }
+    }
+
+    // add_object_indirect, but errors are returned
+    pub fn try_add_object_indirect<T: Drawcall, B>(
+        &mut self,
+        object: &T,
+        commands: Arc<B>,
+    ) -> Result<(), RenderError>
+    where
+        B: TypedBufferAccess<Content = [DrawIndexedIndirectCommand]> + Send + Sync + 'static,
+    {
+        if self.render_pass_ended {
+            let msg = "Can't add objects after record_custom, call next_pass first!";
+            return Err(RenderError::InvalidState(msg.to_string()));
+        }
+
+        self.object_stats.objects_submitted += 1;
+        self.object_stats.objects_drawn += 1;
+
+        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+        match state {
+            DrawState::Uninitialized => {
+                let msg = "You tried to render an object without calling begin_render first!";
+                Err(RenderError::InvalidState(msg.to_string()))
+            }
+            DrawState::Drawing {
+                mut cmd_buf,
+                pass_idx,
+                images,
+                framebuffers,
+                cur_dims,
+            } => {
+                let mut dynamic_state = if let Some(dynstate) = object.custom_dynstate() {
+                    dynstate
+                } else {
+                    dynamic_state_for_dimensions(
+                        cur_dims,
+                        supported_num_viewports(&self.device, object.pipe_spec().num_viewports),
+                    )
+                };
+                apply_depth_bias(&mut dynamic_state, object.pipe_spec().depth_bias);
+
+                let pipeline = self.pipeline_caches[pass_idx].get(object.pipe_spec());
+
+                let mut collection = self.collection_cache.get(
+                    object.pipe_spec(),
+                    pipeline.clone(),
+                    &self.passes[pass_idx],
+                    &images,
+                );
+                collection.append(&mut object.collection());
+
+                let pass_name = self.passes[pass_idx].name;
+                let draw_error = |e| {
+                    RenderError::Recording(format!(
+                        "error building cmd buf for indirect draw, in pass {}: {:?}",
+                        pass_name, e
+                    ))
+                };
+
+                if commands.len() <= 1 || self.device.enabled_features().multi_draw_indirect {
+                    cmd_buf = cmd_buf
+                        .draw_indexed_indirect(
+                            pipeline,
+                            &dynamic_state,
+                            object.vbufs(),
+                            object.ibuf(),
+                            commands,
+                            collection,
+                            (),
+                        )
+                        .map_err(draw_error)?;
+                } else {
+                    // more than one command per call needs multiDrawIndirect,
+                    // so without it every command gets its own call. same
+                    // result, just more work for the CPU.
+                    for idx in 0..commands.len() {
+                        // idx is always in range, slice only fails past the end
+                        let command = commands
+                            .clone()
+                            .into_buffer_slice()
+                            .slice(idx..idx + 1)
+                            .ok_or_else(|| {
+                                RenderError::Recording(format!(
+                                    "indirect command {} is out of range",
+                                    idx
+                                ))
+                            })?;
+
+                        cmd_buf = cmd_buf
+                            .draw_indexed_indirect(
+                                pipeline.clone(),
+                                &dynamic_state,
+                                object.vbufs(),
+                                object.ibuf(),
+                                command,
+                                collection.clone(),
+                                (),
+                            )
+                            .map_err(draw_error)?;
+                    }
+                }
+
+                self.state = DrawState::Drawing {
+                    cmd_buf,
+                    pass_idx,
+                    images,
+                    framebuffers,
+                    cur_dims,
+                };
+
+                Ok(())
+            }
+        }
+    }
+
+    // draws fs_path over the whole of the current pass's first subpass, for
+    // post-processing without making a fullscreen quad object for every
+    // effect:
+    //
+    // system.draw_fullscreen("tonemap", &relative_path("tonemap.glsl"), &[("color", "hdr")], exposure);
+    //
+    // inputs are (name, tag) pairs, and the image with each tag goes in set 0
+    // of the fragment shader, at the binding of its position in inputs. the
+    // names don't have to match anything, they're for error messages. the
+    // tags have to be in the pass's images_needed_tags, and get the sampler
+    // they would get there (see set_sampler). uniforms go in the set after
+    // the inputs, or nowhere if it's (). the shader gets the uv of the pixel
+    // as vec2 input 0, with 0, 0 at the top left.
+    // pass_name has to be the current pass. it's only checked, so a call in
+    // the wrong place fails loudly instead of drawing over the wrong image.
+    pub fn draw_fullscreen<D: Data>(
+        &mut self,
+        pass_name: &str,
+        fs_path: &Path,
+        inputs: &[(&str, &str)],
+        uniforms: D,
+    ) {
+        if let Err(e) = self.try_draw_fullscreen(pass_name, fs_path, inputs, uniforms) {
+            panic!("{}", e);
+        }
+    }
+
+    // draw_fullscreen, but errors are returned
+    pub fn try_draw_fullscreen<D: Data>(
+        &mut self,
+        pass_name: &str,
+        fs_path: &Path,
+        inputs: &[(&str, &str)],
+        uniforms: D,
+    ) -> Result<(), RenderError> {
+        if self.render_pass_ended {
+            let msg = "Can't draw after record_custom, call next_pass first!";
+            return Err(RenderError::InvalidState(msg.to_string()));
+        }
+        let pass_idx = match &self.state {
+            DrawState::Uninitialized => {
+                let msg = "Can't draw fullscreen without having begun rendering";
+                return Err(RenderError::InvalidState(msg.to_string()));
+            }
+            DrawState::Drawing { pass_idx, .. } => *pass_idx,
+        };
+        let pass = &self.passes[pass_idx];
+        if pass.name != pass_name {
+            return Err(RenderError::InvalidState(format!(
+                "draw_fullscreen was told to draw in pass {}, but the current pass is {}",
+                pass_name, pass.name
+            )));
+        }
+        for (name, tag) in inputs.iter() {
+            if !pass.images_needed_tags.contains(tag) {
+                return Err(RenderError::InvalidObject(format!(
+                    "Input {} of fullscreen draw in pass {} is {}, which isn't in its images_needed_tags",
+                    name, pass.name, tag
+                )));
+            }
+        }
+
+        // deferred draws are opaque geometry, they'd end up drawn on top
+        self.flush_deferred_draws()?;
+
+        if self.fullscreen_quad.is_none() {
+            let mesh = fullscreen_quad();
+            self.fullscreen_quad = Some((
+                mesh.get_vbuf(self.queue.clone()),
+                mesh.get_ibuf(self.queue.clone()),
+            ));
+        }
+        let (vbuf, ibuf) = self.fullscreen_quad.clone().unwrap();
+
+        let spec = PipelineSpec {
+            vs_path: relative_path("shaders/fullscreen/fullscreen_vert.glsl"),
+            fs_path: fs_path.to_path_buf(),
+            fill_type: PrimitiveTopology::TriangleStrip,
+            read_depth: false,
+            write_depth: false,
+            depth_compare: Compare::LessOrEqual,
+            depth_clamp: false,
+            subpass: 0,
+            depth_bias: 0.0,
+            stencil: None,
+            alpha_blend: false,
+            num_viewports: 1,
+            prepass_stage: None,
+            vtype: VertexType::<DebugVertex>::new(),
+        };
+        let pipeline = self.pipeline_caches[pass_idx].get(&spec);
+
+        self.object_stats.objects_submitted += 1;
+        self.object_stats.objects_drawn += 1;
+
+        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+        if let DrawState::Drawing {
+            cmd_buf,
+            pass_idx,
+            images,
+            framebuffers,
+            cur_dims,
+        } = state
+        {
+            let pass = &self.passes[pass_idx];
+
+            // like the lines in flush_debug_lines, these don't go through the
+            // collection cache since they're only the images in inputs
+            let mut sets: Vec<Arc<dyn DescriptorSet + Send + Sync>> = vec![];
+            let input_images: Vec<Arc<dyn ImageViewAccess + Send + Sync>> = inputs
+                .iter()
+                .map(|(name, tag)| {
+                    images.get(*tag).cloned().ok_or_else(|| {
+                        RenderError::InvalidState(format!(
+                            "missing image {} for input {}",
+                            tag, name
+                        ))
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            let samplers: Vec<_> = inputs
+                .iter()
+                .map(|(_, tag)| self.collection_cache.sampler_for(pass.name, tag))
+                .collect();
+            if let Some(set) = pds_for_images(&samplers, pipeline.clone(), &input_images, 0) {
+                sets.push(set);
+            }
+            if std::mem::size_of::<D>() > 0 {
+                let set_idx = sets.len();
+                sets.push(Arc::new(
+                    PersistentDescriptorSet::start(pipeline.clone(), set_idx)
+                        .add_buffer(upload_data(self.device.clone(), uniforms))
+                        .map_err(|e| {
+                            RenderError::Recording(format!(
+                                "couldn't add uniforms of fullscreen draw at set idx {}: {:?}",
+                                set_idx, e
+                            ))
+                        })?
+                        .build()
+                        .map_err(recording_error)?,
+                ));
+            }
+            count_descriptor_sets(sets.len());
+
+            let cmd_buf = cmd_buf
+                .draw_indexed(
+                    pipeline,
+                    &dynamic_state_for_dimensions(cur_dims, 1),
+                    vec![vbuf],
+                    ibuf,
+                    sets,
+                    (),
+                )
+                .map_err(|e| {
+                    RenderError::Recording(format!(
+                        "error drawing {:?} fullscreen in pass {}: {:?}",
+                        fs_path, pass.name, e
+                    ))
+                })?;
+
+            self.state = DrawState::Drawing {
+                cmd_buf,
+                pass_idx,
+                images,
+                framebuffers,
+                cur_dims,
+            };
+        }
+
+        Ok(())
+    }
+
+    // draws every subpass of the current pass, one list of objects per
+    // subpass, moving to the next subpass in between. there has to be exactly
+    // one list for each subpass in the render pass.
+    pub fn draw_subpasses(&mut self, subpasses: &[Vec<&dyn Drawcall>]) {
+        let pass_idx = match &self.state {
+            DrawState::Uninitialized => {
+                panic!("Can't draw subpasses without having begun rendering")
+            }
+            DrawState::Drawing { pass_idx, .. } => *pass_idx,
+        };
+        let pass = &self.passes[pass_idx];
+
+        assert_eq!(
+            subpasses.len(),
+            pass.num_subpasses(),
+            "Got objects for {} subpasses, but pass {} has {}",
+            subpasses.len(),
+            pass.name,
+            pass.num_subpasses(),
+        );
+
+        for (subpass_idx, objects) in subpasses.iter().enumerate() {
+            if subpass_idx > 0 {
+                self.next_subpass();
+            }
+
+            for object in objects.iter() {
+                if object.pipe_spec().subpass as usize != subpass_idx {
+                    panic!(
+                        "Object with vertex shader {:?} is meant for subpass {}, but was given for subpass {} of pass {}",
+                        object.pipe_spec().vs_path,
+                        object.pipe_spec().subpass,
+                        subpass_idx,
+                        self.passes[pass_idx].name,
+                    );
+                }
+
+                self.add_object(*object);
+            }
+        }
+    }
+
+    // moves to the next subpass of the current pass's render pass. objects
+    // drawn afterwards need their pipeline_spec.subpass set to match.
+    pub fn next_subpass(&mut self) {
+        if let Err(e) = self.try_next_subpass() {
+            panic!("{}", e);
+        }
+    }
+
+    pub fn try_next_subpass(&mut self) -> Result<(), RenderError> {
+        // the held back draws belong to the subpass we're leaving
+        self.flush_deferred_draws()?;
+
+        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+        match state {
+            DrawState::Uninitialized => Err(RenderError::InvalidState(
+                "Can't enter next subpass without having begun rendering".to_string(),
+            )),
+            DrawState::Drawing {
+                cmd_buf,
+                pass_idx,
+                images,
+                framebuffers,
+                cur_dims,
+            } => {
+                let cmd_buf = cmd_buf.next_subpass(false).map_err(|e| {
+                    RenderError::Recording(format!(
+                        "Couldn't enter next subpass in pass {}: {:?}",
+                        self.passes[pass_idx].name, e
+                    ))
+                })?;
+
+                self.state = DrawState::Drawing {
+                    cmd_buf,
+                    pass_idx,
+                    images,
+                    framebuffers,
+                    cur_dims,
+                };
+
+                Ok(())
+            }
+        }
+    }
+
+    pub fn next_pass(&mut self) {
+        if let Err(e) = self.try_next_pass() {
+            panic!("{}", e);
+        }
+    }
+
+    pub fn try_next_pass(&mut self) -> Result<(), RenderError> {
+        self.flush_deferred_draws()?;
+        self.flush_debug_lines();
+
+        // again, temporarily take ownership
+        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+        match state {
+            DrawState::Uninitialized => Err(RenderError::InvalidState(
+                "Can't enter next pass without having begun rendering".to_string(),
+            )),
+            DrawState::Drawing {
+                mut cmd_buf,
+                mut pass_idx,
+                images,
+                framebuffers,
+                cur_dims: _,
+            } => {
+                self.pass_timers[pass_idx].stop();
+                pass_idx += 1;
+                self.pass_timers[pass_idx].start();
+
+                let framebuffer = framebuffers[pass_idx].clone();
+                let clear_values = self.clear_values(pass_idx);
+
+                if !self.render_pass_ended {
+                    cmd_buf = cmd_buf.end_render_pass().map_err(recording_error)?;
+                }
+                self.render_pass_ended = false;
+
+                let async_done = match &self.async_passes {
+                    Some(async_passes) => async_passes.num_passes == pass_idx,
+                    None => false,
+                };
+                if async_done {
+                    cmd_buf = self.submit_async_passes(cmd_buf)?;
+                }
+
+                cmd_buf = cmd_buf
+                    .begin_render_pass(framebuffer, false, clear_values)
+                    .map_err(recording_error)?;
+
+                // give state a real value again
+                self.state = DrawState::Drawing {
+                    cmd_buf,
+                    pass_idx,
+                    images,
+                    framebuffers,
+                    cur_dims: self.pass_dims[pass_idx],
+                };
+
+                Ok(())
+            }
+        }
+    }
+
+    // lets you record your own commands (copies, blits, clears...) after the
+    // current pass. it ends the current pass's render pass, so everything for
+    // it has to be added before calling this, and next_pass has to be called
+    // before adding more objects. vulkano inserts whatever barriers are needed
+    // between the passes and your commands.
+    // images can be looked up by tag with get_image.
+    // NOTE: there's no System::barrier for inserting pipeline barriers by
+    // hand. vulkano 0.14's AutoCommandBufferBuilder doesn't let you record
+    // one, it tracks every buffer and image its commands use and puts in the
+    // barriers itself. that covers the usual hazards, like a compute shader
+    // writing a particle buffer that a later draw reads as vertices: as long
+    // as both are recorded here (or through add_object) with the same buffer,
+    // the draw waits for the dispatch. what it can't see is memory it doesn't
+    // know about, e.g. two buffers aliasing the same memory. avoid that.
+    // TODO: if we ever record into unsafe command buffers, add the barrier
+    // method then.
+    pub fn record_custom<F>(&mut self, f: F)
+    where
+        F: FnOnce(AutoCommandBufferBuilder) -> AutoCommandBufferBuilder,
+    {
+        if let Err(e) = self.try_record_custom(f) {
+            panic!("{}", e);
+        }
+    }
+
+    pub fn try_record_custom<F>(&mut self, f: F) -> Result<(), RenderError>
+    where
+        F: FnOnce(AutoCommandBufferBuilder) -> AutoCommandBufferBuilder,
+    {
+        self.flush_deferred_draws()?;
+        self.flush_debug_lines();
+
+        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+        match state {
+            DrawState::Uninitialized => Err(RenderError::InvalidState(
+                "Can't record custom commands without having begun rendering".to_string(),
+            )),
+            DrawState::Drawing {
+                mut cmd_buf,
+                pass_idx,
+                images,
+                framebuffers,
+                cur_dims,
+            } => {
+                if !self.render_pass_ended {
+                    cmd_buf = cmd_buf.end_render_pass().map_err(recording_error)?;
+                    self.render_pass_ended = true;
+                }
+
+                let cmd_buf = f(cmd_buf);
+
+                self.state = DrawState::Drawing {
+                    cmd_buf,
+                    pass_idx,
+                    images,
+                    framebuffers,
+                    cur_dims,
+                };
+
+                Ok(())
+            }
+        }
+    }
+
+    // submits the command buffer for the async passes and returns a new one
+    // for the rest of the frame
+    fn submit_async_passes(
+        &mut self,
+        cmd_buf: AutoCommandBufferBuilder,
+    ) -> Result<AutoCommandBufferBuilder, RenderError> {
+        let async_passes = match self.async_passes.as_mut() {
+            Some(async_passes) => async_passes,
+            None => {
+                let msg = "Tried to submit async passes, but there aren't any";
+                return Err(RenderError::InvalidState(msg.to_string()));
+            }
+        };
+        let async_cmd_buf = Arc::new(cmd_buf.build().map_err(recording_error)?);
+
+        let submitted = sync::now(self.device.clone())
+            .then_execute(async_passes.queue.clone(), async_cmd_buf.clone());
+
+        match submitted {
+            Ok(future) => {
+                let future = future
+                    .then_signal_semaphore_and_flush()
+                    .map_err(submit_error)?;
+                async_passes.future = Some(Box::new(future));
+            }
+            // usually means an image the async passes use is still being used
+            // by the last frame
+            Err(e) => {
+                if !async_passes.warned {
+                    println!(
+                        "Warning: couldn't submit async passes early ({:?}), running them on the main queue",
+                        e
+                    );
+                    async_passes.warned = true;
+                }
+                async_passes.fallback = Some(async_cmd_buf);
+            }
+        }
+
+        AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family())
+            .map_err(recording_error)
+    }
+
+    // like get_image, but returns the concrete image, which is needed for
+    // blits and copies. only works for images System creates itself, not
+    // custom images or the output image.
+    pub fn get_attachment_image(&self, tag: &str) -> Arc<AttachmentImage> {
+        if tag == self.output_tag || self.custom_images.contains_key(tag) {
+            panic!("Image {} wasn't created by System, use get_image instead", tag);
+        }
+
+        self.cached_images
+            .as_ref()
+            .and_then(|cached| cached.get(tag))
+            .unwrap_or_else(|| panic!("No image with tag {}", tag))
+            .clone()
+    }
+
+    // returns the image for a tag in the frame currently being drawn
+    pub fn get_image(&self, tag: &str) -> Arc<dyn ImageViewAccess + Send + Sync> {
+        match &self.state {
+            DrawState::Uninitialized => panic!("Images only exist while drawing"),
+            DrawState::Drawing { images, .. } => images
+                .get(tag)
+                .unwrap_or_else(|| panic!("No image with tag {}", tag))
+                .clone(),
+        }
+    }
+
+    pub fn finish<F: GpuFuture + 'static>(&mut self, future: F) -> Box<dyn GpuFuture> {
+        match self.try_finish(future) {
+            Ok(future) => future,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_finish<F: GpuFuture + 'static>(
+        &mut self,
+        future: F,
+    ) -> Result<Box<dyn GpuFuture>, RenderError> {
+        self.flush_deferred_draws()?;
+        self.flush_debug_lines();
+        // lines for a pass that didn't run this frame shouldn't pile up
+        self.debug_lines.clear();
+        self.cmd_buf_timer.stop();
+
+        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+
+        match state {
+            DrawState::Uninitialized => Err(RenderError::InvalidState(
+                "Can't finish render without having begun it".to_string(),
+            )),
+            DrawState::Drawing {
+                cmd_buf, pass_idx, ..
+            } => {
+                self.pass_timers[pass_idx].stop();
+                let mut cmd_buf = if self.render_pass_ended {
+                    cmd_buf
+                } else {
+                    cmd_buf.end_render_pass().map_err(recording_error)?
+                };
+                self.render_pass_ended = false;
+
+                if let Some(dest) = self.upscale_dest.take() {
+                    let src = self.cached_images.as_ref().unwrap()[self.output_tag].clone();
+                    cmd_buf = blit_image(cmd_buf, src, dest, Filter::Linear);
+                }
+
+                // copy the finished frame out if we're dumping frames
+                if let Some(dump) = &mut self.frame_dump {
+                    if let Some(image) = self.output_image.take() {
+                        let path = dump.next_path();
+                        let (new_cmd_buf, capture) = PendingCapture::record(
+                            self.device.clone(),
+                            cmd_buf,
+                            image,
+                            self.capture_encoding,
+                            &path,
+                        );
+                        cmd_buf = new_cmd_buf;
+                        self.pending_captures.push(capture);
+                    }
+                }
+
+                let cmd_buf = cmd_buf.build().map_err(recording_error)?;
+
+                // wait for the async passes if there are any
+                let mut future: Box<dyn GpuFuture> = Box::new(future);
+                if let Some(async_passes) = &mut self.async_passes {
+                    if let Some(async_future) = async_passes.future.take() {
+                        future = Box::new(future.join(async_future));
+                    }
+                    if let Some(fallback) = async_passes.fallback.take() {
+                        future = Box::new(
+                            future
+                                .then_execute(self.queue.clone(), fallback)
+                                .map_err(submit_error)?,
+                        );
+                    }
+                }
+
+                Ok(Box::new(
+                    future
+                        .then_execute(self.queue.clone(), cmd_buf)
+                        .map_err(submit_error)?,
+                ))
+            }
+        }
+    }
+
+    // trades input latency (fewer) for throughput (more), see
+    // VkWindow::set_max_frames_in_flight. applied to the window when
+    // presenting, which waits for frames to finish if it's been lowered.
+    pub fn set_max_frames_in_flight(&mut self, max: usize) {
+        assert!(max >= 1, "At least 1 frame has to be in flight");
+        self.frames_in_flight = max;
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
+    pub fn finish_to_window(&mut self, window: &mut Window) {
+        self.present_timer.start();
+        window.set_max_frames_in_flight(self.frames_in_flight);
+
+        let swapchain_fut = window.get_future();
+        let cmd_buf_fut = self.finish(swapchain_fut);
+        window.present_future(cmd_buf_fut);
+
+        self.present_timer.stop();
+    }
+
+    // finish_to_window, but errors are returned. the frame is abandoned if
+    // something goes wrong, so the next one can be started right away.
+    pub fn try_finish_to_window(&mut self, window: &mut Window) -> Result<(), RenderError> {
+        self.present_timer.start();
+        window.set_max_frames_in_flight(self.frames_in_flight);
+
+        let swapchain_fut = window.get_future();
+        let result = match self.try_finish(swapchain_fut) {
+            Ok(cmd_buf_fut) => window.try_present_future(cmd_buf_fut),
+            Err(e) => {
+                self.abandon_frame(window);
+                Err(e)
+            }
+        };
+
+        self.present_timer.stop();
+        result
+    }
+
+    // gives up on the frame being drawn to window, after one of the try_
+    // methods failed. whatever was recorded so far is thrown away.
+    pub fn abandon_frame(&mut self, window: &mut Window) {
+        self.state = DrawState::Uninitialized;
+        self.render_pass_ended = false;
+        self.deferred_draws.clear();
+        self.deferred_late_draws.clear();
+        self.debug_lines.clear();
+        self.output_image = None;
+        self.upscale_dest = None;
+        window.abandon_frame();
+    }
+
+    // like finish_to_window, but also shows other images on more windows, for
+    // example one per monitor. each (window, tag) pair gets the image for tag
+    // scaled to fill the window. main_window gets output_tag like usual and
+    // has to be the one passed to start_window.
+    // the extra images are blitted, so they have to be color images created
+    // by System (see get_attachment_image) and the windows' swapchains need
+    // to support being blitted to.
+    pub fn finish_to_windows(
+        &mut self,
+        main_window: &mut Window,
+        extra: &mut [(&mut Window, &str)],
+    ) {
+        if let Err(e) = self.try_finish_to_windows(main_window, extra) {
+            panic!("{}", e);
+        }
+    }
+
+    // finish_to_windows, but errors are returned. the frame is abandoned if
+    // something goes wrong, like with try_finish_to_window.
+    // the extra images are blitted after every pass has run, so a transient
+    // image whose memory gets reused by a later pass can't be shown this way.
+    // RenderError::InvalidState is returned for those, make the tag
+    // persistent (read it in a later pass) or the output tag instead.
+    pub fn try_finish_to_windows(
+        &mut self,
+        main_window: &mut Window,
+        extra: &mut [(&mut Window, &str)],
+    ) -> Result<(), RenderError> {
+        self.present_timer.start();
+        main_window.set_max_frames_in_flight(self.frames_in_flight);
+
+        let result = self.finish_extra_windows(main_window, extra);
+        if result.is_err() {
+            self.abandon_frame(main_window);
+        }
+
+        self.present_timer.stop();
+        result
+    }
+
+    fn finish_extra_windows(
+        &mut self,
+        main_window: &mut Window,
+        extra: &mut [(&mut Window, &str)],
+    ) -> Result<(), RenderError> {
+        let mut srcs = vec![];
+        for (_, tag) in extra.iter() {
+            srcs.push(self.shown_image(tag)?);
+        }
+
+        let mut future = main_window.get_future();
+
+        // minimized extra windows are left out of the frame
+        let mut shown = vec![false; extra.len()];
+        for (((window, _), src), shown) in extra.iter_mut().zip(srcs).zip(shown.iter_mut()) {
+            let dst = match window.next_image() {
+                Some(image) => image,
+                None => continue,
+            };
+            self.try_record_custom(|cmd_buf| blit_image(cmd_buf, src, dst, Filter::Linear))?;
+
+            future = Box::new(future.join(window.get_future()));
+            *shown = true;
+        }
+
+        let mut future = self.try_finish(future)?;
+
+        // present to every extra window, then to the main one. only the main
+        // window keeps the future around to wait on next frame, which covers
+        // the other windows too.
+        for ((window, _), shown) in extra.iter_mut().zip(shown.iter()) {
+            if *shown {
+                future = window.then_present(future);
+            }
+        }
+        main_window.try_present_future(future)
+    }
+
+    // the image for tag to show on an extra window at the end of the frame.
+    // fails if tag isn't a System-created image, or if its memory is shared
+    // with a tag that's first drawn after tag's last use, since that would
+    // have overwritten it by then.
+    fn shown_image(&self, tag: &str) -> Result<Arc<AttachmentImage>, RenderError> {
+        if tag == self.output_tag || self.custom_images.contains_key(tag) {
+            return Err(RenderError::InvalidState(format!(
+                "Image {} wasn't created by System and can't be shown on an extra window",
+                tag
+            )));
+        }
+
+        let cached = match &self.cached_images {
+            Some(cached) => cached,
+            None => return Err(RenderError::InvalidState("Not drawing a frame".to_string())),
+        };
+        let image = cached
+            .get(tag)
+            .ok_or_else(|| RenderError::InvalidState(format!("No image with tag {}", tag)))?;
+
+        let lifetimes = tag_lifetimes(&self.passes);
+        let last_use = lifetimes[tag].1;
+        let reused = cached.iter().any(|(other, other_image)| {
+            other != tag
+                && Arc::ptr_eq(image, other_image)
+                && lifetimes.get(other.as_str()).map_or(false, |&(first, _)| first > last_use)
+        });
+        if reused {
+            return Err(RenderError::InvalidState(format!(
+                "Image {} is transient and gets reused before the end of the frame, so it \
+                 can't be shown on an extra window",
+                tag
+            )));
+        }
+
+        Ok(image.clone())
+    }
+
+    // draws a frame with only the pass called name and shows its output in
+    // window, for looking at a pass in isolation while debugging. objects
+    // should be ones built for that pass. the pass gets the same custom images
+    // as this System, and any other image it reads is black, so to feed it
+    // something specific (like a known shadow map) add it to custom_images
+    // first.
+    // the pass's first color image is what gets shown, blitted to the window
+    // so it doesn't have to be in the window's format. it's drawn by a
+    // separate System that's kept around until a different pass is asked for,
+    // so switching back and forth every frame is slow.
+    // NOTE: the black stand-ins are always 2d rgba8 images at window size, so
+    // passes sampling a cubemap or a depth image with a comparison sampler
+    // need a custom image for that tag. depth-only passes (like shadow maps)
+    // can't be shown this way since depth can't be blitted to a color image.
+    pub fn render_pass_only(&mut self, name: &str, objects: &[&dyn Drawcall], window: &mut Window) {
+        let dims = window.get_dimensions();
+        let up_to_date = match &self.isolated_pass {
+            Some(isolated) => isolated.name == name && isolated.dims == dims,
+            None => false,
+        };
+
+        if !up_to_date {
+            self.isolated_pass = Some(self.isolate_pass(name, dims));
+        }
+
+        let isolated = self.isolated_pass.as_mut().unwrap();
+        let system = &mut isolated.system;
+        let target = isolated.target.clone();
+        // the window goes back to this System's setting afterwards
+        let max_frames_in_flight = window.max_frames_in_flight();
+        window.set_max_frames_in_flight(system.frames_in_flight);
+
+        let swapchain_image = match window.next_image() {
+            Some(image) => image,
+            // minimized, nothing to show it in
+            None => {
+                window.set_max_frames_in_flight(max_frames_in_flight);
+                return;
+            }
+        };
+        system.start(target.clone());
+        for object in objects.iter() {
+            system.add_object(*object);
+        }
+        system
+            .record_custom(|cmd_buf| blit_image(cmd_buf, target, swapchain_image, Filter::Linear));
+
+        let future = system.finish(window.get_future());
+        window.present_future(future);
+        window.set_max_frames_in_flight(max_frames_in_flight);
+    }
+
+    fn isolate_pass(&self, name: &str, dims: [u32; 2]) -> IsolatedPass<'a> {
+        let pass = self
+            .passes
+            .iter()
+            .find(|pass| pass.name == name)
+            .unwrap_or_else(|| panic!("render_pass_only: no pass called {}", name))
+            .clone();
+
+        let output_idx = (0..pass.images_created_tags.len())
+            .find(|&idx| {
+                let desc = pass.render_pass.attachment_desc(idx).unwrap();
+                desc.format.ty() == FormatTy::Float && desc.samples == 1
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "render_pass_only: pass {} has no single-sampled color image to show",
+                    name
+                )
+            });
+        let output_tag = pass.images_created_tags[output_idx];
+        let output_desc = pass.render_pass.attachment_desc(output_idx).unwrap();
+        let target = create_image_for_desc(self.device.clone(), dims, output_desc);
+
+        let mut custom_images = CustomImages::new();
+        for (&tag, image) in self.custom_images.iter() {
+            let used =
+                pass.images_needed_tags.contains(&tag) || pass.images_created_tags.contains(&tag);
+            if used && tag != output_tag {
+                custom_images = custom_images.insert(tag, image.clone());
+            }
+        }
+
+        for &tag in pass.images_needed_tags.iter() {
+            let provided =
+                self.custom_images.contains_key(tag) || pass.images_created_tags.contains(&tag);
+            if !provided {
+                let black = solid_texture(
+                    self.queue.clone(),
+                    dims,
+                    [0, 0, 0, 255],
+                    Format::R8G8B8A8Unorm,
+                );
+                custom_images = custom_images.insert(tag, black);
+            }
+        }
+
+        let system = System::new(
+            self.queue.clone(),
+            vec![pass.clone()],
+            custom_images,
+            output_tag,
+        );
+
+        IsolatedPass {
+            name: pass.name,
+            dims,
+            target,
+            system: Box::new(system),
+        }
+    }
+
+    // writes every frame drawn to a window from now on to a numbered png in
+    // dir. the frames are meant to be played back at fps, so anything animated
+    // should advance by frame_dump_timestep() each frame instead of using the
+    // real time, otherwise the video will speed up and slow down with the
+    // render speed.
+    pub fn start_frame_dump(&mut self, dir: &Path, fps: f32) {
+        self.frame_dump = Some(FrameDump::new(dir, fps));
+    }
+
+    pub fn stop_frame_dump(&mut self) {
+        // frames still in flight get written during the next few frames, or
+        // right away with flush_captures
+        self.frame_dump = None;
+        self.write_finished_captures();
+    }
+
+    pub fn frame_dump_timestep(&self) -> Option<f32> {
+        self.frame_dump.as_ref().map(|dump| 1.0 / dump.fps)
+    }
+
+    // how captured frames get encoded. the default writes the window's bytes
+    // as they are, which matches the screen as long as the color space rules
+    // in render_passes.rs are followed. use LinearToSrgb if the last pass
+    // writes linear colors to a UNORM window.
+    pub fn set_capture_encoding(&mut self, encoding: CaptureEncoding) {
+        self.capture_encoding = encoding;
+    }
+
+    // blocks until the GPU has finished everything submitted so far, for
+    // example before tearing things down, recreating images something might
+    // still be drawing to or reading back a buffer. can't be called in the
+    // middle of a frame, since its commands haven't been submitted yet.
+    // NOTE: buffers used by a frame stay locked until the frame's future is
+    // cleaned up, even if the GPU is done. for frames drawn to a window call
+    // Window::wait_for_all_frames too, the window owns those futures.
+    pub fn wait_idle(&mut self) -> Result<(), RenderError> {
+        if let DrawState::Drawing { .. } = self.state {
+            let msg = "Can't wait for the GPU to go idle in the middle of a frame";
+            return Err(RenderError::InvalidState(msg.to_string()));
+        }
+
+        // vkDeviceWaitIdle isn't thread safe, nothing can be submitted while
+        // it waits. System only submits from the thread it's used on, so
+        // this is fine as long as nothing else shares the device's queues.
+        unsafe { self.device.wait() }.map_err(|e| RenderError::Wait(format!("{:?}", e)))?;
+
+        self.write_finished_captures();
+        Ok(())
+    }
+
+    // writes every capture that's still pending, waiting for the window's
+    // frames and the GPU first. call it before exiting, or after
+    // stop_frame_dump if the last frames matter: otherwise they only get
+    // written as later frames come in. anything still unreadable after that
+    // (a frame only some other window kept the future of) is reported
+    // instead of silently dropped.
+    pub fn flush_captures(&mut self, window: &mut Window) {
+        window.wait_for_all_frames();
+        if let Err(e) = self.wait_idle() {
+            panic!("{}", e);
+        }
+        self.report_unwritten_captures();
+    }
+
+    fn write_finished_captures(&mut self) {
+        self.pending_captures.retain(|capture| !capture.try_write());
+    }
 
-        self.setup_timer.stop();
-        self.cmd_buf_timer.start();
-        self.pass_timers[0].start();
+    fn report_unwritten_captures(&mut self) {
+        for capture in self.pending_captures.drain(..) {
+            println!(
+                "Couldn't write capture to {:?}, its frame was still in flight",
+                capture.path()
+            );
+        }
     }
 
-    pub fn start_window(&mut self, window: &mut Window) {
-        self.acquire_timer.start();
-        let swapchain_image = window.next_image();
-        self.acquire_timer.stop();
-        self.start(swapchain_image);
+    // what the device supports, see capabilities.rs
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::new(self.device.clone())
     }
 
-    pub fn add_object<T: Drawcall>(&mut self, object: &T) {
-        // we need to take ownership for a while
-        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
-        match state {
-            DrawState::Uninitialized => {
-                panic!("You tried to render an object without calling begin_render first!")
+    // sums up the memory used by the images System knows about, which are the
+    // ones it creates for passes plus custom_images. buffers belong to objects
+    // and aren't counted.
+    // TODO: vulkano doesn't support VK_EXT_memory_budget, so the budget is
+    // just the size of the device-local heaps, not what's actually available
+    // to us.
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut images: Vec<Arc<dyn ImageViewAccess + Send + Sync>> = vec![];
+        let all_images = self
+            .cached_images
+            .iter()
+            .flat_map(|cached| cached.values())
+            .map(|image| image.clone() as Arc<dyn ImageViewAccess + Send + Sync>)
+            .chain(self.custom_images.values().cloned());
+        // several tags can share an image, only count each one once
+        for image in all_images {
+            if !images.iter().any(|counted| Arc::ptr_eq(counted, &image)) {
+                images.push(image);
             }
-            DrawState::Drawing {
-                mut cmd_buf,
-                pass_idx,
-                images,
-                framebuffers,
-                cur_dims,
-            } => {
-                // TODO: dynamic state is re-created for every object, shouldn't be
-                let dynamic_state = if let Some(dynstate) = object.custom_dynstate() {
-                    dynstate
-                } else {
-                    // TODO: this is another spot preventing passes with
-                    // different dimensions
-                    dynamic_state_for_dimensions(cur_dims)
-                };
+        }
 
-                let pipeline = self.pipeline_caches[pass_idx].get(object.pipe_spec());
+        let image_bytes = images.iter().map(|image| image_size_bytes(image)).sum();
 
-                let mut collection = self.collection_cache.get(
-                    object.pipe_spec(),
-                    pipeline.clone(),
-                    &self.passes[pass_idx],
-                    &images,
-                );
+        let budget_bytes = self
+            .device
+            .physical_device()
+            .memory_heaps()
+            .filter(|heap| heap.is_device_local())
+            .map(|heap| heap.size() as u64)
+            .sum();
 
-                let mut obj_collection =
-                    object.collection();
+        MemoryReport {
+            image_count: images.len(),
+            image_bytes,
+            budget_bytes,
+        }
+    }
 
-                collection.append(&mut obj_collection);
+    // replaces a custom image with a new one of the same format but different
+    // dimensions. the old contents are lost.
+    pub fn resize_custom_image(&mut self, tag: &str, dimensions: [u32; 2]) {
+        let (&key, old) = self
+            .custom_images
+            .get_key_value(tag)
+            .unwrap_or_else(|| panic!("No custom image with tag {}", tag));
+        let format = old.parent().format();
+
+        let new = AttachmentImage::sampled(self.device.clone(), dimensions, format).unwrap();
+        self.custom_images.insert(key, new);
+    }
 
-                cmd_buf = cmd_buf
-                    .draw_indexed(
-                        pipeline,
-                        &dynamic_state,
-                        vec![object.vbuf()],
-                        object.ibuf(),
-                        collection,
-                        (),
-                    )
-                    .expect(&format!(
-                        "error building cmd buf, in pass {}",
-                        self.passes[pass_idx].name
-                    ));
+    // applies the parts of a quality preset that System is responsible for.
+    // shadow_tags are the custom images to resize to settings.shadow_map_size.
+    // only those get recreated, framebuffers pick them up next frame.
+    pub fn apply_quality(&mut self, settings: &QualitySettings, shadow_tags: &[&str]) {
+        for tag in shadow_tags.iter() {
+            let old_dims = self
+                .custom_images
+                .get(tag)
+                .unwrap_or_else(|| panic!("No custom image with tag {}", tag))
+                .dimensions();
+            let aspect = old_dims.width() as f32 / old_dims.height() as f32;
+            let height = settings.shadow_map_size;
+            let width = (height as f32 * aspect).round() as u32;
 
-                // give state a real value again
-                self.state = DrawState::Drawing {
-                    cmd_buf,
-                    pass_idx,
-                    images,
-                    framebuffers,
-                    cur_dims,
-                }
+            if [old_dims.width(), old_dims.height()] != [width, height] {
+                self.resize_custom_image(tag, [width, height]);
             }
         }
     }
 
-    pub fn next_pass(&mut self) {
-        // again, temporarily take ownership
-        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
-        match state {
-            DrawState::Uninitialized => {
-                panic!("Can't enter next pass without having begun rendering")
-            }
-            DrawState::Drawing {
-                mut cmd_buf,
-                mut pass_idx,
-                images,
-                framebuffers,
-                cur_dims,
-            } => {
-                self.pass_timers[pass_idx].stop();
-                pass_idx += 1;
-                self.pass_timers[pass_idx].start();
+    pub fn get_passes(&self) -> &[Pass] {
+        &self.passes
+    }
 
-                let framebuffer = framebuffers[pass_idx].clone();
-                let render_pass = self.passes[pass_idx].render_pass.clone();
-                let clear_values = clear_values_for_pass(render_pass);
+    pub fn image_lifetimes(&self) -> HashMap<&'a str, ImageLifetime> {
+        let custom_tags: Vec<&str> = self.custom_images.keys().cloned().collect();
+        classify_tags(&self.passes, &custom_tags)
+    }
 
-                cmd_buf = cmd_buf
-                    .end_render_pass()
-                    .unwrap()
-                    .begin_render_pass(framebuffer, false, clear_values)
-                    .unwrap();
+    // returns the pass graph in graphviz's DOT format, with an edge for every
+    // image a pass needs from the pass that last created it. edges for
+    // persistent images are bold, read-only ones dashed. render it with
+    // something like:
+    // dot -Tpng graph.dot -o graph.png
+    pub fn export_graphviz(&self) -> String {
+        use std::fmt::Write;
+
+        let mut dot = String::new();
+        writeln!(dot, "digraph system {{").unwrap();
+        writeln!(dot, "    rankdir=LR;").unwrap();
+        writeln!(dot, "    node [shape=box];").unwrap();
+
+        for (pass_idx, pass) in self.passes.iter().enumerate() {
+            writeln!(dot, "    pass_{} [label=\"{}\"];", pass_idx, pass.name).unwrap();
+        }
 
-                // give state a real value again
-                self.state = DrawState::Drawing {
-                    cmd_buf,
-                    pass_idx,
-                    images,
-                    framebuffers,
-                    cur_dims,
-                }
-            }
+        let lifetimes = self.image_lifetimes();
+
+        let mut custom_tags: Vec<&str> = self.custom_images.keys().cloned().collect();
+        custom_tags.sort();
+        for tag in custom_tags.iter() {
+            writeln!(
+                dot,
+                "    \"custom_{}\" [label=\"{}\", shape=ellipse, style=dashed];",
+                tag, tag
+            )
+            .unwrap();
         }
-    }
 
-    pub fn finish<F: GpuFuture + 'static>(&mut self, future: F) -> Box<dyn GpuFuture> {
-        self.cmd_buf_timer.stop();
+        for (pass_idx, pass) in self.passes.iter().enumerate() {
+            for tag in pass.images_needed_tags.iter() {
+                // the pass reads whatever the last pass before it wrote
+                let creator = self.passes[..pass_idx]
+                    .iter()
+                    .rposition(|other| other.images_created_tags.contains(tag));
+
+                let source = match creator {
+                    Some(creator_idx) => format!("pass_{}", creator_idx),
+                    None if self.custom_images.contains_key(tag) => format!("\"custom_{}\"", tag),
+                    None => {
+                        // nothing creates it, which will panic when drawing
+                        writeln!(
+                            dot,
+                            "    \"missing_{}\" [label=\"{} (missing)\", shape=ellipse, color=red];",
+                            tag, tag
+                        )
+                        .unwrap();
+                        format!("\"missing_{}\"", tag)
+                    }
+                };
 
-        let state = std::mem::replace(&mut self.state, DrawState::Uninitialized);
+                // persistent images get bold edges, read-only ones dashed
+                let style = if self.read_only_tags.contains(tag) {
+                    "dashed"
+                } else {
+                    match lifetimes.get(tag) {
+                        Some(ImageLifetime::Persistent) => "bold",
+                        _ => "solid",
+                    }
+                };
 
-        let fut = match state {
-            DrawState::Uninitialized => panic!("Can't finish render without having begun it"),
-            DrawState::Drawing {
-                cmd_buf, pass_idx, ..
-            } => {
-                self.pass_timers[pass_idx].stop();
-                Box::new(
-                    future
-                        .then_execute(
-                            self.queue.clone(),
-                            cmd_buf.end_render_pass().unwrap().build().unwrap(),
-                        )
-                        .unwrap(),
+                writeln!(
+                    dot,
+                    "    {} -> pass_{} [label=\"{}\", style={}];",
+                    source, pass_idx, tag, style
                 )
+                .unwrap();
             }
-        };
-
-        fut
-    }
-
-    pub fn finish_to_window(&mut self, window: &mut Window) {
-        self.present_timer.start();
+        }
 
-        let swapchain_fut = window.get_future();
-        let cmd_buf_fut = self.finish(swapchain_fut);
-        window.present_future(cmd_buf_fut);
+        let output_creator = self
+            .passes
+            .iter()
+            .rposition(|pass| pass.images_created_tags.contains(&self.output_tag));
+        if let Some(creator_idx) = output_creator {
+            writeln!(dot, "    output [shape=doublecircle];").unwrap();
+            writeln!(
+                dot,
+                "    pass_{} -> output [label=\"{}\"];",
+                creator_idx, self.output_tag
+            )
+            .unwrap();
+        }
 
-        self.present_timer.stop();
-    }
+        writeln!(dot, "}}").unwrap();
 
-    pub fn get_passes(&self) -> &[Pass] {
-        &self.passes
+        dot
     }
 
     pub fn print_stats(&self) {
@@ -319,6 +2633,19 @@ impl<'a> System<'a> {
 
         println!();
 
+        let stats = self.last_object_stats;
+        println!(
+            "Objects last frame: {} submitted, {} culled, {} drawn",
+            stats.objects_submitted, stats.objects_culled, stats.objects_drawn
+        );
+        let sets = self.descriptor_set_stats();
+        println!(
+            "Descriptor sets: {} created last frame, {} in total, {} cached collections",
+            sets.created_last_frame, sets.created_total, sets.cached_collections
+        );
+
+        println!();
+
         (0..self.passes.len()).for_each(|idx| {
             println!("Pipeline cache stats for pass {}:", self.passes[idx].name);
             self.pipeline_caches[idx].print_stats();
@@ -338,25 +2665,165 @@ impl<'a> System<'a> {
 
         // if there is a cache, make sure its dimensions are the same as what we want
         if let Some(cached) = &self.cached_images {
-            let cached_vk_dims = cached
-                .get(self.output_tag)
-                .unwrap_or_else(|| {
+            // AttachmentImage::dimensions, not the ImageAccess one
+            let cached_dimensions =
+                AttachmentImage::dimensions(cached.get(self.output_tag).unwrap_or_else(|| {
                     panic!("Couldn't find output tag {} in cached_images", self.output_tag)
-                })
-                .dimensions();
-            let cached_dimensions = [cached_vk_dims.width(), cached_vk_dims.height()];
+                }));
 
-            if cached_dimensions != dimensions {
+            // only transient images need to be recreated here. custom images
+            // are persistent and never end up in the cache.
+            if cached_dimensions != dimensions || self.cached_output_tag != self.output_tag {
                 self.cached_images = None;
             }
         }
 
-        if let Some(cached) = &self.cached_images {
-            cached.clone()
+        if self.cached_images.is_none() {
+            let custom_tags: Vec<&str> = self.custom_images.keys().cloned().collect();
+            let new = images_for_passes(
+                self.device.clone(),
+                dimensions,
+                &self.passes,
+                &custom_tags,
+                self.output_tag,
+            );
+            self.cached_images = Some(new);
+            self.cached_output_tag = self.output_tag;
+        }
+
+        self.cached_images
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|(tag, image)| {
+                (
+                    tag.clone(),
+                    image.clone() as Arc<dyn ImageViewAccess + Send + Sync>,
+                )
+            })
+            .collect()
+    }
+}
+
+// last chance for captures nobody flushed. without the window this can't wait
+// for its frames, so whatever they still hold is only reported, see
+// flush_captures.
+impl<'a> Drop for System<'a> {
+    fn drop(&mut self) {
+        if self.pending_captures.is_empty() {
+            return;
+        }
+
+        if unsafe { self.device.wait() }.is_ok() {
+            self.write_finished_captures();
+        }
+        self.report_unwritten_captures();
+    }
+}
+
+// whether an image's contents have to survive from one frame to the next.
+// transient images are created and used up within a frame, so they can share
+// memory with each other and don't need to be kept around. persistent ones are
+// either custom images or images read by a pass before any pass writes them
+// that frame (i.e. they read last frame's contents).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageLifetime {
+    Transient,
+    Persistent,
+}
+
+pub struct MemoryReport {
+    pub image_count: usize,
+    pub image_bytes: u64,
+    pub budget_bytes: u64,
+}
+
+impl MemoryReport {
+    // how much of the budget the images use, 0-100. None if the device
+    // doesn't report any device-local memory (some software implementations),
+    // so there's no budget to compare against.
+    pub fn budget_percent(&self) -> Option<f32> {
+        if self.budget_bytes == 0 {
+            None
         } else {
-            let new = images_for_passes(self.device.clone(), dimensions, &self.passes);
-            self.cached_images = Some(new.clone());
-            new
+            Some(self.image_bytes as f32 / self.budget_bytes as f32 * 100.0)
+        }
+    }
+
+    pub fn print(&self) {
+        let mib = |bytes: u64| bytes as f32 / 1_048_576.0;
+        match self.budget_percent() {
+            Some(percent) => println!(
+                "GPU memory: {} images using {:.1} MiB of {:.1} MiB ({:.1}%)",
+                self.image_count,
+                mib(self.image_bytes),
+                mib(self.budget_bytes),
+                percent,
+            ),
+            None => println!(
+                "GPU memory: {} images using {:.1} MiB, device reports no device-local memory",
+                self.image_count,
+                mib(self.image_bytes),
+            ),
+        }
+    }
+}
+
+// panics if a custom image doesn't match how the passes creating it use it
+fn validate_custom_images(
+    passes: &[Pass],
+    custom_images: &HashMap<&str, Arc<dyn ImageViewAccess + Send + Sync>>,
+) {
+    for tag in custom_images.keys() {
+        let used = passes.iter().any(|pass| {
+            pass.images_created_tags.contains(tag) || pass.images_needed_tags.contains(tag)
+        });
+        if !used {
+            println!("Warning: custom image {} isn't used by any pass", tag);
+        }
+    }
+
+    for pass in passes.iter() {
+        // every image in a framebuffer has to have the same dimensions
+        let mut first_custom: Option<(&str, [u32; 2])> = None;
+
+        for (image_idx, tag) in pass.images_created_tags.iter().enumerate() {
+            let image = match custom_images.get(tag) {
+                Some(image) => image,
+                None => continue,
+            };
+
+            let desc = pass.render_pass.attachment_desc(image_idx).expect(&format!(
+                "Pass {} has more image tags than its render pass has attachments",
+                pass.name
+            ));
+
+            let format = image.parent().format();
+            if format != desc.format {
+                panic!(
+                    "Custom image {} has format {:?}, but pass {} uses it as an attachment with format {:?}",
+                    tag, format, pass.name, desc.format
+                );
+            }
+
+            let samples = image.parent().samples();
+            if samples != desc.samples {
+                panic!(
+                    "Custom image {} has {} samples, but pass {} uses it as an attachment with {} samples",
+                    tag, samples, pass.name, desc.samples
+                );
+            }
+
+            let vk_dims = image.dimensions();
+            let dims = [vk_dims.width(), vk_dims.height()];
+            match first_custom {
+                Some((other_tag, other_dims)) if other_dims != dims => panic!(
+                    "Custom images {} ({:?}) and {} ({:?}) are both created by pass {}, so they need the same dimensions",
+                    other_tag, other_dims, tag, dims, pass.name
+                ),
+                Some(_) => {}
+                None => first_custom = Some((*tag, dims)),
+            }
         }
     }
 }
@@ -365,12 +2832,139 @@ fn create_image_for_desc(
     device: Arc<Device>,
     dimensions: [u32; 2],
     desc: AttachmentDescription,
-) -> Arc<dyn ImageViewAccess + Send + Sync> {
-    AttachmentImage::sampled_multisampled(device.clone(), dimensions, desc.samples, desc.format)
-        .unwrap()
+) -> Arc<AttachmentImage> {
+    // any image might be read as an input attachment by a later subpass, or
+    // blitted somewhere with record_custom
+    let usage = ImageUsage {
+        sampled: true,
+        input_attachment: true,
+        transfer_source: true,
+        transfer_destination: true,
+        ..ImageUsage::none()
+    };
+
+    AttachmentImage::multisampled_with_usage(
+        device.clone(),
+        dimensions,
+        desc.samples,
+        desc.format,
+        usage,
+    )
+    .unwrap()
+}
+
+// checks the things about a pass's render pass that a pipeline depends on,
+// which otherwise only fail deep inside vulkano when the pipeline is built, or
+// don't fail at all and just draw garbage. outputs are the fragment shader's,
+// see ShaderSystem::fragment_outputs.
+fn check_object_fits_pass(
+    spec: &PipelineSpec,
+    outputs: &[(u32, Format)],
+    pass: &Pass,
+) -> Result<(), String> {
+    let render_pass = &pass.render_pass;
+    let subpass = match render_pass.subpass_desc(spec.subpass as usize) {
+        Some(subpass) => subpass,
+        None => {
+            return Err(format!(
+                "it's for subpass {}, but the render pass has {}",
+                spec.subpass,
+                render_pass.num_subpasses()
+            ))
+        }
+    };
+
+    // every color attachment needs an output of the same kind (float, int or
+    // uint) with at least as many components, otherwise what ends up in it is
+    // undefined. outputs without an attachment are just dropped. the empty
+    // shader of depth-only draws has none, but they don't write color anyway.
+    if spec.prepass_stage != Some(PrepassStage::DepthOnly) {
+        for (location, &(idx, _)) in subpass.color_attachments.iter().enumerate() {
+            let attachment_format = render_pass.attachment_desc(idx).unwrap().format;
+            let output_format = outputs
+                .iter()
+                .find(|(output_location, _)| *output_location == location as u32)
+                .map(|&(_, format)| format);
+
+            let output_format = match output_format {
+                Some(format) => format,
+                None => {
+                    return Err(format!(
+                        "the render pass has {} color attachments, but the fragment shader has nothing at location {}",
+                        subpass.color_attachments.len(),
+                        location
+                    ))
+                }
+            };
+
+            let same_kind = attachment_format.ty() == output_format.ty();
+            if !same_kind || num_components(output_format) < num_components(attachment_format) {
+                return Err(format!(
+                    "color attachment {} is {:?}, but the fragment shader writes {:?} to it",
+                    location, attachment_format, output_format
+                ));
+            }
+        }
+    }
+
+    let needs_depth = spec.read_depth || spec.write_depth || spec.stencil.is_some();
+    let depth_format = subpass
+        .depth_stencil
+        .map(|(idx, _)| render_pass.attachment_desc(idx).unwrap().format);
+
+    match depth_format {
+        None if needs_depth => {
+            Err("it uses depth, but the render pass has no depth attachment".to_string())
+        }
+        Some(format) if spec.stencil.is_some() => match format.ty() {
+            FormatTy::DepthStencil | FormatTy::Stencil => Ok(()),
+            _ => Err(format!("it uses stencil, but the depth attachment is {:?}", format)),
+        },
+        _ => Ok(()),
+    }
+}
+
+// how many channels format has, by its name: R8G8B8A8Unorm has 4,
+// B10G11R11UfloatPack32 3. shared exponents (the E5 of E5B9G9R9) don't count.
+fn num_components(format: Format) -> usize {
+    let name: Vec<char> = format!("{:?}", format).chars().collect();
+    name.windows(2)
+        .filter(|pair| "RGBA".contains(pair[0]) && pair[1].is_ascii_digit())
+        .count()
+}
+
+// the corners of an axis-aligned box, in the order Aabb::corners gives them
+fn box_corners(center: [f32; 3], half_extents: [f32; 3]) -> [[f32; 3]; 8] {
+    let mut bounds = Aabb {
+        min: center,
+        max: center,
+    };
+    for axis in 0..3 {
+        bounds.min[axis] -= half_extents[axis];
+        bounds.max[axis] += half_extents[axis];
+    }
+
+    bounds.corners()
+}
+
+// objects that can go through a depth prepass, see System::set_depth_prepass
+fn is_opaque(spec: &PipelineSpec) -> bool {
+    spec.write_depth && !spec.alpha_blend && spec.prepass_stage.is_none()
+}
+
+// has to match what the pipeline was created with, see
+// VertexTypeAbstract::create_pipeline
+fn supported_num_viewports(device: &Device, requested: u32) -> u32 {
+    if device.enabled_features().multi_viewport {
+        requested
+    } else {
+        1
+    }
 }
 
-fn dynamic_state_for_dimensions(dimensions: [u32; 2]) -> DynamicState {
+// every viewport covers the whole framebuffer, so with layered rendering each
+// layer maps to the whole face. see PipelineSpec::num_viewports.
+fn dynamic_state_for_dimensions(dimensions: [u32; 2], num_viewports: u32) -> DynamicState {
     let viewport = Viewport {
         origin: [0.0, 0.0],
         dimensions: [dimensions[0] as f32, dimensions[1] as f32],
@@ -379,11 +2973,38 @@ fn dynamic_state_for_dimensions(dimensions: [u32; 2]) -> DynamicState {
 
     DynamicState {
         line_width: None,
-        viewports: Some(vec![viewport]),
+        viewports: Some(vec![viewport; num_viewports.max(1) as usize]),
         scissors: None,
     }
 }
 
+// for map_err on vulkano's command buffer errors
+fn recording_error<E: std::fmt::Debug>(e: E) -> RenderError {
+    RenderError::Recording(format!("{:?}", e))
+}
+
+fn submit_error<E: std::fmt::Debug>(e: E) -> RenderError {
+    RenderError::Submit(format!("{:?}", e))
+}
+
+// see PipelineSpec::depth_bias
+fn apply_depth_bias(dynamic_state: &mut DynamicState, depth_bias: f32) {
+    if depth_bias == 0.0 {
+        return;
+    }
+
+    if let Some(viewports) = &mut dynamic_state.viewports {
+        for viewport in viewports.iter_mut() {
+            let (min, max) = (viewport.depth_range.start, viewport.depth_range.end);
+            viewport.depth_range = if depth_bias < 0.0 {
+                min..(max + depth_bias).max(min)
+            } else {
+                (min + depth_bias).min(max)..max
+            };
+        }
+    }
+}
+
 fn fb_from_images(
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
     images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
@@ -434,15 +3055,129 @@ fn fb_from_images(
     }
 }
 
+// hands out images for passes, re-using ones whose contents are no longer
+// needed instead of always allocating new ones. two tags can share an image if
+// their lifetimes (the range of passes that use them) don't overlap, and the
+// image's size, format and sample count match.
+struct TransientPool {
+    device: Arc<Device>,
+    entries: Vec<PoolEntry>,
+}
+
+struct PoolEntry {
+    image: Arc<AttachmentImage>,
+    dimensions: [u32; 2],
+    format: Format,
+    samples: u32,
+    // index of the last pass that uses whatever is currently in the image
+    busy_until: usize,
+}
+
+impl TransientPool {
+    fn new(device: Arc<Device>) -> Self {
+        Self {
+            device,
+            entries: vec![],
+        }
+    }
+
+    // gets an image that's free from first_use through last_use
+    fn get(
+        &mut self,
+        dimensions: [u32; 2],
+        desc: AttachmentDescription,
+        first_use: usize,
+        last_use: usize,
+    ) -> Arc<AttachmentImage> {
+        let reusable = self.entries.iter_mut().find(|entry| {
+            entry.busy_until < first_use
+                && entry.dimensions == dimensions
+                && entry.format == desc.format
+                && entry.samples == desc.samples
+        });
+
+        if let Some(entry) = reusable {
+            entry.busy_until = last_use;
+            return entry.image.clone();
+        }
+
+        let image = create_image_for_desc(self.device.clone(), dimensions, desc.clone());
+        self.entries.push(PoolEntry {
+            image: image.clone(),
+            dimensions,
+            format: desc.format,
+            samples: desc.samples,
+            busy_until: last_use,
+        });
+
+        image
+    }
+}
+
+// returns the first and last pass index that use each tag, either by creating
+// it or needing it
+fn tag_lifetimes<'a>(passes: &[Pass<'a>]) -> HashMap<&'a str, (usize, usize)> {
+    let mut lifetimes: HashMap<&str, (usize, usize)> = HashMap::new();
+
+    for (pass_idx, pass) in passes.iter().enumerate() {
+        for &tag in pass.images_created_tags.iter().chain(&pass.images_needed_tags) {
+            let lifetime = lifetimes.entry(tag).or_insert((pass_idx, pass_idx));
+            lifetime.1 = pass_idx;
+        }
+    }
+
+    lifetimes
+}
+
+fn classify_tags<'a>(passes: &[Pass<'a>], custom_tags: &[&str]) -> HashMap<&'a str, ImageLifetime> {
+    let mut lifetimes = HashMap::new();
+
+    for (pass_idx, pass) in passes.iter().enumerate() {
+        for &tag in pass.images_created_tags.iter() {
+            lifetimes.entry(tag).or_insert(ImageLifetime::Transient);
+        }
+
+        for &tag in pass.images_needed_tags.iter() {
+            let created_before = passes[..pass_idx]
+                .iter()
+                .any(|other| other.images_created_tags.contains(&tag));
+            let lifetime = if created_before {
+                ImageLifetime::Transient
+            } else {
+                ImageLifetime::Persistent
+            };
+            // a tag is persistent if any pass needs it to be
+            let entry = lifetimes.entry(tag).or_insert(lifetime);
+            if lifetime == ImageLifetime::Persistent {
+                *entry = ImageLifetime::Persistent;
+            }
+        }
+    }
+
+    for tag in custom_tags.iter() {
+        if let Some(lifetime) = lifetimes.get_mut(*tag) {
+            *lifetime = ImageLifetime::Persistent;
+        }
+    }
+
+    lifetimes
+}
+
 fn images_for_passes<'a>(
     device: Arc<Device>,
     dimensions: [u32; 2],
     passes: &'a [Pass],
-) -> HashMap<String, Arc<dyn ImageViewAccess + Send + Sync>> {
+    custom_tags: &[&str],
+    output_tag: &str,
+) -> HashMap<String, Arc<AttachmentImage>> {
     // for now this ignores the fact that the output image is special and
     // provided from outside System if drawing to a window. any users of this
     // function should replace that image with the real one afterwards.
 
+    let lifetimes = tag_lifetimes(passes);
+    let classes = classify_tags(passes, custom_tags);
+    let mut pool = TransientPool::new(device);
+
     let mut images = HashMap::new();
     for pass in passes.iter() {
         let num_tags = pass.images_created_tags.len();
@@ -456,6 +3191,15 @@ This means the image description for each tag cannot be determined from the rend
         );
 
         for (image_idx, &image_tag) in pass.images_created_tags.iter().enumerate() {
+            // a tag created by several passes (like a depth buffer that gets
+            // added to) is the same image every time. custom images get
+            // replaced anyway, so don't allocate anything for them. the output
+            // always needs an entry though, it's used to check the cache.
+            let is_custom = custom_tags.contains(&image_tag) && image_tag != output_tag;
+            if images.contains_key(image_tag) || is_custom {
+                continue;
+            }
+
             let desc = pass
                 .render_pass
                 .attachment_desc(image_idx)
@@ -471,12 +3215,22 @@ Image tag: {}",
                 ));
 
             // FIXME: yeah this needs a better solution
-            let image = if image_tag.contains("lowres") {
-                create_image_for_desc(device.clone(), [512, 512], desc)
+            let image_dims = if image_tag.contains("lowres") {
+                [512, 512]
             } else {
-                create_image_for_desc(device.clone(), dimensions, desc)
+                dimensions
             };
 
+            // persistent images hold on to their contents for the whole
+            // frame, so nothing else can use them. the output image is read
+            // after the last pass when it gets upscaled into the real
+            // destination, so it has to stay untouched until then too.
+            let (first_use, last_use) = match classes[image_tag] {
+                ImageLifetime::Transient if image_tag != output_tag => lifetimes[image_tag],
+                ImageLifetime::Transient => (lifetimes[image_tag].0, passes.len()),
+                ImageLifetime::Persistent => (0, passes.len()),
+            };
+            let image = pool.get(image_dims, desc, first_use, last_use);
             images.insert(image_tag.to_string(), image);
         }
     }
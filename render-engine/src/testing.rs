@@ -0,0 +1,148 @@
+/*
+Helpers for checking rendered images against known-good "golden" images, so
+rendering regressions can be caught automatically.
+
+let diff = compare_images(&produced, &golden_path, 0.02);
+if !diff.passed {
+    write_diff_image(&diff, &diff_path);
+    panic!("{:?}", diff);
+}
+
+Tolerance is the largest per-channel difference (from 0 to 1) a pixel may have
+before it counts as different. Small differences are expected between drivers,
+so don't set it to 0.
+ */
+
+use image::{Pixel, Rgba, RgbaImage};
+
+use std::path::Path;
+
+pub struct ImageDiff {
+    pub passed: bool,
+    pub dimensions_match: bool,
+    // number of pixels with a channel differing by more than the tolerance
+    pub differing_pixels: u32,
+    // largest difference of any channel in any pixel, from 0 to 1
+    pub max_difference: f32,
+    // average per-channel difference over the whole image, from 0 to 1
+    pub mean_difference: f32,
+    // average perceptual color distance over the whole image, from 0 to 1.
+    // weights channels roughly like the eye does, so a shift in green counts
+    // more than the same shift in blue.
+    pub perceptual_difference: f32,
+    // golden image darkened, with differing pixels in red
+    pub diff_image: RgbaImage,
+}
+
+impl std::fmt::Debug for ImageDiff {
+    // leaves out diff_image, which would be thousands of lines
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ImageDiff")
+            .field("passed", &self.passed)
+            .field("dimensions_match", &self.dimensions_match)
+            .field("differing_pixels", &self.differing_pixels)
+            .field("max_difference", &self.max_difference)
+            .field("mean_difference", &self.mean_difference)
+            .field("perceptual_difference", &self.perceptual_difference)
+            .finish()
+    }
+}
+
+pub fn compare_images(produced: &RgbaImage, golden_path: &Path, tolerance: f32) -> ImageDiff {
+    let golden = image::open(golden_path)
+        .unwrap_or_else(|e| panic!("Couldn't open golden image {:?}: {:?}", golden_path, e))
+        .to_rgba();
+
+    if produced.dimensions() != golden.dimensions() {
+        return ImageDiff {
+            passed: false,
+            dimensions_match: false,
+            differing_pixels: produced.width() * produced.height(),
+            max_difference: 1.0,
+            mean_difference: 1.0,
+            perceptual_difference: 1.0,
+            diff_image: produced.clone(),
+        };
+    }
+
+    let (width, height) = golden.dimensions();
+
+    // two empty images are the same, and the averages below would be 0 / 0
+    if width == 0 || height == 0 {
+        return ImageDiff {
+            passed: true,
+            dimensions_match: true,
+            differing_pixels: 0,
+            max_difference: 0.0,
+            mean_difference: 0.0,
+            perceptual_difference: 0.0,
+            diff_image: RgbaImage::new(width, height),
+        };
+    }
+
+    let mut diff_image = RgbaImage::new(width, height);
+    let mut differing_pixels = 0;
+    let mut max_difference: f32 = 0.0;
+    let mut total_difference = 0.0;
+    let mut total_perceptual = 0.0;
+
+    for (x, y, golden_px) in golden.enumerate_pixels() {
+        let produced_px = produced.get_pixel(x, y);
+
+        let channel_diffs: Vec<f32> = (0..4)
+            .map(|c| (produced_px[c] as f32 - golden_px[c] as f32).abs() / 255.0)
+            .collect();
+        let pixel_max = channel_diffs.iter().cloned().fold(0.0, f32::max);
+
+        max_difference = max_difference.max(pixel_max);
+        total_difference += channel_diffs.iter().sum::<f32>() / 4.0;
+        total_perceptual += perceptual_distance(produced_px, golden_px);
+
+        let diff_px = if pixel_max > tolerance {
+            differing_pixels += 1;
+            Rgba::from_channels(255, 0, 0, 255)
+        } else {
+            // darkened grayscale version of the golden image for context
+            let luma = (golden_px[0] as u32 + golden_px[1] as u32 + golden_px[2] as u32) / 3;
+            let dim = (luma / 3) as u8;
+            Rgba::from_channels(dim, dim, dim, 255)
+        };
+        diff_image.put_pixel(x, y, diff_px);
+    }
+
+    let pixel_count = (width * height) as f32;
+
+    ImageDiff {
+        passed: differing_pixels == 0,
+        dimensions_match: true,
+        differing_pixels,
+        max_difference,
+        mean_difference: total_difference / pixel_count,
+        perceptual_difference: total_perceptual / pixel_count,
+        diff_image,
+    }
+}
+
+pub fn write_diff_image(diff: &ImageDiff, path: &Path) {
+    diff.diff_image
+        .save(path)
+        .unwrap_or_else(|e| panic!("Couldn't write diff image to {:?}: {:?}", path, e));
+}
+
+// "redmean" color distance, a cheap approximation of how different two colors
+// look. see https://www.compuphase.com/cmetric.htm
+fn perceptual_distance(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let r_mean = (a[0] as f32 + b[0] as f32) / 2.0;
+    let dr = a[0] as f32 - b[0] as f32;
+    let dg = a[1] as f32 - b[1] as f32;
+    let db = a[2] as f32 - b[2] as f32;
+
+    let dist = ((2.0 + r_mean / 256.0) * dr * dr
+        + 4.0 * dg * dg
+        + (2.0 + (255.0 - r_mean) / 256.0) * db * db)
+        .sqrt();
+
+    // the largest possible distance is between black and white. the weights
+    // add up to about 9 there, so it's sqrt(9 * 255^2) = 3 * 255
+    dist / (3.0 * 255.0)
+}
@@ -1,13 +1,22 @@
 use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, ImmutableBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBuffer};
 use vulkano::device::{Device, Queue};
-use vulkano::format::Format;
-use vulkano::image::{Dimensions, ImageViewAccess, ImmutableImage};
+use vulkano::format::{AcceptsPixels, Format};
+use vulkano::image::{
+    Dimensions, ImageAccess, ImageLayout, ImageUsage, ImageViewAccess, ImmutableImage,
+    MipmapsCount, StorageImage,
+};
 use vulkano::memory::Content;
-use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::pipeline::depth_stencil::Compare;
+use vulkano::sampler::{BorderColor, Filter, MipmapMode, Sampler, SamplerAddressMode};
 use vulkano::sync::GpuFuture;
 
 use crate::input::get_elapsed;
 
+use image::hdr::HDRDecoder;
+
+use std::fs::File;
+use std::io::BufReader;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Instant;
@@ -43,23 +52,328 @@ pub fn upload_data<T: Content + 'static + Send + Sync>(
     CpuAccessibleBuffer::from_data(device, BufferUsage::all(), data).unwrap()
 }
 
+// records a blit of all of src into all of dst, scaling with filter if their
+// sizes differ. only the first mip level and array layer are used. vulkano
+// takes care of layout transitions, but both images need transfer usage and
+// it can't be recorded inside a render pass (see System::record_custom).
+pub fn blit_image<S, D>(
+    cmd_buf: AutoCommandBufferBuilder,
+    src: S,
+    dst: D,
+    filter: Filter,
+) -> AutoCommandBufferBuilder
+where
+    S: ImageAccess + Send + Sync + 'static,
+    D: ImageAccess + Send + Sync + 'static,
+{
+    let src_dims = src.dimensions();
+    let dst_dims = dst.dimensions();
+
+    cmd_buf
+        .blit_image(
+            src,
+            [0, 0, 0],
+            [
+                src_dims.width() as i32,
+                src_dims.height() as i32,
+                src_dims.depth() as i32,
+            ],
+            0,
+            0,
+            dst,
+            [0, 0, 0],
+            [
+                dst_dims.width() as i32,
+                dst_dims.height() as i32,
+                dst_dims.depth() as i32,
+            ],
+            0,
+            0,
+            1,
+            filter,
+        )
+        .expect("Couldn't record image blit")
+}
+
+// same as blit_image, but without any scaling or format conversion. the
+// images need the same format, and the copied region is whatever fits in both.
+pub fn copy_image<S, D>(cmd_buf: AutoCommandBufferBuilder, src: S, dst: D) -> AutoCommandBufferBuilder
+where
+    S: ImageAccess + Send + Sync + 'static,
+    D: ImageAccess + Send + Sync + 'static,
+{
+    let src_dims = src.dimensions();
+    let dst_dims = dst.dimensions();
+    let extent = [
+        src_dims.width().min(dst_dims.width()),
+        src_dims.height().min(dst_dims.height()),
+        src_dims.depth().min(dst_dims.depth()),
+    ];
+
+    cmd_buf
+        .copy_image(src, [0, 0, 0], 0, 0, dst, [0, 0, 0], 0, 0, extent, 1)
+        .expect("Couldn't record image copy")
+}
+
+// roughly how much memory image takes up on the GPU. only the first mip level
+// is counted, and drivers are free to pad things, so it's a lower bound.
+pub fn image_size_bytes(image: &Arc<dyn ImageViewAccess + Send + Sync>) -> u64 {
+    let dims = image.dimensions();
+    let parent = image.parent();
+    // compressed formats don't have a size per texel, they won't show up here
+    let texel_size = parent.format().size().unwrap_or(0) as u64;
+
+    dims.width() as u64
+        * dims.height() as u64
+        * dims.depth() as u64
+        * dims.array_layers() as u64
+        * parent.samples() as u64
+        * texel_size
+}
+
+// if path doesn't exist or can't be decoded, prints a warning and returns
+// checker_texture instead of panicking, so a broken asset shows up as
+// obviously wrong instead of taking everything down with it
 pub fn load_texture(
     queue: Arc<Queue>,
     path: &Path,
     format: Format,
 ) -> Arc<dyn ImageViewAccess + Send + Sync> {
-    let (texture, tex_future) = {
-        let image = image::open(path).unwrap().to_rgba();
-        let (width, height) = image.dimensions();
-        let image_data = image.into_raw().clone();
-
-        ImmutableImage::from_iter(
-            image_data.iter().cloned(),
-            Dimensions::Dim2d { width, height },
-            format,
-            queue.clone(),
+    match try_load_texture(queue.clone(), path, format) {
+        Ok(texture) => texture,
+        Err(e) => {
+            println!("{}, using a checkerboard", e);
+            checker_texture(queue, format)
+        }
+    }
+}
+
+// like load_texture, but returns an error saying what went wrong instead of
+// falling back to the checkerboard, for when you'd rather substitute
+// something else
+pub fn try_load_texture(
+    queue: Arc<Queue>,
+    path: &Path,
+    format: Format,
+) -> Result<Arc<dyn ImageViewAccess + Send + Sync>, String> {
+    let image = image::open(path)
+        .map_err(|e| format!("Couldn't load texture {:?} ({})", path, e))?
+        .to_rgba();
+    let (width, height) = image.dimensions();
+
+    Ok(upload_rgba8(queue, width, height, image.into_raw(), format))
+}
+
+// a magenta and black checkerboard, what load_texture gives you when it
+// can't load something
+pub fn checker_texture(
+    queue: Arc<Queue>,
+    format: Format,
+) -> Arc<dyn ImageViewAccess + Send + Sync> {
+    const SIZE: u32 = 64;
+    const SQUARE: u32 = 8;
+
+    let mut data = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let magenta = (x / SQUARE + y / SQUARE) % 2 == 0;
+            let pixel = if magenta {
+                [255, 0, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            };
+            data.extend_from_slice(&pixel);
+        }
+    }
+
+    upload_rgba8(queue, SIZE, SIZE, data, format)
+}
+
+// a 2d image filled with a single rgba8 color, for example a black stand-in
+// for an input that isn't available
+pub fn solid_texture(
+    queue: Arc<Queue>,
+    dims: [u32; 2],
+    color: [u8; 4],
+    format: Format,
+) -> Arc<dyn ImageViewAccess + Send + Sync> {
+    let num_pixels = (dims[0] * dims[1]) as usize;
+    let data: Vec<u8> = color.iter().cloned().cycle().take(num_pixels * 4).collect();
+
+    upload_rgba8(queue, dims[0], dims[1], data, format)
+}
+
+// uploads data as the first mip level, with the rest of the chain generated on
+// the CPU down to 1x1. vulkano can't blit between levels of the same image in
+// one command buffer, so every level is its own copy from a buffer instead.
+fn upload_rgba8(
+    queue: Arc<Queue>,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    format: Format,
+) -> Arc<dyn ImageViewAccess + Send + Sync> {
+    let device = queue.device().clone();
+    let usage = ImageUsage {
+        transfer_destination: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+
+    let (texture, init) = ImmutableImage::uninitialized(
+        device.clone(),
+        Dimensions::Dim2d { width, height },
+        format,
+        MipmapsCount::Log2,
+        usage,
+        ImageLayout::ShaderReadOnlyOptimal,
+        device.active_queue_families(),
+    )
+    .expect("Couldn't create texture");
+    let init = Arc::new(init);
+
+    let srgb = match format {
+        Format::R8G8B8A8Srgb | Format::B8G8R8A8Srgb => true,
+        _ => false,
+    };
+
+    let mut cmd_buf = AutoCommandBufferBuilder::new(device.clone(), queue.family()).unwrap();
+    let (mut level_w, mut level_h, mut level_data) = (width, height, data);
+    for level in 0..texture.mipmap_levels() {
+        if level > 0 {
+            let (w, h, data) = downsample_rgba8(level_w, level_h, &level_data, srgb);
+            level_w = w;
+            level_h = h;
+            level_data = data;
+        }
+
+        let buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_source(),
+            level_data.iter().cloned(),
         )
+        .unwrap();
+
+        cmd_buf = cmd_buf
+            .copy_buffer_to_image_dimensions(
+                buffer,
+                init.clone(),
+                [0, 0, 0],
+                [level_w, level_h, 1],
+                0,
+                1,
+                level,
+            )
+            .expect("Couldn't record mip level copy");
+    }
+
+    cmd_buf
+        .build()
         .unwrap()
+        .execute(queue)
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    texture
+}
+
+// halves an RGBA8 image with a 2x2 box filter. odd sizes round down the way
+// vulkan sizes mip levels, so the last row/column of an odd level is dropped.
+// sRGB data is averaged in linear space, otherwise distant mips
+// come out darker than they should.
+fn downsample_rgba8(width: u32, height: u32, data: &[u8], srgb: bool) -> (u32, u32, Vec<u8>) {
+    let new_w = (width / 2).max(1);
+    let new_h = (height / 2).max(1);
+
+    let to_linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if srgb {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        } else {
+            c
+        }
+    };
+    let from_linear = |c: f32| {
+        let c = if srgb {
+            if c <= 0.003_130_8 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            }
+        } else {
+            c
+        };
+        (c * 255.0).round().max(0.0).min(255.0) as u8
+    };
+
+    let mut out = Vec::with_capacity((new_w * new_h * 4) as usize);
+    for y in 0..new_h {
+        for x in 0..new_w {
+            let xs = [(x * 2).min(width - 1), (x * 2 + 1).min(width - 1)];
+            let ys = [(y * 2).min(height - 1), (y * 2 + 1).min(height - 1)];
+            for channel in 0..4 {
+                let mut sum = 0.0;
+                for &sy in ys.iter() {
+                    for &sx in xs.iter() {
+                        let c = data[((sy * width + sx) * 4 + channel) as usize];
+                        // alpha is always linear
+                        sum += if channel == 3 {
+                            c as f32 / 255.0
+                        } else {
+                            to_linear(c)
+                        };
+                    }
+                }
+                let avg = sum / 4.0;
+                out.push(if channel == 3 {
+                    (avg * 255.0).round() as u8
+                } else {
+                    from_linear(avg)
+                });
+            }
+        }
+    }
+
+    (new_w, new_h, out)
+}
+
+// loads a Radiance .hdr image (the usual format for environment maps) without
+// clamping it to 0-1 or losing precision like load_texture would. format has
+// to be R16G16B16A16Sfloat or R32G32B32A32Sfloat, 16 bits is plenty for
+// colors and half the memory. alpha is always 1.
+// TODO: .exr and 16 bit PNGs. the image crate version we're on doesn't load
+// either, and upgrading it is its own can of worms.
+pub fn load_texture_hdr(
+    queue: Arc<Queue>,
+    path: &Path,
+    format: Format,
+) -> Arc<dyn ImageViewAccess + Send + Sync> {
+    let file = File::open(path).expect("Couldn't open HDR image");
+    let decoder = HDRDecoder::new(BufReader::new(file)).expect("Couldn't read HDR image header");
+    let meta = decoder.metadata();
+    let (width, height) = (meta.width, meta.height);
+    let pixels = decoder.read_image_hdr().expect("Couldn't decode HDR image");
+
+    let rgba = pixels.iter().flat_map(|p| vec![p.data[0], p.data[1], p.data[2], 1.0]);
+    let dimensions = Dimensions::Dim2d { width, height };
+
+    let (texture, tex_future) = match format {
+        Format::R32G32B32A32Sfloat => {
+            let data: Vec<f32> = rgba.collect();
+            ImmutableImage::from_iter(data.into_iter(), dimensions, format, queue.clone()).unwrap()
+        }
+        Format::R16G16B16A16Sfloat => {
+            let data: Vec<u16> = rgba.map(f32_to_f16).collect();
+            ImmutableImage::from_iter(data.into_iter(), dimensions, format, queue.clone()).unwrap()
+        }
+        _ => panic!("HDR textures need a float format, got {:?}", format),
     };
 
     tex_future
@@ -71,15 +385,334 @@ pub fn load_texture(
     texture
 }
 
-pub fn default_sampler(device: Arc<Device>) -> Arc<Sampler> {
+// converts to the bits of a half float, rounding toward zero. values too big
+// for a half become infinity, too small ones become zero.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        // infinity stays infinity, NaN stays NaN
+        let nan_bit = if mantissa != 0 { 0x0200 } else { 0 };
+        return sign | 0x7c00 | nan_bit;
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        sign | 0x7c00
+    } else if half_exponent <= 0 {
+        // too small for a normal half, make a subnormal if we can
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x0080_0000;
+        sign | (mantissa >> (14 - half_exponent)) as u16
+    } else {
+        sign | ((half_exponent as u16) << 10) | (mantissa >> 13) as u16
+    }
+}
+
+// creates an empty 3D image that shaders can both write to (as an image3D) and
+// sample from (as a sampler3D)
+pub fn create_volume_image(
+    device: Arc<Device>,
+    dimensions: [u32; 3],
+    format: Format,
+) -> Arc<StorageImage<Format>> {
+    let queue_families = device.active_queue_families().collect::<Vec<_>>();
+
+    StorageImage::new(
+        device.clone(),
+        Dimensions::Dim3d {
+            width: dimensions[0],
+            height: dimensions[1],
+            depth: dimensions[2],
+        },
+        format,
+        queue_families,
+    )
+    .unwrap()
+}
+
+// uploads 3D data to an immutable image. data is laid out x first, then y,
+// then z, and must contain whatever format expects for each texel (4 u8s for
+// R8G8B8A8Unorm, for example).
+pub fn load_volume<T: Send + Sync + Clone + 'static>(
+    queue: Arc<Queue>,
+    data: &[T],
+    dimensions: [u32; 3],
+    format: Format,
+) -> Arc<dyn ImageViewAccess + Send + Sync>
+where
+    Format: AcceptsPixels<T>,
+{
+    let (volume, volume_future) = ImmutableImage::from_iter(
+        data.iter().cloned(),
+        Dimensions::Dim3d {
+            width: dimensions[0],
+            height: dimensions[1],
+            depth: dimensions[2],
+        },
+        format,
+        queue.clone(),
+    )
+    .unwrap();
+
+    volume_future
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    volume
+}
+
+// creates a 2D image that can be sampled and updated after creation with
+// update_texture_region, unlike the ImmutableImages load_texture makes.
+// it only asks for transfer destination and sampled usage, not storage, since
+// lots of formats (sRGB ones included) can't be storage images.
+pub fn create_dynamic_texture(
+    device: Arc<Device>,
+    dimensions: [u32; 2],
+    format: Format,
+) -> Arc<StorageImage<Format>> {
+    let queue_families = device.active_queue_families().collect::<Vec<_>>();
+    let usage = ImageUsage {
+        transfer_destination: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+
+    StorageImage::with_usage(
+        device.clone(),
+        Dimensions::Dim2d {
+            width: dimensions[0],
+            height: dimensions[1],
+        },
+        format,
+        usage,
+        queue_families,
+    )
+    .expect("Couldn't create dynamic texture")
+}
+
+// copies data into the dims sized rectangle of image starting at origin,
+// leaving the rest of the image alone. data is laid out row by row like in
+// load_volume. image needs transfer destination usage, so it can't be one
+// load_texture made, use create_dynamic_texture.
+// the copy runs after before, which should be whatever last used the image:
+// usually the window's future, since last frame may still be sampling it.
+// pass the returned future on to System::finish so the frame waits for the
+// copy, vulkano takes care of the layout transitions:
+// let future = update_texture_region(window.get_future(), queue, image, ...);
+// system.add_object(...);
+// let future = system.finish(future);
+pub fn update_texture_region<F, I, T>(
+    before: F,
+    queue: Arc<Queue>,
+    image: Arc<I>,
+    origin: [u32; 2],
+    dims: [u32; 2],
+    data: &[T],
+) -> Box<dyn GpuFuture>
+where
+    F: GpuFuture + 'static,
+    I: ImageAccess + Send + Sync + 'static,
+    T: Content + Send + Sync + Clone + 'static,
+    Format: AcceptsPixels<T>,
+{
+    let image_dims = image.dimensions();
+    if origin[0] + dims[0] > image_dims.width() || origin[1] + dims[1] > image_dims.height() {
+        panic!(
+            "Texture region at {:?} with size {:?} doesn't fit in a {}x{} image",
+            origin,
+            dims,
+            image_dims.width(),
+            image_dims.height()
+        );
+    }
+    if data.len() != (dims[0] * dims[1]) as usize {
+        panic!(
+            "Texture region is {}x{} but got data for {} texels",
+            dims[0],
+            dims[1],
+            data.len()
+        );
+    }
+
+    let device = queue.device().clone();
+    let staging = CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::transfer_source(),
+        data.iter().cloned(),
+    )
+    .unwrap();
+
+    let cmd_buf = AutoCommandBufferBuilder::primary_one_time_submit(device, queue.family())
+        .unwrap()
+        .copy_buffer_to_image_dimensions(
+            staging,
+            image,
+            [origin[0], origin[1], 0],
+            [dims[0], dims[1], 1],
+            0,
+            1,
+            0,
+        )
+        .expect("Couldn't record texture region copy")
+        .build()
+        .unwrap();
+
+    Box::new(
+        before
+            .then_execute(queue, cmd_buf)
+            .expect("Couldn't execute texture region copy"),
+    )
+}
+
+// describes a sampler for System::set_sampler. the default is the sampler
+// System uses for images_needed when nothing else is set.
+// border_color is what ClampToBorder returns outside the image. vulkano keeps
+// it inside the address mode, whatever color is in address_mode gets replaced
+// with this one so there's only one place to set it. for shadow maps you want
+// FloatOpaqueWhite, so everything outside the map is fully lit:
+//
+// SamplerSpec {
+//     address_mode: SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueWhite),
+//     border_color: BorderColor::FloatOpaqueWhite,
+//     ..Default::default()
+// }
+//
+// compare makes a comparison sampler, for depth images bound as
+// sampler2DShadow. texture(map, vec3(uv, ref_depth)) then returns how much of
+// the texel passes "ref_depth compare stored_depth" instead of the depth, and
+// with Linear filtering the GPU blends the results of the 4 nearest texels
+// for free 2x2 PCF. Compare::LessOrEqual means 1 is lit.
+// comparison samplers only work on depth formats, and shaders sampling the
+// image as a plain sampler2D get garbage, so use set_pass_sampler if other
+// passes read it too.
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerSpec {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub mipmap_mode: MipmapMode,
+    pub address_mode: SamplerAddressMode,
+    pub border_color: BorderColor,
+    pub compare: Option<Compare>,
+    // added to the mip level picked, positive is blurrier, negative sharper
+    // (and more aliased). clamped to what the device supports in build.
+    pub lod_bias: f32,
+    // 1.0 is no anisotropic filtering. clamped to the device's max in build,
+    // and ignored if sampler_anisotropy isn't enabled.
+    pub max_anisotropy: f32,
+}
+
+impl Default for SamplerSpec {
+    fn default() -> Self {
+        Self {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: MipmapMode::Nearest,
+            address_mode: SamplerAddressMode::ClampToEdge,
+            border_color: BorderColor::FloatOpaqueWhite,
+            compare: None,
+            lod_bias: 0.0,
+            max_anisotropy: 1.0,
+        }
+    }
+}
+
+impl SamplerSpec {
+    // the comparison sampler shaders/include/shadow.glsl expects: linear for
+    // 2x2 PCF, LessOrEqual so 1 is lit, and white outside the map
+    pub fn shadow() -> Self {
+        Self {
+            address_mode: SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueWhite),
+            border_color: BorderColor::FloatOpaqueWhite,
+            compare: Some(Compare::LessOrEqual),
+            ..Default::default()
+        }
+    }
+
+    // what Set uses for its images unless told otherwise: repeating, for
+    // textures on meshes. set mipmap_mode to Linear for trilinear filtering,
+    // every texture from load_texture has a full mip chain.
+    pub fn texture() -> Self {
+        Self {
+            address_mode: SamplerAddressMode::Repeat,
+            ..Default::default()
+        }
+    }
+
+    pub fn build(&self, device: Arc<Device>) -> Arc<Sampler> {
+        let limits = device.physical_device().limits();
+
+        let max_bias = limits.max_sampler_lod_bias();
+        let lod_bias = self.lod_bias.max(-max_bias).min(max_bias);
+
+        let anisotropy = if device.enabled_features().sampler_anisotropy {
+            self.max_anisotropy
+                .max(1.0)
+                .min(limits.max_sampler_anisotropy())
+        } else {
+            1.0
+        };
+
+        let address_mode = match self.address_mode {
+            SamplerAddressMode::ClampToBorder(_) => {
+                SamplerAddressMode::ClampToBorder(self.border_color)
+            }
+            mode => mode,
+        };
+
+        match self.compare {
+            Some(compare) => Sampler::compare(
+                device,
+                self.mag_filter,
+                self.min_filter,
+                self.mipmap_mode,
+                address_mode,
+                address_mode,
+                address_mode,
+                lod_bias,
+                anisotropy,
+                0.0,
+                // every mip level there is
+                1000.0,
+                compare,
+            ),
+            None => Sampler::new(
+                device,
+                self.mag_filter,
+                self.min_filter,
+                self.mipmap_mode,
+                address_mode,
+                address_mode,
+                address_mode,
+                lod_bias,
+                anisotropy,
+                0.0,
+                // every mip level there is
+                1000.0,
+            ),
+        }
+        .expect("Couldn't create sampler")
+    }
+}
+
+// like SamplerSpec::texture(), but clamps on all 3 axes instead of repeating, which is
+// almost always what you want for volumes
+pub fn volume_sampler(device: Arc<Device>) -> Arc<Sampler> {
     Sampler::new(
         device,
         Filter::Linear,
         Filter::Linear,
         MipmapMode::Nearest,
-        SamplerAddressMode::Repeat,
-        SamplerAddressMode::Repeat,
-        SamplerAddressMode::Repeat,
+        SamplerAddressMode::ClampToEdge,
+        SamplerAddressMode::ClampToEdge,
+        SamplerAddressMode::ClampToEdge,
         0.0,
         1.0,
         0.0,
@@ -119,6 +752,24 @@ impl Timer {
         self.samples += 1;
     }
 
+    // starts the timer and stops it when the returned guard is dropped, so
+    // early returns can't skip the stop:
+    // let _t = timer.scope();
+    // (not let _ = ..., that drops the guard immediately)
+    pub fn scope(&mut self) -> TimerGuard {
+        self.start();
+        TimerGuard { timer: self }
+    }
+
+    // average time per sample in seconds, 0 if the timer never ran
+    pub fn average(&self) -> f32 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            self.total_time / (self.samples as f32)
+        }
+    }
+
     pub fn print(&self) {
         // prints average time taken
         println!(
@@ -129,6 +780,82 @@ impl Timer {
     }
 }
 
+// a bunch of timers arranged in a tree, for printing a breakdown of where the
+// time in a frame goes. timers without a parent are assumed to add up to the
+// whole frame, percentages are relative to their sum.
+pub struct TimerRegistry {
+    timers: Vec<RegisteredTimer>,
+}
+
+struct RegisteredTimer {
+    timer: Timer,
+    parent: Option<TimerId>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimerId(usize);
+
+impl TimerRegistry {
+    pub fn new() -> Self {
+        Self { timers: vec![] }
+    }
+
+    pub fn register(&mut self, name: &str, parent: Option<TimerId>) -> TimerId {
+        self.timers.push(RegisteredTimer {
+            timer: Timer::new(name),
+            parent,
+        });
+
+        TimerId(self.timers.len() - 1)
+    }
+
+    pub fn get(&mut self, id: TimerId) -> &mut Timer {
+        &mut self.timers[id.0].timer
+    }
+
+    pub fn print_tree(&self) {
+        let total: f32 = self
+            .timers
+            .iter()
+            .filter(|registered| registered.parent.is_none())
+            .map(|registered| registered.timer.average())
+            .sum();
+
+        println!("Total: {:.3} ms", total * 1_000.0);
+        self.print_children(None, 1, total);
+    }
+
+    fn print_children(&self, parent: Option<TimerId>, depth: usize, total: f32) {
+        for (idx, registered) in self.timers.iter().enumerate() {
+            if registered.parent != parent {
+                continue;
+            }
+
+            let avg = registered.timer.average();
+            let percent = if total > 0.0 { avg / total * 100.0 } else { 0.0 };
+            println!(
+                "{}{}: {:.3} ms ({:.1}%)",
+                "  ".repeat(depth),
+                registered.timer.name,
+                avg * 1_000.0,
+                percent
+            );
+
+            self.print_children(Some(TimerId(idx)), depth + 1, total);
+        }
+    }
+}
+
+pub struct TimerGuard<'a> {
+    timer: &'a mut Timer,
+}
+
+impl<'a> Drop for TimerGuard<'a> {
+    fn drop(&mut self) {
+        self.timer.stop();
+    }
+}
+
 pub struct Stopwatch {
     start_time: Instant,
 }
@@ -0,0 +1,214 @@
+/*
+Reordering meshes so the GPU has less work to do per triangle.
+
+After a vertex is shaded the GPU keeps the result around in a small cache, so
+triangles that reuse recent vertices don't have to shade them again. OBJ files
+usually come in whatever order the modelling program wrote them, which
+doesn't reuse much. optimize_vertex_cache reorders the triangles with Tom
+Forsyth's "Linear-Speed Vertex Cache Optimisation", and optimize_vertex_fetch
+then reorders the vertices so they're read from memory in order.
+
+ACMR (average cache miss ratio) is how many vertices get shaded per triangle.
+3.0 is the worst case, 0.5 is the best possible for a big regular grid, and
+most optimized meshes end up around 0.6-0.7.
+
+All of these work on the whole index list, so call them before merging meshes
+with merge_indexed, otherwise the draw ranges won't be right anymore.
+ */
+
+use crate::mesh::{Mesh, Vertex};
+
+// cache size the optimizer assumes. real caches differ between GPUs, but 32
+// is close enough for all of them and optimizing for a bigger cache than the
+// real one costs very little.
+pub const OPTIMIZER_CACHE_SIZE: usize = 32;
+
+// cache size acmr is usually measured with. older GPUs had FIFO caches about
+// this big.
+pub const DEFAULT_ACMR_CACHE_SIZE: usize = 16;
+
+// constants from the paper
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRI_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+impl<V: Vertex> Mesh<V> {
+    // average cache miss ratio of the index list with a FIFO cache of
+    // cache_size vertices, see the top of the file
+    pub fn acmr(&self, cache_size: usize) -> f32 {
+        acmr(&self.indices, self.vertices.len(), cache_size)
+    }
+
+    // reorders the triangles so consecutive triangles share vertices as much
+    // as possible. the triangles themselves don't change, and neither does
+    // their winding.
+    pub fn optimize_vertex_cache(&mut self) {
+        self.indices = forsyth_order(&self.indices, self.vertices.len());
+    }
+
+    // reorders the vertices in the order the index list first uses them, so
+    // vertex fetches go through memory linearly. vertices no triangle uses
+    // are dropped. do this after optimize_vertex_cache.
+    pub fn optimize_vertex_fetch(&mut self) {
+        let mut remap: Vec<Option<u32>> = vec![None; self.vertices.len()];
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+
+        for index in self.indices.iter_mut() {
+            let new_index = match remap[*index as usize] {
+                Some(new_index) => new_index,
+                None => {
+                    let new_index = vertices.len() as u32;
+                    vertices.push(self.vertices[*index as usize].clone());
+                    remap[*index as usize] = Some(new_index);
+                    new_index
+                }
+            };
+            *index = new_index;
+        }
+
+        self.vertices = vertices;
+    }
+
+    // TODO: optimize_overdraw. it needs vertex positions, so it would have to
+    // live in the VertexAttributes impl, and it'd have to keep the clusters
+    // optimize_vertex_cache makes together to not undo it.
+}
+
+pub fn acmr(indices: &[u32], vertex_count: usize, cache_size: usize) -> f32 {
+    if indices.len() < 3 {
+        return 0.0;
+    }
+
+    // a FIFO cache of size n holds exactly the vertices of the last n misses,
+    // so we only need to remember when each vertex last missed
+    let mut missed_at: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut misses = 0;
+
+    for &index in indices.iter() {
+        let cached = match missed_at[index as usize] {
+            Some(time) => misses - time <= cache_size,
+            None => false,
+        };
+        if !cached {
+            missed_at[index as usize] = Some(misses);
+            misses += 1;
+        }
+    }
+
+    misses as f32 / (indices.len() / 3) as f32
+}
+
+fn vertex_score(cache_pos: Option<usize>, remaining_tris: usize) -> f32 {
+    // vertices no triangle needs anymore shouldn't attract anything
+    if remaining_tris == 0 {
+        return -1.0;
+    }
+
+    let mut score = match cache_pos {
+        // the last triangle's vertices get a fixed score, otherwise the
+        // optimizer would prefer triangles that share an edge with it, which
+        // makes long thin strips that hurt the cache more than they help
+        Some(pos) if pos < 3 => LAST_TRI_SCORE,
+        Some(pos) => {
+            let scale = 1.0 / (OPTIMIZER_CACHE_SIZE - 3) as f32;
+            (1.0 - (pos - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+
+    // boost vertices with few triangles left so they get finished off
+    // instead of leaving lone triangles around to be drawn later
+    score += VALENCE_BOOST_SCALE * (remaining_tris as f32).powf(-VALENCE_BOOST_POWER);
+
+    score
+}
+
+fn forsyth_order(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let tri_count = indices.len() / 3;
+
+    // triangles each vertex is still needed for
+    let mut vertex_tris: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+    for tri in 0..tri_count {
+        for corner in 0..3 {
+            vertex_tris[indices[tri * 3 + corner] as usize].push(tri);
+        }
+    }
+
+    let mut vertex_scores: Vec<f32> = vertex_tris
+        .iter()
+        .map(|tris| vertex_score(None, tris.len()))
+        .collect();
+
+    let tri_vertices = |tri: usize| -> [usize; 3] {
+        [
+            indices[tri * 3] as usize,
+            indices[tri * 3 + 1] as usize,
+            indices[tri * 3 + 2] as usize,
+        ]
+    };
+
+    let mut tri_added = vec![false; tri_count];
+
+    let mut cache: Vec<usize> = Vec::with_capacity(OPTIMIZER_CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(tri_count * 3);
+
+    // when nothing in the cache has triangles left (new island of the mesh),
+    // we start again from the first triangle that hasn't been added. scanning
+    // every triangle for the best score instead would be quadratic on meshes
+    // with lots of islands, like sponza.
+    let mut next_unadded = 0;
+    let mut best_tri: Option<usize> = None;
+
+    for _ in 0..tri_count {
+        let tri = match best_tri {
+            Some(tri) => tri,
+            None => {
+                while tri_added[next_unadded] {
+                    next_unadded += 1;
+                }
+                next_unadded
+            }
+        };
+
+        let vertices = tri_vertices(tri);
+        tri_added[tri] = true;
+        for &v in vertices.iter() {
+            output.push(v as u32);
+            let pos = vertex_tris[v].iter().position(|&t| t == tri).unwrap();
+            vertex_tris[v].swap_remove(pos);
+        }
+
+        // the triangle's vertices move to the front of the cache. the cache
+        // can grow by 3 here, the ones pushed past the end get evicted.
+        let mut new_cache = Vec::with_capacity(OPTIMIZER_CACHE_SIZE + 3);
+        new_cache.extend(vertices.iter().cloned());
+        new_cache.extend(cache.iter().cloned().filter(|v| !vertices.contains(v)));
+
+        for &v in new_cache.iter().skip(OPTIMIZER_CACHE_SIZE) {
+            vertex_scores[v] = vertex_score(None, vertex_tris[v].len());
+        }
+        new_cache.truncate(OPTIMIZER_CACHE_SIZE);
+        for (pos, &v) in new_cache.iter().enumerate() {
+            vertex_scores[v] = vertex_score(Some(pos), vertex_tris[v].len());
+        }
+
+        // only triangles touching the cache can have changed score, so the
+        // next best triangle is among them
+        best_tri = None;
+        let mut best_score = -1.0;
+        for &v in new_cache.iter() {
+            for &t in vertex_tris[v].iter() {
+                let score: f32 = tri_vertices(t).iter().map(|&v| vertex_scores[v]).sum();
+                if score > best_score {
+                    best_score = score;
+                    best_tri = Some(t);
+                }
+            }
+        }
+
+        cache = new_cache;
+    }
+
+    output
+}
@@ -1,20 +1,25 @@
 use vulkano::device::{Device, DeviceExtensions, Queue};
 use vulkano::framebuffer::RenderPassAbstract;
 use vulkano::image::SwapchainImage;
-use vulkano::instance::{Instance, PhysicalDevice};
-use vulkano::swapchain::Surface;
-use vulkano::sync::GpuFuture;
+use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice};
+use vulkano::swapchain::{AcquireError, Surface};
+use vulkano::sync::{FlushError, GpuFuture};
 
 use vulkano_win::VkSurfaceBuild;
 
 use winit::{EventsLoop, WindowBuilder};
 
+use std::path::Path;
 use std::sync::Arc;
 
 use re_ll::vk_window::VkWindow;
+pub use re_ll::vk_window::SwapchainConfig;
+pub use vulkano::swapchain::PresentMode;
 
-use crate::input::{EventHandler, FrameInfo};
+use crate::capture::{try_to_rgba8, CaptureError};
+use crate::input::{ActionMap, EventHandler, FrameInfo};
 use crate::render_passes;
+use crate::system::RenderError;
 use crate::utils::Timer;
 
 pub struct Window {
@@ -27,9 +32,49 @@ pub struct Window {
 
 impl Window {
     pub fn new() -> (Self, Arc<Queue>) {
-        // defaults to a basic render pass
+        Self::new_with_swapchain_config(SwapchainConfig::default())
+    }
+
+    // unsupported formats or image counts fall back to supported ones with a
+    // warning. whatever pass draws to the window needs to output in
+    // get_format(), see render_passes::basic_with_format.
+    pub fn new_with_swapchain_config(config: SwapchainConfig) -> (Self, Arc<Queue>) {
+        let (window, queue, _) = Self::create(config, false);
+        (window, queue)
+    }
+
+    // also returns a second queue that passes can be submitted on with
+    // System::run_on_async_queue, if the device has one to spare. it's
+    // always from the same family as the main queue, so images don't have to
+    // be shared between families.
+    pub fn new_with_async_queue() -> (Self, Arc<Queue>, Option<Arc<Queue>>) {
+        Self::create(SwapchainConfig::default(), true)
+    }
+
+    // opens another window that uses the same device as an existing one, for
+    // drawing to several windows at once (see System::finish_to_windows).
+    // NOTE: every window has its own events loop and needs update() called
+    // every frame. some platforms (macOS) don't support more than one events
+    // loop, this only works on the others.
+    pub fn new_secondary(queue: Arc<Queue>) -> Self {
+        let instance = queue.device().instance().clone();
+        Self::with_queue(instance, queue, SwapchainConfig::default())
+    }
+
+    fn create(config: SwapchainConfig, want_async: bool) -> (Self, Arc<Queue>, Option<Arc<Queue>>) {
         let instance = get_instance();
-        let queue = get_queue(instance.clone());
+        let device_ext = DeviceExtensions {
+            khr_swapchain: true,
+            ..DeviceExtensions::none()
+        };
+        let (queue, async_queue) = get_queues(instance.clone(), want_async, device_ext);
+        let window = Self::with_queue(instance, queue.clone(), config);
+
+        (window, queue, async_queue)
+    }
+
+    fn with_queue(instance: Arc<Instance>, queue: Arc<Queue>, config: SwapchainConfig) -> Self {
+        // defaults to a basic render pass
         let device = queue.device().clone();
 
         let events_loop = EventsLoop::new();
@@ -46,33 +91,100 @@ impl Window {
 
         let render_pass = render_passes::basic(device.clone());
 
-        let vk_window = VkWindow::new(
+        let vk_window = VkWindow::new_with_config(
             queue.device().clone(),
             queue.clone(),
             surface.clone(),
             render_pass.clone(),
             swapchain_caps.clone(),
+            config,
         );
 
-        let window = Self {
+        Self {
             vk_window,
             event_handler,
             queue: queue.clone(),
             recenter: true,
             update_timer: Timer::new("Avg. time to update window"),
-        };
-
-        (window, queue)
+        }
     }
 
     pub fn present_future<F: GpuFuture + 'static>(&mut self, future: F) {
         self.vk_window.present_image(self.queue.clone(), future);
     }
 
-    pub fn next_image(&mut self) -> Arc<SwapchainImage<winit::Window>> {
+    // see VkWindow::set_max_frames_in_flight
+    pub fn set_max_frames_in_flight(&mut self, max: usize) {
+        self.vk_window.set_max_frames_in_flight(max);
+    }
+
+    pub fn max_frames_in_flight(&self) -> usize {
+        self.vk_window.max_frames_in_flight()
+    }
+
+    // see VkWindow::wait_for_all_frames
+    pub fn wait_for_all_frames(&mut self) {
+        self.vk_window.wait_for_all_frames();
+    }
+
+    // see VkWindow::then_present
+    pub fn then_present(&mut self, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        self.vk_window.then_present(self.queue.clone(), future)
+    }
+
+    // see VkWindow::next_image, None while minimized
+    pub fn next_image(&mut self) -> Option<Arc<SwapchainImage<winit::Window>>> {
         self.vk_window.next_image()
     }
 
+    // see VkWindow::try_next_image
+    pub fn try_next_image(&mut self) -> Result<Arc<SwapchainImage<winit::Window>>, RenderError> {
+        if self.is_minimized() {
+            return Err(RenderError::Minimized);
+        }
+        self.vk_window.try_next_image().map_err(|e| match e {
+            AcquireError::OutOfDate => RenderError::SwapchainOutOfDate,
+            e => RenderError::Acquire(format!("{:?}", e)),
+        })
+    }
+
+    // present_future, but errors are returned instead of printed
+    pub fn try_present_future<F: GpuFuture + 'static>(
+        &mut self,
+        future: F,
+    ) -> Result<(), RenderError> {
+        self.vk_window
+            .try_present_image(self.queue.clone(), future)
+            .map_err(|e| match e {
+                FlushError::OutOfDate => RenderError::SwapchainOutOfDate,
+                e => RenderError::Submit(format!("{:?}", e)),
+            })
+    }
+
+    // see VkWindow::abandon_frame
+    pub fn abandon_frame(&mut self) {
+        self.vk_window.abandon_frame(self.queue.clone());
+    }
+
+    // recreates the swapchain at the window's current size, e.g. after
+    // RenderError::SwapchainOutOfDate. next_image does this by itself.
+    pub fn rebuild_swapchain(&mut self) {
+        self.vk_window.rebuild();
+    }
+
+    // Fifo for vsync, Immediate or Mailbox for uncapped. falls back to a
+    // supported mode with a warning. can't be called between
+    // System::start_window and finishing the frame. System makes its
+    // framebuffers from the swapchain image every frame, so the next
+    // start_window picks up the new swapchain by itself.
+    pub fn set_present_mode(&mut self, mode: PresentMode) {
+        self.vk_window.set_present_mode(mode);
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.vk_window.present_mode()
+    }
+
     pub fn get_future(&mut self) -> Box<dyn GpuFuture> {
         self.vk_window.get_future()
     }
@@ -83,7 +195,7 @@ impl Window {
         // returns whether to exit the program or not
         // TODO: return an enum or move the done-checking to its own function
         let done = self.event_handler.update(self.get_dimensions());
-        if self.recenter {
+        if self.recenter && !self.is_minimized() {
             self.recenter_cursor();
         }
 
@@ -92,6 +204,67 @@ impl Window {
         done
     }
 
+    // true while the window is minimized. there's nothing to draw to then, so
+    // skip drawing (and ideally sleep a bit) until it's false again.
+    // System::start_window returns false and try_start_window returns
+    // RenderError::Minimized in the meantime.
+    // NOTE: winit 0.19 can't tell when the window is covered by other
+    // windows, so those still get drawn to.
+    pub fn is_minimized(&self) -> bool {
+        self.vk_window.is_minimized()
+    }
+
+    // makes FrameInfo report the same delta every frame regardless of how long
+    // the frame actually took, which makes animations reproducible. FPS and
+    // average delta still use the real time. None goes back to real time.
+    pub fn set_fixed_timestep(&mut self, timestep: Option<f32>) {
+        self.event_handler.set_fixed_timestep(timestep);
+    }
+
+    // see EventHandler::start_recording. combined with a fixed timestep and
+    // capturing frames, a replay renders exactly the same images every time.
+    pub fn start_input_recording(&mut self, path: &Path) {
+        self.event_handler.start_recording(path);
+    }
+
+    pub fn stop_input_recording(&mut self) {
+        self.event_handler.stop_recording();
+    }
+
+    // see EventHandler::start_replay
+    pub fn replay_input(&mut self, path: &Path) {
+        self.event_handler.start_replay(path);
+    }
+
+    pub fn is_replaying_input(&self) -> bool {
+        self.event_handler.is_replaying()
+    }
+
+    // keeps a copy of every frame as it's presented, which capture_to_file
+    // needs. off by default, it costs a copy of the whole window per frame.
+    pub fn set_keep_last_frame(&mut self, keep: bool) {
+        self.vk_window.set_keep_last_frame(keep);
+    }
+
+    // writes the last presented frame to a png, with the bytes as they were
+    // on screen (see CaptureEncoding::AsStored). waits for the GPU to finish
+    // the frame first.
+    pub fn capture_to_file(&self, path: &Path) -> Result<(), CaptureError> {
+        let frame = self.vk_window.last_frame().ok_or(CaptureError::NoFrame)?;
+        let data = frame.buffer.read().map_err(|_| CaptureError::InUse)?;
+        let rgba = try_to_rgba8(&data, frame.format)
+            .ok_or(CaptureError::UnsupportedFormat(frame.format))?;
+
+        image::save_buffer(
+            path,
+            &rgba,
+            frame.dimensions[0],
+            frame.dimensions[1],
+            image::RGBA(8),
+        )
+        .map_err(CaptureError::Write)
+    }
+
     pub fn get_surface(&self) -> Arc<Surface<winit::Window>> {
         self.vk_window.get_surface()
     }
@@ -117,6 +290,18 @@ impl Window {
         self.vk_window.get_dimensions()
     }
 
+    // format of the swapchain images
+    pub fn get_format(&self) -> vulkano::format::Format {
+        self.vk_window.get_format()
+    }
+
+    // whether writes to the swapchain get converted from linear to sRGB by
+    // the hardware. if not, the last pass has to gamma correct by itself. see
+    // the top of render_passes.rs.
+    pub fn is_srgb(&self) -> bool {
+        re_ll::vk_window::is_srgb_format(self.get_format())
+    }
+
     // TODO: make these more consistent
     pub fn get_fps(&self) -> f32 {
         // TODO: move fps counting to Window instead of EventHandler
@@ -135,14 +320,40 @@ impl Window {
         self.event_handler.frame_info.clone()
     }
 
+    // see ActionMap
+    pub fn set_action_map(&mut self, actions: ActionMap) {
+        self.event_handler.frame_info.actions = actions;
+    }
+
+    // for rebinding keys while running
+    pub fn action_map_mut(&mut self) -> &mut ActionMap {
+        &mut self.event_handler.frame_info.actions
+    }
+
     pub fn set_render_pass(&mut self, new_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>) {
         self.vk_window.set_render_pass(new_render_pass);
         self.vk_window.rebuild();
     }
 }
 
-fn get_queue(instance: Arc<Instance>) -> Arc<Queue> {
-    // gets some queue that will be used for everything else
+// a queue for rendering without a window, like System::render_headless does.
+// nothing needs a surface or a display, so it works in CI and over ssh as long
+// as there's a Vulkan driver (a software one like lavapipe is fine).
+pub fn headless_queue() -> Arc<Queue> {
+    let instance = Instance::new(None, &InstanceExtensions::none(), None)
+        .expect("Couldn't create Vulkan instance");
+    let (queue, _) = get_queues(instance, false, DeviceExtensions::none());
+
+    queue
+}
+
+fn get_queues(
+    instance: Arc<Instance>,
+    want_async: bool,
+    device_ext: DeviceExtensions,
+) -> (Arc<Queue>, Option<Arc<Queue>>) {
+    // gets some queue that will be used for everything else, plus optionally
+    // a second one from the same family
     let physical = PhysicalDevice::enumerate(&instance).next().unwrap();
 
     let queue_family = physical
@@ -150,19 +361,25 @@ fn get_queue(instance: Arc<Instance>) -> Arc<Queue> {
         .find(|&q| q.supports_graphics())
         .unwrap();
 
-    let device_ext = DeviceExtensions {
-        khr_swapchain: true,
-        ..DeviceExtensions::none()
+    let num_queues = if want_async && queue_family.queues_count() >= 2 {
+        2
+    } else {
+        if want_async {
+            println!("Warning: graphics queue family only has 1 queue, async passes will run on the main queue");
+        }
+        1
     };
+    let priorities = vec![0.5; num_queues];
+
     let (_device, mut queues) = Device::new(
         physical,
         physical.supported_features(),
         &device_ext,
-        [(queue_family, 0.5)].iter().cloned(),
+        priorities.iter().map(|&priority| (queue_family, priority)),
     )
     .unwrap();
 
-    queues.next().unwrap()
+    (queues.next().unwrap(), queues.next())
 }
 
 fn get_instance() -> Arc<Instance> {